@@ -1,4 +1,4 @@
-use spacetimedb::{table, reducer, Table, ReducerContext, Identity, Timestamp, SpacetimeType};
+use spacetimedb::{table, reducer, client_visibility_filter, Filter, Table, ReducerContext, Identity, Timestamp, TimeDuration, ScheduleAt, SpacetimeType};
 
 // Core game enums
 #[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
@@ -11,6 +11,10 @@ pub enum Suit {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, SpacetimeType)]
 pub enum Rank {
+    Two = 2,
+    Three = 3,
+    Four = 4,
+    Five = 5,
     Six = 6,
     Seven = 7,
     Eight = 8,
@@ -35,9 +39,130 @@ pub enum LobbyStatus {
     Finished,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum SkillLevel {
+    Casual,
+    Intermediate,
+    Competitive,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SpacetimeType)]
+pub struct LobbyTags {
+    region: Option<String>,   // e.g. "eu", "na"
+    language: Option<String>, // e.g. "ru", "en"
+    skill_level: Option<SkillLevel>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum LobbyRoleKind {
+    CoHost, // May change settings and kick players
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum LobbyVisibility {
+    Public,  // Listed in the lobby browser
+    Private, // Joined by lobby id only
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum TournamentStatus {
+    Registration, // Taking sign-ups until registration closes
+    Seeded,       // Registration closed with enough entrants
+    Cancelled,    // Too few entrants signed up
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum TimerAction {
+    Attack, // Attacker must lead, throw in or pass
+    Defend, // Defender must beat the cards or take
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum GameEventKind {
+    RoundStarted,   // player: first attacker, target: first defender, suit: trump
+    Dealt,          // player was dealt card
+    TrumpShown,     // card is the exposed bottom card of the deck
+    Attack,         // player attacked target with card (draw_id)
+    UndoAttack,     // player took back card (draw_id)
+    Defend,         // player beat draw_id with card
+    DefendFaceDown, // player covered draw_id with a hidden card
+    Challenge,      // player challenged target's face-down defense, card is the revealed one
+    Transfer,       // player passed the attack on to target with card (draw_id)
+    Take,           // player declared a take
+    Pass,           // player is done throwing in
+    TableTaken,     // player picked up card from the table
+    TableDiscarded, // table went to the discard pile, player defended
+    Refill,         // player drew card from the deck
+    RoundEnded,     // player is the fool, none for a draw
+    RoundVoided,    // round thrown away by a restart vote
+    PlayerLeft,     // player left the game
+    PlayerReturned, // player took back leaving
+    Substituted,    // player took over the seat target left
+    GameFinished,   // player is the final loser, if any
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum CountdownKind {
+    Attack,     // Move timer of the attacker
+    Defend,     // Move timer of the defender
+    TakeWindow, // Attackers' last chance to throw in before a take completes
+    TimeBank,   // Chess clock of the player to act
+    Pause,      // Pause ends by itself
+    GameLimit,  // Total game time runs out
+    RoundLimit, // Round time runs out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum GameResult {
+    Won,
+    Lost,  // Final fool, on the fool's team or knocked out
+    Drawn, // Game ended without a loser
+    Left,  // Quit before the end
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum RoundOutcome {
+    Escaped, // Got rid of their cards in time
+    Lost,    // The round's fool, or on the fool's team
+    Drawn,   // Round ended without a fool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum FameRecord {
+    LongestGame,       // Seconds from deal to finish
+    MostRounds,        // Rounds in one game
+    LongestFoolStreak, // Games lost in a row by one player
+    BiggestComeback,   // Points a winner was behind the leader at their worst
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum RatingPool {
+    Duel,        // Two-player games
+    Multiplayer, // Three or more players
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, SpacetimeType)]
+pub enum RankTier {
+    Bronze,
+    Silver,
+    Gold,
+    Platinum,
+    Diamond,
+    Master,
+    Grandmaster,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum BotReason {
+    Disconnected, // Took over after the grace period, ends when the player reconnects
+    Autopilot,    // Turned on by the player or after repeated timeouts
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
 pub enum GameStatus {
     Active,
+    Paused,   // Frozen by a unanimous vote, no moves or timers
+    Adjourned, // Saved between rounds by the lobby creator, players released until it resumes
     Finished,
 }
 
@@ -46,17 +171,21 @@ pub enum PlayerStatus {
     Active,
     Left,      // Quit early
     Finished,  // Emptied hand successfully
+    Eliminated, // Knocked out as a round's fool (knockout mode)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
 pub enum RoundStatus {
     Active,
     Finished,
+    Drawn,  // Last players went out together or agreed to a draw, nobody lost
+    Voided, // Thrown away by a unanimous restart vote and dealt again
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
 pub enum TurnStatus {
     Active,
+    Taking,        // Defender declared a take, attackers may still throw in
     DefenderTook,  // Defender took cards
     DefenderBeat,  // Defender beat all attacks
 }
@@ -74,6 +203,8 @@ pub enum CardLocation {
     Deck,
     Discarded,
     OnTable,
+    TrumpCard, // Exposed bottom card of the deck, drawn last
+    FaceDown, // Face-down defense (cheating durak), only its holder sees it until challenged
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
@@ -82,6 +213,124 @@ pub enum DeckSize {
     Extended52,  // Full deck 2-A
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum ScoringMode {
+    Flat,       // Fixed 5 points per lost round
+    PerCard,    // 1 point per card left in the loser's hand
+    CardValue,  // Sum of rank values of the cards left in the loser's hand
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum RefillOrder {
+    Traditional, // Primary attacker, other attackers clockwise, defender last
+    SeatOrder,   // Everyone by seat position
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum TrumpSelection {
+    BottomCard,        // Suit of the exposed bottom card of the deck
+    RotatingEachRound, // Bottom card in the first round, then the next suit every round
+    RandomAnnounced,   // Random suit announced without exposing a card
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum GameVariant {
+    Podkidnoy,   // Throw-in Durak, the default rules
+    Perevodnoy,  // Transfers allowed
+    NoTrump,     // Bez kozyrei
+    Cheating,    // Verki, face-down defenses
+    Teams,       // Pairs play as partners
+}
+
+/// The rule choices that make one variant of Durak play differently from another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub struct VariantFingerprint {
+    perevodnoy: bool,
+    team_mode: bool,
+    no_trump: bool,
+    deck_size: DeckSize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum PresenceStatus {
+    Online,
+    InLobby,
+    InGame,
+    Idle, // Connected but hasn't done anything for a while
+    Offline,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, SpacetimeType)]
+pub enum ClanRole {
+    Member,
+    Officer, // Can invite new members
+    Leader,  // Can also promote and demote
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum ChallengeStatus {
+    Pending,  // Waiting for the challenged clan to answer
+    Accepted, // Series in progress
+    Declined,
+    Finished,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum ReportStatus {
+    Open,
+    Actioned,  // An admin acted on it
+    Dismissed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum CosmeticKind {
+    CardBack,
+    TableTheme,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub struct Cosmetic {
+    kind: CosmeticKind,
+    item_id: u32, // 0 is the default design everyone has
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum QuestKind {
+    DefendAttacks,     // Beat attacking cards
+    AttackCards,       // Play attacking cards
+    PlayGames,         // Finish games
+    WinGames,          // Finish games without being the fool
+    WinFourPlayerGame, // Win a game of four or more players
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum Title {
+    GrandFool,          // Ended up the fool in 10 games
+    IronDefender,       // Defended 8 turns in a row without taking
+    Veteran,            // Finished 100 games
+    TournamentChampion, // Won a tournament
+    BrickWall,          // Beat six attacks in one turn
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum HighlightKind {
+    PerfectDefense, // Defender beat six or more attacks in one turn, value is the count
+    LastCardOut,    // Drew the last card of the deck and still went out of the round
+    FirstTurnOut,   // Went out of the round on its first turn
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum BlockKind {
+    Block, // No messages, invites or lobby joins either way; chat is hidden too
+    Mute,  // Only their chat is hidden
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SpacetimeType)]
+pub enum LeaverHand {
+    Discard,      // Cards of a leaving player go out of play
+    ReturnToDeck, // Cards are shuffled back into the deck for others to draw
+}
+
 #[table(name = user, public)]
 pub struct User {
     #[primary_key]
@@ -95,9 +344,18 @@ pub struct User {
     
     // Game state (if in a game)
     current_game_id: Option<u64>,
-    game_position: Option<u8>, // 0-5, determines turn order
+    game_position: Option<u8>, // 0-11, determines turn order
     total_points: Option<u8>, // Points accumulated across hands
     player_status: Option<PlayerStatus>, // Active, Left, Finished
+    team: Option<u8>, // 0 or 1 in team mode, chosen in the lobby
+
+    // Clan state (if in a clan)
+    clan_tag: Option<String>, // Shown next to the name
+
+    level: u32, // From player_xp, shown in lobbies
+    title: Option<Title>, // Equipped title, shown next to the name
+    registered: bool, // Signed in with an identity provider; guests only play casual games
+    rank: Option<RankTier>, // Best tier over the rating pools, shown in lobby and game player lists
 }
 
 #[table(name = lobby, public)]
@@ -110,6 +368,256 @@ pub struct Lobby {
     current_players: u8,
     status: LobbyStatus,
     created_at: Timestamp,
+    visibility: LobbyVisibility,
+    listed: bool, // Public visibility; private lobbies are only seen by players let in or invited
+    has_password: bool,
+    last_activity: Timestamp, // Idle lobbies are cleaned up after a while
+    auto_start_when_full: bool, // Start the game shortly after the last seat is taken
+    carry_scores_from: Option<u64>, // Finished game whose points seed the next game (rematch)
+    tags: LobbyTags, // Region, language and skill level for the lobby browser
+    scheduled_start: Option<Timestamp>, // Game starts on its own at this time
+}
+
+#[client_visibility_filter]
+const LOBBY_LISTED: Filter = Filter::Sql("SELECT * FROM lobby WHERE listed = true");
+
+#[client_visibility_filter]
+const LOBBY_ACCESS: Filter = Filter::Sql(
+    "SELECT lobby.* FROM lobby JOIN lobby_access ON lobby.id = lobby_access.lobby_id WHERE lobby_access.identity = :sender"
+);
+
+#[client_visibility_filter]
+const LOBBY_ACCESS_LINKED: Filter = Filter::Sql(
+    "SELECT lobby.* FROM lobby JOIN lobby_access ON lobby.id = lobby_access.lobby_id JOIN device_link ON lobby_access.identity = device_link.account WHERE device_link.device = :sender"
+);
+
+#[client_visibility_filter]
+const LOBBY_INVITED: Filter = Filter::Sql(
+    "SELECT lobby.* FROM lobby JOIN lobby_invite ON lobby.id = lobby_invite.lobby_id WHERE lobby_invite.invitee = :sender"
+);
+
+#[client_visibility_filter]
+const LOBBY_INVITED_LINKED: Filter = Filter::Sql(
+    "SELECT lobby.* FROM lobby JOIN lobby_invite ON lobby.id = lobby_invite.lobby_id JOIN device_link ON lobby_invite.invitee = device_link.account WHERE device_link.device = :sender"
+);
+
+/// Who has been let in to a private lobby as a player or spectator, so they can see it
+#[table(name = lobby_access, public)]
+pub struct LobbyAccess {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    lobby_id: u64,
+    #[index(btree)]
+    identity: Identity,
+}
+
+#[client_visibility_filter]
+const LOBBY_ACCESS_OWN: Filter = Filter::Sql("SELECT * FROM lobby_access WHERE identity = :sender");
+
+#[client_visibility_filter]
+const LOBBY_ACCESS_OWN_LINKED: Filter = Filter::Sql(
+    "SELECT lobby_access.* FROM lobby_access JOIN device_link ON lobby_access.identity = device_link.account WHERE device_link.device = :sender"
+);
+
+#[table(name = auto_start_schedule, scheduled(auto_start_game))]
+pub struct AutoStartSchedule {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    lobby_id: u64,
+}
+
+#[table(name = open_lobby, public)]
+pub struct OpenLobby {
+    #[primary_key]
+    lobby_id: u64,
+    name: String,
+    current_players: u8,
+    max_players: u8,
+    #[index(btree)]
+    player_bucket: u8, // 0 = heads-up, 1 = 3-4 players, 2 = 5-6 players, 3 = 7+ players
+    #[index(btree)]
+    is_full: bool,
+    #[index(btree)]
+    standard_rules: bool, // Lobby plays with the default settings
+    has_password: bool,
+    tags: LobbyTags,
+    // The tags again as plain columns for subscription queries; empty or 0 when not set
+    #[index(btree)]
+    region: String,
+    #[index(btree)]
+    language: String,
+    #[index(btree)]
+    skill_level_id: u8, // 1 = Casual, 2 = Intermediate, 3 = Competitive
+    created_at: Timestamp,
+}
+
+#[table(name = lobby_start_schedule, scheduled(start_scheduled_lobby))]
+pub struct LobbyStartSchedule {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    lobby_id: u64,
+}
+
+#[table(name = lobby_seat_release, scheduled(release_lobby_seat))]
+pub struct LobbySeatRelease {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    player: Identity,
+    lobby_id: u64,
+}
+
+#[table(name = lobby_expiry_schedule, scheduled(expire_stale_lobbies))]
+pub struct LobbyExpirySchedule {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    max_idle_micros: i64, // Waiting lobbies idle for longer than this are deleted
+}
+
+#[table(name = game_expiry_schedule, scheduled(finish_idle_games))]
+pub struct GameExpirySchedule {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    max_idle_micros: i64, // Games nobody touched for longer than this are finished
+}
+
+#[table(name = presence_schedule, scheduled(refresh_presence))]
+pub struct PresenceSchedule {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+}
+
+#[table(name = spectator, public)]
+pub struct Spectator {
+    #[primary_key]
+    identity: Identity,
+    lobby_id: u64, // Spectators follow the lobby and any game started from it
+    joined_at: Timestamp,
+}
+
+#[table(name = game_spectator, public)]
+pub struct GameSpectator {
+    #[primary_key]
+    identity: Identity,
+    game_id: u64, // Joined while the game was already running
+    joined_at: Timestamp,
+}
+
+#[derive(Debug, Clone, SpacetimeType)]
+pub struct SeatView {
+    player: Identity,
+    cards: u8,
+}
+
+/// Public state of a game after one action, as spectators see it. With a spectator delay,
+/// snapshots wait in the private buffer until they are old enough to be published.
+#[table(name = spectator_event, public)]
+#[table(name = spectator_event_buffer)]
+#[derive(Clone)]
+pub struct SpectatorEvent {
+    #[primary_key]
+    id: u64,
+    game_id: u64,
+    seq: u64, // Game's event_seq after the action
+    round_number: u32,
+    turn_id: Option<u64>,
+    attacker: Option<Identity>,
+    defender: Option<Identity>,
+    table: Vec<Draw>, // Cards on the table of the current turn
+    hands: Vec<SeatView>,
+    happened_at: Timestamp,
+}
+
+#[table(name = spectator_release, scheduled(release_spectator_event))]
+pub struct SpectatorRelease {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    event_id: u64,
+}
+
+/// Cards held per player, so spectators can follow a game without looking at hands
+#[table(name = hand_count, public)]
+pub struct HandCount {
+    #[primary_key]
+    player: Identity,
+    game_id: u64,
+    cards: u8,
+}
+
+// Live rows of delayed games are kept from spectators, who follow spectator_event instead
+#[client_visibility_filter]
+const HAND_COUNT_LIVE: Filter = Filter::Sql(
+    "SELECT hand_count.* FROM hand_count JOIN game ON hand_count.game_id = game.id WHERE game.spectated_live = true"
+);
+
+#[client_visibility_filter]
+const HAND_COUNT_SEATED: Filter = Filter::Sql(
+    "SELECT hand_count.* FROM hand_count JOIN game_seat ON hand_count.game_id = game_seat.game_id WHERE game_seat.player = :sender"
+);
+
+#[client_visibility_filter]
+const HAND_COUNT_SEATED_LINKED: Filter = Filter::Sql(
+    "SELECT hand_count.* FROM hand_count JOIN game_seat ON hand_count.game_id = game_seat.game_id JOIN device_link ON game_seat.player = device_link.account WHERE device_link.device = :sender"
+);
+
+/// Players seated in each running game, mirrored with hand_count; they keep the live view of a delayed game
+#[table(name = game_seat, public)]
+pub struct GameSeat {
+    #[primary_key]
+    player: Identity,
+    #[index(btree)]
+    game_id: u64,
+}
+
+#[table(name = lobby_role, public)]
+pub struct LobbyRole {
+    #[primary_key]
+    id: u64,
+    lobby_id: u64,
+    player: Identity,
+    role: LobbyRoleKind,
+}
+
+#[table(name = lobby_secret)]
+pub struct LobbySecret {
+    #[primary_key]
+    lobby_id: u64,
+    salt: Vec<u8>,
+    password_hash: Vec<u8>, // SHA-256 of the salt and password
+}
+
+#[table(name = lobby_invite, public)]
+pub struct LobbyInvite {
+    #[primary_key]
+    id: u64,
+    lobby_id: u64,
+    inviter: Identity,
+    invitee: Identity,
+    created_at: Timestamp,
+    expires_at: Timestamp, // The invite holds a seat until it expires
+}
+
+#[table(name = lobby_kick, public)]
+pub struct LobbyKick {
+    #[primary_key]
+    id: u64,
+    lobby_id: u64,
+    player: Identity,
+    kicked_at: Timestamp,
 }
 
 #[table(name = game, public)]
@@ -118,12 +626,17 @@ pub struct Game {
     id: u64,
     lobby_id: u64,
     status: GameStatus,
-    trump_suit: Suit,
+    trump_suit: Option<Suit>, // None in no-trump games
     current_round: u32,
     started_at: Timestamp,
     finished_at: Option<Timestamp>,
+    paused_at: Option<Timestamp>,
+    last_activity: Timestamp, // Last move or vote made by a player (not by timers or bots)
+    event_seq: u64, // Bumped on every action in the game
+    spectated_live: bool, // No spectator delay: everyone may read the live game tables
 }
 
+#[derive(Clone, PartialEq)]
 #[table(name = game_settings, public)]
 pub struct GameSettings {
     #[primary_key]
@@ -134,1359 +647,12567 @@ pub struct GameSettings {
     multi_round_mode: bool, // Default true (traditional Durak)
     max_points: u8, // Default 15 (traditional "Fool" threshold)
     anyone_can_attack: bool, // Default true (traditional - any player can join attack)
-    trump_card_to_player: bool, // Default true (traditional - trump card goes to last dealt player)
+    trump_card_to_player: bool, // Default true (traditional - exposed trump card is drawn last from the deck)
+    perevodnoy: bool, // Default false (transfer variant - defender can pass the attack on with a same-rank card)
+    team_mode: bool, // Default false (2v2 or 3v3 - teammates sit apart and share points)
+    no_trump: bool, // Default false (bez kozyrei - only higher cards of the same suit beat)
+    cheating_durak: bool, // Default false (verki - defenses may be played face-down and challenged)
+    deck_count: u8, // Default 1, 2 combines two decks for 7-12 players
+    throw_in_priority: bool, // Default true (traditional - main attacker throws in first, then others clockwise)
+    scoring_mode: ScoringMode, // Default Flat (traditional 5 points per loss)
+    knockout_mode: bool, // Default false (each round's fool is eliminated until a final of two)
+    first_turn_five_cards: bool, // Default true (traditional - at most 5 cards in the first turn of a round)
+    refill_order: RefillOrder, // Default Traditional (attackers first, defender last)
+    trump_selection: TrumpSelection, // Default BottomCard (traditional)
+    max_spectators: u8, // Default 10, 0 = no spectators
+    seat_grace_minutes: u8, // Default 3 (a disconnected player's lobby seat is held this long)
+    attack_timeout_secs: u16, // Default 0 = no timer (attacker who stalls passes, or leads their lowest card)
+    defend_timeout_secs: u16, // Default 0 = no timer (defender who stalls takes the cards)
+    time_bank_secs: u16, // Default 0 = off (per round thinking time; running out loses the round)
+    disconnect_grace_secs: u16, // Default 120 (a disconnected player leaves the game after this long)
+    bot_takeover: bool, // Default false (a bot plays for a disconnected player instead of them leaving)
+    leaver_hand: LeaverHand, // Default Discard
+    spectator_delay_secs: u16, // Default 0 (spectators see the game this many seconds late)
+    max_game_minutes: u16, // Default 0 = no limit (when time is up the game ends with a sudden-death loser)
+    max_round_minutes: u16, // Default 0 = no limit (when time is up the player holding the most cards loses the round)
+    ranked: bool, // Default false (casual; ranked games are closed to guests)
 }
 
-#[derive(Clone)]
-#[table(name = round, public)]
-pub struct Round {
+#[table(name = game_rules, public)]
+pub struct GameRules {
     #[primary_key]
-    id: u64,
     game_id: u64,
-    round_number: u32,
-    status: RoundStatus, // Active, Finished
-    loser: Option<Identity>, // Who lost this hand/round
-    started_at: Timestamp,
-    finished_at: Option<Timestamp>,
+    settings: GameSettings, // Lobby settings as they were when the game started
 }
 
-#[derive(Clone)]
-#[table(name = turn, public)]
-pub struct Turn {
+#[table(name = settings_preset, public)]
+pub struct SettingsPreset {
     #[primary_key]
     id: u64,
-    round_id: u64,
-    turn_number: u32,
-    attacker: Identity,
-    defender: Identity,
-    status: TurnStatus, // Active, DefenderTook, DefenderBeat
-    started_at: Timestamp,
-    finished_at: Option<Timestamp>,
+    owner: Identity,
+    name: String,
+    settings: GameSettings, // lobby_id is the lobby the preset was saved from
 }
 
-#[derive(Clone)]
-#[table(name = draw, public)]
-pub struct Draw {
+#[table(name = game_participant, public)]
+pub struct GameParticipant {
     #[primary_key]
     id: u64,
-    turn_id: u64,
-    attacker: Identity,
-    attacking_card: Card,
-    defending_card: Option<Card>,
-    status: DrawStatus, // Pending, Beaten, Taken
-    created_at: Timestamp,
+    game_id: u64,
+    player: Identity,
+    final_points: u8,
 }
 
-#[derive(Clone)]
-#[table(name = player_card, public)]
-pub struct PlayerCard {
+/// Results screen of a finished game, one row per player
+#[table(name = game_summary, public)]
+pub struct GameSummary {
     #[primary_key]
     id: u64,
     game_id: u64,
     player: Identity,
-    card: Card,
-    location: CardLocation, // Hand, Deck, Discarded, OnTable
+    final_points: u8,
+    rounds_lost: u32,
+    cards_taken: u32, // Cards picked up from the table over the whole game
+    longest_defense_streak: u32, // Most turns in a row defended without taking
+    highlights: u32, // Notable moments, listed in highlight
+    duration_secs: u64,
 }
 
-#[table(name = message, public)]
-pub struct Message {
-    sender: Identity,
-    sent: Timestamp,
-    text: String,
+/// Notable moments of a game as they happen, for tickers, results screens and titles
+#[table(name = highlight, public)]
+pub struct Highlight {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    game_id: u64,
+    round_number: u32,
+    turn_id: u64,
+    player: Identity,
+    kind: HighlightKind,
+    value: u32,
+    happened_at: Timestamp,
 }
 
+/// Score sheet of a multi-round game: every player's result and points after each round
+#[table(name = round_score, public)]
+pub struct RoundScore {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    game_id: u64,
+    round_id: u64,
+    round_number: u32,
+    player: Identity,
+    outcome: RoundOutcome,
+    points_delta: u8,
+    total_points: u8, // Running total after this round
+}
 
-#[reducer]
-/// Clients invoke this reducer to set their user names.
-pub fn set_name(ctx: &ReducerContext, name: String) -> Result<(), String> {
-    let name = validate_name(name)?;
-    if let Some(user) = ctx.db.user().identity().find(ctx.sender) {
-        ctx.db.user().identity().update(User { name: Some(name), ..user });
-        Ok(())
-    } else {
-        Err("Cannot set name for unknown user".to_string())
-    }
+/// Every finished game a player took part in, read by subscribing on player and finished_at
+#[table(name = game_history, public)]
+pub struct GameHistory {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    player: Identity,
+    #[index(btree)]
+    game_id: u64,
+    opponents: Vec<Identity>,
+    result: GameResult,
+    rules: GameSettings, // Variant the game was played with
+    variant: VariantFingerprint, // Groups the game in variant_stats
+    duration_secs: u64,
+    #[index(btree)]
+    finished_at: Timestamp,
+    expected_score: Option<f64>, // Ranked games: average score the ratings predicted against each opponent
+    actual_score: Option<f64>, // Ranked games: average score actually taken from each opponent (1 win, 0.5 draw)
 }
 
-/// Takes a name and checks if it's acceptable as a user's name.
+/// People a user recently shared a table with, for quick re-invites
+#[table(name = recent_player, public)]
+pub struct RecentPlayer {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    owner: Identity,
+    player: Identity,
+    game_id: u64, // Last game played together
+    played_at: Timestamp,
+}
+
+#[client_visibility_filter]
+const RECENT_PLAYER_OWN: Filter = Filter::Sql("SELECT * FROM recent_player WHERE owner = :sender");
+
+#[client_visibility_filter]
+const RECENT_PLAYER_OWN_LINKED: Filter = Filter::Sql(
+    "SELECT recent_player.* FROM recent_player JOIN device_link ON recent_player.owner = device_link.account WHERE device_link.device = :sender"
+);
+
+#[table(name = rematch_vote, public)]
+pub struct RematchVote {
+    #[primary_key]
+    id: u64,
+    game_id: u64,
+    player: Identity,
+    carry_scores: bool, // Keep this game's points in the rematch
+}
+
+#[table(name = turn_timer, scheduled(expire_turn_timer))]
+pub struct TurnTimer {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    game_id: u64,
+    turn_id: u64,
+    player: Identity, // Who has to act before the deadline
+    action: TimerAction,
+}
+
+#[table(name = player_clock, public)]
+pub struct PlayerClock {
+    #[primary_key]
+    id: u64,
+    game_id: u64,
+    player: Identity,
+    round_id: u64, // The bank refills every round
+    remaining_micros: i64,
+    running_since: Option<Timestamp>, // Set while it's this player's move
+}
+
+#[table(name = clock_expiry, scheduled(expire_player_clock))]
+pub struct ClockExpiry {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    game_id: u64,
+    round_id: u64,
+    player: Identity,
+}
+
+#[table(name = disconnect_forfeit, scheduled(forfeit_disconnected_player))]
+pub struct DisconnectForfeit {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    game_id: u64,
+    player: Identity,
+}
+
+/// Operators allowed to repair games
+#[table(name = admin, public)]
+pub struct Admin {
+    #[primary_key]
+    identity: Identity,
+    added_at: Timestamp,
+}
+
+/// Moderation queue of player reports
+#[table(name = player_report, public)]
+pub struct PlayerReport {
+    #[primary_key]
+    id: u64,
+    reporter: Identity,
+    #[index(btree)]
+    target: Identity,
+    reason: String,
+    context_game_id: Option<u64>, // Game the report is about; its game_event log shows what happened
+    event_seq: Option<u32>, // Last logged event of that game when the report was filed
+    status: ReportStatus,
+    created_at: Timestamp,
+    reviewed_by: Option<Identity>,
+}
+
+/// Which admins see a report; every admin gets a row for every report
+#[table(name = report_delivery, public)]
+pub struct ReportDelivery {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    report_id: u64,
+    #[index(btree)]
+    admin: Identity,
+}
+
+#[client_visibility_filter]
+const REPORT_DELIVERY_OWN: Filter = Filter::Sql("SELECT * FROM report_delivery WHERE admin = :sender");
+
+#[client_visibility_filter]
+const REPORT_DELIVERY_OWN_LINKED: Filter = Filter::Sql(
+    "SELECT report_delivery.* FROM report_delivery JOIN device_link ON report_delivery.admin = device_link.account WHERE device_link.device = :sender"
+);
+
+// Admins see the whole queue, reporters only their own reports
+#[client_visibility_filter]
+const PLAYER_REPORT_ADMINS: Filter = Filter::Sql(
+    "SELECT player_report.* FROM player_report JOIN report_delivery ON player_report.id = report_delivery.report_id WHERE report_delivery.admin = :sender"
+);
+
+#[client_visibility_filter]
+const PLAYER_REPORT_ADMINS_LINKED: Filter = Filter::Sql(
+    "SELECT player_report.* FROM player_report JOIN report_delivery ON player_report.id = report_delivery.report_id JOIN device_link ON report_delivery.admin = device_link.account WHERE device_link.device = :sender"
+);
+
+#[client_visibility_filter]
+const PLAYER_REPORT_OWN: Filter = Filter::Sql("SELECT * FROM player_report WHERE reporter = :sender");
+
+#[client_visibility_filter]
+const PLAYER_REPORT_OWN_LINKED: Filter = Filter::Sql(
+    "SELECT player_report.* FROM player_report JOIN device_link ON player_report.reporter = device_link.account WHERE device_link.device = :sender"
+);
+
+#[derive(Debug, Clone, SpacetimeType)]
+pub struct SeatSnapshot {
+    player: Identity,
+    game_position: Option<u8>,
+    total_points: Option<u8>,
+    player_status: Option<PlayerStatus>,
+    team: Option<u8>,
+}
+
+/// Copy of every row of a game at one moment, to roll a broken game back (private: holds hidden cards)
+#[table(name = game_snapshot)]
+pub struct GameSnapshot {
+    #[primary_key]
+    id: u64,
+    game_id: u64,
+    taken_at: Timestamp,
+    taken_by: Identity,
+    game: Game,
+    rounds: Vec<Round>,
+    turns: Vec<Turn>,
+    draws: Vec<Draw>,
+    turn_passes: Vec<TurnPass>,
+    hidden_defenses: Vec<HiddenDefense>,
+    player_cards: Vec<PlayerCard>,
+    seats: Vec<SeatSnapshot>,
+}
+
+#[table(name = bot_seat, public)]
+pub struct BotSeat {
+    #[primary_key]
+    player: Identity, // Seat the server is playing for
+    game_id: u64,
+    reason: BotReason,
+    since: Timestamp,
+}
+
+/// Timeouts in a row per player, reset whenever they move themselves
+#[table(name = timeout_strike, public)]
+pub struct TimeoutStrike {
+    #[primary_key]
+    id: u64,
+    game_id: u64,
+    player: Identity,
+    count: u8,
+}
+
+#[table(name = bot_move, scheduled(play_bot_move))]
+pub struct BotMove {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    game_id: u64,
+}
+
+/// Seat and score of a player in an adjourned game
+#[table(name = adjourned_seat, public)]
+pub struct AdjournedSeat {
+    #[primary_key]
+    id: u64,
+    game_id: u64,
+    player: Identity,
+    game_position: Option<u8>,
+    total_points: Option<u8>,
+    player_status: Option<PlayerStatus>, // Eliminated players stay out after resuming
+    team: Option<u8>,
+    rejoined: bool,
+}
+
+#[table(name = game_time_limit, scheduled(end_game_on_time_limit))]
+pub struct GameTimeLimit {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    game_id: u64,
+}
+
+#[table(name = round_time_limit, scheduled(end_round_on_time_limit))]
+pub struct RoundTimeLimit {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    game_id: u64,
+    round_id: u64,
+}
+
+/// Players who agreed to pause a game; the first vote is the request
+#[table(name = pause_vote, public)]
+pub struct PauseVote {
+    #[primary_key]
+    id: u64,
+    game_id: u64,
+    player: Identity,
+    voted_at: Timestamp,
+}
+
+#[table(name = pause_expiry, scheduled(end_pause))]
+pub struct PauseExpiry {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    game_id: u64,
+}
+
+/// Players who agreed to end the current round without a fool; the first vote is the offer
+#[table(name = draw_vote, public)]
+pub struct DrawVote {
+    #[primary_key]
+    id: u64,
+    game_id: u64,
+    round_id: u64,
+    player: Identity,
+    voted_at: Timestamp,
+}
+
+/// Append-only record of everything that happened in a game, for replays
+#[table(name = game_event, public)]
+pub struct GameEvent {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    game_id: u64,
+    seq: u32, // Order within the game, starting at 1
+    kind: GameEventKind,
+    player: Option<Identity>,
+    target: Option<Identity>,
+    card: Option<Card>,
+    suit: Option<Suit>,
+    draw_id: Option<u64>,
+    created_at: Timestamp,
+}
+
+#[client_visibility_filter]
+const GAME_EVENT_LIVE: Filter = Filter::Sql(
+    "SELECT game_event.* FROM game_event JOIN game ON game_event.game_id = game.id WHERE game.spectated_live = true"
+);
+
+#[client_visibility_filter]
+const GAME_EVENT_SEATED: Filter = Filter::Sql(
+    "SELECT game_event.* FROM game_event JOIN game_seat ON game_event.game_id = game_seat.game_id WHERE game_seat.player = :sender"
+);
+
+#[client_visibility_filter]
+const GAME_EVENT_SEATED_LINKED: Filter = Filter::Sql(
+    "SELECT game_event.* FROM game_event JOIN game_seat ON game_event.game_id = game_seat.game_id JOIN device_link ON game_seat.player = device_link.account WHERE device_link.device = :sender"
+);
+
+/// Deadlines currently running in a game, for client countdowns
+#[table(name = active_timer, public)]
+pub struct ActiveTimer {
+    #[primary_key]
+    id: u64,
+    game_id: u64,
+    kind: CountdownKind,
+    player: Option<Identity>, // Who has to act before the deadline, if anyone
+    deadline: Timestamp,
+}
+
+/// What a player needs to pick up a game after reconnecting
+#[table(name = resume_state, public)]
+pub struct ResumeState {
+    #[primary_key]
+    player: Identity,
+    game_id: u64,
+    turn_id: Option<u64>,
+    pending_action: Option<TimerAction>, // What this player is expected to do now, if anything
+    timer_deadline: Option<Timestamp>, // When their move timer or chess clock runs out
+    last_event_seq: u64, // Game's event_seq at the last update, to spot missed actions
+    updated_at: Timestamp,
+}
+
+/// A player's last exit from a lobby or game, kept so it can be taken back for a short while
+#[table(name = recent_leave, public)]
+pub struct RecentLeave {
+    #[primary_key]
+    player: Identity,
+    lobby_id: Option<u64>,
+    lobby_joined_at: Option<Timestamp>,
+    team: Option<u8>,
+    game_id: Option<u64>,
+    player_status: Option<PlayerStatus>,
+    total_points: Option<u8>,
+    hand: Vec<u64>, // Player card ids held when leaving
+    turn_id: Option<u64>, // Turn in play right after leaving
+    draw_count: u32, // Cards on the table of that turn right after leaving
+    left_at: Timestamp,
+}
+
+/// Players who agreed to throw away the current round and deal it again
+#[table(name = restart_vote, public)]
+pub struct RestartVote {
+    #[primary_key]
+    id: u64,
+    game_id: u64,
+    round_id: u64,
+    player: Identity,
+    voted_at: Timestamp,
+}
+
+/// Seat given up mid-game, open for a substitute
+#[table(name = seat_vacancy, public)]
+pub struct SeatVacancy {
+    #[primary_key]
+    player: Identity, // Who left
+    game_id: u64,
+    game_position: Option<u8>,
+    total_points: Option<u8>,
+    team: Option<u8>,
+    hand: Vec<u64>, // Player card ids held when leaving
+    left_at: Timestamp,
+}
+
+/// A user asking to take over a vacant seat
+#[table(name = substitution_request, public)]
+pub struct SubstitutionRequest {
+    #[primary_key]
+    id: u64,
+    game_id: u64,
+    seat_of: Identity,
+    candidate: Identity,
+    requested_at: Timestamp,
+}
+
+#[table(name = substitution_vote, public)]
+pub struct SubstitutionVote {
+    #[primary_key]
+    id: u64,
+    request_id: u64,
+    voter: Identity,
+}
+
+/// Votes to remove an unresponsive player from a game
+#[table(name = kick_vote, public)]
+pub struct KickVote {
+    #[primary_key]
+    id: u64,
+    game_id: u64,
+    target: Identity,
+    voter: Identity,
+    voted_at: Timestamp,
+}
+
+#[table(name = tournament, public)]
+pub struct Tournament {
+    #[primary_key]
+    id: u64,
+    name: String,
+    organizer: Identity,
+    status: TournamentStatus, // Registration, Seeded, Cancelled
+    registration_opens_at: Timestamp,
+    registration_closes_at: Timestamp,
+    min_entrants: u32,
+    max_entrants: u32,
+    created_at: Timestamp,
+}
+
+#[table(name = tournament_entrant, public)]
+pub struct TournamentEntrant {
+    #[primary_key]
+    id: u64,
+    tournament_id: u64,
+    player: Identity,
+    registered_at: Timestamp,
+    seed: Option<u32>, // 1 = top seed, assigned when registration closes
+}
+
+#[table(name = tournament_match, public)]
+pub struct TournamentMatch {
+    #[primary_key]
+    id: u64,
+    tournament_id: u64,
+    round: u32, // 1 = first round of the bracket
+    slot: u32,  // Position within the round, winners of slots 2k and 2k+1 meet next round
+    player_a: Option<Identity>, // None until the feeding match is decided (or a bye)
+    player_b: Option<Identity>,
+    scheduled_at: Option<Timestamp>,
+    lobby_id: Option<u64>, // Lobby where the match is being played, for spectators
+    winner: Option<Identity>,
+}
+
+#[table(name = tournament_close_schedule, scheduled(close_tournament_registration))]
+pub struct TournamentCloseSchedule {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    tournament_id: u64,
+}
+
+#[derive(Clone)]
+#[table(name = round, public)]
+pub struct Round {
+    #[primary_key]
+    id: u64,
+    game_id: u64,
+    round_number: u32,
+    status: RoundStatus, // Active, Finished, Drawn
+    loser: Option<Identity>, // Who lost this hand/round
+    losing_team: Option<u8>, // Team of the loser in team mode
+    first_attacker: Option<Identity>, // Who opens the round
+    first_defender: Option<Identity>, // Who defends the opening attack
+    epaulettes: bool, // Loser was left holding the lowest cards ("fool with epaulettes")
+    started_at: Timestamp,
+    finished_at: Option<Timestamp>,
+}
+
+#[derive(Clone)]
+#[table(name = turn, public)]
+pub struct Turn {
+    #[primary_key]
+    id: u64,
+    game_id: u64,
+    round_id: u64,
+    turn_number: u32,
+    attacker: Identity,
+    lead_attacker: Identity, // Who opened the turn; transfers move `attacker` but not this
+    defender: Identity,
+    status: TurnStatus, // Active, Taking, DefenderTook, DefenderBeat
+    transfers: u8, // How many times the attack was transferred (perevodnoy)
+    started_at: Timestamp,
+    finished_at: Option<Timestamp>,
+}
+
+#[client_visibility_filter]
+const TURN_LIVE: Filter = Filter::Sql(
+    "SELECT turn.* FROM turn JOIN game ON turn.game_id = game.id WHERE game.spectated_live = true"
+);
+
+#[client_visibility_filter]
+const TURN_SEATED: Filter = Filter::Sql(
+    "SELECT turn.* FROM turn JOIN game_seat ON turn.game_id = game_seat.game_id WHERE game_seat.player = :sender"
+);
+
+#[client_visibility_filter]
+const TURN_SEATED_LINKED: Filter = Filter::Sql(
+    "SELECT turn.* FROM turn JOIN game_seat ON turn.game_id = game_seat.game_id JOIN device_link ON game_seat.player = device_link.account WHERE device_link.device = :sender"
+);
+
+#[derive(Clone)]
+#[table(name = draw, public)]
+pub struct Draw {
+    #[primary_key]
+    id: u64,
+    game_id: u64,
+    turn_id: u64,
+    attacker: Identity,
+    attacking_card: Card,
+    defending_card: Option<Card>,
+    status: DrawStatus, // Pending, Beaten, Taken
+    transferred: bool, // Card was played by the defender to transfer the attack
+    face_down: bool, // Defended face-down (cheating durak), card kept in hidden_defense
+    created_at: Timestamp,
+}
+
+#[client_visibility_filter]
+const DRAW_LIVE: Filter = Filter::Sql(
+    "SELECT draw.* FROM draw JOIN game ON draw.game_id = game.id WHERE game.spectated_live = true"
+);
+
+#[client_visibility_filter]
+const DRAW_SEATED: Filter = Filter::Sql(
+    "SELECT draw.* FROM draw JOIN game_seat ON draw.game_id = game_seat.game_id WHERE game_seat.player = :sender"
+);
+
+#[client_visibility_filter]
+const DRAW_SEATED_LINKED: Filter = Filter::Sql(
+    "SELECT draw.* FROM draw JOIN game_seat ON draw.game_id = game_seat.game_id JOIN device_link ON game_seat.player = device_link.account WHERE device_link.device = :sender"
+);
+
+/// Attackers who declared "bito" (no more cards) for a turn
+#[table(name = turn_pass, public)]
+pub struct TurnPass {
+    #[primary_key]
+    id: u64,
+    turn_id: u64,
+    player: Identity,
+    passed_at: Timestamp,
+}
+
+/// Face-down defending cards, kept out of the public draw table until challenged
+#[table(name = hidden_defense)]
+pub struct HiddenDefense {
+    #[primary_key]
+    draw_id: u64,
+    turn_id: u64,
+    card: Card,
+}
+
+#[derive(Clone)]
+#[table(name = player_card, public)]
+pub struct PlayerCard {
+    #[primary_key]
+    id: u64,
+    game_id: u64,
+    player: Identity,
+    card: Card,
+    location: CardLocation, // Hand, Deck, Discarded, OnTable, TrumpCard, FaceDown
+    shown_to: u8, // CARD_SHOWN_TO_*, kept in step with the location for the visibility filters
+    deck_position: u32, // Place in the shuffled deck; the deck is drawn from the lowest position up
+}
+
+// Hands are only visible to their holder, the deck to nobody; with a spectator delay the table
+// is only live for the seated players
+#[client_visibility_filter]
+const PLAYER_CARD_SHOWN: Filter = Filter::Sql(
+    "SELECT player_card.* FROM player_card JOIN game ON player_card.game_id = game.id WHERE player_card.shown_to = 0 AND game.spectated_live = true"
+);
+
+#[client_visibility_filter]
+const PLAYER_CARD_SHOWN_SEATED: Filter = Filter::Sql(
+    "SELECT player_card.* FROM player_card JOIN game_seat ON player_card.game_id = game_seat.game_id WHERE game_seat.player = :sender AND player_card.shown_to = 0"
+);
+
+#[client_visibility_filter]
+const PLAYER_CARD_SHOWN_SEATED_LINKED: Filter = Filter::Sql(
+    "SELECT player_card.* FROM player_card JOIN game_seat ON player_card.game_id = game_seat.game_id JOIN device_link ON game_seat.player = device_link.account WHERE device_link.device = :sender AND player_card.shown_to = 0"
+);
+
+#[client_visibility_filter]
+const PLAYER_CARD_OWN: Filter = Filter::Sql("SELECT * FROM player_card WHERE player = :sender AND shown_to = 1");
+
+#[client_visibility_filter]
+const PLAYER_CARD_OWN_LINKED: Filter = Filter::Sql(
+    "SELECT player_card.* FROM player_card JOIN device_link ON player_card.player = device_link.account WHERE device_link.device = :sender AND player_card.shown_to = 1"
+);
+
+/// Cards left in the deck of a game, now that deck rows are hidden
+#[table(name = deck_count, public)]
+pub struct DeckCount {
+    #[primary_key]
+    game_id: u64,
+    cards: u32,
+}
+
+/// Names a user went by before renaming
+#[table(name = name_history, public)]
+pub struct NameHistory {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    identity: Identity,
+    name: String,
+    changed_at: Timestamp, // When this name was given up
+}
+
+/// A guest's pending request to move their progress onto a registered account
+#[table(name = account_upgrade, public)]
+pub struct AccountUpgrade {
+    #[primary_key]
+    guest: Identity,
+    #[unique]
+    code: String, // Entered on the registered account
+    expires_at: Timestamp,
+}
+
+/// Wrong link and upgrade codes entered from a device, to stop codes being guessed
+#[table(name = link_attempt)]
+pub struct LinkAttempt {
+    #[primary_key]
+    device: Identity,
+    failures: u32, // Since the last lockout
+    locked_until: Option<Timestamp>,
+}
+
+/// Placeholder identities that stand in for deleted players in finished games
+#[table(name = deleted_player, public)]
+pub struct DeletedPlayer {
+    #[primary_key]
+    identity: Identity,
+    deleted_at: Timestamp,
+}
+
+/// Server-wide sign-in settings, a single row changed by admins
+#[table(name = auth_config)]
+pub struct AuthConfig {
+    #[primary_key]
+    id: u8,
+    guest_issuer: String, // Tokens from this issuer are guests
+}
+
+#[client_visibility_filter]
+const ACCOUNT_UPGRADE_OWN: Filter = Filter::Sql("SELECT * FROM account_upgrade WHERE guest = :sender");
+
+#[client_visibility_filter]
+const ACCOUNT_UPGRADE_OWN_LINKED: Filter = Filter::Sql(
+    "SELECT account_upgrade.* FROM account_upgrade JOIN device_link ON account_upgrade.guest = device_link.account WHERE device_link.device = :sender"
+);
+
+/// Devices signed in to an account; the account is the identity of its first device
+#[table(name = device_link, public)]
+pub struct DeviceLink {
+    #[primary_key]
+    device: Identity,
+    #[index(btree)]
+    account: Identity,
+    online: bool,
+    linked_at: Timestamp,
+}
+
+#[client_visibility_filter]
+const DEVICE_LINK_ACCOUNT: Filter = Filter::Sql("SELECT * FROM device_link WHERE account = :sender");
+
+#[client_visibility_filter]
+const DEVICE_LINK_OWN: Filter = Filter::Sql("SELECT * FROM device_link WHERE device = :sender");
+
+/// A code shown on one device to sign another device in to the same account
+#[table(name = device_link_code, public)]
+pub struct DeviceLinkCode {
+    #[primary_key]
+    account: Identity,
+    #[unique]
+    code: String,
+    expires_at: Timestamp,
+}
+
+#[client_visibility_filter]
+const DEVICE_LINK_CODE_OWN: Filter = Filter::Sql("SELECT * FROM device_link_code WHERE account = :sender");
+
+#[client_visibility_filter]
+const DEVICE_LINK_CODE_OWN_LINKED: Filter = Filter::Sql(
+    "SELECT device_link_code.* FROM device_link_code JOIN device_link ON device_link_code.account = device_link.account WHERE device_link.device = :sender"
+);
+
+/// Experience earned from finished games
+#[table(name = player_xp, public)]
+pub struct PlayerXp {
+    #[primary_key]
+    identity: Identity,
+    xp: u64,
+    level: u32,
+    updated_at: Timestamp,
+}
+
+/// Lifetime numbers for a player, kept up to date as rounds and games end
+#[table(name = player_stats, public)]
+pub struct PlayerStats {
+    #[primary_key]
+    identity: Identity,
+    games_played: u32,
+    wins: u32,
+    fool_count: u32, // Rounds lost
+    cards_beaten: u32, // Attacking cards beaten while defending
+    total_game_secs: u64,
+    average_game_secs: u64,
+    fool_streak: u32, // Games lost in a row, up to now
+    longest_fool_streak: u32,
+    updated_at: Timestamp,
+}
+
+/// A player's Glicko rating, kept separately for duels and multiplayer games
+#[derive(Clone)]
+#[table(name = player_rating, public)]
+pub struct PlayerRating {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    player: Identity,
+    pool: RatingPool,
+    rating: f64,
+    deviation: f64, // Uncertainty of the rating; shrinks with every game and grows while the player is away
+    games: u32,
+    provisional: bool, // Still in placement (games out of PLACEMENT_GAMES); off the ladder and without a tier
+    updated_at: Timestamp,
+}
+
+/// Rating thresholds of each rank tier, with a margin between promotion and demotion
+#[table(name = rank_tier, public)]
+pub struct RankTierThreshold {
+    #[primary_key]
+    id: u8, // Tier order, Bronze = 0
+    tier: RankTier,
+    promote_at: f64, // Rating needed to reach this tier
+    demote_below: f64, // Rating at which a player drops out of it
+    icon_id: u32,
+}
+
+/// A player's rank tier in a rating pool
+#[table(name = player_rank, public)]
+pub struct PlayerRank {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    player: Identity,
+    pool: RatingPool,
+    tier: RankTier,
+    updated_at: Timestamp,
+}
+
+/// Placed players in rating order, one ladder per pool, kept sorted as ratings change
+#[table(name = leaderboard, public, index(name = pool_rank, btree(columns = [pool_id, rank])))]
+pub struct LeaderboardEntry {
+    #[primary_key]
+    id: u64, // Same as the player's rating id
+    #[index(btree)]
+    player: Identity,
+    pool: RatingPool,
+    pool_id: u8, // The pool as a number, for subscription queries
+    rank: u32, // 1 = highest rating in the pool
+    rating: f64,
+}
+
+/// How many players are on each pool's ladder, so newcomers start at the bottom without a scan
+#[table(name = leaderboard_size, public)]
+pub struct LeaderboardSize {
+    #[primary_key]
+    pool_id: u8,
+    pool: RatingPool,
+    entries: u32,
+}
+
+/// A player waiting to be matched for a ranked game of a given size
+#[table(name = matchmaking_ticket, public)]
+pub struct MatchmakingTicket {
+    #[primary_key]
+    player: Identity,
+    players: u8, // Game size wanted, which decides the rating pool
+    pool: RatingPool,
+    rating: f64, // Rating in the pool when the player queued
+    queued_at: Timestamp,
+}
+
+#[table(name = matchmaking_schedule, scheduled(run_matchmaking))]
+pub struct MatchmakingSchedule {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    base_window: f64, // Rating difference accepted right after queueing
+    window_growth_per_minute: f64, // How much wider the window gets for every minute in the queue
+    max_window: f64,
+}
+
+/// A ranked season; only one is active at a time
+#[table(name = season, public)]
+pub struct Season {
+    #[primary_key]
+    id: u32, // Season number, starting at 1
+    starts_at: Timestamp,
+    ends_at: Timestamp,
+    active: bool,
+}
+
+/// A player's place on a season's ladder, frozen with a final rank when the season ends
+#[table(name = season_standing, public)]
+pub struct SeasonStanding {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    season_id: u32,
+    player: Identity,
+    pool: RatingPool,
+    rating: f64,
+    games: u32,
+    wins: u32,
+    final_rank: Option<u32>, // 1 = top of the pool, set at rollover
+}
+
+#[table(name = season_rollover_schedule, scheduled(end_season))]
+pub struct SeasonRolloverSchedule {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+}
+
+#[derive(Debug, Clone, SpacetimeType)]
+pub struct RatingChange {
+    pool: RatingPool,
+    rating: f64, // At the end of the week
+    change: f64, // Since the previous digest
+}
+
+/// What a player did over one week (Monday to Monday, UTC), for a week in review
+#[table(name = weekly_digest, public)]
+pub struct WeeklyDigest {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    player: Identity,
+    week: i64, // Weeks since the epoch
+    games_played: u32,
+    wins: u32,
+    ratings: Vec<RatingChange>,
+    best_moment: Option<u64>, // Rarest highlight of the week, see highlight
+    created_at: Timestamp,
+}
+
+#[table(name = weekly_digest_schedule, scheduled(write_weekly_digests))]
+pub struct WeeklyDigestSchedule {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    post_summary: bool, // Also sum the week up for everyone in the chat
+}
+
+/// A player's record in one variant, so profiles can compare how they do in each
+#[table(name = variant_stats, public)]
+pub struct VariantStats {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    player: Identity,
+    variant: VariantFingerprint,
+    games_played: u32,
+    wins: u32,
+    losses: u32,
+    total_game_secs: u64,
+}
+
+/// Record-holding games and players, one row per record
+#[table(name = hall_of_fame, public)]
+pub struct HallOfFame {
+    #[primary_key]
+    id: u8, // The record kind as a number
+    record: FameRecord,
+    value: u64,
+    game_id: Option<u64>,
+    player: Option<Identity>,
+    set_at: Timestamp,
+}
+
+/// Numbers for the server stats screen, a single row kept up to date as things happen
+#[table(name = server_stats, public)]
+pub struct ServerStats {
+    #[primary_key]
+    id: u8, // Always SERVER_STATS_ID
+    day: i64, // Day games_today counts, days since the Unix epoch (UTC)
+    games_today: u32,
+    active_games: u32, // Running or paused
+    online_players: u32,
+    popular_variant: Option<VariantFingerprint>, // Variant started most often today
+    popular_variant_games: u32,
+    updated_at: Timestamp,
+}
+
+/// Games started today per variant, to find the most popular one
+#[table(name = variant_popularity, public)]
+pub struct VariantPopularity {
+    #[primary_key]
+    id: u64,
+    day: i64,
+    variant: VariantFingerprint,
+    games: u32,
+}
+
+/// Today's quests, the same for everyone and replaced every day
+#[table(name = daily_quest, public)]
+pub struct DailyQuest {
+    #[primary_key]
+    id: u64,
+    day: i64, // Days since the Unix epoch (UTC)
+    kind: QuestKind,
+    target: u32,
+    reward_xp: u64,
+    reward_cosmetic: Option<Cosmetic>,
+}
+
+/// How far a player got with a daily quest
+#[table(name = quest_progress, public)]
+pub struct QuestProgress {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    player: Identity,
+    quest_id: u64,
+    progress: u32,
+    completed_at: Option<Timestamp>,
+}
+
+#[table(name = quest_rotation_schedule, scheduled(rotate_daily_quests))]
+pub struct QuestRotationSchedule {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+}
+
+/// A player's daily login reward streak
+#[table(name = daily_reward, public)]
+pub struct DailyReward {
+    #[primary_key]
+    identity: Identity,
+    streak: u32, // Days in a row claimed, 0 once a day is missed
+    last_claim_day: i64, // Days since the Unix epoch (UTC)
+    last_claimed_at: Timestamp,
+}
+
+/// Cosmetics a player owns
+#[table(name = inventory, public)]
+pub struct InventoryItem {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    owner: Identity,
+    cosmetic: Cosmetic,
+    equipped: bool, // At most one equipped item per kind
+    acquired_at: Timestamp,
+}
+
+/// What each player showed in a game, so every client can render it
+#[table(name = game_cosmetic, public)]
+pub struct GameCosmetic {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    game_id: u64,
+    player: Identity,
+    card_back: u32,
+    table_theme: u32,
+}
+
+/// Titles a player has earned
+#[table(name = player_title, public)]
+pub struct PlayerTitle {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    player: Identity,
+    title: Title,
+    earned_at: Timestamp,
+}
+
+/// What a user tells others about themselves, edited with update_profile
+#[table(name = profile)]
+pub struct Profile {
+    #[primary_key]
+    identity: Identity,
+    avatar_id: u32,
+    bio: String,
+    country: Option<String>, // ISO 3166-1 alpha-2 code
+    favorite_variant: Option<GameVariant>,
+    updated_at: Timestamp,
+}
+
+/// Profile screen: the profile joined with the user's name and game record
+#[table(name = profile_view, public)]
+pub struct ProfileView {
+    #[primary_key]
+    identity: Identity,
+    name: Option<String>,
+    avatar_id: u32,
+    bio: String,
+    country: Option<String>,
+    favorite_variant: Option<GameVariant>,
+    games_played: u32,
+    games_won: u32,
+    games_lost: u32,
+    games_left: u32,
+    level: u32,
+}
+
+#[table(name = message, public)]
+pub struct Message {
+    #[primary_key]
+    id: u64,
+    sender: Identity,
+    sent: Timestamp,
+    text: String,
+}
+
+/// Who gets to see a chat message; users who muted or blocked the sender get no row
+#[table(name = chat_delivery, public)]
+pub struct ChatDelivery {
+    #[primary_key]
+    id: u64,
+    message_id: u64,
+    #[index(btree)]
+    recipient: Identity,
+    sender: Identity,
+}
+
+// Chat messages only reach the subscriptions they were delivered to
+#[client_visibility_filter]
+const MESSAGE_DELIVERED: Filter = Filter::Sql(
+    "SELECT message.* FROM message JOIN chat_delivery ON message.id = chat_delivery.message_id WHERE chat_delivery.recipient = :sender"
+);
+
+#[client_visibility_filter]
+const MESSAGE_DELIVERED_LINKED: Filter = Filter::Sql(
+    "SELECT message.* FROM message JOIN chat_delivery ON message.id = chat_delivery.message_id JOIN device_link ON chat_delivery.recipient = device_link.account WHERE device_link.device = :sender"
+);
+
+#[client_visibility_filter]
+const CHAT_DELIVERY_OWN: Filter = Filter::Sql("SELECT * FROM chat_delivery WHERE recipient = :sender");
+
+#[client_visibility_filter]
+const CHAT_DELIVERY_OWN_LINKED: Filter = Filter::Sql(
+    "SELECT chat_delivery.* FROM chat_delivery JOIN device_link ON chat_delivery.recipient = device_link.account WHERE device_link.device = :sender"
+);
+
+/// Private messages between two users, grouped into one conversation per pair
+#[table(name = direct_message, public)]
+pub struct DirectMessage {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    conversation_id: u64,
+    seq: u32, // Position in the conversation, starting at 1
+    sender: Identity,
+    recipient: Identity,
+    text: String,
+    sent: Timestamp,
+}
+
+// Only the two participants of a conversation can see its messages
+#[client_visibility_filter]
+const DIRECT_MESSAGE_SENT: Filter = Filter::Sql("SELECT * FROM direct_message WHERE sender = :sender");
+
+#[client_visibility_filter]
+const DIRECT_MESSAGE_SENT_LINKED: Filter = Filter::Sql(
+    "SELECT direct_message.* FROM direct_message JOIN device_link ON direct_message.sender = device_link.account WHERE device_link.device = :sender"
+);
+
+#[client_visibility_filter]
+const DIRECT_MESSAGE_RECEIVED: Filter = Filter::Sql("SELECT * FROM direct_message WHERE recipient = :sender");
+
+#[client_visibility_filter]
+const DIRECT_MESSAGE_RECEIVED_LINKED: Filter = Filter::Sql(
+    "SELECT direct_message.* FROM direct_message JOIN device_link ON direct_message.recipient = device_link.account WHERE device_link.device = :sender"
+);
+
+/// Users someone doesn't want to hear from
+#[table(name = user_block, public)]
+pub struct UserBlock {
+    #[primary_key]
+    id: u64,
+    blocker: Identity,
+    blocked: Identity,
+    kind: BlockKind,
+    created_at: Timestamp,
+}
+
+// Nobody learns who blocked or muted them
+#[client_visibility_filter]
+const USER_BLOCK_OWN: Filter = Filter::Sql("SELECT * FROM user_block WHERE blocker = :sender");
+
+#[client_visibility_filter]
+const USER_BLOCK_OWN_LINKED: Filter = Filter::Sql(
+    "SELECT user_block.* FROM user_block JOIN device_link ON user_block.blocker = device_link.account WHERE device_link.device = :sender"
+);
+
+/// Friend requests waiting for an answer
+#[table(name = friend_request, public)]
+pub struct FriendRequest {
+    #[primary_key]
+    id: u64,
+    sender: Identity,
+    recipient: Identity,
+    created_at: Timestamp,
+}
+
+#[client_visibility_filter]
+const FRIEND_REQUEST_SENT: Filter = Filter::Sql("SELECT * FROM friend_request WHERE sender = :sender");
+
+#[client_visibility_filter]
+const FRIEND_REQUEST_SENT_LINKED: Filter = Filter::Sql(
+    "SELECT friend_request.* FROM friend_request JOIN device_link ON friend_request.sender = device_link.account WHERE device_link.device = :sender"
+);
+
+#[client_visibility_filter]
+const FRIEND_REQUEST_RECEIVED: Filter = Filter::Sql("SELECT * FROM friend_request WHERE recipient = :sender");
+
+#[client_visibility_filter]
+const FRIEND_REQUEST_RECEIVED_LINKED: Filter = Filter::Sql(
+    "SELECT friend_request.* FROM friend_request JOIN device_link ON friend_request.recipient = device_link.account WHERE device_link.device = :sender"
+);
+
+/// Accepted friendships, one row for each side
+#[table(name = friendship, public)]
+pub struct Friendship {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    owner: Identity,
+    friend: Identity,
+    since: Timestamp,
+}
+
+#[client_visibility_filter]
+const FRIENDSHIP_OWN: Filter = Filter::Sql("SELECT * FROM friendship WHERE owner = :sender");
+
+#[client_visibility_filter]
+const FRIENDSHIP_OWN_LINKED: Filter = Filter::Sql(
+    "SELECT friendship.* FROM friendship JOIN device_link ON friendship.owner = device_link.account WHERE device_link.device = :sender"
+);
+
+/// The friend a user follows from game to game
+#[table(name = friend_watch, public)]
+pub struct FriendWatch {
+    #[primary_key]
+    watcher: Identity,
+    #[index(btree)]
+    friend: Identity,
+    since: Timestamp,
+}
+
+#[client_visibility_filter]
+const FRIEND_WATCH_OWN: Filter = Filter::Sql("SELECT * FROM friend_watch WHERE watcher = :sender");
+
+#[client_visibility_filter]
+const FRIEND_WATCH_OWN_LINKED: Filter = Filter::Sql(
+    "SELECT friend_watch.* FROM friend_watch JOIN device_link ON friend_watch.watcher = device_link.account WHERE device_link.device = :sender"
+);
+
+/// Where a user is and when they were last around
+#[table(name = presence, public)]
+pub struct Presence {
+    #[primary_key]
+    identity: Identity,
+    status: PresenceStatus,
+    last_active: Timestamp, // Last action, used for idle detection
+    last_seen: Timestamp, // Last time the user was connected
+}
+
+// Presence is only shared with friends
+#[client_visibility_filter]
+const PRESENCE_OWN: Filter = Filter::Sql("SELECT * FROM presence WHERE identity = :sender");
+
+#[client_visibility_filter]
+const PRESENCE_OWN_LINKED: Filter = Filter::Sql(
+    "SELECT presence.* FROM presence JOIN device_link ON presence.identity = device_link.account WHERE device_link.device = :sender"
+);
+
+#[client_visibility_filter]
+const PRESENCE_FRIENDS: Filter = Filter::Sql(
+    "SELECT presence.* FROM presence JOIN friendship ON presence.identity = friendship.owner WHERE friendship.friend = :sender"
+);
+
+#[client_visibility_filter]
+const PRESENCE_FRIENDS_LINKED: Filter = Filter::Sql(
+    "SELECT presence.* FROM presence JOIN friendship ON presence.identity = friendship.owner JOIN device_link ON friendship.friend = device_link.account WHERE device_link.device = :sender"
+);
+
+#[derive(Clone)]
+#[table(name = clan, public)]
+pub struct Clan {
+    #[primary_key]
+    id: u64,
+    name: String,
+    tag: String, // 2-5 letters or digits, unique
+    leader: Identity,
+    created_at: Timestamp,
+}
+
+#[table(name = clan_member, public)]
+pub struct ClanMember {
+    #[primary_key]
+    identity: Identity, // A user belongs to at most one clan
+    #[index(btree)]
+    clan_id: u64,
+    role: ClanRole,
+    joined_at: Timestamp,
+}
+
+#[table(name = clan_invite, public)]
+pub struct ClanInvite {
+    #[primary_key]
+    id: u64,
+    clan_id: u64,
+    inviter: Identity,
+    invitee: Identity,
+    created_at: Timestamp,
+}
+
+#[client_visibility_filter]
+const CLAN_INVITE_RECEIVED: Filter = Filter::Sql("SELECT * FROM clan_invite WHERE invitee = :sender");
+
+#[client_visibility_filter]
+const CLAN_INVITE_RECEIVED_LINKED: Filter = Filter::Sql(
+    "SELECT clan_invite.* FROM clan_invite JOIN device_link ON clan_invite.invitee = device_link.account WHERE device_link.device = :sender"
+);
+
+#[client_visibility_filter]
+const CLAN_INVITE_SENT: Filter = Filter::Sql("SELECT * FROM clan_invite WHERE inviter = :sender");
+
+#[client_visibility_filter]
+const CLAN_INVITE_SENT_LINKED: Filter = Filter::Sql(
+    "SELECT clan_invite.* FROM clan_invite JOIN device_link ON clan_invite.inviter = device_link.account WHERE device_link.device = :sender"
+);
+
+/// Clan leaderboard row: members' game results plus a series rating
+#[table(name = clan_rating, public)]
+pub struct ClanRating {
+    #[primary_key]
+    clan_id: u64,
+    #[index(btree)]
+    rating: i32, // Elo from clan-vs-clan series, starting at 1000
+    games_played: u32, // Every finished game of every member
+    games_won: u32,
+    games_lost: u32,
+    series_won: u32,
+    series_lost: u32,
+}
+
+/// A best-of-N team series between two clans
+#[table(name = clan_challenge, public)]
+pub struct ClanChallenge {
+    #[primary_key]
+    id: u64,
+    challenger: u64, // Clan ids
+    challenged: u64,
+    best_of: u8,
+    scheduled_at: Option<Timestamp>,
+    lobby_id: Option<u64>, // Team mode lobby where the series games are played
+    status: ChallengeStatus,
+    challenger_wins: u8,
+    challenged_wins: u8,
+    winner: Option<u64>,
+    created_at: Timestamp,
+}
+
+/// Chat only the members of a clan can read
+#[table(name = clan_message, public)]
+pub struct ClanMessage {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    clan_id: u64,
+    sender: Identity,
+    text: String,
+    sent: Timestamp,
+}
+
+#[client_visibility_filter]
+const CLAN_MESSAGE_MEMBERS: Filter = Filter::Sql(
+    "SELECT clan_message.* FROM clan_message JOIN clan_member ON clan_message.clan_id = clan_member.clan_id WHERE clan_member.identity = :sender"
+);
+
+#[client_visibility_filter]
+const CLAN_MESSAGE_MEMBERS_LINKED: Filter = Filter::Sql(
+    "SELECT clan_message.* FROM clan_message JOIN clan_member ON clan_message.clan_id = clan_member.clan_id JOIN device_link ON clan_member.identity = device_link.account WHERE device_link.device = :sender"
+);
+
+#[table(name = notification, public)]
+pub struct Notification {
+    #[primary_key]
+    #[auto_inc]
+    id: u64,
+    recipient: Identity,
+    text: String,
+    created_at: Timestamp,
+}
+
+// Notifications can carry private details such as notes, so only the recipient sees them
+#[client_visibility_filter]
+const NOTIFICATION_OWN: Filter = Filter::Sql("SELECT * FROM notification WHERE recipient = :sender");
+
+#[client_visibility_filter]
+const NOTIFICATION_OWN_LINKED: Filter = Filter::Sql(
+    "SELECT notification.* FROM notification JOIN device_link ON notification.recipient = device_link.account WHERE device_link.device = :sender"
+);
+
+/// Private notes a user keeps about other players
+#[table(name = player_note, public)]
+pub struct PlayerNote {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    author: Identity,
+    subject: Identity,
+    text: String,
+    updated_at: Timestamp,
+}
+
+#[client_visibility_filter]
+const PLAYER_NOTE_OWN: Filter = Filter::Sql("SELECT * FROM player_note WHERE author = :sender");
+
+#[client_visibility_filter]
+const PLAYER_NOTE_OWN_LINKED: Filter = Filter::Sql(
+    "SELECT player_note.* FROM player_note JOIN device_link ON player_note.author = device_link.account WHERE device_link.device = :sender"
+);
+
+/// Client preferences kept on the server so they follow the user to every device
+#[table(name = user_settings, public)]
+pub struct UserSettings {
+    #[primary_key]
+    identity: Identity,
+    auto_sort_hand: bool,
+    confirm_before_take: bool, // Ask before picking up the table
+    locale: String, // Language tag such as "en" or "pt-BR"
+    disable_invites: bool, // Refuse lobby invites from other players
+    updated_at: Timestamp,
+}
+
+#[client_visibility_filter]
+const USER_SETTINGS_OWN: Filter = Filter::Sql("SELECT * FROM user_settings WHERE identity = :sender");
+
+#[client_visibility_filter]
+const USER_SETTINGS_OWN_LINKED: Filter = Filter::Sql(
+    "SELECT user_settings.* FROM user_settings JOIN device_link ON user_settings.identity = device_link.account WHERE device_link.device = :sender"
+);
+
+/// One piece of a user's data in their export
+#[derive(SpacetimeType)]
+pub enum ExportRecord {
+    User(User),
+    Profile(Profile),
+    Settings(UserSettings),
+    NameChange(NameHistory),
+    Experience(PlayerXp),
+    Stats(PlayerStats),
+    VariantStats(VariantStats),
+    Rating(PlayerRating),
+    Rank(PlayerRank),
+    Standing(SeasonStanding),
+    Title(PlayerTitle),
+    Cosmetic(InventoryItem),
+    Friend(Friendship),
+    ChatMessage(Message),
+    DirectMessage(DirectMessage),
+    ClanMessage(ClanMessage),
+    Note(PlayerNote),
+    Game(GameHistory),
+    GameSummary(GameSummary),
+    Highlight(Highlight),
+    WeeklyDigest(WeeklyDigest),
+}
+
+/// The latest export of a user's data, built by export_my_data
+#[table(name = data_export, public)]
+pub struct DataExport {
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    owner: Identity,
+    seq: u32, // Order within the export, starting at 1
+    record: ExportRecord,
+    exported_at: Timestamp,
+}
+
+#[client_visibility_filter]
+const DATA_EXPORT_OWN: Filter = Filter::Sql("SELECT * FROM data_export WHERE owner = :sender");
+
+#[client_visibility_filter]
+const DATA_EXPORT_OWN_LINKED: Filter = Filter::Sql(
+    "SELECT data_export.* FROM data_export JOIN device_link ON data_export.owner = device_link.account WHERE device_link.device = :sender"
+);
+
+/// Minimum time between two renames
+const NAME_CHANGE_COOLDOWN_MICROS: i64 = 24 * 60 * 60 * 1_000_000;
+
+fn generate_name_history_id(identity: Identity, changed_at: Timestamp) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    identity.hash(&mut hasher);
+    changed_at.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[reducer]
+/// Clients invoke this reducer to set their user names.
+pub fn set_name(ctx: &ReducerContext, name: String) -> Result<(), String> {
+    let name = validate_name(name)?;
+    if let Some(user) = ctx.db.user().identity().find(caller(ctx)) {
+        // Picking a first name is free, renaming is rate limited and remembered
+        if let Some(old_name) = user.name.clone() {
+            if old_name == name {
+                return Err("That is already your name".to_string());
+            }
+
+            let last_change = ctx.db.name_history()
+                .identity()
+                .filter(caller(ctx))
+                .map(|entry| entry.changed_at)
+                .max();
+            if last_change.is_some_and(|changed_at| ctx.timestamp < changed_at + TimeDuration::from_micros(NAME_CHANGE_COOLDOWN_MICROS)) {
+                return Err("You can only change your name once a day".to_string());
+            }
+
+            ctx.db.name_history().insert(NameHistory {
+                id: generate_name_history_id(caller(ctx), ctx.timestamp),
+                identity: caller(ctx),
+                name: old_name,
+                changed_at: ctx.timestamp,
+            });
+        }
+
+        ctx.db.user().identity().update(User { name: Some(name), ..user });
+        sync_profile_view(ctx, caller(ctx));
+        Ok(())
+    } else {
+        Err("Cannot set name for unknown user".to_string())
+    }
+}
+
+/// Takes a name and checks if it's acceptable as a user's name.
 fn validate_name(name: String) -> Result<String, String> {
     if name.is_empty() {
-        Err("Names must not be empty".to_string())
+        Err("Names must not be empty".to_string())
+    } else {
+        Ok(name)
+    }
+}
+
+const MAX_BIO_LENGTH: usize = 280;
+
+#[reducer]
+/// Edit your public profile
+pub fn update_profile(
+    ctx: &ReducerContext,
+    avatar_id: u32,
+    bio: String,
+    country: Option<String>,
+    favorite_variant: Option<GameVariant>
+) -> Result<(), String> {
+    ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    if bio.chars().count() > MAX_BIO_LENGTH {
+        return Err(format!("Bio must be at most {} characters", MAX_BIO_LENGTH));
+    }
+
+    let country = match country {
+        Some(code) if code.len() == 2 && code.chars().all(|c| c.is_ascii_alphabetic()) => Some(code.to_ascii_uppercase()),
+        Some(_) => return Err("Country must be a two-letter code".to_string()),
+        None => None,
+    };
+
+    ctx.db.profile().identity().delete(caller(ctx));
+    ctx.db.profile().insert(Profile {
+        identity: caller(ctx),
+        avatar_id,
+        bio,
+        country,
+        favorite_variant,
+        updated_at: ctx.timestamp,
+    });
+    sync_profile_view(ctx, caller(ctx));
+
+    log::info!("User {:?} updated their profile", caller(ctx));
+    Ok(())
+}
+
+const MAX_LOCALE_LENGTH: usize = 16;
+
+#[reducer]
+/// Save your client preferences, shared by all your devices
+pub fn update_preferences(
+    ctx: &ReducerContext,
+    auto_sort_hand: bool,
+    confirm_before_take: bool,
+    locale: String,
+    disable_invites: bool
+) -> Result<(), String> {
+    ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    if locale.is_empty()
+        || locale.len() > MAX_LOCALE_LENGTH
+        || !locale.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err("Locale must be a language tag such as \"en\" or \"pt-BR\"".to_string());
+    }
+
+    ctx.db.user_settings().identity().delete(caller(ctx));
+    ctx.db.user_settings().insert(UserSettings {
+        identity: caller(ctx),
+        auto_sort_hand,
+        confirm_before_take,
+        locale,
+        disable_invites,
+        updated_at: ctx.timestamp,
+    });
+
+    log::info!("User {:?} updated their preferences", caller(ctx));
+    Ok(())
+}
+
+/// Rebuild a user's profile_view row from their profile, name and game history
+fn sync_profile_view(ctx: &ReducerContext, identity: Identity) {
+    ctx.db.profile_view().identity().delete(identity);
+
+    let Some(user) = ctx.db.user().identity().find(identity) else {
+        return;
+    };
+
+    let profile = ctx.db.profile().identity().find(identity);
+    let results: Vec<GameResult> = ctx.db.game_history()
+        .player()
+        .filter(identity)
+        .map(|entry| entry.result)
+        .collect();
+    let count = |result: GameResult| results.iter().filter(|r| **r == result).count() as u32;
+
+    ctx.db.profile_view().insert(ProfileView {
+        identity,
+        name: user.name,
+        avatar_id: profile.as_ref().map_or(0, |profile| profile.avatar_id),
+        bio: profile.as_ref().map_or(String::new(), |profile| profile.bio.clone()),
+        country: profile.as_ref().and_then(|profile| profile.country.clone()),
+        favorite_variant: profile.as_ref().and_then(|profile| profile.favorite_variant),
+        games_played: results.len() as u32,
+        games_won: count(GameResult::Won),
+        games_lost: count(GameResult::Lost),
+        games_left: count(GameResult::Left),
+        level: user.level,
+    });
+}
+
+#[reducer]
+/// Clients invoke this reducer to send messages.
+pub fn send_message(ctx: &ReducerContext, text: String) -> Result<(), String> {
+    let text = validate_message(text)?;
+    log::info!("{}", text);
+    let message_id = generate_message_id(caller(ctx), ctx.timestamp, &text);
+    ctx.db.message().insert(Message {
+        id: message_id,
+        sender: caller(ctx),
+        text,
+        sent: ctx.timestamp,
+    });
+
+    // Everyone who hasn't muted or blocked the sender, the sender included
+    let recipients: Vec<Identity> = ctx.db.user()
+        .iter()
+        .map(|user| user.identity)
+        .filter(|recipient| ctx.db.user_block().id().find(generate_block_id(*recipient, caller(ctx))).is_none())
+        .collect();
+
+    for recipient in recipients {
+        ctx.db.chat_delivery().insert(ChatDelivery {
+            id: generate_chat_delivery_id(message_id, recipient),
+            message_id,
+            recipient,
+            sender: caller(ctx),
+        });
+    }
+
+    Ok(())
+}
+
+/// Takes a message's text and checks if it's acceptable to send.
+fn validate_message(text: String) -> Result<String, String> {
+    if text.is_empty() {
+        Err("Messages must not be empty".to_string())
+    } else {
+        Ok(text)
+    }
+}
+
+fn generate_message_id(sender: Identity, timestamp: Timestamp, text: &str) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    sender.hash(&mut hasher);
+    timestamp.hash(&mut hasher);
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn generate_chat_delivery_id(message_id: u64, recipient: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    message_id.hash(&mut hasher);
+    recipient.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn generate_conversation_id(a: Identity, b: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    // Same conversation whoever writes first
+    let (first, second) = if a < b { (a, b) } else { (b, a) };
+    let mut hasher = DefaultHasher::new();
+    first.hash(&mut hasher);
+    second.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn generate_direct_message_id(conversation_id: u64, seq: u32) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    conversation_id.hash(&mut hasher);
+    seq.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn generate_block_id(blocker: Identity, blocked: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    blocker.hash(&mut hasher);
+    blocked.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Whether either user has blocked the other (mutes don't count)
+fn is_blocked_between(ctx: &ReducerContext, a: Identity, b: Identity) -> bool {
+    let blocks = |blocker: Identity, blocked: Identity| {
+        ctx.db.user_block().id().find(generate_block_id(blocker, blocked))
+            .is_some_and(|block| block.kind == BlockKind::Block)
+    };
+    blocks(a, b) || blocks(b, a)
+}
+
+/// Drop chat a user already received from someone they no longer want to hear
+fn clear_chat_deliveries(ctx: &ReducerContext, recipient: Identity, sender: Identity) {
+    let deliveries: Vec<u64> = ctx.db.chat_delivery()
+        .recipient()
+        .filter(recipient)
+        .filter(|delivery| delivery.sender == sender)
+        .map(|delivery| delivery.id)
+        .collect();
+
+    for delivery_id in deliveries {
+        ctx.db.chat_delivery().id().delete(delivery_id);
+    }
+}
+
+/// Block or mute a user; blocking also covers a mute that is already in place
+fn add_user_block(ctx: &ReducerContext, target: Identity, kind: BlockKind) -> Result<(), String> {
+    if target == caller(ctx) {
+        return Err("You cannot block or mute yourself".to_string());
+    }
+
+    ctx.db.user().identity().find(target)
+        .ok_or("User not found")?;
+
+    let id = generate_block_id(caller(ctx), target);
+    if let Some(existing) = ctx.db.user_block().id().find(id) {
+        if existing.kind == BlockKind::Block || existing.kind == kind {
+            return Err(match existing.kind {
+                BlockKind::Block => "User is already blocked".to_string(),
+                BlockKind::Mute => "User is already muted".to_string(),
+            });
+        }
+        ctx.db.user_block().id().delete(id);
+    }
+
+    ctx.db.user_block().insert(UserBlock {
+        id,
+        blocker: caller(ctx),
+        blocked: target,
+        kind,
+        created_at: ctx.timestamp,
+    });
+    clear_chat_deliveries(ctx, caller(ctx), target);
+    if kind == BlockKind::Block {
+        clear_friendship(ctx, caller(ctx), target);
+    }
+
+    log::info!("User {:?} added a {:?} on {:?}", caller(ctx), kind, target);
+    Ok(())
+}
+
+/// Lift a block or mute
+fn remove_user_block(ctx: &ReducerContext, target: Identity, kind: BlockKind) -> Result<(), String> {
+    let id = generate_block_id(caller(ctx), target);
+    if !ctx.db.user_block().id().find(id).is_some_and(|block| block.kind == kind) {
+        return Err(match kind {
+            BlockKind::Block => "User is not blocked".to_string(),
+            BlockKind::Mute => "User is not muted".to_string(),
+        });
+    }
+
+    ctx.db.user_block().id().delete(id);
+
+    log::info!("User {:?} lifted a {:?} on {:?}", caller(ctx), kind, target);
+    Ok(())
+}
+
+#[reducer]
+/// Send a private message to another user
+pub fn send_dm(ctx: &ReducerContext, target: Identity, text: String) -> Result<(), String> {
+    let text = validate_message(text)?;
+
+    if target == caller(ctx) {
+        return Err("You cannot message yourself".to_string());
+    }
+
+    ctx.db.user().identity().find(target)
+        .ok_or("User not found")?;
+
+    if is_blocked_between(ctx, caller(ctx), target) {
+        return Err("You cannot message this user".to_string());
+    }
+
+    let conversation_id = generate_conversation_id(caller(ctx), target);
+    let seq = ctx.db.direct_message().conversation_id().filter(conversation_id).count() as u32 + 1;
+
+    ctx.db.direct_message().insert(DirectMessage {
+        id: generate_direct_message_id(conversation_id, seq),
+        conversation_id,
+        seq,
+        sender: caller(ctx),
+        recipient: target,
+        text,
+        sent: ctx.timestamp,
+    });
+
+    Ok(())
+}
+
+#[reducer]
+/// Stop a user from messaging you, inviting you or joining your lobbies
+pub fn block_user(ctx: &ReducerContext, target: Identity) -> Result<(), String> {
+    add_user_block(ctx, target, BlockKind::Block)
+}
+
+#[reducer]
+/// Lift a block on a user
+pub fn unblock_user(ctx: &ReducerContext, target: Identity) -> Result<(), String> {
+    remove_user_block(ctx, target, BlockKind::Block)
+}
+
+#[reducer]
+/// Hide a user's chat messages from you
+pub fn mute_user(ctx: &ReducerContext, target: Identity) -> Result<(), String> {
+    add_user_block(ctx, target, BlockKind::Mute)
+}
+
+#[reducer]
+/// Show a muted user's new chat messages again
+pub fn unmute_user(ctx: &ReducerContext, target: Identity) -> Result<(), String> {
+    remove_user_block(ctx, target, BlockKind::Mute)
+}
+
+// Friends
+
+fn generate_friend_request_id(from: Identity, to: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    "friend_request".hash(&mut hasher);
+    from.hash(&mut hasher);
+    to.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn generate_friendship_id(owner: Identity, friend: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    "friendship".hash(&mut hasher);
+    owner.hash(&mut hasher);
+    friend.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn are_friends(ctx: &ReducerContext, a: Identity, b: Identity) -> bool {
+    ctx.db.friendship().id().find(generate_friendship_id(a, b)).is_some()
+}
+
+/// End a friendship on both sides and drop any open requests between the two
+fn clear_friendship(ctx: &ReducerContext, a: Identity, b: Identity) {
+    ctx.db.friendship().id().delete(generate_friendship_id(a, b));
+    ctx.db.friendship().id().delete(generate_friendship_id(b, a));
+    ctx.db.friend_request().id().delete(generate_friend_request_id(a, b));
+    ctx.db.friend_request().id().delete(generate_friend_request_id(b, a));
+
+    for (watcher, friend) in [(a, b), (b, a)] {
+        if ctx.db.friend_watch().watcher().find(watcher).is_some_and(|watch| watch.friend == friend) {
+            ctx.db.friend_watch().watcher().delete(watcher);
+        }
+    }
+}
+
+#[reducer]
+/// Ask another user to be friends
+pub fn send_friend_request(ctx: &ReducerContext, target: Identity) -> Result<(), String> {
+    if target == caller(ctx) {
+        return Err("You cannot befriend yourself".to_string());
+    }
+
+    ctx.db.user().identity().find(target)
+        .ok_or("User not found")?;
+
+    if is_blocked_between(ctx, caller(ctx), target) {
+        return Err("You cannot befriend this user".to_string());
+    }
+
+    if are_friends(ctx, caller(ctx), target) {
+        return Err("You are already friends".to_string());
+    }
+
+    if ctx.db.friend_request().id().find(generate_friend_request_id(target, caller(ctx))).is_some() {
+        return Err("This user already sent you a friend request".to_string());
+    }
+
+    let id = generate_friend_request_id(caller(ctx), target);
+    if ctx.db.friend_request().id().find(id).is_some() {
+        return Err("Friend request already sent".to_string());
+    }
+
+    ctx.db.friend_request().insert(FriendRequest {
+        id,
+        sender: caller(ctx),
+        recipient: target,
+        created_at: ctx.timestamp,
+    });
+    notify(ctx, target, "You have a new friend request".to_string());
+
+    log::info!("User {:?} sent a friend request to {:?}", caller(ctx), target);
+    Ok(())
+}
+
+#[reducer]
+/// Accept a friend request sent to you
+pub fn accept_friend_request(ctx: &ReducerContext, request_id: u64) -> Result<(), String> {
+    let request = ctx.db.friend_request().id().find(request_id)
+        .ok_or("Friend request not found")?;
+
+    if request.recipient != caller(ctx) {
+        return Err("This friend request is not for you".to_string());
+    }
+
+    ctx.db.friend_request().id().delete(request_id);
+
+    for (owner, friend) in [(request.sender, request.recipient), (request.recipient, request.sender)] {
+        ctx.db.friendship().insert(Friendship {
+            id: generate_friendship_id(owner, friend),
+            owner,
+            friend,
+            since: ctx.timestamp,
+        });
+    }
+    notify(ctx, request.sender, "Your friend request was accepted".to_string());
+
+    log::info!("User {:?} and {:?} are now friends", request.sender, request.recipient);
+    Ok(())
+}
+
+#[reducer]
+/// Turn down a friend request sent to you
+pub fn decline_friend_request(ctx: &ReducerContext, request_id: u64) -> Result<(), String> {
+    let request = ctx.db.friend_request().id().find(request_id)
+        .ok_or("Friend request not found")?;
+
+    if request.recipient != caller(ctx) {
+        return Err("This friend request is not for you".to_string());
+    }
+
+    ctx.db.friend_request().id().delete(request_id);
+    Ok(())
+}
+
+#[reducer]
+/// Stop being friends with someone
+pub fn remove_friend(ctx: &ReducerContext, target: Identity) -> Result<(), String> {
+    if !are_friends(ctx, caller(ctx), target) {
+        return Err("You are not friends".to_string());
+    }
+
+    clear_friendship(ctx, caller(ctx), target);
+
+    log::info!("User {:?} removed friend {:?}", caller(ctx), target);
+    Ok(())
+}
+
+// Player Notes
+
+const MAX_NOTE_LENGTH: usize = 200;
+
+fn generate_player_note_id(author: Identity, subject: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    author.hash(&mut hasher);
+    subject.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[reducer]
+/// Write a private note about another player; an empty note removes it
+pub fn set_player_note(ctx: &ReducerContext, subject: Identity, text: String) -> Result<(), String> {
+    if subject == caller(ctx) {
+        return Err("You cannot write a note about yourself".to_string());
+    }
+
+    ctx.db.user().identity().find(subject)
+        .ok_or("User not found")?;
+
+    let text = text.trim().to_string();
+    if text.chars().count() > MAX_NOTE_LENGTH {
+        return Err(format!("Notes must be at most {} characters", MAX_NOTE_LENGTH));
+    }
+
+    let id = generate_player_note_id(caller(ctx), subject);
+    ctx.db.player_note().id().delete(id);
+    if !text.is_empty() {
+        ctx.db.player_note().insert(PlayerNote {
+            id,
+            author: caller(ctx),
+            subject,
+            text,
+            updated_at: ctx.timestamp,
+        });
+    }
+
+    Ok(())
+}
+
+/// Remind lobby members of their notes on someone joining, and the newcomer of theirs on the members
+fn surface_player_notes(ctx: &ReducerContext, lobby_id: u64, newcomer: Identity) {
+    let members: Vec<Identity> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_lobby_id == Some(lobby_id) && user.identity != newcomer)
+        .map(|user| user.identity)
+        .collect();
+
+    for member in members {
+        for (author, subject) in [(member, newcomer), (newcomer, member)] {
+            if let Some(note) = ctx.db.player_note().id().find(generate_player_note_id(author, subject)) {
+                let name = ctx.db.user().identity().find(subject)
+                    .and_then(|user| user.name)
+                    .unwrap_or_else(|| "a player".to_string());
+                notify(ctx, author, format!("Your note on {} in your lobby: {}", name, note.text));
+            }
+        }
+    }
+}
+
+/// Leave a notification for a user
+fn notify(ctx: &ReducerContext, recipient: Identity, text: String) {
+    ctx.db.notification().insert(Notification {
+        id: 0,
+        recipient,
+        text,
+        created_at: ctx.timestamp,
+    });
+}
+
+#[reducer(init)]
+// Called when the module is first published
+pub fn init(ctx: &ReducerContext) {
+    // Whoever publishes the module can repair games
+    ctx.db.admin().insert(Admin {
+        identity: ctx.sender,
+        added_at: ctx.timestamp,
+    });
+
+    // Recurring jobs and seeded rows; later schema changes need a republish with --clear-database
+    ctx.db.lobby_expiry_schedule().insert(LobbyExpirySchedule {
+        scheduled_id: 0,
+        scheduled_at: ScheduleAt::Interval(TimeDuration::from_micros(LOBBY_EXPIRY_CHECK_MICROS)),
+        max_idle_micros: LOBBY_MAX_IDLE_MICROS,
+    });
+    ctx.db.game_expiry_schedule().insert(GameExpirySchedule {
+        scheduled_id: 0,
+        scheduled_at: ScheduleAt::Interval(TimeDuration::from_micros(GAME_EXPIRY_CHECK_MICROS)),
+        max_idle_micros: GAME_MAX_IDLE_MICROS,
+    });
+    ctx.db.matchmaking_schedule().insert(MatchmakingSchedule {
+        scheduled_id: 0,
+        scheduled_at: ScheduleAt::Interval(TimeDuration::from_micros(MATCHMAKING_CHECK_MICROS)),
+        base_window: MATCHMAKING_BASE_WINDOW,
+        window_growth_per_minute: MATCHMAKING_WINDOW_GROWTH_PER_MINUTE,
+        max_window: MATCHMAKING_MAX_WINDOW,
+    });
+    ctx.db.presence_schedule().insert(PresenceSchedule {
+        scheduled_id: 0,
+        scheduled_at: ScheduleAt::Interval(TimeDuration::from_micros(PRESENCE_CHECK_MICROS)),
+    });
+    generate_daily_quests(ctx);
+    schedule_quest_rotation(ctx);
+    start_season(ctx, 1);
+    schedule_weekly_digest(ctx, true);
+    for (tier, promote_at, demote_below, icon_id) in RANK_TIERS {
+        ctx.db.rank_tier().insert(RankTierThreshold {
+            id: tier as u8,
+            tier,
+            promote_at,
+            demote_below,
+            icon_id,
+        });
+    }
+}
+
+#[reducer(client_connected)]
+// Called when a client connects to a SpacetimeDB database server
+pub fn client_connected(ctx: &ReducerContext) {
+    let account = caller(ctx);
+    if let Some(link) = ctx.db.device_link().device().find(ctx.sender) {
+        ctx.db.device_link().device().update(DeviceLink { online: true, ..link });
+    }
+
+    if let Some(user) = ctx.db.user().identity().find(account) {
+        // If this is a returning user, i.e. we already have a `User` with this `Identity`,
+        // set `online: true`, but leave other fields unchanged.
+        // Only the account's own identity decides whether it is registered
+        let registered = if account == ctx.sender { is_registered_sender(ctx) } else { user.registered };
+        if !user.online {
+            update_server_stats(ctx, |stats| stats.online_players += 1);
+        }
+        ctx.db.user().identity().update(User { online: true, registered, ..user });
+        mark_active(ctx, account);
+        cancel_seat_release(ctx, account);
+        cancel_disconnect_forfeit(ctx, account);
+        if let Some(game_id) = user.current_game_id {
+            // Fresh resume row for the reconnecting client to read
+            if let Err(err) = update_resume_states(ctx, game_id) {
+                log::warn!("Could not refresh resume state of game {}: {}", game_id, err);
+            }
+        }
+        if ctx.db.bot_seat().player().find(account).is_some_and(|seat| seat.reason == BotReason::Disconnected) {
+            ctx.db.bot_seat().player().delete(account);
+        }
+    } else {
+        // If this is a new user, create a `User` row for the `Identity`,
+        // which is online, but hasn't set a name or joined any lobbies/games.
+        ctx.db.user().insert(User {
+            name: None,
+            identity: account,
+            online: true,
+            current_lobby_id: None,
+            lobby_joined_at: None,
+            current_game_id: None,
+            game_position: None,
+            total_points: None,
+            player_status: None,
+            team: None,
+            clan_tag: None,
+            level: 1,
+            title: None,
+            registered: is_registered_sender(ctx),
+            rank: None,
+        });
+        sync_profile_view(ctx, account);
+        mark_active(ctx, account);
+        update_server_stats(ctx, |stats| stats.online_players += 1);
+    }
+}
+
+#[reducer(client_disconnected)]
+// Called when a client disconnects from SpacetimeDB database server
+pub fn identity_disconnected(ctx: &ReducerContext) {
+    let account = caller(ctx);
+    if let Some(link) = ctx.db.device_link().device().find(ctx.sender) {
+        ctx.db.device_link().device().update(DeviceLink { online: false, ..link });
+        // The account stays online as long as one of its devices is connected
+        if ctx.db.device_link().account().filter(account).any(|other| other.online) {
+            return;
+        }
+    }
+
+    account_went_offline(ctx, account);
+}
+
+/// Start the grace periods of an account whose last device disconnected and mark it offline
+fn account_went_offline(ctx: &ReducerContext, account: Identity) {
+    ctx.db.matchmaking_ticket().player().delete(account);
+    if let Some(user) = ctx.db.user().identity().find(account) {
+        // Hold a waiting lobby seat for a while in case they come back
+        if let Some(lobby) = user.current_lobby_id.and_then(|id| ctx.db.lobby().id().find(id)) {
+            if lobby.status == LobbyStatus::Waiting {
+                let grace_minutes = get_game_settings(ctx, lobby.id).seat_grace_minutes as i64;
+                ctx.db.lobby_seat_release().insert(LobbySeatRelease {
+                    scheduled_id: 0,
+                    scheduled_at: ScheduleAt::Time(ctx.timestamp + TimeDuration::from_micros(grace_minutes * 60 * 1_000_000)),
+                    player: account,
+                    lobby_id: lobby.id,
+                });
+            }
+        }
+
+        // Give a player in a running game some time to come back before they forfeit
+        if let Some(game_id) = user.current_game_id {
+            if let Ok(settings) = get_game_settings_for_game(ctx, game_id) {
+                ctx.db.disconnect_forfeit().insert(DisconnectForfeit {
+                    scheduled_id: 0,
+                    scheduled_at: ScheduleAt::Time(ctx.timestamp + TimeDuration::from_micros(settings.disconnect_grace_secs as i64 * 1_000_000)),
+                    game_id,
+                    player: account,
+                });
+            }
+        }
+        if user.online {
+            update_server_stats(ctx, |stats| stats.online_players = stats.online_players.saturating_sub(1));
+        }
+        ctx.db.user().identity().update(User { online: false, ..user });
+        sync_presence(ctx, account);
+    } else {
+        // This branch should be unreachable,
+        // as it doesn't make sense for a client to disconnect without connecting first.
+        log::warn!("Disconnect event for unknown user with identity {:?}", ctx.sender);
+    }
+}
+
+// Clans
+
+fn generate_clan_id(tag: &str, timestamp: Timestamp) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    tag.hash(&mut hasher);
+    timestamp.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn generate_clan_invite_id(clan_id: u64, invitee: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    clan_id.hash(&mut hasher);
+    invitee.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Takes a clan tag and checks it's 2-5 letters or digits, returning it upper-cased
+fn validate_clan_tag(tag: String) -> Result<String, String> {
+    let length = tag.chars().count();
+    if length < 2 || length > 5 || !tag.chars().all(|c| c.is_alphanumeric()) {
+        Err("Clan tags must be 2 to 5 letters or digits".to_string())
+    } else {
+        Ok(tag.to_uppercase())
+    }
+}
+
+/// Put a user into a clan (or take them out) and show the tag next to their name
+fn set_clan_membership(ctx: &ReducerContext, identity: Identity, clan: Option<&Clan>, role: ClanRole) {
+    ctx.db.clan_member().identity().delete(identity);
+    if let Some(clan) = clan {
+        ctx.db.clan_member().insert(ClanMember {
+            identity,
+            clan_id: clan.id,
+            role,
+            joined_at: ctx.timestamp,
+        });
+    }
+
+    if let Some(user) = ctx.db.user().identity().find(identity) {
+        ctx.db.user().identity().update(User {
+            clan_tag: clan.map(|clan| clan.tag.clone()),
+            ..user
+        });
+    }
+}
+
+/// The caller's membership, if they have at least the given role
+fn check_clan_role(ctx: &ReducerContext, role: ClanRole) -> Result<ClanMember, String> {
+    let member = ctx.db.clan_member().identity().find(caller(ctx))
+        .ok_or("You are not in a clan")?;
+
+    if member.role < role {
+        return Err(match role {
+            ClanRole::Leader => "Only the clan leader can do this".to_string(),
+            _ => "Only clan officers can do this".to_string(),
+        });
+    }
+
+    Ok(member)
+}
+
+/// Remove a clan with its members, invites and chat
+fn delete_clan(ctx: &ReducerContext, clan_id: u64) {
+    let members: Vec<Identity> = ctx.db.clan_member()
+        .clan_id()
+        .filter(clan_id)
+        .map(|member| member.identity)
+        .collect();
+    for member in members {
+        set_clan_membership(ctx, member, None, ClanRole::Member);
+    }
+
+    let invites: Vec<u64> = ctx.db.clan_invite()
+        .iter()
+        .filter(|invite| invite.clan_id == clan_id)
+        .map(|invite| invite.id)
+        .collect();
+    for invite_id in invites {
+        ctx.db.clan_invite().id().delete(invite_id);
+    }
+
+    let messages: Vec<u64> = ctx.db.clan_message()
+        .clan_id()
+        .filter(clan_id)
+        .map(|message| message.id)
+        .collect();
+    for message_id in messages {
+        ctx.db.clan_message().id().delete(message_id);
+    }
+
+    let challenges: Vec<u64> = ctx.db.clan_challenge()
+        .iter()
+        .filter(|challenge| challenge.challenger == clan_id || challenge.challenged == clan_id)
+        .filter(|challenge| matches!(challenge.status, ChallengeStatus::Pending | ChallengeStatus::Accepted))
+        .map(|challenge| challenge.id)
+        .collect();
+    for challenge_id in challenges {
+        ctx.db.clan_challenge().id().delete(challenge_id);
+    }
+
+    ctx.db.clan_rating().clan_id().delete(clan_id);
+    ctx.db.clan().id().delete(clan_id);
+}
+
+#[reducer]
+/// Found a clan and lead it
+pub fn create_clan(ctx: &ReducerContext, name: String, tag: String) -> Result<(), String> {
+    ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    if name.trim().is_empty() {
+        return Err("Clan name must not be empty".to_string());
+    }
+
+    let tag = validate_clan_tag(tag)?;
+
+    if ctx.db.clan_member().identity().find(caller(ctx)).is_some() {
+        return Err("You are already in a clan".to_string());
+    }
+
+    if ctx.db.clan().iter().any(|clan| clan.tag == tag) {
+        return Err("Clan tag is already taken".to_string());
+    }
+
+    let clan = Clan {
+        id: generate_clan_id(&tag, ctx.timestamp),
+        name,
+        tag,
+        leader: caller(ctx),
+        created_at: ctx.timestamp,
+    };
+    ctx.db.clan().insert(clan.clone());
+    ctx.db.clan_rating().insert(ClanRating {
+        clan_id: clan.id,
+        rating: CLAN_START_RATING,
+        games_played: 0,
+        games_won: 0,
+        games_lost: 0,
+        series_won: 0,
+        series_lost: 0,
+    });
+    set_clan_membership(ctx, caller(ctx), Some(&clan), ClanRole::Leader);
+
+    log::info!("User {:?} created clan [{}] {}", caller(ctx), clan.tag, clan.name);
+    Ok(())
+}
+
+#[reducer]
+/// Invite a user to your clan (officers and the leader)
+pub fn invite_to_clan(ctx: &ReducerContext, target: Identity) -> Result<(), String> {
+    let member = check_clan_role(ctx, ClanRole::Officer)?;
+
+    ctx.db.user().identity().find(target)
+        .ok_or("User not found")?;
+
+    if ctx.db.clan_member().identity().find(target).is_some() {
+        return Err("User is already in a clan".to_string());
+    }
+
+    if is_blocked_between(ctx, caller(ctx), target) {
+        return Err("You cannot invite this user".to_string());
+    }
+
+    let clan = ctx.db.clan().id().find(member.clan_id)
+        .ok_or("Clan not found")?;
+
+    let invite_id = generate_clan_invite_id(clan.id, target);
+    ctx.db.clan_invite().id().delete(invite_id);
+    ctx.db.clan_invite().insert(ClanInvite {
+        id: invite_id,
+        clan_id: clan.id,
+        inviter: caller(ctx),
+        invitee: target,
+        created_at: ctx.timestamp,
+    });
+    notify(ctx, target, format!("You were invited to join clan [{}] {}", clan.tag, clan.name));
+
+    log::info!("User {:?} invited {:?} to clan {}", caller(ctx), target, clan.id);
+    Ok(())
+}
+
+#[reducer]
+/// Join a clan you were invited to
+pub fn join_clan(ctx: &ReducerContext, invite_id: u64) -> Result<(), String> {
+    let invite = ctx.db.clan_invite().id().find(invite_id)
+        .ok_or("Invite not found")?;
+
+    if invite.invitee != caller(ctx) {
+        return Err("This invite is not for you".to_string());
+    }
+
+    if ctx.db.clan_member().identity().find(caller(ctx)).is_some() {
+        return Err("You are already in a clan".to_string());
+    }
+
+    ctx.db.clan_invite().id().delete(invite_id);
+
+    let clan = ctx.db.clan().id().find(invite.clan_id)
+        .ok_or("Clan not found")?;
+
+    // Other invites are moot once the user picked a clan
+    let other_invites: Vec<u64> = ctx.db.clan_invite()
+        .iter()
+        .filter(|other| other.invitee == caller(ctx))
+        .map(|other| other.id)
+        .collect();
+    for other_id in other_invites {
+        ctx.db.clan_invite().id().delete(other_id);
+    }
+
+    set_clan_membership(ctx, caller(ctx), Some(&clan), ClanRole::Member);
+
+    log::info!("User {:?} joined clan {}", caller(ctx), clan.id);
+    Ok(())
+}
+
+#[reducer]
+/// Turn down a clan invite
+pub fn decline_clan_invite(ctx: &ReducerContext, invite_id: u64) -> Result<(), String> {
+    let invite = ctx.db.clan_invite().id().find(invite_id)
+        .ok_or("Invite not found")?;
+
+    if invite.invitee != caller(ctx) {
+        return Err("This invite is not for you".to_string());
+    }
+
+    ctx.db.clan_invite().id().delete(invite_id);
+    Ok(())
+}
+
+#[reducer]
+/// Leave your clan; a leaving leader hands over to the longest-serving officer, then member,
+/// and the last one out disbands the clan
+pub fn leave_clan(ctx: &ReducerContext) -> Result<(), String> {
+    let member = ctx.db.clan_member().identity().find(caller(ctx))
+        .ok_or("You are not in a clan")?;
+
+    let clan = ctx.db.clan().id().find(member.clan_id)
+        .ok_or("Clan not found")?;
+
+    set_clan_membership(ctx, caller(ctx), None, ClanRole::Member);
+
+    if member.role == ClanRole::Leader {
+        let successor = ctx.db.clan_member()
+            .clan_id()
+            .filter(clan.id)
+            .max_by_key(|other| (other.role, std::cmp::Reverse(other.joined_at)));
+
+        match successor {
+            Some(successor) => {
+                ctx.db.clan_member().identity().update(ClanMember {
+                    role: ClanRole::Leader,
+                    ..successor
+                });
+                notify(ctx, successor.identity, format!("You are now the leader of clan [{}]", clan.tag));
+                ctx.db.clan().id().update(Clan {
+                    leader: successor.identity,
+                    ..clan
+                });
+            }
+            None => {
+                log::info!("Clan {} disbanded", clan.id);
+                delete_clan(ctx, clan.id);
+            }
+        }
+    }
+
+    log::info!("User {:?} left clan {}", caller(ctx), member.clan_id);
+    Ok(())
+}
+
+#[reducer]
+/// Change a member's role (only the clan leader); making someone leader hands over the clan
+pub fn promote_clan_member(ctx: &ReducerContext, target: Identity, role: ClanRole) -> Result<(), String> {
+    let leader = check_clan_role(ctx, ClanRole::Leader)?;
+
+    if target == caller(ctx) {
+        return Err("You cannot change your own role".to_string());
+    }
+
+    let member = ctx.db.clan_member().identity().find(target)
+        .filter(|member| member.clan_id == leader.clan_id)
+        .ok_or("User is not in your clan")?;
+
+    let clan = ctx.db.clan().id().find(leader.clan_id)
+        .ok_or("Clan not found")?;
+
+    if role == ClanRole::Leader {
+        ctx.db.clan_member().identity().update(ClanMember {
+            role: ClanRole::Officer,
+            ..leader
+        });
+        ctx.db.clan().id().update(Clan {
+            leader: target,
+            ..clan
+        });
+    }
+
+    ctx.db.clan_member().identity().update(ClanMember {
+        role,
+        ..member
+    });
+
+    log::info!("User {:?} made {:?} a {:?} of clan {}", caller(ctx), target, role, leader.clan_id);
+    Ok(())
+}
+
+fn generate_clan_message_id(clan_id: u64, sender: Identity, timestamp: Timestamp) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    clan_id.hash(&mut hasher);
+    sender.hash(&mut hasher);
+    timestamp.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[reducer]
+/// Send a message to your clan's chat
+pub fn send_clan_message(ctx: &ReducerContext, text: String) -> Result<(), String> {
+    let text = validate_message(text)?;
+
+    let member = ctx.db.clan_member().identity().find(caller(ctx))
+        .ok_or("You are not in a clan")?;
+
+    ctx.db.clan_message().insert(ClanMessage {
+        id: generate_clan_message_id(member.clan_id, caller(ctx), ctx.timestamp),
+        clan_id: member.clan_id,
+        sender: caller(ctx),
+        text,
+        sent: ctx.timestamp,
+    });
+
+    Ok(())
+}
+
+// Clan Ratings and Series
+
+const CLAN_START_RATING: i32 = 1000;
+const CLAN_RATING_K: f64 = 32.0;
+
+fn generate_clan_challenge_id(challenger: u64, challenged: u64, timestamp: Timestamp) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    challenger.hash(&mut hasher);
+    challenged.hash(&mut hasher);
+    timestamp.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Count a member's finished game towards their clan's totals
+fn add_clan_game_result(ctx: &ReducerContext, player: Identity, result: GameResult) {
+    let Some(member) = ctx.db.clan_member().identity().find(player) else {
+        return;
+    };
+    let Some(rating) = ctx.db.clan_rating().clan_id().find(member.clan_id) else {
+        return;
+    };
+
+    ctx.db.clan_rating().clan_id().update(ClanRating {
+        games_played: rating.games_played + 1,
+        games_won: rating.games_won + (result == GameResult::Won) as u32,
+        games_lost: rating.games_lost + (result == GameResult::Lost) as u32,
+        ..rating
+    });
+}
+
+#[reducer]
+/// Challenge another clan to a best-of-N team series (officers and the leader)
+pub fn challenge_clan(ctx: &ReducerContext, clan_id: u64, best_of: u8, scheduled_at: Option<Timestamp>) -> Result<(), String> {
+    let member = check_clan_role(ctx, ClanRole::Officer)?;
+
+    if clan_id == member.clan_id {
+        return Err("A clan cannot challenge itself".to_string());
+    }
+
+    let opponent = ctx.db.clan().id().find(clan_id)
+        .ok_or("Clan not found")?;
+
+    if best_of == 0 || best_of > 9 || best_of % 2 == 0 {
+        return Err("Series must be best of 1, 3, 5, 7 or 9".to_string());
+    }
+
+    let open = ctx.db.clan_challenge().iter().any(|challenge| {
+        matches!(challenge.status, ChallengeStatus::Pending | ChallengeStatus::Accepted)
+            && ((challenge.challenger == member.clan_id && challenge.challenged == clan_id)
+                || (challenge.challenger == clan_id && challenge.challenged == member.clan_id))
+    });
+    if open {
+        return Err("These clans already have an open challenge".to_string());
+    }
+
+    ctx.db.clan_challenge().insert(ClanChallenge {
+        id: generate_clan_challenge_id(member.clan_id, clan_id, ctx.timestamp),
+        challenger: member.clan_id,
+        challenged: clan_id,
+        best_of,
+        scheduled_at,
+        lobby_id: None,
+        status: ChallengeStatus::Pending,
+        challenger_wins: 0,
+        challenged_wins: 0,
+        winner: None,
+        created_at: ctx.timestamp,
+    });
+    notify(ctx, opponent.leader, format!("Your clan was challenged to a best of {} series", best_of));
+
+    log::info!("Clan {} challenged clan {} to a best of {}", member.clan_id, clan_id, best_of);
+    Ok(())
+}
+
+/// The caller's clan must be the challenged one, and they an officer or the leader
+fn check_challenged_clan(ctx: &ReducerContext, challenge: &ClanChallenge) -> Result<(), String> {
+    let member = check_clan_role(ctx, ClanRole::Officer)?;
+
+    if member.clan_id != challenge.challenged {
+        return Err("This challenge is not for your clan".to_string());
+    }
+
+    if challenge.status != ChallengeStatus::Pending {
+        return Err("Challenge was already answered".to_string());
+    }
+
+    Ok(())
+}
+
+#[reducer]
+/// Take up a challenge to your clan (officers and the leader)
+pub fn accept_clan_challenge(ctx: &ReducerContext, challenge_id: u64) -> Result<(), String> {
+    let challenge = ctx.db.clan_challenge().id().find(challenge_id)
+        .ok_or("Challenge not found")?;
+
+    check_challenged_clan(ctx, &challenge)?;
+
+    ctx.db.clan_challenge().id().update(ClanChallenge {
+        status: ChallengeStatus::Accepted,
+        ..challenge
+    });
+
+    log::info!("Clan challenge {} accepted", challenge_id);
+    Ok(())
+}
+
+#[reducer]
+/// Turn down a challenge to your clan (officers and the leader)
+pub fn decline_clan_challenge(ctx: &ReducerContext, challenge_id: u64) -> Result<(), String> {
+    let challenge = ctx.db.clan_challenge().id().find(challenge_id)
+        .ok_or("Challenge not found")?;
+
+    check_challenged_clan(ctx, &challenge)?;
+
+    ctx.db.clan_challenge().id().update(ClanChallenge {
+        status: ChallengeStatus::Declined,
+        ..challenge
+    });
+
+    log::info!("Clan challenge {} declined", challenge_id);
+    Ok(())
+}
+
+#[reducer]
+/// Set when and in which team mode lobby an accepted series is played (officers of either clan)
+pub fn schedule_clan_series(
+    ctx: &ReducerContext,
+    challenge_id: u64,
+    scheduled_at: Option<Timestamp>,
+    lobby_id: Option<u64>,
+) -> Result<(), String> {
+    let member = check_clan_role(ctx, ClanRole::Officer)?;
+
+    let challenge = ctx.db.clan_challenge().id().find(challenge_id)
+        .ok_or("Challenge not found")?;
+
+    if member.clan_id != challenge.challenger && member.clan_id != challenge.challenged {
+        return Err("Your clan is not part of this series".to_string());
+    }
+
+    if challenge.status != ChallengeStatus::Accepted {
+        return Err("Series is not in progress".to_string());
+    }
+
+    if let Some(lobby_id) = lobby_id {
+        ctx.db.lobby().id().find(lobby_id)
+            .ok_or("Lobby not found")?;
+
+        if !get_game_settings(ctx, lobby_id).team_mode {
+            return Err("Clan series are played in team mode lobbies".to_string());
+        }
+    }
+
+    ctx.db.clan_challenge().id().update(ClanChallenge {
+        scheduled_at,
+        lobby_id,
+        ..challenge
+    });
+
+    log::info!("Clan series {} scheduled at {:?} in lobby {:?}", challenge_id, scheduled_at, lobby_id);
+    Ok(())
+}
+
+/// Score a finished game of a clan series played in its lobby. The game only counts when each
+/// team is made up of one of the two clans and there is a loser.
+fn record_clan_series_game(ctx: &ReducerContext, lobby_id: u64, game_id: u64, final_loser: Option<Identity>) {
+    let Some(challenge) = ctx.db.clan_challenge()
+        .iter()
+        .find(|challenge| challenge.status == ChallengeStatus::Accepted && challenge.lobby_id == Some(lobby_id)) else {
+        return;
+    };
+
+    let Some(loser) = final_loser.and_then(|loser| ctx.db.user().identity().find(loser)) else {
+        return;
+    };
+
+    let players: Vec<User> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id))
+        .collect();
+
+    let team_clan = |losing: bool| -> Option<u64> {
+        let clans: Vec<Option<u64>> = players.iter()
+            .filter(|player| is_teammate(player, &loser) == losing)
+            .map(|player| ctx.db.clan_member().identity().find(player.identity).map(|member| member.clan_id))
+            .collect();
+        match clans.first() {
+            Some(Some(clan_id)) if clans.iter().all(|clan| *clan == Some(*clan_id)) => Some(*clan_id),
+            _ => None,
+        }
+    };
+
+    let (Some(losing_clan), Some(winning_clan)) = (team_clan(true), team_clan(false)) else {
+        log::info!("Game {} does not count for clan series {}: mixed teams", game_id, challenge.id);
+        return;
+    };
+
+    let sides = (challenge.challenger, challenge.challenged);
+    if (winning_clan, losing_clan) != sides && (losing_clan, winning_clan) != sides {
+        return;
+    }
+
+    let challenger_wins = challenge.challenger_wins + (winning_clan == challenge.challenger) as u8;
+    let challenged_wins = challenge.challenged_wins + (winning_clan == challenge.challenged) as u8;
+    let needed = challenge.best_of / 2 + 1;
+
+    let winner = if challenger_wins >= needed {
+        Some(challenge.challenger)
+    } else if challenged_wins >= needed {
+        Some(challenge.challenged)
+    } else {
+        None
+    };
+
+    ctx.db.clan_challenge().id().update(ClanChallenge {
+        challenger_wins,
+        challenged_wins,
+        winner,
+        status: if winner.is_some() { ChallengeStatus::Finished } else { ChallengeStatus::Accepted },
+        ..challenge
+    });
+
+    if let Some(winner) = winner {
+        let loser = if winner == challenge.challenger { challenge.challenged } else { challenge.challenger };
+        finish_clan_series(ctx, winner, loser);
+        log::info!("Clan {} won series {} against clan {}", winner, challenge.id, loser);
+    }
+}
+
+/// Move both clans' ratings after a series (Elo)
+fn finish_clan_series(ctx: &ReducerContext, winner: u64, loser: u64) {
+    let (Some(winner_rating), Some(loser_rating)) = (
+        ctx.db.clan_rating().clan_id().find(winner),
+        ctx.db.clan_rating().clan_id().find(loser),
+    ) else {
+        return;
+    };
+
+    let expected = 1.0 / (1.0 + 10f64.powf((loser_rating.rating - winner_rating.rating) as f64 / 400.0));
+    let change = (CLAN_RATING_K * (1.0 - expected)).round() as i32;
+
+    ctx.db.clan_rating().clan_id().update(ClanRating {
+        rating: winner_rating.rating + change,
+        series_won: winner_rating.series_won + 1,
+        ..winner_rating
+    });
+    ctx.db.clan_rating().clan_id().update(ClanRating {
+        rating: loser_rating.rating - change,
+        series_lost: loser_rating.series_lost + 1,
+        ..loser_rating
+    });
+}
+
+// Guest Accounts
+
+const AUTH_CONFIG_ID: u8 = 0;
+
+/// Issuer of the tokens the SpacetimeDB host mints for anonymous connections, unless configured otherwise
+const DEFAULT_GUEST_TOKEN_ISSUER: &str = "localhost";
+
+/// How long an account upgrade code stays valid
+const UPGRADE_CODE_MICROS: i64 = 10 * 60 * 1_000_000;
+
+/// Wrong codes a device may enter before it is locked out
+const MAX_LINK_CODE_FAILURES: u32 = 5;
+
+const LINK_CODE_LOCKOUT_MICROS: i64 = 15 * 60 * 1_000_000;
+
+/// Characters of link codes, leaving out look-alikes such as 0/O and 1/I
+const LINK_CODE_ALPHABET: &[u8; 32] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+
+const LINK_CODE_LENGTH: usize = 12;
+
+fn guest_token_issuer(ctx: &ReducerContext) -> String {
+    ctx.db.auth_config().id().find(AUTH_CONFIG_ID)
+        .map_or(DEFAULT_GUEST_TOKEN_ISSUER.to_string(), |config| config.guest_issuer)
+}
+
+/// Whether the caller signed in with an identity provider rather than as a guest
+fn is_registered_sender(ctx: &ReducerContext) -> bool {
+    let guest_issuer = guest_token_issuer(ctx);
+    ctx.sender_auth()
+        .jwt()
+        .is_some_and(|jwt| jwt.issuer() != guest_issuer)
+}
+
+#[reducer]
+/// Set the token issuer whose connections count as guests (only admins can do this)
+pub fn set_guest_issuer(ctx: &ReducerContext, issuer: String) -> Result<(), String> {
+    check_admin(ctx)?;
+
+    if issuer.is_empty() {
+        return Err("Issuer must not be empty".to_string());
+    }
+
+    ctx.db.auth_config().id().delete(AUTH_CONFIG_ID);
+    ctx.db.auth_config().insert(AuthConfig {
+        id: AUTH_CONFIG_ID,
+        guest_issuer: issuer,
+    });
+    Ok(())
+}
+
+/// Guests may only take part in casual games
+fn check_guest_allowed(user: &User, settings: &GameSettings) -> Result<(), String> {
+    if settings.ranked && !user.registered {
+        return Err("Guests can only play casual games".to_string());
+    }
+    Ok(())
+}
+
+/// Random twelve-character code for linking two identities (60 bits)
+fn generate_link_code(ctx: &ReducerContext) -> String {
+    let mut bits: u64 = ctx.random();
+    (0..LINK_CODE_LENGTH)
+        .map(|_| {
+            let character = LINK_CODE_ALPHABET[(bits % 32) as usize] as char;
+            bits /= 32;
+            character
+        })
+        .collect()
+}
+
+fn check_link_lockout(ctx: &ReducerContext) -> Result<(), String> {
+    let locked = ctx.db.link_attempt().device().find(ctx.sender)
+        .and_then(|attempt| attempt.locked_until)
+        .is_some_and(|until| until > ctx.timestamp);
+    if locked {
+        return Err("Too many wrong codes, try again later".to_string());
+    }
+    Ok(())
+}
+
+/// Count a wrong code against the calling device, locking it out after too many.
+/// The reducer has to succeed for this to stick, so the caller is told with a notification.
+fn record_link_failure(ctx: &ReducerContext) {
+    let failures = ctx.db.link_attempt().device().find(ctx.sender)
+        .map_or(0, |attempt| attempt.failures) + 1;
+    let locked = failures >= MAX_LINK_CODE_FAILURES;
+
+    ctx.db.link_attempt().device().delete(ctx.sender);
+    ctx.db.link_attempt().insert(LinkAttempt {
+        device: ctx.sender,
+        failures: if locked { 0 } else { failures },
+        locked_until: locked.then(|| ctx.timestamp + TimeDuration::from_micros(LINK_CODE_LOCKOUT_MICROS)),
+    });
+    notify(ctx, caller(ctx), "Invalid or expired code".to_string());
+}
+
+#[reducer]
+/// Get a code to carry this guest's progress over to a registered account
+pub fn start_account_upgrade(ctx: &ReducerContext) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    if user.registered {
+        return Err("Your account is already registered".to_string());
+    }
+
+    ctx.db.account_upgrade().guest().delete(caller(ctx));
+    ctx.db.account_upgrade().insert(AccountUpgrade {
+        guest: caller(ctx),
+        code: generate_link_code(ctx),
+        expires_at: ctx.timestamp + TimeDuration::from_micros(UPGRADE_CODE_MICROS),
+    });
+
+    Ok(())
+}
+
+#[reducer]
+/// From a registered account, take over a guest's history, stats and unlocks with their code
+pub fn upgrade_account(ctx: &ReducerContext, code: String) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    if !user.registered {
+        return Err("Sign in with a registered account to upgrade".to_string());
+    }
+
+    check_link_lockout(ctx)?;
+    let Some(upgrade) = ctx.db.account_upgrade().code().find(&code)
+        .filter(|upgrade| upgrade.expires_at > ctx.timestamp)
+    else {
+        record_link_failure(ctx);
+        return Ok(());
+    };
+    ctx.db.link_attempt().device().delete(ctx.sender);
+    let guest = upgrade.guest;
+
+    let guest_user = ctx.db.user().identity().find(guest)
+        .ok_or("Guest account not found")?;
+
+    if guest_user.current_lobby_id.is_some() || guest_user.current_game_id.is_some() {
+        return Err("The guest account must leave its lobby or game first".to_string());
+    }
+
+    ctx.db.account_upgrade().guest().delete(guest);
+    absorb_user(ctx, guest_user, user.identity);
+
+    log::info!("Guest {:?} upgraded to registered account {:?}", guest, user.identity);
+    Ok(())
+}
+
+/// Fold a user into another account and remove what is left of it
+fn absorb_user(ctx: &ReducerContext, from: User, into: Identity) {
+    merge_account_progress(ctx, from.identity, into);
+
+    // The name carries over unless the account already has one
+    if let Some(user) = ctx.db.user().identity().find(into) {
+        if user.name.is_none() {
+            ctx.db.user().identity().update(User { name: from.name, ..user });
+        }
+    }
+    ctx.db.user().identity().delete(from.identity);
+    ctx.db.presence().identity().delete(from.identity);
+    ctx.db.profile_view().identity().delete(from.identity);
+    sync_profile_view(ctx, into);
+}
+
+/// Move one identity's game history, stats, unlocks and friends onto another
+fn merge_account_progress(ctx: &ReducerContext, from: Identity, to: Identity) {
+    ctx.db.matchmaking_ticket().player().delete(from);
+
+    let history: Vec<GameHistory> = ctx.db.game_history().player().filter(from).collect();
+    for entry in history {
+        ctx.db.game_history().id().delete(entry.id);
+        let id = generate_participant_id(entry.game_id, to);
+        if ctx.db.game_history().id().find(id).is_none() {
+            ctx.db.game_history().insert(GameHistory { id, player: to, ..entry });
+        }
+    }
+
+    // Other players' history lists the merged identity as their opponent
+    let mentions: Vec<GameHistory> = ctx.db.game_history()
+        .iter()
+        .filter(|entry| entry.opponents.contains(&from))
+        .collect();
+    for entry in mentions {
+        let opponents = entry.opponents.iter()
+            .map(|opponent| if *opponent == from { to } else { *opponent })
+            .collect();
+        ctx.db.game_history().id().update(GameHistory { opponents, ..entry });
+    }
+
+    let summaries: Vec<GameSummary> = ctx.db.game_summary()
+        .iter()
+        .filter(|summary| summary.player == from)
+        .collect();
+    for summary in summaries {
+        ctx.db.game_summary().id().delete(summary.id);
+        let id = generate_participant_id(summary.game_id, to);
+        if ctx.db.game_summary().id().find(id).is_none() {
+            ctx.db.game_summary().insert(GameSummary { id, player: to, ..summary });
+        }
+    }
+
+    if let Some(xp) = ctx.db.player_xp().identity().find(from) {
+        ctx.db.player_xp().identity().delete(from);
+        add_xp(ctx, to, xp.xp);
+    }
+
+    // Ratings aren't added up: the account keeps its own where it has one
+    let ratings: Vec<PlayerRating> = ctx.db.player_rating().player().filter(from).collect();
+    for rating in ratings {
+        ctx.db.player_rating().id().delete(rating.id);
+        remove_from_leaderboard(ctx, rating.id);
+        let id = generate_rating_id(to, rating.pool);
+        if ctx.db.player_rating().id().find(id).is_none() {
+            let rating = ctx.db.player_rating().insert(PlayerRating { id, player: to, ..rating });
+            update_player_rank(ctx, &rating, true);
+            update_leaderboard(ctx, &rating);
+        }
+    }
+    let ranks: Vec<u64> = ctx.db.player_rank().player().filter(from).map(|rank| rank.id).collect();
+    for id in ranks {
+        ctx.db.player_rank().id().delete(id);
+    }
+
+    let variants: Vec<VariantStats> = ctx.db.variant_stats().player().filter(from).collect();
+    for merged in variants {
+        ctx.db.variant_stats().id().delete(merged.id);
+        let id = generate_variant_stats_id(to, merged.variant);
+        let (games_played, wins, losses, total_game_secs) = ctx.db.variant_stats().id().find(id)
+            .map_or((0, 0, 0, 0), |stats| (stats.games_played, stats.wins, stats.losses, stats.total_game_secs));
+        ctx.db.variant_stats().id().delete(id);
+        ctx.db.variant_stats().insert(VariantStats {
+            id,
+            player: to,
+            games_played: games_played + merged.games_played,
+            wins: wins + merged.wins,
+            losses: losses + merged.losses,
+            total_game_secs: total_game_secs + merged.total_game_secs,
+            ..merged
+        });
+    }
+
+    // A week both identities have a digest for keeps the account's own
+    let digests: Vec<WeeklyDigest> = ctx.db.weekly_digest().player().filter(from).collect();
+    for digest in digests {
+        ctx.db.weekly_digest().id().delete(digest.id);
+        let id = generate_weekly_digest_id(to, digest.week);
+        if ctx.db.weekly_digest().id().find(id).is_none() {
+            ctx.db.weekly_digest().insert(WeeklyDigest { id, player: to, ..digest });
+        }
+    }
+
+    if let Some(merged) = ctx.db.player_stats().identity().find(from) {
+        ctx.db.player_stats().identity().delete(from);
+        let stats = get_player_stats(ctx, to);
+        save_player_stats(ctx, PlayerStats {
+            games_played: stats.games_played + merged.games_played,
+            wins: stats.wins + merged.wins,
+            fool_count: stats.fool_count + merged.fool_count,
+            cards_beaten: stats.cards_beaten + merged.cards_beaten,
+            total_game_secs: stats.total_game_secs + merged.total_game_secs,
+            longest_fool_streak: stats.longest_fool_streak.max(merged.longest_fool_streak),
+            ..stats
+        });
+    }
+
+    let titles: Vec<PlayerTitle> = ctx.db.player_title().player().filter(from).collect();
+    for title in titles {
+        ctx.db.player_title().id().delete(title.id);
+        grant_title(ctx, to, title.title);
+    }
+
+    let items: Vec<InventoryItem> = ctx.db.inventory().owner().filter(from).collect();
+    for item in items {
+        ctx.db.inventory().id().delete(item.id);
+        grant_cosmetic(ctx, to, item.cosmetic);
+    }
+
+    if let Some(profile) = ctx.db.profile().identity().find(from) {
+        ctx.db.profile().identity().delete(from);
+        if ctx.db.profile().identity().find(to).is_none() {
+            ctx.db.profile().insert(Profile { identity: to, ..profile });
+        }
+    }
+
+    if let Some(settings) = ctx.db.user_settings().identity().find(from) {
+        ctx.db.user_settings().identity().delete(from);
+        if ctx.db.user_settings().identity().find(to).is_none() {
+            ctx.db.user_settings().insert(UserSettings { identity: to, ..settings });
+        }
+    }
+
+    let friendships: Vec<Friendship> = ctx.db.friendship().owner().filter(from).collect();
+    for friendship in friendships {
+        let friend = friendship.friend;
+        clear_friendship(ctx, from, friend);
+        if friend == to || are_friends(ctx, to, friend) || is_blocked_between(ctx, to, friend) {
+            continue;
+        }
+        for (owner, friend) in [(to, friend), (friend, to)] {
+            ctx.db.friendship().insert(Friendship {
+                id: generate_friendship_id(owner, friend),
+                owner,
+                friend,
+                since: friendship.since,
+            });
+        }
+    }
+}
+
+// Linked Devices
+
+/// How long a device link code stays valid
+const DEVICE_LINK_CODE_MICROS: i64 = 5 * 60 * 1_000_000;
+
+/// The account the calling device belongs to; a device that was never linked is its own account
+fn caller(ctx: &ReducerContext) -> Identity {
+    ctx.db.device_link().device().find(ctx.sender)
+        .map_or(ctx.sender, |link| link.account)
+}
+
+#[reducer]
+/// Get a code to sign in to this account from another device
+pub fn start_device_link(ctx: &ReducerContext) -> Result<(), String> {
+    let account = caller(ctx);
+    ctx.db.user().identity().find(account)
+        .ok_or("User not found")?;
+
+    ctx.db.device_link_code().account().delete(account);
+    ctx.db.device_link_code().insert(DeviceLinkCode {
+        account,
+        code: generate_link_code(ctx),
+        expires_at: ctx.timestamp + TimeDuration::from_micros(DEVICE_LINK_CODE_MICROS),
+    });
+
+    Ok(())
+}
+
+#[reducer]
+/// Join this device to the account that showed the code; what was played on it moves over
+pub fn link_device(ctx: &ReducerContext, code: String) -> Result<(), String> {
+    let device = ctx.sender;
+
+    if ctx.db.device_link().device().find(device).is_some()
+        || ctx.db.device_link().account().filter(device).next().is_some()
+    {
+        return Err("This device is already linked to an account".to_string());
+    }
+
+    check_link_lockout(ctx)?;
+    let Some(link_code) = ctx.db.device_link_code().code().find(&code)
+        .filter(|link_code| link_code.expires_at > ctx.timestamp)
+    else {
+        record_link_failure(ctx);
+        return Ok(());
+    };
+    ctx.db.link_attempt().device().delete(device);
+    let account = link_code.account;
+
+    if account == device {
+        return Err("Enter the code on your other device".to_string());
+    }
+
+    let device_user = ctx.db.user().identity().find(device)
+        .ok_or("User not found")?;
+    let account_user = ctx.db.user().identity().find(account)
+        .ok_or("Account not found")?;
+
+    if device_user.current_lobby_id.is_some() || device_user.current_game_id.is_some() {
+        return Err("Leave your lobby or game before linking this device".to_string());
+    }
+
+    if ctx.db.clan_member().identity().find(device).is_some() {
+        return Err("Leave your clan before linking this device".to_string());
+    }
+
+    ctx.db.device_link_code().account().delete(account);
+
+    // The account's own identity is tracked too, so it counts towards being online
+    if ctx.db.device_link().device().find(account).is_none() {
+        ctx.db.device_link().insert(DeviceLink {
+            device: account,
+            account,
+            online: account_user.online,
+            linked_at: ctx.timestamp,
+        });
+    }
+    ctx.db.device_link().insert(DeviceLink {
+        device,
+        account,
+        online: true,
+        linked_at: ctx.timestamp,
+    });
+
+    // The device's own user row goes away and the account is online through it
+    if device_user.online {
+        update_server_stats(ctx, |stats| stats.online_players = stats.online_players.saturating_sub(1));
+    }
+    if !account_user.online {
+        update_server_stats(ctx, |stats| stats.online_players += 1);
+    }
+    absorb_user(ctx, device_user, account);
+    if let Some(user) = ctx.db.user().identity().find(account) {
+        ctx.db.user().identity().update(User { online: true, ..user });
+    }
+    mark_active(ctx, account);
+    notify(ctx, account, "A new device was linked to your account".to_string());
+
+    log::info!("Device {:?} linked to account {:?}", device, account);
+    Ok(())
+}
+
+#[reducer]
+/// Sign a device out of this account; it starts over as a new player when it next connects
+pub fn unlink_device(ctx: &ReducerContext, device: Identity) -> Result<(), String> {
+    let account = caller(ctx);
+
+    if device == account {
+        return Err("The account's first device can't be unlinked".to_string());
+    }
+
+    let link = ctx.db.device_link().device().find(device)
+        .ok_or("Device not linked")?;
+    if link.account != account {
+        return Err("That device belongs to another account".to_string());
+    }
+
+    ctx.db.device_link().device().delete(device);
+
+    // Signing out the only connected device takes the account offline
+    if link.online && !ctx.db.device_link().account().filter(account).any(|other| other.online) {
+        account_went_offline(ctx, account);
+    }
+
+    log::info!("Device {:?} unlinked from account {:?}", device, account);
+    Ok(())
+}
+
+// Account Deletion
+
+/// Stand-in for a deleted player in finished games, not derivable from the real identity without the time of deletion
+fn generate_deleted_identity(identity: Identity, timestamp: Timestamp) -> Identity {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut bytes = [0u8; 32];
+    for (part, chunk) in bytes.chunks_mut(8).enumerate() {
+        let mut hasher = DefaultHasher::new();
+        "deleted".hash(&mut hasher);
+        identity.hash(&mut hasher);
+        timestamp.hash(&mut hasher);
+        part.hash(&mut hasher);
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    Identity::from_byte_array(bytes)
+}
+
+#[reducer]
+/// Delete the caller's account: personal data is removed, finished games keep an anonymous placeholder
+pub fn delete_account(ctx: &ReducerContext) -> Result<(), String> {
+    let account = caller(ctx);
+    let user = ctx.db.user().identity().find(account)
+        .ok_or("User not found")?;
+
+    if user.current_lobby_id.is_some() || user.current_game_id.is_some() {
+        return Err("Leave your lobby or game before deleting your account".to_string());
+    }
+
+    let unplayed_match = ctx.db.tournament_match()
+        .iter()
+        .any(|tournament_match| tournament_match.winner.is_none()
+            && (tournament_match.player_a == Some(account) || tournament_match.player_b == Some(account)));
+    if unplayed_match {
+        return Err("Finish your tournament matches before deleting your account".to_string());
+    }
+
+    if ctx.db.clan_member().identity().find(account).is_some() {
+        leave_clan(ctx)?;
+    }
+
+    let placeholder = generate_deleted_identity(account, ctx.timestamp);
+    wipe_personal_data(ctx, account);
+    anonymize_game_records(ctx, account, placeholder);
+
+    // Finished games still name every seat, so the placeholder gets a user row of its own
+    if user.online {
+        update_server_stats(ctx, |stats| stats.online_players = stats.online_players.saturating_sub(1));
+    }
+    ctx.db.user().identity().delete(account);
+    ctx.db.deleted_player().insert(DeletedPlayer {
+        identity: placeholder,
+        deleted_at: ctx.timestamp,
+    });
+    ctx.db.user().insert(User {
+        name: Some(DELETED_PLAYER_NAME.to_string()),
+        identity: placeholder,
+        online: false,
+        current_lobby_id: None,
+        lobby_joined_at: None,
+        current_game_id: None,
+        game_position: None,
+        total_points: None,
+        player_status: None,
+        team: None,
+        clan_tag: None,
+        level: 1,
+        title: None,
+        registered: false,
+        rank: None,
+    });
+
+    log::info!("Account {:?} was deleted", account);
+    Ok(())
+}
+
+/// Name shown for deleted players in finished games
+const DELETED_PLAYER_NAME: &str = "Deleted player";
+
+/// Remove every row that belongs to a user rather than to a game
+fn wipe_personal_data(ctx: &ReducerContext, account: Identity) {
+    let friends: Vec<Identity> = ctx.db.friendship()
+        .owner()
+        .filter(account)
+        .map(|friendship| friendship.friend)
+        .collect();
+    for friend in friends {
+        clear_friendship(ctx, account, friend);
+    }
+
+    let watchers: Vec<Identity> = ctx.db.friend_watch()
+        .friend()
+        .filter(account)
+        .map(|watch| watch.watcher)
+        .collect();
+    for watcher in watchers {
+        ctx.db.friend_watch().watcher().delete(watcher);
+    }
+    ctx.db.friend_watch().watcher().delete(account);
+
+    let messages: Vec<u64> = ctx.db.message()
+        .iter()
+        .filter(|message| message.sender == account)
+        .map(|message| message.id)
+        .collect();
+    for id in messages {
+        ctx.db.message().id().delete(id);
+    }
+
+    let deliveries: Vec<u64> = ctx.db.chat_delivery()
+        .iter()
+        .filter(|delivery| delivery.sender == account || delivery.recipient == account)
+        .map(|delivery| delivery.id)
+        .collect();
+    for id in deliveries {
+        ctx.db.chat_delivery().id().delete(id);
+    }
+
+    let direct_messages: Vec<u64> = ctx.db.direct_message()
+        .iter()
+        .filter(|message| message.sender == account || message.recipient == account)
+        .map(|message| message.id)
+        .collect();
+    for id in direct_messages {
+        ctx.db.direct_message().id().delete(id);
+    }
+
+    let clan_messages: Vec<u64> = ctx.db.clan_message()
+        .iter()
+        .filter(|message| message.sender == account)
+        .map(|message| message.id)
+        .collect();
+    for id in clan_messages {
+        ctx.db.clan_message().id().delete(id);
+    }
+
+    let blocks: Vec<u64> = ctx.db.user_block()
+        .iter()
+        .filter(|block| block.blocker == account || block.blocked == account)
+        .map(|block| block.id)
+        .collect();
+    for id in blocks {
+        ctx.db.user_block().id().delete(id);
+    }
+
+    let requests: Vec<u64> = ctx.db.friend_request()
+        .iter()
+        .filter(|request| request.sender == account || request.recipient == account)
+        .map(|request| request.id)
+        .collect();
+    for id in requests {
+        ctx.db.friend_request().id().delete(id);
+    }
+
+    let clan_invites: Vec<u64> = ctx.db.clan_invite()
+        .iter()
+        .filter(|invite| invite.inviter == account || invite.invitee == account)
+        .map(|invite| invite.id)
+        .collect();
+    for id in clan_invites {
+        ctx.db.clan_invite().id().delete(id);
+    }
+
+    let lobby_invites: Vec<u64> = ctx.db.lobby_invite()
+        .iter()
+        .filter(|invite| invite.inviter == account || invite.invitee == account)
+        .map(|invite| invite.id)
+        .collect();
+    for id in lobby_invites {
+        ctx.db.lobby_invite().id().delete(id);
+    }
+
+    let notifications: Vec<u64> = ctx.db.notification()
+        .iter()
+        .filter(|notification| notification.recipient == account)
+        .map(|notification| notification.id)
+        .collect();
+    for id in notifications {
+        ctx.db.notification().id().delete(id);
+    }
+
+    let notes: Vec<u64> = ctx.db.player_note()
+        .iter()
+        .filter(|note| note.author == account || note.subject == account)
+        .map(|note| note.id)
+        .collect();
+    for id in notes {
+        ctx.db.player_note().id().delete(id);
+    }
+
+    let reports: Vec<u64> = ctx.db.player_report()
+        .iter()
+        .filter(|report| report.reporter == account)
+        .map(|report| report.id)
+        .collect();
+    for id in reports {
+        ctx.db.player_report().id().delete(id);
+        let deliveries: Vec<u64> = ctx.db.report_delivery()
+            .report_id()
+            .filter(id)
+            .map(|delivery| delivery.id)
+            .collect();
+        for delivery_id in deliveries {
+            ctx.db.report_delivery().id().delete(delivery_id);
+        }
+    }
+
+    let recent_players: Vec<u64> = ctx.db.recent_player()
+        .iter()
+        .filter(|recent| recent.owner == account || recent.player == account)
+        .map(|recent| recent.id)
+        .collect();
+    for id in recent_players {
+        ctx.db.recent_player().id().delete(id);
+    }
+
+    let names: Vec<u64> = ctx.db.name_history()
+        .identity()
+        .filter(account)
+        .map(|entry| entry.id)
+        .collect();
+    for id in names {
+        ctx.db.name_history().id().delete(id);
+    }
+
+    let presets: Vec<u64> = ctx.db.settings_preset()
+        .iter()
+        .filter(|preset| preset.owner == account)
+        .map(|preset| preset.id)
+        .collect();
+    for id in presets {
+        ctx.db.settings_preset().id().delete(id);
+    }
+
+    let kicks: Vec<u64> = ctx.db.lobby_kick()
+        .iter()
+        .filter(|kick| kick.player == account)
+        .map(|kick| kick.id)
+        .collect();
+    for id in kicks {
+        ctx.db.lobby_kick().id().delete(id);
+    }
+
+    let votes: Vec<u64> = ctx.db.rematch_vote()
+        .iter()
+        .filter(|vote| vote.player == account)
+        .map(|vote| vote.id)
+        .collect();
+    for id in votes {
+        ctx.db.rematch_vote().id().delete(id);
+    }
+
+    let strikes: Vec<u64> = ctx.db.timeout_strike()
+        .iter()
+        .filter(|strike| strike.player == account)
+        .map(|strike| strike.id)
+        .collect();
+    for id in strikes {
+        ctx.db.timeout_strike().id().delete(id);
+    }
+
+    let standings: Vec<u64> = ctx.db.season_standing()
+        .iter()
+        .filter(|standing| standing.player == account)
+        .map(|standing| standing.id)
+        .collect();
+    for id in standings {
+        ctx.db.season_standing().id().delete(id);
+    }
+
+    let ranks: Vec<u64> = ctx.db.player_rank()
+        .player()
+        .filter(account)
+        .map(|rank| rank.id)
+        .collect();
+    for id in ranks {
+        ctx.db.player_rank().id().delete(id);
+        remove_from_leaderboard(ctx, id);
+    }
+
+    let ratings: Vec<u64> = ctx.db.player_rating()
+        .player()
+        .filter(account)
+        .map(|rating| rating.id)
+        .collect();
+    for id in ratings {
+        ctx.db.player_rating().id().delete(id);
+    }
+
+    let variants: Vec<u64> = ctx.db.variant_stats()
+        .player()
+        .filter(account)
+        .map(|stats| stats.id)
+        .collect();
+    for id in variants {
+        ctx.db.variant_stats().id().delete(id);
+    }
+
+    let digests: Vec<u64> = ctx.db.weekly_digest()
+        .player()
+        .filter(account)
+        .map(|digest| digest.id)
+        .collect();
+    for id in digests {
+        ctx.db.weekly_digest().id().delete(id);
+    }
+
+    let quests: Vec<u64> = ctx.db.quest_progress()
+        .player()
+        .filter(account)
+        .map(|progress| progress.id)
+        .collect();
+    for id in quests {
+        ctx.db.quest_progress().id().delete(id);
+    }
+
+    let items: Vec<u64> = ctx.db.inventory()
+        .owner()
+        .filter(account)
+        .map(|item| item.id)
+        .collect();
+    for id in items {
+        ctx.db.inventory().id().delete(id);
+    }
+
+    let titles: Vec<u64> = ctx.db.player_title()
+        .player()
+        .filter(account)
+        .map(|title| title.id)
+        .collect();
+    for id in titles {
+        ctx.db.player_title().id().delete(id);
+    }
+
+    let history: Vec<u64> = ctx.db.game_history()
+        .player()
+        .filter(account)
+        .map(|entry| entry.id)
+        .collect();
+    for id in history {
+        ctx.db.game_history().id().delete(id);
+    }
+
+    let entries: Vec<u64> = ctx.db.tournament_entrant()
+        .iter()
+        .filter(|entrant| entrant.player == account)
+        .filter(|entrant| ctx.db.tournament().id().find(entrant.tournament_id)
+            .is_some_and(|tournament| tournament.status == TournamentStatus::Registration))
+        .map(|entrant| entrant.id)
+        .collect();
+    for id in entries {
+        ctx.db.tournament_entrant().id().delete(id);
+    }
+
+    let devices: Vec<Identity> = ctx.db.device_link()
+        .account()
+        .filter(account)
+        .map(|link| link.device)
+        .collect();
+    for device in devices {
+        ctx.db.device_link().device().delete(device);
+    }
+    ctx.db.device_link_code().account().delete(account);
+    ctx.db.account_upgrade().guest().delete(account);
+    clear_data_export(ctx, account);
+
+    ctx.db.spectator().identity().delete(account);
+    ctx.db.game_spectator().identity().delete(account);
+    ctx.db.matchmaking_ticket().player().delete(account);
+    let access: Vec<u64> = ctx.db.lobby_access()
+        .identity()
+        .filter(account)
+        .map(|access| access.id)
+        .collect();
+    for access_id in access {
+        ctx.db.lobby_access().id().delete(access_id);
+    }
+    ctx.db.hand_count().player().delete(account);
+    ctx.db.game_seat().player().delete(account);
+    ctx.db.recent_leave().player().delete(account);
+    ctx.db.admin().identity().delete(account);
+    clear_report_deliveries(ctx, account);
+    ctx.db.player_xp().identity().delete(account);
+    ctx.db.player_stats().identity().delete(account);
+    ctx.db.daily_reward().identity().delete(account);
+    ctx.db.profile().identity().delete(account);
+    ctx.db.profile_view().identity().delete(account);
+    ctx.db.user_settings().identity().delete(account);
+    ctx.db.presence().identity().delete(account);
+}
+
+/// Replace a deleted player with a placeholder wherever finished games and tournaments mention them
+fn anonymize_game_records(ctx: &ReducerContext, account: Identity, placeholder: Identity) {
+    let swap = |player: Identity| if player == account { placeholder } else { player };
+    let swap_option = |player: Option<Identity>| player.map(swap);
+
+    for participant in ctx.db.game_participant().iter().filter(|row| row.player == account).collect::<Vec<_>>() {
+        ctx.db.game_participant().id().update(GameParticipant { player: placeholder, ..participant });
+    }
+
+    for summary in ctx.db.game_summary().iter().filter(|row| row.player == account).collect::<Vec<_>>() {
+        ctx.db.game_summary().id().update(GameSummary { player: placeholder, ..summary });
+    }
+
+    for entry in ctx.db.game_history().iter().filter(|row| row.opponents.contains(&account)).collect::<Vec<_>>() {
+        let opponents = entry.opponents.iter().map(|opponent| swap(*opponent)).collect();
+        ctx.db.game_history().id().update(GameHistory { opponents, ..entry });
+    }
+
+    for record in ctx.db.hall_of_fame().iter().filter(|row| row.player == Some(account)).collect::<Vec<_>>() {
+        ctx.db.hall_of_fame().id().update(HallOfFame { player: Some(placeholder), ..record });
+    }
+
+    for highlight in ctx.db.highlight().iter().filter(|row| row.player == account).collect::<Vec<_>>() {
+        ctx.db.highlight().id().update(Highlight { player: placeholder, ..highlight });
+    }
+
+    for score in ctx.db.round_score().iter().filter(|row| row.player == account).collect::<Vec<_>>() {
+        ctx.db.round_score().id().update(RoundScore { player: placeholder, ..score });
+    }
+
+    for cosmetic in ctx.db.game_cosmetic().iter().filter(|row| row.player == account).collect::<Vec<_>>() {
+        ctx.db.game_cosmetic().id().update(GameCosmetic { player: placeholder, ..cosmetic });
+    }
+
+    for event in ctx.db.game_event()
+        .iter()
+        .filter(|row| row.player == Some(account) || row.target == Some(account))
+        .collect::<Vec<_>>()
+    {
+        ctx.db.game_event().id().update(GameEvent {
+            player: swap_option(event.player),
+            target: swap_option(event.target),
+            ..event
+        });
+    }
+
+    for event in ctx.db.spectator_event()
+        .iter()
+        .filter(|row| row.attacker == Some(account) || row.defender == Some(account) || row.hands.iter().any(|seat| seat.player == account))
+        .collect::<Vec<_>>()
+    {
+        let hands = event.hands.iter()
+            .map(|seat| SeatView { player: swap(seat.player), cards: seat.cards })
+            .collect();
+        ctx.db.spectator_event().id().update(SpectatorEvent {
+            attacker: swap_option(event.attacker),
+            defender: swap_option(event.defender),
+            hands,
+            ..event
+        });
+    }
+
+    for round in ctx.db.round()
+        .iter()
+        .filter(|row| [row.loser, row.first_attacker, row.first_defender].contains(&Some(account)))
+        .collect::<Vec<_>>()
+    {
+        ctx.db.round().id().update(Round {
+            loser: swap_option(round.loser),
+            first_attacker: swap_option(round.first_attacker),
+            first_defender: swap_option(round.first_defender),
+            ..round
+        });
+    }
+
+    for turn in ctx.db.turn().iter().filter(|row| row.attacker == account || row.lead_attacker == account || row.defender == account).collect::<Vec<_>>() {
+        ctx.db.turn().id().update(Turn {
+            attacker: swap(turn.attacker),
+            lead_attacker: swap(turn.lead_attacker),
+            defender: swap(turn.defender),
+            ..turn
+        });
+    }
+
+    for draw in ctx.db.draw().iter().filter(|row| row.attacker == account).collect::<Vec<_>>() {
+        ctx.db.draw().id().update(Draw { attacker: placeholder, ..draw });
+    }
+
+    for pass in ctx.db.turn_pass().iter().filter(|row| row.player == account).collect::<Vec<_>>() {
+        ctx.db.turn_pass().id().update(TurnPass { player: placeholder, ..pass });
+    }
+
+    for card in ctx.db.player_card().iter().filter(|row| row.player == account).collect::<Vec<_>>() {
+        put_card(ctx, PlayerCard { player: placeholder, ..card });
+    }
+
+    for snapshot in ctx.db.game_snapshot().iter().filter(|row| row.taken_by == account).collect::<Vec<_>>() {
+        ctx.db.game_snapshot().id().update(GameSnapshot { taken_by: placeholder, ..snapshot });
+    }
+
+    // Reports against the player stay in the moderation history
+    for report in ctx.db.player_report().target().filter(account).collect::<Vec<_>>() {
+        ctx.db.player_report().id().update(PlayerReport { target: placeholder, ..report });
+    }
+
+    for report in ctx.db.player_report().iter().filter(|row| row.reviewed_by == Some(account)).collect::<Vec<_>>() {
+        ctx.db.player_report().id().update(PlayerReport { reviewed_by: Some(placeholder), ..report });
+    }
+
+    for lobby in ctx.db.lobby().iter().filter(|row| row.creator == account).collect::<Vec<_>>() {
+        ctx.db.lobby().id().update(Lobby { creator: placeholder, ..lobby });
+    }
+
+    for tournament in ctx.db.tournament().iter().filter(|row| row.organizer == account).collect::<Vec<_>>() {
+        ctx.db.tournament().id().update(Tournament { organizer: placeholder, ..tournament });
+    }
+
+    for entrant in ctx.db.tournament_entrant().iter().filter(|row| row.player == account).collect::<Vec<_>>() {
+        ctx.db.tournament_entrant().id().update(TournamentEntrant { player: placeholder, ..entrant });
+    }
+
+    for tournament_match in ctx.db.tournament_match()
+        .iter()
+        .filter(|row| [row.player_a, row.player_b, row.winner].contains(&Some(account)))
+        .collect::<Vec<_>>()
+    {
+        ctx.db.tournament_match().id().update(TournamentMatch {
+            player_a: swap_option(tournament_match.player_a),
+            player_b: swap_option(tournament_match.player_b),
+            winner: swap_option(tournament_match.winner),
+            ..tournament_match
+        });
+    }
+}
+
+// Data Export
+
+/// Minimum time between two exports
+const DATA_EXPORT_COOLDOWN_MICROS: i64 = 60 * 60 * 1_000_000;
+
+fn generate_data_export_id(owner: Identity, seq: u32) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    "data_export".hash(&mut hasher);
+    owner.hash(&mut hasher);
+    seq.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn clear_data_export(ctx: &ReducerContext, owner: Identity) {
+    let records: Vec<u64> = ctx.db.data_export()
+        .owner()
+        .filter(owner)
+        .map(|export| export.id)
+        .collect();
+    for id in records {
+        ctx.db.data_export().id().delete(id);
+    }
+}
+
+#[reducer]
+/// Gather the caller's profile, stats, messages and game history into data_export rows for download
+pub fn export_my_data(ctx: &ReducerContext) -> Result<(), String> {
+    let account = caller(ctx);
+    let user = ctx.db.user().identity().find(account)
+        .ok_or("User not found")?;
+
+    let last_export = ctx.db.data_export()
+        .owner()
+        .filter(account)
+        .map(|export| export.exported_at)
+        .next();
+    if let Some(exported_at) = last_export {
+        if ctx.timestamp < exported_at + TimeDuration::from_micros(DATA_EXPORT_COOLDOWN_MICROS) {
+            return Err("You can export your data once an hour".to_string());
+        }
+    }
+    clear_data_export(ctx, account);
+
+    let mut records = vec![ExportRecord::User(user)];
+    records.extend(ctx.db.profile().identity().find(account).map(ExportRecord::Profile));
+    records.extend(ctx.db.user_settings().identity().find(account).map(ExportRecord::Settings));
+    records.extend(ctx.db.name_history().identity().filter(account).map(ExportRecord::NameChange));
+    records.extend(ctx.db.player_xp().identity().find(account).map(ExportRecord::Experience));
+    records.extend(ctx.db.player_stats().identity().find(account).map(ExportRecord::Stats));
+    records.extend(ctx.db.variant_stats().player().filter(account).map(ExportRecord::VariantStats));
+    records.extend(ctx.db.player_rating().player().filter(account).map(ExportRecord::Rating));
+    records.extend(ctx.db.player_rank().player().filter(account).map(ExportRecord::Rank));
+    records.extend(ctx.db.season_standing().iter().filter(|standing| standing.player == account).map(ExportRecord::Standing));
+    records.extend(ctx.db.player_title().player().filter(account).map(ExportRecord::Title));
+    records.extend(ctx.db.inventory().owner().filter(account).map(ExportRecord::Cosmetic));
+    records.extend(ctx.db.friendship().owner().filter(account).map(ExportRecord::Friend));
+    records.extend(ctx.db.message().iter().filter(|message| message.sender == account).map(ExportRecord::ChatMessage));
+    records.extend(ctx.db.direct_message()
+        .iter()
+        .filter(|message| message.sender == account || message.recipient == account)
+        .map(ExportRecord::DirectMessage));
+    records.extend(ctx.db.clan_message().iter().filter(|message| message.sender == account).map(ExportRecord::ClanMessage));
+    records.extend(ctx.db.player_note().author().filter(account).map(ExportRecord::Note));
+    records.extend(ctx.db.game_history().player().filter(account).map(ExportRecord::Game));
+    records.extend(ctx.db.game_summary().iter().filter(|summary| summary.player == account).map(ExportRecord::GameSummary));
+    records.extend(ctx.db.highlight().iter().filter(|highlight| highlight.player == account).map(ExportRecord::Highlight));
+    records.extend(ctx.db.weekly_digest().player().filter(account).map(ExportRecord::WeeklyDigest));
+
+    let count = records.len();
+    for (index, record) in records.into_iter().enumerate() {
+        let seq = index as u32 + 1;
+        ctx.db.data_export().insert(DataExport {
+            id: generate_data_export_id(account, seq),
+            owner: account,
+            seq,
+            record,
+            exported_at: ctx.timestamp,
+        });
+    }
+
+    log::info!("User {:?} exported {} records of their data", account, count);
+    Ok(())
+}
+
+// Presence
+
+/// How often presence is refreshed for idle detection
+const PRESENCE_CHECK_MICROS: i64 = 60 * 1_000_000;
+
+/// Connected users who do nothing for this long show as idle
+const IDLE_AFTER_MICROS: i64 = 10 * 60 * 1_000_000;
+
+/// Recompute a user's presence from their connection, lobby, game and last action
+fn sync_presence(ctx: &ReducerContext, identity: Identity) {
+    let Some(user) = ctx.db.user().identity().find(identity) else {
+        return;
+    };
+
+    let presence = ctx.db.presence().identity().find(identity)
+        .unwrap_or(Presence {
+            identity,
+            status: PresenceStatus::Offline,
+            last_active: ctx.timestamp,
+            last_seen: ctx.timestamp,
+        });
+    let idle_since = ctx.timestamp - TimeDuration::from_micros(IDLE_AFTER_MICROS);
+
+    let status = if !user.online {
+        PresenceStatus::Offline
+    } else if presence.last_active < idle_since {
+        PresenceStatus::Idle
+    } else if user.current_game_id.is_some() {
+        PresenceStatus::InGame
+    } else if user.current_lobby_id.is_some() {
+        PresenceStatus::InLobby
+    } else {
+        PresenceStatus::Online
+    };
+
+    let last_seen = if status == PresenceStatus::Offline && presence.status != PresenceStatus::Offline {
+        ctx.timestamp
+    } else {
+        presence.last_seen
+    };
+
+    ctx.db.presence().identity().delete(identity);
+    ctx.db.presence().insert(Presence {
+        status,
+        last_seen,
+        ..presence
+    });
+}
+
+/// Note that a user just did something
+fn mark_active(ctx: &ReducerContext, identity: Identity) {
+    if let Some(presence) = ctx.db.presence().identity().find(identity) {
+        ctx.db.presence().identity().update(Presence {
+            last_active: ctx.timestamp,
+            ..presence
+        });
+    }
+    sync_presence(ctx, identity);
+}
+
+#[reducer]
+/// Clients call this on user input so they don't show as idle
+pub fn report_activity(ctx: &ReducerContext) -> Result<(), String> {
+    ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    mark_active(ctx, caller(ctx));
+    Ok(())
+}
+
+#[reducer]
+/// Refresh every connected user's presence, marking idle ones (scheduled)
+pub fn refresh_presence(ctx: &ReducerContext, _schedule: PresenceSchedule) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can refresh presence".to_string());
+    }
+
+    let online: Vec<Identity> = ctx.db.user()
+        .iter()
+        .filter(|user| user.online)
+        .map(|user| user.identity)
+        .collect();
+
+    for identity in online {
+        sync_presence(ctx, identity);
+    }
+
+    Ok(())
+}
+
+// Lobby Management
+
+/// Generate a unique lobby ID (simple counter approach for now)
+fn generate_lobby_id(_timestamp: Timestamp) -> u64 {
+    // For now, use a simple random-like ID. In production, this could be more sophisticated.
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    
+    let mut hasher = DefaultHasher::new();
+    _timestamp.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Hash a lobby password with its salt
+fn hash_lobby_password(salt: &[u8], password: &str) -> Vec<u8> {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(password.as_bytes());
+    hasher.finalize().to_vec()
+}
+
+/// Whether the given password opens a lobby; lobbies without one are always open
+fn lobby_password_matches(ctx: &ReducerContext, lobby_id: u64, password: Option<String>) -> bool {
+    match ctx.db.lobby_secret().lobby_id().find(lobby_id) {
+        Some(secret) => password.is_some_and(|p| hash_lobby_password(&secret.salt, &p) == secret.password_hash),
+        None => true,
+    }
+}
+
+fn generate_lobby_access_id(lobby_id: u64, identity: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    "lobby_access".hash(&mut hasher);
+    lobby_id.hash(&mut hasher);
+    identity.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Let a player see a private lobby from now on
+fn grant_lobby_access(ctx: &ReducerContext, lobby: &Lobby, identity: Identity) {
+    let id = generate_lobby_access_id(lobby.id, identity);
+    if !lobby.listed && ctx.db.lobby_access().id().find(id).is_none() {
+        ctx.db.lobby_access().insert(LobbyAccess { id, lobby_id: lobby.id, identity });
+    }
+}
+
+#[reducer]
+/// Creates a new lobby with the specified name and max players, optionally private or password protected
+pub fn create_lobby(
+    ctx: &ReducerContext,
+    name: String,
+    max_players: u8,
+    visibility: LobbyVisibility,
+    password: Option<String>,
+    tags: LobbyTags,
+) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Lobby name cannot be empty".to_string());
+    }
+
+    validate_lobby_tags(&tags)?;
+    
+    if max_players < 2 || max_players > 12 {
+        return Err("Max players must be between 2 and 12".to_string());
+    }
+
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    if user.current_lobby_id.is_some() {
+        return Err("You are already in a lobby".to_string());
+    }
+
+    if user.current_game_id.is_some() {
+        return Err("You are currently in a game".to_string());
+    }
+
+    if password.as_ref().is_some_and(|p| p.is_empty()) {
+        return Err("Password cannot be empty".to_string());
+    }
+
+    let lobby_id = generate_lobby_id(ctx.timestamp);
+    
+    // Create the lobby
+    let lobby = ctx.db.lobby().insert(Lobby {
+        id: lobby_id,
+        name,
+        creator: caller(ctx),
+        max_players,
+        current_players: 1,
+        status: LobbyStatus::Waiting,
+        created_at: ctx.timestamp,
+        visibility,
+        listed: visibility == LobbyVisibility::Public,
+        has_password: password.is_some(),
+        last_activity: ctx.timestamp,
+        auto_start_when_full: false,
+        carry_scores_from: None,
+        tags,
+        scheduled_start: None,
+    });
+
+    // Only the salted hash is kept, in a private table
+    if let Some(password) = password {
+        let salt = ctx.random::<u128>().to_le_bytes().to_vec();
+        let password_hash = hash_lobby_password(&salt, &password);
+        ctx.db.lobby_secret().insert(LobbySecret {
+            lobby_id,
+            salt,
+            password_hash,
+        });
+    }
+    grant_lobby_access(ctx, &lobby, caller(ctx));
+    sync_open_lobby(ctx, lobby_id);
+
+    // Update user to join the lobby
+    ctx.db.user().identity().update(User {
+        current_lobby_id: Some(lobby_id),
+        lobby_joined_at: Some(ctx.timestamp),
+        ..user
+    });
+
+    log::info!("User {:?} created lobby {}", caller(ctx), lobby_id);
+    Ok(())
+}
+
+#[reducer]
+/// Join an existing lobby by ID, with the password if the lobby has one
+pub fn join_lobby(ctx: &ReducerContext, lobby_id: u64, password: Option<String>) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    if user.current_lobby_id.is_some() {
+        return Err("You are already in a lobby".to_string());
+    }
+
+    if user.current_game_id.is_some() {
+        return Err("You are currently in a game".to_string());
+    }
+
+    let lobby = ctx.db.lobby().id().find(lobby_id)
+        .ok_or("Lobby not found")?;
+
+    if lobby.status != LobbyStatus::Waiting {
+        return Err("Lobby is not accepting new players".to_string());
+    }
+
+    check_not_kicked(ctx, lobby_id, caller(ctx))?;
+
+    if is_blocked_between(ctx, lobby.creator, caller(ctx)) {
+        return Err("You cannot join this lobby".to_string());
+    }
+
+    check_guest_allowed(&user, &get_game_settings(ctx, lobby_id))?;
+
+    // Seats held by other players' invites are not available
+    if lobby.current_players as usize + count_reserved_seats(ctx, lobby_id, caller(ctx)) >= lobby.max_players as usize {
+        return Err("Lobby is full".to_string());
+    }
+
+    if !lobby_password_matches(ctx, lobby_id, password) {
+        return Err("Wrong lobby password".to_string());
+    }
+
+    enter_lobby(ctx, user, lobby);
+
+    log::info!("User {:?} joined lobby {}", caller(ctx), lobby_id);
+    Ok(())
+}
+
+/// Seat a user in a lobby, using up their invite if they had one
+fn enter_lobby(ctx: &ReducerContext, user: User, lobby: Lobby) {
+    let lobby_id = lobby.id;
+    grant_lobby_access(ctx, &lobby, user.identity);
+
+    if lobby.auto_start_when_full && lobby.current_players + 1 >= lobby.max_players {
+        schedule_auto_start(ctx, lobby_id);
+    }
+
+    // Update lobby player count
+    ctx.db.lobby().id().update(Lobby {
+        current_players: lobby.current_players + 1,
+        last_activity: ctx.timestamp,
+        ..lobby
+    });
+
+    ctx.db.lobby_invite().id().delete(generate_invite_id(lobby_id, user.identity));
+    ctx.db.spectator().identity().delete(user.identity);
+    ctx.db.game_spectator().identity().delete(user.identity);
+    sync_open_lobby(ctx, lobby_id);
+    surface_player_notes(ctx, lobby_id, user.identity);
+
+    // Update user to join the lobby
+    ctx.db.user().identity().update(User {
+        current_lobby_id: Some(lobby_id),
+        lobby_joined_at: Some(ctx.timestamp),
+        ..user
+    });
+}
+
+/// Tags are short codes like "eu" or "ru"
+fn validate_lobby_tags(tags: &LobbyTags) -> Result<(), String> {
+    for tag in [&tags.region, &tags.language].into_iter().flatten() {
+        if tag.is_empty() || tag.len() > 8 {
+            return Err("Lobby tags must be 1 to 8 characters".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Whether a lobby's tags match a filter (unset filter fields match anything)
+#[reducer]
+/// Change the lobby's tags (only creator can do this)
+pub fn set_lobby_tags(ctx: &ReducerContext, tags: LobbyTags) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    let lobby_id = user.current_lobby_id
+        .ok_or("You are not in a lobby")?;
+
+    let lobby = ctx.db.lobby().id().find(lobby_id)
+        .ok_or("Lobby not found")?;
+
+    if lobby.creator != caller(ctx) {
+        return Err("Only lobby creator can change tags".to_string());
+    }
+
+    validate_lobby_tags(&tags)?;
+
+    ctx.db.lobby().id().update(Lobby {
+        tags,
+        last_activity: ctx.timestamp,
+        ..lobby
+    });
+    sync_open_lobby(ctx, lobby_id);
+
+    log::info!("Tags updated for lobby {}", lobby_id);
+    Ok(())
+}
+
+/// Bucket lobbies by table size for the lobby browser
+fn player_count_bucket(max_players: u8) -> u8 {
+    match max_players {
+        0..=2 => 0,
+        3..=4 => 1,
+        5..=6 => 2,
+        _ => 3,
+    }
+}
+
+/// Keep the lobby browser row in step with the lobby: only public lobbies that are waiting are listed
+fn sync_open_lobby(ctx: &ReducerContext, lobby_id: u64) {
+    ctx.db.open_lobby().lobby_id().delete(lobby_id);
+
+    let Some(lobby) = ctx.db.lobby().id().find(lobby_id) else {
+        return;
+    };
+
+    if lobby.status != LobbyStatus::Waiting || lobby.visibility != LobbyVisibility::Public {
+        return;
+    }
+
+    let settings = get_game_settings(ctx, lobby_id);
+
+    ctx.db.open_lobby().insert(OpenLobby {
+        lobby_id,
+        name: lobby.name,
+        current_players: lobby.current_players,
+        max_players: lobby.max_players,
+        player_bucket: player_count_bucket(lobby.max_players),
+        is_full: lobby.current_players >= lobby.max_players,
+        standard_rules: settings == get_default_settings(lobby_id),
+        has_password: lobby.has_password,
+        region: lobby.tags.region.clone().unwrap_or_default(),
+        language: lobby.tags.language.clone().unwrap_or_default(),
+        skill_level_id: lobby.tags.skill_level.map_or(0, |level| level as u8 + 1),
+        tags: lobby.tags,
+        created_at: lobby.created_at,
+    });
+}
+
+/// Record activity in a lobby so it isn't cleaned up as stale
+fn touch_lobby(ctx: &ReducerContext, lobby: Lobby) {
+    ctx.db.lobby().id().update(Lobby {
+        last_activity: ctx.timestamp,
+        ..lobby
+    });
+}
+
+/// Remove a lobby along with its password and pending invites
+fn delete_lobby(ctx: &ReducerContext, lobby_id: u64) {
+    ctx.db.lobby().id().delete(lobby_id);
+    let access: Vec<u64> = ctx.db.lobby_access()
+        .lobby_id()
+        .filter(lobby_id)
+        .map(|access| access.id)
+        .collect();
+    for access_id in access {
+        ctx.db.lobby_access().id().delete(access_id);
+    }
+    ctx.db.lobby_secret().lobby_id().delete(lobby_id);
+    ctx.db.open_lobby().lobby_id().delete(lobby_id);
+    clear_lobby_invites(ctx, lobby_id);
+    clear_lobby_start_schedule(ctx, lobby_id);
+
+    let roles: Vec<u64> = ctx.db.lobby_role()
+        .iter()
+        .filter(|role| role.lobby_id == lobby_id)
+        .map(|role| role.id)
+        .collect();
+
+    for role_id in roles {
+        ctx.db.lobby_role().id().delete(role_id);
+    }
+
+    let spectators: Vec<Identity> = ctx.db.spectator()
+        .iter()
+        .filter(|spectator| spectator.lobby_id == lobby_id)
+        .map(|spectator| spectator.identity)
+        .collect();
+
+    for identity in spectators {
+        ctx.db.spectator().identity().delete(identity);
+    }
+
+    let kicks: Vec<u64> = ctx.db.lobby_kick()
+        .iter()
+        .filter(|kick| kick.lobby_id == lobby_id)
+        .map(|kick| kick.id)
+        .collect();
+
+    for kick_id in kicks {
+        ctx.db.lobby_kick().id().delete(kick_id);
+    }
+}
+
+/// Drop all pending invites to a lobby
+fn clear_lobby_invites(ctx: &ReducerContext, lobby_id: u64) {
+    let invites: Vec<u64> = ctx.db.lobby_invite()
+        .iter()
+        .filter(|invite| invite.lobby_id == lobby_id)
+        .map(|invite| invite.id)
+        .collect();
+
+    for invite_id in invites {
+        ctx.db.lobby_invite().id().delete(invite_id);
+    }
+}
+
+#[reducer]
+/// Leave the current lobby
+pub fn leave_lobby(ctx: &ReducerContext) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    let lobby_id = user.current_lobby_id;
+    let lobby_joined_at = user.lobby_joined_at;
+    let team = user.team;
+    remove_from_lobby(ctx, user)?;
+
+    ctx.db.recent_leave().player().delete(caller(ctx));
+    ctx.db.recent_leave().insert(RecentLeave {
+        player: caller(ctx),
+        lobby_id,
+        lobby_joined_at,
+        team,
+        game_id: None,
+        player_status: None,
+        total_points: None,
+        hand: Vec::new(),
+        turn_id: None,
+        draw_count: 0,
+        left_at: ctx.timestamp,
+    });
+    Ok(())
+}
+
+/// Take a user out of their lobby, passing ownership on or deleting the lobby if it empties
+fn remove_from_lobby(ctx: &ReducerContext, user: User) -> Result<(), String> {
+    let identity = user.identity;
+
+    let lobby_id = user.current_lobby_id
+        .ok_or("You are not in a lobby")?;
+
+    let lobby = ctx.db.lobby().id().find(lobby_id)
+        .ok_or("Lobby not found")?;
+
+    // Update lobby player count
+    let new_player_count = lobby.current_players.saturating_sub(1);
+    
+    // The longest-waiting member takes over if the creator leaves
+    let successor = ctx.db.user()
+        .iter()
+        .filter(|u| u.current_lobby_id == Some(lobby_id) && u.identity != identity)
+        .min_by_key(|u| u.lobby_joined_at)
+        .map(|u| u.identity);
+
+    match successor {
+        None => {
+            // If lobby is empty, delete the lobby
+            delete_lobby(ctx, lobby_id);
+            log::info!("Lobby {} deleted", lobby_id);
+        }
+        Some(successor) => {
+            let creator = if lobby.creator == identity { successor } else { lobby.creator };
+            if creator != lobby.creator {
+                log::info!("Lobby {} ownership passed to {:?}", lobby_id, creator);
+            }
+
+            ctx.db.lobby().id().update(Lobby {
+                current_players: new_player_count,
+                creator,
+                last_activity: ctx.timestamp,
+                ..lobby
+            });
+            sync_open_lobby(ctx, lobby_id);
+        }
+    }
+
+    ctx.db.lobby_role().id().delete(generate_role_id(lobby_id, identity));
+
+    // Update user to leave the lobby
+    ctx.db.user().identity().update(User {
+        current_lobby_id: None,
+        lobby_joined_at: None,
+        team: None,
+        ..user
+    });
+
+    log::info!("User {:?} left lobby {}", identity, lobby_id);
+    Ok(())
+}
+
+fn cancel_seat_release(ctx: &ReducerContext, player: Identity) {
+    let pending: Vec<u64> = ctx.db.lobby_seat_release()
+        .iter()
+        .filter(|release| release.player == player)
+        .map(|release| release.scheduled_id)
+        .collect();
+
+    for scheduled_id in pending {
+        ctx.db.lobby_seat_release().scheduled_id().delete(scheduled_id);
+    }
+}
+
+#[reducer]
+/// Free the lobby seat of a player who didn't come back in time (scheduled)
+pub fn release_lobby_seat(ctx: &ReducerContext, release: LobbySeatRelease) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can release seats".to_string());
+    }
+
+    let Some(user) = ctx.db.user().identity().find(release.player) else {
+        return Ok(());
+    };
+
+    if user.online || user.current_lobby_id != Some(release.lobby_id) {
+        return Ok(());
+    }
+
+    let still_waiting = ctx.db.lobby().id().find(release.lobby_id)
+        .is_some_and(|lobby| lobby.status == LobbyStatus::Waiting);
+    if !still_waiting {
+        return Ok(());
+    }
+
+    log::info!("Releasing lobby seat of disconnected user {:?}", release.player);
+    remove_from_lobby(ctx, user)
+}
+
+#[reducer]
+/// Hand the lobby over to another member (only creator can do this)
+pub fn transfer_ownership(ctx: &ReducerContext, new_owner: Identity) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    let lobby_id = user.current_lobby_id
+        .ok_or("You are not in a lobby")?;
+
+    let lobby = ctx.db.lobby().id().find(lobby_id)
+        .ok_or("Lobby not found")?;
+
+    if lobby.creator != caller(ctx) {
+        return Err("Only lobby creator can transfer ownership".to_string());
+    }
+
+    if new_owner == caller(ctx) {
+        return Err("You already own this lobby".to_string());
+    }
+
+    let owner = ctx.db.user().identity().find(new_owner)
+        .ok_or("User not found")?;
+
+    if owner.current_lobby_id != Some(lobby_id) {
+        return Err("User is not in your lobby".to_string());
+    }
+
+    ctx.db.lobby().id().update(Lobby {
+        creator: new_owner,
+        last_activity: ctx.timestamp,
+        ..lobby
+    });
+    ctx.db.lobby_role().id().delete(generate_role_id(lobby_id, new_owner));
+
+    log::info!("User {:?} transferred lobby {} to {:?}", caller(ctx), lobby_id, new_owner);
+    Ok(())
+}
+
+/// How often stale lobbies are looked for
+const LOBBY_EXPIRY_CHECK_MICROS: i64 = 60 * 1_000_000;
+
+/// Default time a waiting lobby may sit idle before it is deleted
+const LOBBY_MAX_IDLE_MICROS: i64 = 30 * 60 * 1_000_000;
+
+#[reducer]
+/// Delete waiting lobbies nobody has touched for a while, and finished ones nobody asked for a rematch of (scheduled)
+pub fn expire_stale_lobbies(ctx: &ReducerContext, schedule: LobbyExpirySchedule) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can expire lobbies".to_string());
+    }
+
+    let deadline = ctx.timestamp - TimeDuration::from_micros(schedule.max_idle_micros);
+
+    let finished: Vec<u64> = ctx.db.lobby()
+        .iter()
+        .filter(|lobby| lobby.status == LobbyStatus::Finished && lobby.last_activity < deadline)
+        .map(|lobby| lobby.id)
+        .collect();
+
+    for lobby_id in finished {
+        let votes: Vec<u64> = ctx.db.rematch_vote()
+            .iter()
+            .filter(|vote| ctx.db.game().id().find(vote.game_id).is_some_and(|game| game.lobby_id == lobby_id))
+            .map(|vote| vote.id)
+            .collect();
+        for vote_id in votes {
+            ctx.db.rematch_vote().id().delete(vote_id);
+        }
+
+        delete_lobby(ctx, lobby_id);
+        log::info!("Finished lobby {} closed without a rematch", lobby_id);
+    }
+
+    let stale: Vec<Lobby> = ctx.db.lobby()
+        .iter()
+        .filter(|lobby| lobby.status == LobbyStatus::Waiting && lobby.last_activity < deadline)
+        .collect();
+
+    for lobby in stale {
+        let members: Vec<User> = ctx.db.user()
+            .iter()
+            .filter(|user| user.current_lobby_id == Some(lobby.id))
+            .collect();
+
+        for member in members {
+            notify(ctx, member.identity, format!("Lobby \"{}\" was closed after being idle", lobby.name));
+            ctx.db.user().identity().update(User {
+                current_lobby_id: None,
+                lobby_joined_at: None,
+                team: None,
+                ..member
+            });
+        }
+
+        delete_lobby(ctx, lobby.id);
+        log::info!("Lobby {} expired after inactivity", lobby.id);
+    }
+
+    Ok(())
+}
+
+#[reducer]
+/// Change how long a waiting lobby may sit idle before it is closed (only admins can do this)
+pub fn set_lobby_max_idle(ctx: &ReducerContext, minutes: u32) -> Result<(), String> {
+    check_admin(ctx)?;
+
+    if minutes == 0 {
+        return Err("Idle time must be at least a minute".to_string());
+    }
+
+    let schedules: Vec<LobbyExpirySchedule> = ctx.db.lobby_expiry_schedule().iter().collect();
+    for schedule in schedules {
+        ctx.db.lobby_expiry_schedule().scheduled_id().update(LobbyExpirySchedule {
+            max_idle_micros: minutes as i64 * 60 * 1_000_000,
+            ..schedule
+        });
+    }
+
+    log::info!("Lobbies now expire after {} idle minutes", minutes);
+    Ok(())
+}
+
+/// Countdown between the lobby filling up and the game starting on its own
+const AUTO_START_DELAY_MICROS: i64 = 10 * 1_000_000;
+
+#[reducer]
+/// Turn automatic start on a full lobby on or off (only creator can do this)
+pub fn set_auto_start(ctx: &ReducerContext, enabled: bool) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    let lobby_id = user.current_lobby_id
+        .ok_or("You are not in a lobby")?;
+
+    let lobby = ctx.db.lobby().id().find(lobby_id)
+        .ok_or("Lobby not found")?;
+
+    if lobby.creator != caller(ctx) {
+        return Err("Only lobby creator can change auto start".to_string());
+    }
+
+    if lobby.status != LobbyStatus::Waiting {
+        return Err("Game has already been started".to_string());
+    }
+
+    if enabled && lobby.current_players >= lobby.max_players {
+        schedule_auto_start(ctx, lobby_id);
+    }
+
+    ctx.db.lobby().id().update(Lobby {
+        auto_start_when_full: enabled,
+        last_activity: ctx.timestamp,
+        ..lobby
+    });
+
+    log::info!("Auto start {} for lobby {}", if enabled { "enabled" } else { "disabled" }, lobby_id);
+    Ok(())
+}
+
+/// Arm the auto start countdown for a lobby, replacing any running one
+fn schedule_auto_start(ctx: &ReducerContext, lobby_id: u64) {
+    let pending: Vec<u64> = ctx.db.auto_start_schedule()
+        .iter()
+        .filter(|schedule| schedule.lobby_id == lobby_id)
+        .map(|schedule| schedule.scheduled_id)
+        .collect();
+
+    for scheduled_id in pending {
+        ctx.db.auto_start_schedule().scheduled_id().delete(scheduled_id);
+    }
+
+    ctx.db.auto_start_schedule().insert(AutoStartSchedule {
+        scheduled_id: 0,
+        scheduled_at: ScheduleAt::Time(ctx.timestamp + TimeDuration::from_micros(AUTO_START_DELAY_MICROS)),
+        lobby_id,
+    });
+}
+
+#[reducer]
+/// Start a full lobby once its countdown runs out (scheduled)
+pub fn auto_start_game(ctx: &ReducerContext, schedule: AutoStartSchedule) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can auto start games".to_string());
+    }
+
+    let Some(lobby) = ctx.db.lobby().id().find(schedule.lobby_id) else {
+        return Ok(());
+    };
+
+    // Someone left or auto start was switched off during the countdown
+    if !lobby.auto_start_when_full || lobby.status != LobbyStatus::Waiting || lobby.current_players < lobby.max_players {
+        return Ok(());
+    }
+
+    log::info!("Auto starting lobby {}", lobby.id);
+    launch_game(ctx, lobby)
+}
+
+#[reducer]
+/// Set or clear a time at which the lobby starts on its own (only creator can do this)
+pub fn schedule_lobby_start(ctx: &ReducerContext, start_at: Option<Timestamp>) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    let lobby_id = user.current_lobby_id
+        .ok_or("You are not in a lobby")?;
+
+    let lobby = ctx.db.lobby().id().find(lobby_id)
+        .ok_or("Lobby not found")?;
+
+    if lobby.creator != caller(ctx) {
+        return Err("Only lobby creator can schedule the start".to_string());
+    }
+
+    if lobby.status != LobbyStatus::Waiting {
+        return Err("Game has already been started".to_string());
+    }
+
+    if start_at.is_some_and(|time| time <= ctx.timestamp) {
+        return Err("Start time must be in the future".to_string());
+    }
+
+    clear_lobby_start_schedule(ctx, lobby_id);
+    if let Some(time) = start_at {
+        ctx.db.lobby_start_schedule().insert(LobbyStartSchedule {
+            scheduled_id: 0,
+            scheduled_at: ScheduleAt::Time(time),
+            lobby_id,
+        });
+    }
+
+    ctx.db.lobby().id().update(Lobby {
+        scheduled_start: start_at,
+        last_activity: ctx.timestamp,
+        ..lobby
+    });
+
+    log::info!("Scheduled start of lobby {} set to {:?}", lobby_id, start_at);
+    Ok(())
+}
+
+fn clear_lobby_start_schedule(ctx: &ReducerContext, lobby_id: u64) {
+    let pending: Vec<u64> = ctx.db.lobby_start_schedule()
+        .iter()
+        .filter(|schedule| schedule.lobby_id == lobby_id)
+        .map(|schedule| schedule.scheduled_id)
+        .collect();
+
+    for scheduled_id in pending {
+        ctx.db.lobby_start_schedule().scheduled_id().delete(scheduled_id);
+    }
+}
+
+#[reducer]
+/// Start a lobby at its scheduled time, or call it off if there aren't enough players (scheduled)
+pub fn start_scheduled_lobby(ctx: &ReducerContext, schedule: LobbyStartSchedule) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can start scheduled lobbies".to_string());
+    }
+
+    let Some(lobby) = ctx.db.lobby().id().find(schedule.lobby_id) else {
+        return Ok(());
+    };
+
+    if lobby.status != LobbyStatus::Waiting {
+        return Ok(());
+    }
+
+    if lobby.current_players >= 2 {
+        log::info!("Starting lobby {} at its scheduled time", lobby.id);
+        return launch_game(ctx, Lobby { scheduled_start: None, ..lobby });
+    }
+
+    let members: Vec<Identity> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_lobby_id == Some(lobby.id))
+        .map(|user| user.identity)
+        .collect();
+
+    for member in members {
+        notify(ctx, member, format!("Scheduled start of \"{}\" was cancelled: not enough players", lobby.name));
+    }
+
+    log::info!("Scheduled start of lobby {} cancelled", lobby.id);
+    ctx.db.lobby().id().update(Lobby {
+        scheduled_start: None,
+        ..lobby
+    });
+    Ok(())
+}
+
+fn generate_role_id(lobby_id: u64, player: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    lobby_id.hash(&mut hasher);
+    player.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The creator and co-hosts may change settings and kick players
+fn can_manage_lobby(ctx: &ReducerContext, lobby: &Lobby, player: Identity) -> bool {
+    lobby.creator == player || ctx.db.lobby_role().id().find(generate_role_id(lobby.id, player)).is_some()
+}
+
+#[reducer]
+/// Make a lobby member co-host, or take the role back (only creator can do this)
+pub fn set_cohost(ctx: &ReducerContext, target: Identity, enabled: bool) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    let lobby_id = user.current_lobby_id
+        .ok_or("You are not in a lobby")?;
+
+    let lobby = ctx.db.lobby().id().find(lobby_id)
+        .ok_or("Lobby not found")?;
+
+    if lobby.creator != caller(ctx) {
+        return Err("Only lobby creator can manage co-hosts".to_string());
+    }
+
+    if target == caller(ctx) {
+        return Err("You already own this lobby".to_string());
+    }
+
+    let member = ctx.db.user().identity().find(target)
+        .ok_or("User not found")?;
+
+    if member.current_lobby_id != Some(lobby_id) {
+        return Err("User is not in your lobby".to_string());
+    }
+
+    let role_id = generate_role_id(lobby_id, target);
+    ctx.db.lobby_role().id().delete(role_id);
+    if enabled {
+        ctx.db.lobby_role().insert(LobbyRole {
+            id: role_id,
+            lobby_id,
+            player: target,
+            role: LobbyRoleKind::CoHost,
+        });
+    }
+
+    log::info!("User {:?} co-host in lobby {}: {}", target, lobby_id, enabled);
+    Ok(())
+}
+
+/// How long a kicked player has to wait before rejoining the same lobby
+const KICK_COOLDOWN_MICROS: i64 = 10 * 60 * 1_000_000;
+
+fn generate_kick_id(lobby_id: u64, player: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    lobby_id.hash(&mut hasher);
+    player.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Refuse players who were recently kicked from the lobby
+fn check_not_kicked(ctx: &ReducerContext, lobby_id: u64, player: Identity) -> Result<(), String> {
+    if let Some(kick) = ctx.db.lobby_kick().id().find(generate_kick_id(lobby_id, player)) {
+        if kick.kicked_at + TimeDuration::from_micros(KICK_COOLDOWN_MICROS) > ctx.timestamp {
+            return Err("You were kicked from this lobby, try again later".to_string());
+        }
+        ctx.db.lobby_kick().id().delete(kick.id);
+    }
+    Ok(())
+}
+
+#[reducer]
+/// Remove a player from the lobby (only creator or co-host can do this)
+pub fn kick_from_lobby(ctx: &ReducerContext, target: Identity) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    let lobby_id = user.current_lobby_id
+        .ok_or("You are not in a lobby")?;
+
+    let lobby = ctx.db.lobby().id().find(lobby_id)
+        .ok_or("Lobby not found")?;
+
+    if !can_manage_lobby(ctx, &lobby, caller(ctx)) {
+        return Err("Only lobby creator or co-host can kick players".to_string());
+    }
+
+    if target == lobby.creator {
+        return Err("Cannot kick the lobby creator".to_string());
+    }
+
+    if lobby.status != LobbyStatus::Waiting {
+        return Err("Cannot kick players after game has started".to_string());
+    }
+
+    if target == caller(ctx) {
+        return Err("Cannot kick yourself".to_string());
+    }
+
+    let kicked = ctx.db.user().identity().find(target)
+        .ok_or("User not found")?;
+
+    if kicked.current_lobby_id != Some(lobby_id) {
+        return Err("User is not in your lobby".to_string());
+    }
+
+    ctx.db.lobby().id().update(Lobby {
+        current_players: lobby.current_players.saturating_sub(1),
+        last_activity: ctx.timestamp,
+        ..lobby
+    });
+    sync_open_lobby(ctx, lobby_id);
+
+    ctx.db.user().identity().update(User {
+        current_lobby_id: None,
+        lobby_joined_at: None,
+        team: None,
+        ..kicked
+    });
+
+    ctx.db.lobby_role().id().delete(generate_role_id(lobby_id, target));
+
+    let kick_id = generate_kick_id(lobby_id, target);
+    ctx.db.lobby_kick().id().delete(kick_id);
+    ctx.db.lobby_kick().insert(LobbyKick {
+        id: kick_id,
+        lobby_id,
+        player: target,
+        kicked_at: ctx.timestamp,
+    });
+
+    log::info!("User {:?} kicked {:?} from lobby {}", caller(ctx), target, lobby_id);
+    Ok(())
+}
+
+#[reducer]
+/// Watch a lobby and its games without taking a seat
+pub fn join_as_spectator(ctx: &ReducerContext, lobby_id: u64, password: Option<String>) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    if user.current_lobby_id.is_some() || user.current_game_id.is_some() {
+        return Err("You are already in a lobby or game".to_string());
+    }
+
+    if ctx.db.spectator().identity().find(caller(ctx)).is_some() {
+        return Err("You are already spectating".to_string());
+    }
+
+    let lobby = ctx.db.lobby().id().find(lobby_id)
+        .ok_or("Lobby not found")?;
+
+    if !lobby_password_matches(ctx, lobby_id, password) {
+        return Err("Wrong lobby password".to_string());
+    }
+
+    let settings = get_game_settings(ctx, lobby_id);
+    let spectators = ctx.db.spectator()
+        .iter()
+        .filter(|spectator| spectator.lobby_id == lobby_id)
+        .count();
+
+    if spectators >= settings.max_spectators as usize {
+        return Err("No spectator slots left".to_string());
+    }
+
+    ctx.db.spectator().insert(Spectator {
+        identity: caller(ctx),
+        lobby_id,
+        joined_at: ctx.timestamp,
+    });
+    grant_lobby_access(ctx, &lobby, caller(ctx));
+
+    log::info!("User {:?} is spectating lobby {}", caller(ctx), lobby.id);
+    Ok(())
+}
+
+#[reducer]
+/// Stop spectating
+pub fn leave_spectating(ctx: &ReducerContext) -> Result<(), String> {
+    let spectator = ctx.db.spectator().identity().find(caller(ctx))
+        .ok_or("You are not spectating")?;
+
+    ctx.db.spectator().identity().delete(caller(ctx));
+
+    log::info!("User {:?} stopped spectating lobby {}", caller(ctx), spectator.lobby_id);
+    Ok(())
+}
+
+#[reducer]
+/// Watch a game that is already running
+pub fn spectate_game(ctx: &ReducerContext, game_id: u64, password: Option<String>) -> Result<(), String> {
+    join_game_spectators(ctx, game_id, password)
+}
+
+fn join_game_spectators(ctx: &ReducerContext, game_id: u64, password: Option<String>) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    if user.current_lobby_id.is_some() || user.current_game_id.is_some() {
+        return Err("You are already in a lobby or game".to_string());
+    }
+
+    if ctx.db.spectator().identity().find(caller(ctx)).is_some()
+        || ctx.db.game_spectator().identity().find(caller(ctx)).is_some() {
+        return Err("You are already spectating".to_string());
+    }
+
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    if game.status == GameStatus::Finished {
+        return Err("Game is already finished".to_string());
+    }
+
+    if !lobby_password_matches(ctx, game.lobby_id, password) {
+        return Err("Wrong lobby password".to_string());
+    }
+
+    // Lobby spectators who stayed on share the same slots
+    let settings = get_game_settings_for_game(ctx, game_id)?;
+    let spectators = ctx.db.game_spectator()
+        .iter()
+        .filter(|spectator| spectator.game_id == game_id)
+        .count()
+        + ctx.db.spectator()
+            .iter()
+            .filter(|spectator| spectator.lobby_id == game.lobby_id)
+            .count();
+
+    if spectators >= settings.max_spectators as usize {
+        return Err("No spectator slots left".to_string());
+    }
+
+    ctx.db.game_spectator().insert(GameSpectator {
+        identity: caller(ctx),
+        game_id,
+        joined_at: ctx.timestamp,
+    });
+    if let Some(lobby) = ctx.db.lobby().id().find(game.lobby_id) {
+        grant_lobby_access(ctx, &lobby, caller(ctx));
+    }
+
+    log::info!("User {:?} is spectating game {}", caller(ctx), game_id);
+    Ok(())
+}
+
+#[reducer]
+/// Stop watching a running game
+pub fn stop_spectating_game(ctx: &ReducerContext) -> Result<(), String> {
+    let spectator = ctx.db.game_spectator().identity().find(caller(ctx))
+        .ok_or("You are not spectating a game")?;
+
+    ctx.db.game_spectator().identity().delete(caller(ctx));
+
+    log::info!("User {:?} stopped spectating game {}", caller(ctx), spectator.game_id);
+    Ok(())
+}
+
+#[reducer]
+/// Follow a friend: spectate the game they are in now and hear about the ones they start later
+pub fn watch_friend(ctx: &ReducerContext, friend: Identity) -> Result<(), String> {
+    let friend_user = ctx.db.user().identity().find(friend)
+        .ok_or("User not found")?;
+
+    if !are_friends(ctx, caller(ctx), friend) {
+        return Err("You can only watch your friends".to_string());
+    }
+
+    ctx.db.friend_watch().watcher().delete(caller(ctx));
+    ctx.db.friend_watch().insert(FriendWatch {
+        watcher: caller(ctx),
+        friend,
+        since: ctx.timestamp,
+    });
+
+    if let Some(game_id) = friend_user.current_game_id {
+        let game = ctx.db.game().id().find(game_id)
+            .ok_or("Game not found")?;
+
+        if ctx.db.lobby_secret().lobby_id().find(game.lobby_id).is_some() {
+            return Err("Your friend's game is password protected".to_string());
+        }
+
+        join_game_spectators(ctx, game_id, None)?;
+    }
+
+    log::info!("User {:?} is watching friend {:?}", caller(ctx), friend);
+    Ok(())
+}
+
+#[reducer]
+/// Stop following a friend's games
+pub fn stop_watching_friend(ctx: &ReducerContext) -> Result<(), String> {
+    ctx.db.friend_watch().watcher().find(caller(ctx))
+        .ok_or("You are not watching a friend")?;
+
+    ctx.db.friend_watch().watcher().delete(caller(ctx));
+    Ok(())
+}
+
+/// Let friends who follow these players know a new game started
+fn notify_friend_watchers(ctx: &ReducerContext, players: &[Identity]) {
+    for player in players {
+        let watchers: Vec<Identity> = ctx.db.friend_watch()
+            .friend()
+            .filter(*player)
+            .map(|watch| watch.watcher)
+            .collect();
+
+        for watcher in watchers {
+            notify(ctx, watcher, "A friend you watch started a new game".to_string());
+        }
+    }
+}
+
+#[reducer]
+/// Choose a team (0 or 1) for team mode games
+pub fn choose_team(ctx: &ReducerContext, team: u8) -> Result<(), String> {
+    if team > 1 {
+        return Err("Team must be 0 or 1".to_string());
+    }
+
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    let lobby_id = user.current_lobby_id
+        .ok_or("You are not in a lobby")?;
+
+    let lobby = ctx.db.lobby().id().find(lobby_id)
+        .ok_or("Lobby not found")?;
+
+    if lobby.status != LobbyStatus::Waiting {
+        return Err("Cannot change teams after game has started".to_string());
+    }
+
+    if !get_game_settings(ctx, lobby_id).team_mode {
+        return Err("This lobby is not playing in teams".to_string());
+    }
+
+    let team_size = ctx.db.user()
+        .iter()
+        .filter(|u| u.current_lobby_id == Some(lobby_id) && u.team == Some(team) && u.identity != caller(ctx))
+        .count();
+
+    if team_size >= (lobby.max_players / 2) as usize {
+        return Err("Team is full".to_string());
+    }
+
+    ctx.db.user().identity().update(User {
+        team: Some(team),
+        ..user
+    });
+    touch_lobby(ctx, lobby);
+
+    log::info!("User {:?} joined team {} in lobby {}", caller(ctx), team, lobby_id);
+    Ok(())
+}
+
+// Lobby Invitations
+
+/// How long an invite holds a seat in the lobby
+const INVITE_EXPIRY_MICROS: i64 = 5 * 60 * 1_000_000;
+
+fn generate_invite_id(lobby_id: u64, invitee: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    lobby_id.hash(&mut hasher);
+    invitee.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Count seats held by unexpired invites, ignoring the given player's own invite
+fn count_reserved_seats(ctx: &ReducerContext, lobby_id: u64, except: Identity) -> usize {
+    ctx.db.lobby_invite()
+        .iter()
+        .filter(|invite| invite.lobby_id == lobby_id && invite.invitee != except)
+        .filter(|invite| invite.expires_at > ctx.timestamp)
+        .count()
+}
+
+#[reducer]
+/// Invite an online user to your lobby by identity
+pub fn invite_to_lobby(ctx: &ReducerContext, invitee: Identity) -> Result<(), String> {
+    let target = ctx.db.user().identity().find(invitee)
+        .ok_or("User not found")?;
+
+    send_invite(ctx, target)
+}
+
+#[reducer]
+/// Invite an online user to your lobby by name
+pub fn invite_to_lobby_by_name(ctx: &ReducerContext, name: String) -> Result<(), String> {
+    let target = ctx.db.user()
+        .iter()
+        .find(|user| user.name.as_deref() == Some(name.as_str()))
+        .ok_or("User not found")?;
+
+    send_invite(ctx, target)
+}
+
+#[reducer]
+/// Invite a friend to your lobby, reserving them a seat and letting them know right away
+pub fn invite_friend(ctx: &ReducerContext, friend: Identity) -> Result<(), String> {
+    let target = ctx.db.user().identity().find(friend)
+        .ok_or("User not found")?;
+
+    if !are_friends(ctx, caller(ctx), friend) {
+        return Err("You can only use this to invite friends".to_string());
+    }
+
+    send_invite(ctx, target)?;
+
+    let lobby = ctx.db.user().identity().find(caller(ctx))
+        .and_then(|user| user.current_lobby_id)
+        .and_then(|lobby_id| ctx.db.lobby().id().find(lobby_id))
+        .ok_or("Lobby not found")?;
+    notify(ctx, friend, format!("A friend invited you to lobby \"{}\"", lobby.name));
+
+    Ok(())
+}
+
+fn send_invite(ctx: &ReducerContext, target: User) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    let lobby_id = user.current_lobby_id
+        .ok_or("You are not in a lobby")?;
+
+    let lobby = ctx.db.lobby().id().find(lobby_id)
+        .ok_or("Lobby not found")?;
+
+    if lobby.status != LobbyStatus::Waiting {
+        return Err("Lobby is not accepting new players".to_string());
+    }
+
+    if target.identity == caller(ctx) {
+        return Err("Cannot invite yourself".to_string());
+    }
+
+    if is_blocked_between(ctx, caller(ctx), target.identity) {
+        return Err("You cannot invite this user".to_string());
+    }
+
+    if !target.online {
+        return Err("User is offline".to_string());
+    }
+
+    if ctx.db.user_settings().identity().find(target.identity).is_some_and(|settings| settings.disable_invites) {
+        return Err("User is not accepting invites".to_string());
+    }
+
+    if target.current_lobby_id.is_some() || target.current_game_id.is_some() {
+        return Err("User is already in a lobby or game".to_string());
+    }
+
+    if lobby.current_players as usize + count_reserved_seats(ctx, lobby_id, target.identity) >= lobby.max_players as usize {
+        return Err("Lobby is full".to_string());
+    }
+
+    // Re-inviting the same user refreshes the invite
+    let invite_id = generate_invite_id(lobby_id, target.identity);
+    ctx.db.lobby_invite().id().delete(invite_id);
+    ctx.db.lobby_invite().insert(LobbyInvite {
+        id: invite_id,
+        lobby_id,
+        inviter: caller(ctx),
+        invitee: target.identity,
+        created_at: ctx.timestamp,
+        expires_at: ctx.timestamp + TimeDuration::from_micros(INVITE_EXPIRY_MICROS),
+    });
+
+    log::info!("User {:?} invited {:?} to lobby {}", caller(ctx), target.identity, lobby_id);
+    Ok(())
+}
+
+#[reducer]
+/// Accept an invite and join its lobby (no password needed)
+pub fn accept_invite(ctx: &ReducerContext, invite_id: u64) -> Result<(), String> {
+    let invite = ctx.db.lobby_invite().id().find(invite_id)
+        .ok_or("Invite not found")?;
+
+    if invite.invitee != caller(ctx) {
+        return Err("This invite is not for you".to_string());
+    }
+
+    if invite.expires_at <= ctx.timestamp {
+        ctx.db.lobby_invite().id().delete(invite_id);
+        return Err("Invite has expired".to_string());
+    }
+
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    if user.current_lobby_id.is_some() {
+        return Err("You are already in a lobby".to_string());
+    }
+
+    if user.current_game_id.is_some() {
+        return Err("You are currently in a game".to_string());
+    }
+
+    let lobby = ctx.db.lobby().id().find(invite.lobby_id)
+        .ok_or("Lobby not found")?;
+
+    if lobby.status != LobbyStatus::Waiting {
+        return Err("Lobby is not accepting new players".to_string());
+    }
+
+    if lobby.current_players >= lobby.max_players {
+        return Err("Lobby is full".to_string());
+    }
+
+    check_not_kicked(ctx, lobby.id, caller(ctx))?;
+
+    if is_blocked_between(ctx, lobby.creator, caller(ctx)) {
+        return Err("You cannot join this lobby".to_string());
+    }
+
+    check_guest_allowed(&user, &get_game_settings(ctx, lobby.id))?;
+
+    let lobby_id = lobby.id;
+    enter_lobby(ctx, user, lobby);
+
+    log::info!("User {:?} accepted invite to lobby {}", caller(ctx), lobby_id);
+    Ok(())
+}
+
+#[reducer]
+/// Decline an invite, freeing the reserved seat
+pub fn decline_invite(ctx: &ReducerContext, invite_id: u64) -> Result<(), String> {
+    let invite = ctx.db.lobby_invite().id().find(invite_id)
+        .ok_or("Invite not found")?;
+
+    if invite.invitee != caller(ctx) {
+        return Err("This invite is not for you".to_string());
+    }
+
+    ctx.db.lobby_invite().id().delete(invite_id);
+
+    log::info!("User {:?} declined invite to lobby {}", caller(ctx), invite.lobby_id);
+    Ok(())
+}
+
+// Game Settings Management
+
+#[reducer]
+/// Update game settings for a lobby (only creator can do this)
+pub fn update_game_settings(ctx: &ReducerContext, lobby_id: u64, settings: GameSettings) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    if user.current_lobby_id != Some(lobby_id) {
+        return Err("You are not in this lobby".to_string());
+    }
+
+    let lobby = ctx.db.lobby().id().find(lobby_id)
+        .ok_or("Lobby not found")?;
+
+    if !can_manage_lobby(ctx, &lobby, caller(ctx)) {
+        return Err("Only lobby creator or co-host can change settings".to_string());
+    }
+
+    if lobby.status != LobbyStatus::Waiting {
+        return Err("Cannot change settings after game has started".to_string());
+    }
+
+    // Validate settings
+    if settings.starting_cards < 3 || settings.starting_cards > 20 {
+        return Err("Starting cards must be between 3 and 20".to_string());
+    }
+
+    if settings.max_points < 5 || settings.max_points > 50 {
+        return Err("Max points must be between 5 and 50".to_string());
+    }
+
+    if settings.knockout_mode && !settings.multi_round_mode {
+        return Err("Knockout mode requires multi-round mode".to_string());
+    }
+
+    if settings.team_mode && lobby.max_players != 4 && lobby.max_players != 6 {
+        return Err("Team mode requires a lobby of 4 or 6 players".to_string());
+    }
+
+    if settings.deck_count < 1 || settings.deck_count > 2 {
+        return Err("Deck count must be 1 or 2".to_string());
+    }
+
+    if settings.max_spectators > 50 {
+        return Err("Max spectators must be at most 50".to_string());
+    }
+
+    if settings.seat_grace_minutes > 30 {
+        return Err("Seat grace period must be at most 30 minutes".to_string());
+    }
+
+    if settings.attack_timeout_secs > 600 || settings.defend_timeout_secs > 600 {
+        return Err("Move timers must be at most 600 seconds".to_string());
+    }
+
+    if settings.time_bank_secs > 3600 {
+        return Err("Time bank must be at most 3600 seconds".to_string());
+    }
+
+    if settings.disconnect_grace_secs < 10 || settings.disconnect_grace_secs > 1800 {
+        return Err("Disconnect grace period must be between 10 and 1800 seconds".to_string());
+    }
+
+    if settings.spectator_delay_secs > 300 {
+        return Err("Spectator delay must be at most 300 seconds".to_string());
+    }
+
+    if settings.max_game_minutes > 600 {
+        return Err("Game time limit must be at most 600 minutes".to_string());
+    }
+
+    if settings.max_round_minutes > 120 {
+        return Err("Round time limit must be at most 120 minutes".to_string());
+    }
+
+    if settings.ranked && ctx.db.user().iter().any(|member| member.current_lobby_id == Some(lobby_id) && !member.registered) {
+        return Err("Guests in the lobby can only play casual games".to_string());
+    }
+
+    // A full lobby must still be dealable from the chosen deck
+    let deck_cards = deck_card_count(settings.deck_size) * settings.deck_count as usize;
+    if settings.starting_cards as usize * lobby.max_players as usize > deck_cards {
+        return Err(format!(
+            "A {}-card deck cannot deal {} cards to {} players",
+            deck_cards, settings.starting_cards, lobby.max_players
+        ));
+    }
+
+    // Whatever lobby the settings came from, they apply to this one
+    let settings = GameSettings { lobby_id, ..settings };
+    if ctx.db.game_settings().lobby_id().find(lobby_id).is_some() {
+        ctx.db.game_settings().lobby_id().update(settings);
+    } else {
+        ctx.db.game_settings().insert(settings);
+    }
+
+    touch_lobby(ctx, lobby);
+    sync_open_lobby(ctx, lobby_id);
+
+    log::info!("Game settings updated for lobby {}", lobby_id);
+    Ok(())
+}
+
+/// Get default game settings
+fn get_default_settings(lobby_id: u64) -> GameSettings {
+    GameSettings {
+        lobby_id,
+        deck_size: DeckSize::Standard36,
+        starting_cards: 7,
+        max_attack_cards: 6,
+        multi_round_mode: true,
+        max_points: 15,
+        anyone_can_attack: true,
+        trump_card_to_player: true,
+        perevodnoy: false,
+        team_mode: false,
+        no_trump: false,
+        cheating_durak: false,
+        deck_count: 1,
+        throw_in_priority: true,
+        scoring_mode: ScoringMode::Flat,
+        knockout_mode: false,
+        first_turn_five_cards: true,
+        refill_order: RefillOrder::Traditional,
+        trump_selection: TrumpSelection::BottomCard,
+        max_spectators: 10,
+        seat_grace_minutes: 3,
+        attack_timeout_secs: 0,
+        defend_timeout_secs: 0,
+        time_bank_secs: 0,
+        disconnect_grace_secs: 120,
+        bot_takeover: false,
+        leaver_hand: LeaverHand::Discard,
+        spectator_delay_secs: 0,
+        max_game_minutes: 0,
+        max_round_minutes: 0,
+        ranked: false,
+    }
+}
+
+fn generate_preset_id(owner: Identity, name: &str) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    owner.hash(&mut hasher);
+    name.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[reducer]
+/// Save the settings of your current lobby as a named preset (same name overwrites)
+pub fn save_preset(ctx: &ReducerContext, name: String) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Preset name cannot be empty".to_string());
+    }
+
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    let lobby_id = user.current_lobby_id
+        .ok_or("You are not in a lobby")?;
+
+    let settings = get_game_settings(ctx, lobby_id);
+    let id = generate_preset_id(caller(ctx), &name);
+
+    ctx.db.settings_preset().id().delete(id);
+    ctx.db.settings_preset().insert(SettingsPreset {
+        id,
+        owner: caller(ctx),
+        name,
+        settings,
+    });
+
+    log::info!("User {:?} saved settings preset {}", caller(ctx), id);
+    Ok(())
+}
+
+#[reducer]
+/// Apply one of your presets to a lobby (only lobby creator or co-host can do this)
+pub fn apply_preset(ctx: &ReducerContext, lobby_id: u64, preset_id: u64) -> Result<(), String> {
+    let preset = ctx.db.settings_preset().id().find(preset_id)
+        .ok_or("Preset not found")?;
+
+    if preset.owner != caller(ctx) {
+        return Err("This preset is not yours".to_string());
+    }
+
+    // Goes through the regular settings validation for the target lobby
+    update_game_settings(ctx, lobby_id, preset.settings)
+}
+
+// Card and Deck Management
+
+/// Generate a full deck based on deck size setting, combining several decks for big tables
+fn create_deck(deck_size: DeckSize, deck_count: u8) -> Vec<Card> {
+    let mut deck = Vec::new();
+    let suits = [Suit::Hearts, Suit::Diamonds, Suit::Clubs, Suit::Spades];
+    
+    let ranks = match deck_size {
+        DeckSize::Standard36 => vec![
+            Rank::Six, Rank::Seven, Rank::Eight, Rank::Nine, Rank::Ten,
+            Rank::Jack, Rank::Queen, Rank::King, Rank::Ace
+        ],
+        DeckSize::Extended52 => vec![
+            Rank::Two, Rank::Three, Rank::Four, Rank::Five,
+            Rank::Six, Rank::Seven, Rank::Eight, Rank::Nine, Rank::Ten,
+            Rank::Jack, Rank::Queen, Rank::King, Rank::Ace
+        ],
+    };
+
+    // Multi-deck games contain every card more than once
+    for _ in 0..deck_count {
+        for suit in suits {
+            for rank in &ranks {
+                deck.push(Card { suit, rank: *rank });
+            }
+        }
+    }
+
+    deck
+}
+
+/// Number of cards in a full deck of the given size
+fn deck_card_count(deck_size: DeckSize) -> usize {
+    match deck_size {
+        DeckSize::Standard36 => 36,
+        DeckSize::Extended52 => 52,
+    }
+}
+
+/// Shuffle the deck with the module's random generator, so clients can't work out the deal
+fn shuffle_deck(ctx: &ReducerContext, mut deck: Vec<Card>) -> Vec<Card> {
+    // Fisher-Yates
+    for i in (1..deck.len()).rev() {
+        let j = (ctx.random::<u64>() % (i as u64 + 1)) as usize;
+        deck.swap(i, j);
+    }
+
+    deck
+}
+
+/// Next suit in the fixed rotation order (for rotating trumps)
+fn next_suit(suit: Suit) -> Suit {
+    match suit {
+        Suit::Hearts => Suit::Diamonds,
+        Suit::Diamonds => Suit::Clubs,
+        Suit::Clubs => Suit::Spades,
+        Suit::Spades => Suit::Hearts,
+    }
+}
+
+/// Pick a random trump suit for a round without exposing a card
+fn random_suit(ctx: &ReducerContext) -> Suit {
+    let suits = [Suit::Hearts, Suit::Diamonds, Suit::Clubs, Suit::Spades];
+    suits[(ctx.random::<u64>() % suits.len() as u64) as usize]
+}
+
+/// Generate unique IDs for game entities
+fn generate_game_id(timestamp: Timestamp) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    
+    let mut hasher = DefaultHasher::new();
+    timestamp.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn generate_round_id(game_id: u64, round_number: u32) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    
+    let mut hasher = DefaultHasher::new();
+    game_id.hash(&mut hasher);
+    round_number.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn generate_card_id(game_id: u64, round_number: u32, index: u32) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    
+    let mut hasher = DefaultHasher::new();
+    game_id.hash(&mut hasher);
+    round_number.hash(&mut hasher);
+    index.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[reducer]
+/// Start the game from a lobby (only creator can do this)
+pub fn start_game(ctx: &ReducerContext, lobby_id: u64) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    if user.current_lobby_id != Some(lobby_id) {
+        return Err("You are not in this lobby".to_string());
+    }
+
+    let lobby = ctx.db.lobby().id().find(lobby_id)
+        .ok_or("Lobby not found")?;
+
+    if lobby.creator != caller(ctx) {
+        return Err("Only lobby creator can start the game".to_string());
+    }
+
+    launch_game(ctx, lobby)
+}
+
+/// Seat the lobby's players in a new game and deal the first round
+fn launch_game(ctx: &ReducerContext, lobby: Lobby) -> Result<(), String> {
+    let lobby_id = lobby.id;
+
+    if lobby.status != LobbyStatus::Waiting {
+        return Err("Game has already been started".to_string());
+    }
+
+    if lobby.current_players < 2 {
+        return Err("Need at least 2 players to start".to_string());
+    }
+
+    // Get or create game settings
+    let settings = ctx.db.game_settings().lobby_id().find(lobby_id)
+        .unwrap_or_else(|| get_default_settings(lobby_id));
+
+    // Get all players in the lobby
+    let mut players: Vec<User> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_lobby_id == Some(lobby_id))
+        .collect();
+
+    if players.len() != lobby.current_players as usize {
+        return Err("Player count mismatch".to_string());
+    }
+
+    if settings.team_mode {
+        players = seat_teams(players)?;
+    }
+
+    if players.len() * settings.starting_cards as usize > deck_card_count(settings.deck_size) * settings.deck_count as usize {
+        return Err("Not enough cards in deck for this many players".to_string());
+    }
+
+    // Create game
+    let game_id = generate_game_id(ctx.timestamp);
+
+    // Shuffle and deal the first round
+    let seats: Vec<Identity> = players.iter().map(|p| p.identity).collect();
+    let trump_card_exposed = is_trump_card_exposed(&settings, 1);
+    let bottom_card = deal_cards(ctx, game_id, 1, &settings, &seats, trump_card_exposed)?;
+    let trump_suit = choose_trump(ctx, &settings, None, &bottom_card);
+
+    ctx.db.game().insert(Game {
+        id: game_id,
+        lobby_id,
+        status: GameStatus::Active,
+        trump_suit,
+        current_round: 1,
+        started_at: ctx.timestamp,
+        finished_at: None,
+        paused_at: None,
+        last_activity: ctx.timestamp,
+        event_seq: 0,
+        spectated_live: settings.spectator_delay_secs == 0,
+    });
+
+    // Freeze the rules for the whole game
+    ctx.db.game_rules().insert(GameRules {
+        game_id,
+        settings: settings.clone(),
+    });
+    count_game_started(ctx, &settings);
+
+    // Seat players in the game
+    for (position, player) in players.iter().enumerate() {
+        ctx.db.user().identity().update(User {
+            identity: player.identity,
+            name: player.name.clone(),
+            online: player.online,
+            current_lobby_id: None,
+            lobby_joined_at: None,
+            current_game_id: Some(game_id),
+            game_position: Some(position as u8),
+            total_points: Some(carried_points(ctx, &lobby, player.identity)),
+            player_status: Some(PlayerStatus::Active),
+            team: if settings.team_mode { player.team } else { None },
+        });
+    }
+
+    // Player with the lowest trump opens the game against the next player clockwise
+    let first_attacker = find_first_attacker(ctx, game_id, trump_suit, &seats);
+    let first_defender = get_next_opponent_clockwise(ctx, game_id, first_attacker)?;
+
+    // Create first round
+    let round_id = generate_round_id(game_id, 1);
+    ctx.db.round().insert(Round {
+        id: round_id,
+        game_id,
+        round_number: 1,
+        status: RoundStatus::Active,
+        loser: None,
+        losing_team: None,
+        first_attacker: Some(first_attacker),
+        first_defender: Some(first_defender),
+        epaulettes: false,
+        started_at: ctx.timestamp,
+        finished_at: None,
+    });
+
+    // Announce the opening turn
+    create_turn(ctx, game_id, round_id, first_attacker, first_defender);
+    log_round_start(ctx, game_id, first_attacker, first_defender, trump_suit);
+    arm_round_time_limit(ctx, game_id, round_id, &settings);
+
+    // Update lobby status, nobody else can join now
+    ctx.db.lobby().id().update(Lobby {
+        status: LobbyStatus::InGame,
+        carry_scores_from: None,
+        scheduled_start: None,
+        ..lobby
+    });
+    clear_lobby_invites(ctx, lobby_id);
+    clear_lobby_start_schedule(ctx, lobby_id);
+    sync_open_lobby(ctx, lobby_id);
+
+    if settings.max_game_minutes > 0 {
+        ctx.db.game_time_limit().insert(GameTimeLimit {
+            scheduled_id: 0,
+            scheduled_at: ScheduleAt::Time(ctx.timestamp + TimeDuration::from_micros(settings.max_game_minutes as i64 * 60 * 1_000_000)),
+            game_id,
+        });
+    }
+
+    record_game_cosmetics(ctx, game_id, &seats);
+    notify_friend_watchers(ctx, &seats);
+
+    log::info!("Game {} started from lobby {} with {} players", game_id, lobby_id, players.len());
+    log::info!("First turn: {:?} attacks {:?}", first_attacker, first_defender);
+    after_game_action(ctx, game_id)
+}
+
+const CARD_SHOWN_TO_ALL: u8 = 0;
+const CARD_SHOWN_TO_OWNER: u8 = 1;
+const CARD_SHOWN_TO_NOBODY: u8 = 2;
+
+/// Who may see a card where it is: hands and face-down defenses only their holder, the deck nobody,
+/// the rest everyone
+fn card_shown_to(location: CardLocation) -> u8 {
+    match location {
+        CardLocation::Hand | CardLocation::FaceDown => CARD_SHOWN_TO_OWNER,
+        CardLocation::Deck => CARD_SHOWN_TO_NOBODY,
+        CardLocation::Discarded | CardLocation::OnTable | CardLocation::TrumpCard => CARD_SHOWN_TO_ALL,
+    }
+}
+
+/// Update a card row, keeping who may see it in step with where it now is
+fn put_card(ctx: &ReducerContext, player_card: PlayerCard) {
+    ctx.db.player_card().id().update(PlayerCard {
+        shown_to: card_shown_to(player_card.location),
+        ..player_card
+    });
+}
+
+/// Remove the previous round's cards, then shuffle a fresh deck and deal it to the seats in order.
+/// Returns the bottom card of the deck.
+fn deal_cards(
+    ctx: &ReducerContext,
+    game_id: u64,
+    round_number: u32,
+    settings: &GameSettings,
+    seats: &[Identity],
+    trump_card_exposed: bool,
+) -> Result<Card, String> {
+    let old_cards: Vec<u64> = ctx.db.player_card()
+        .iter()
+        .filter(|pc| pc.game_id == game_id)
+        .map(|pc| pc.id)
+        .collect();
+
+    for card_id in old_cards {
+        ctx.db.player_card().id().delete(card_id);
+    }
+
+    let deck = create_deck(settings.deck_size, settings.deck_count);
+    let shuffled_deck = shuffle_deck(ctx, deck);
+
+    if seats.len() * settings.starting_cards as usize > shuffled_deck.len() {
+        return Err("Not enough cards in deck".to_string());
+    }
+
+    let bottom_card = shuffled_deck.last().cloned()
+        .ok_or("Deck is empty")?;
+
+    // Deal starting cards to each player, the rest stays in the deck with the
+    // exposed trump card at the bottom
+    let dealt = seats.len() * settings.starting_cards as usize;
+    let last_index = shuffled_deck.len() - 1;
+    for (index, card) in shuffled_deck.into_iter().enumerate() {
+        let (player, location) = if index < dealt {
+            (seats[index / settings.starting_cards as usize], CardLocation::Hand)
+        } else if index == last_index && trump_card_exposed {
+            (seats[0], CardLocation::TrumpCard) // Owner doesn't matter for deck cards
+        } else {
+            (seats[0], CardLocation::Deck)
+        };
+
+        ctx.db.player_card().insert(PlayerCard {
+            id: generate_card_id(game_id, round_number, index as u32),
+            game_id,
+            player,
+            card,
+            location,
+            shown_to: card_shown_to(location),
+            deck_position: index as u32,
+        });
+    }
+
+    Ok(bottom_card)
+}
+
+/// Whether the bottom card of the deck is shown as the trump card this round
+fn is_trump_card_exposed(settings: &GameSettings, round_number: u32) -> bool {
+    match settings.trump_selection {
+        _ if settings.no_trump => false,
+        TrumpSelection::BottomCard => true,
+        TrumpSelection::RotatingEachRound => round_number == 1,
+        TrumpSelection::RandomAnnounced => false,
+    }
+}
+
+/// Pick the trump for a round according to the trump selection variant
+fn choose_trump(
+    ctx: &ReducerContext,
+    settings: &GameSettings,
+    previous_trump: Option<Suit>,
+    bottom_card: &Card,
+) -> Option<Suit> {
+    if settings.no_trump {
+        return None;
+    }
+
+    match (settings.trump_selection, previous_trump) {
+        (TrumpSelection::BottomCard, _) => Some(bottom_card.suit),
+        (TrumpSelection::RotatingEachRound, Some(suit)) => Some(next_suit(suit)),
+        (TrumpSelection::RotatingEachRound, None) => Some(bottom_card.suit),
+        (TrumpSelection::RandomAnnounced, _) => Some(random_suit(ctx)),
+    }
+}
+
+/// Find who attacks first: lowest trump in hand, or lowest card overall if nobody holds a trump
+fn find_first_attacker(ctx: &ReducerContext, game_id: u64, trump_suit: Option<Suit>, seats: &[Identity]) -> Identity {
+    let lowest_card = |only_trumps: bool| {
+        seats.iter()
+            .flat_map(|player| get_player_cards(ctx, game_id, *player))
+            .filter(|pc| !only_trumps || Some(pc.card.suit) == trump_suit)
+            .min_by_key(|pc| {
+                // Ties on rank go to the earlier seat
+                let seat = seats.iter().position(|p| *p == pc.player).unwrap_or(0);
+                (pc.card.rank, seat)
+            })
+            .map(|pc| pc.player)
+    };
+
+    lowest_card(true)
+        .or_else(|| lowest_card(false))
+        .unwrap_or(seats[0])
+}
+
+/// Order team players so that teammates never sit next to each other
+fn seat_teams(players: Vec<User>) -> Result<Vec<User>, String> {
+    if players.len() != 4 && players.len() != 6 {
+        return Err("Team mode requires exactly 4 or 6 players".to_string());
+    }
+
+    if players.iter().any(|p| p.team.is_none()) {
+        return Err("All players must choose a team".to_string());
+    }
+
+    let (mut team_a, mut team_b): (Vec<User>, Vec<User>) = players
+        .into_iter()
+        .partition(|p| p.team == Some(0));
+
+    if team_a.len() != team_b.len() {
+        return Err("Teams must have the same number of players".to_string());
+    }
+
+    // Keep seating stable: earlier joiners sit first within their team
+    team_a.sort_by_key(|p| p.lobby_joined_at);
+    team_b.sort_by_key(|p| p.lobby_joined_at);
+
+    // Interleave teams around the table: A, B, A, B, ...
+    let mut seated = Vec::new();
+    for (a, b) in team_a.into_iter().zip(team_b) {
+        seated.push(a);
+        seated.push(b);
+    }
+
+    Ok(seated)
+}
+
+// Query functions (these don't modify state, just return data)
+
+/// Get all available public lobbies that can be joined
+pub fn get_available_lobbies(ctx: &ReducerContext) -> Vec<Lobby> {
+    ctx.db.lobby()
+        .iter()
+        .filter(|lobby| lobby.status == LobbyStatus::Waiting && lobby.visibility == LobbyVisibility::Public)
+        .collect()
+}
+
+/// Get all players in a specific lobby
+pub fn get_lobby_players(ctx: &ReducerContext, lobby_id: u64) -> Vec<User> {
+    ctx.db.user()
+        .iter()
+        .filter(|user| user.current_lobby_id == Some(lobby_id))
+        .collect()
+}
+
+/// Get all players in a specific game
+pub fn get_game_players(ctx: &ReducerContext, game_id: u64) -> Vec<User> {
+    ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id))
+        .collect()
+}
+
+/// Get current player's hand
+pub fn get_player_hand(ctx: &ReducerContext, game_id: u64) -> Vec<Card> {
+    ctx.db.player_card()
+        .iter()
+        .filter(|pc| pc.game_id == game_id && pc.player == caller(ctx) && pc.location == CardLocation::Hand)
+        .map(|pc| pc.card.clone())
+        .collect()
+}
+
+/// Get current game state
+pub fn get_game_state(ctx: &ReducerContext, game_id: u64) -> Option<Game> {
+    ctx.db.game().id().find(game_id)
+}
+
+/// Get game settings for a lobby
+pub fn get_game_settings(ctx: &ReducerContext, lobby_id: u64) -> GameSettings {
+    ctx.db.game_settings()
+        .lobby_id()
+        .find(lobby_id)
+        .unwrap_or_else(|| get_default_settings(lobby_id))
+}
+
+/// Get current round for a game
+pub fn get_current_round(ctx: &ReducerContext, game_id: u64) -> Option<Round> {
+    ctx.db.round()
+        .iter()
+        .filter(|round| round.game_id == game_id && round.status == RoundStatus::Active)
+        .next()
+}
+
+// Card Validation Helpers
+
+/// Check if a defending card can beat an attacking card
+fn can_beat_card(attacking_card: &Card, defending_card: &Card, trump_suit: Option<Suit>) -> bool {
+    // Without a trump suit neither card is ever a trump
+    let attack_is_trump = trump_suit == Some(attacking_card.suit);
+    let defend_is_trump = trump_suit == Some(defending_card.suit);
+
+    match (attack_is_trump, defend_is_trump) {
+        // Trump vs trump: higher rank wins
+        (true, true) => defending_card.rank > attacking_card.rank,
+        // Non-trump vs trump: trump always wins
+        (false, true) => true,
+        // Trump vs non-trump: trump always wins (defense invalid)
+        (true, false) => false,
+        // Non-trump vs non-trump: same suit and higher rank
+        (false, false) => {
+            defending_card.suit == attacking_card.suit && defending_card.rank > attacking_card.rank
+        }
+    }
+}
+
+/// Check if an attacking card rank is valid (must match existing ranks on table)
+fn is_valid_attack_rank(rank: Rank, turn_id: u64, ctx: &ReducerContext) -> bool {
+    let existing_draws: Vec<Draw> = ctx.db.draw()
+        .iter()
+        .filter(|draw| draw.turn_id == turn_id)
+        .collect();
+
+    if existing_draws.is_empty() {
+        // First attack can be any rank
+        return true;
+    }
+
+    // Additional attacks must match existing ranks on table
+    existing_draws.iter().any(|draw| {
+        draw.attacking_card.rank == rank || 
+        draw.defending_card.as_ref().map_or(false, |card| card.rank == rank)
+    })
+}
+
+/// Get player's cards in hand
+fn get_player_cards(ctx: &ReducerContext, game_id: u64, player: Identity) -> Vec<PlayerCard> {
+    ctx.db.player_card()
+        .iter()
+        .filter(|pc| pc.game_id == game_id && pc.player == player && pc.location == CardLocation::Hand)
+        .collect()
+}
+
+/// Check if player has the specified card in hand (any copy in multi-deck games)
+fn player_has_card(ctx: &ReducerContext, game_id: u64, player: Identity, card: &Card) -> bool {
+    get_player_cards(ctx, game_id, player)
+        .iter()
+        .any(|pc| pc.card == *card)
+}
+
+/// Generate unique turn ID
+fn generate_turn_id(round_id: u64, turn_number: u32) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    
+    let mut hasher = DefaultHasher::new();
+    round_id.hash(&mut hasher);
+    turn_number.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Generate unique draw ID
+fn generate_draw_id(turn_id: u64, timestamp: Timestamp) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    
+    let mut hasher = DefaultHasher::new();
+    turn_id.hash(&mut hasher);
+    timestamp.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Get current active turn for a round (including one where the defender is taking)
+fn get_active_turn(ctx: &ReducerContext, round_id: u64) -> Option<Turn> {
+    ctx.db.turn()
+        .iter()
+        .filter(|turn| turn.round_id == round_id &&
+                       (turn.status == TurnStatus::Active || turn.status == TurnStatus::Taking))
+        .next()
+}
+
+/// Get a draw of the given turn that is still waiting for defense
+fn find_pending_draw(ctx: &ReducerContext, turn_id: u64, draw_id: u64) -> Result<Draw, String> {
+    let draw = ctx.db.draw().id().find(draw_id)
+        .ok_or("Attack not found")?;
+
+    if draw.turn_id != turn_id {
+        return Err("Attack does not belong to this turn".to_string());
+    }
+
+    if draw.status != DrawStatus::Pending {
+        return Err("Attack has already been answered".to_string());
+    }
+
+    Ok(draw)
+}
+
+/// Maximum number of attack cards in a turn (0 = no limit)
+fn turn_attack_limit(settings: &GameSettings, turn: &Turn) -> usize {
+    let limit = settings.max_attack_cards as usize;
+    if settings.first_turn_five_cards && turn.turn_number == 1 {
+        // First turn of a round allows at most 5 cards
+        if limit == 0 { 5 } else { limit.min(5) }
+    } else {
+        limit
+    }
+}
+
+/// Count pending draws (attacks waiting for defense)
+fn count_pending_draws(ctx: &ReducerContext, turn_id: u64) -> usize {
+    ctx.db.draw()
+        .iter()
+        .filter(|draw| draw.turn_id == turn_id && draw.status == DrawStatus::Pending)
+        .count()
+}
+
+/// Get game settings with defaults if not found
+fn get_game_settings_for_game(ctx: &ReducerContext, game_id: u64) -> Result<GameSettings, String> {
+    ctx.db.game_rules()
+        .game_id()
+        .find(game_id)
+        .map(|rules| rules.settings)
+        .ok_or("Game rules not found".to_string())
+}
+
+// Core Game Actions
+
+#[reducer]
+/// Attack another player with a card
+pub fn attack(ctx: &ReducerContext, game_id: u64, card: Card, target: Identity) -> Result<(), String> {
+    attack_as(ctx, caller(ctx), game_id, card, target)?;
+    after_game_action(ctx, game_id)
+}
+
+fn attack_as(ctx: &ReducerContext, player: Identity, game_id: u64, card: Card, target: Identity) -> Result<(), String> {
+    // Validate game exists and is active
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+    
+    if game.status != GameStatus::Active {
+        return Err("Game is not active".to_string());
+    }
+
+    // Validate attacker is in the game
+    let attacker = ctx.db.user().identity().find(player)
+        .ok_or("User not found")?;
+    
+    if attacker.current_game_id != Some(game_id) {
+        return Err("You are not in this game".to_string());
+    }
+
+    if attacker.player_status != Some(PlayerStatus::Active) {
+        return Err("You are not active in this game".to_string());
+    }
+
+    // Validate target is in the game
+    let defender = ctx.db.user().identity().find(target)
+        .ok_or("Target player not found")?;
+    
+    if defender.current_game_id != Some(game_id) {
+        return Err("Target player is not in this game".to_string());
+    }
+
+    if defender.player_status != Some(PlayerStatus::Active) {
+        return Err("Target player is not active".to_string());
+    }
+
+    if is_teammate(&attacker, &defender) {
+        return Err("Cannot attack your teammate".to_string());
+    }
+
+    // Get current round
+    let round = get_current_round(ctx, game_id)
+        .ok_or("No active round found")?;
+
+    // Check if attacker has the card
+    if !player_has_card(ctx, game_id, player, &card) {
+        return Err("You don't have this card".to_string());
+    }
+
+    // Get current turn or create new one
+    let turn = if let Some(existing_turn) = get_active_turn(ctx, round.id) {
+        // Validate this is an additional attack on existing turn
+        if existing_turn.defender != target {
+            return Err("Can only attack the current defender".to_string());
+        }
+
+        // The announced attacker leads with the first card
+        let has_draws = ctx.db.draw().iter().any(|draw| draw.turn_id == existing_turn.id);
+        if !has_draws && existing_turn.attacker != player {
+            return Err("The attacker must lead this turn".to_string());
+        }
+
+        // Check if rank is valid for additional attack
+        if !is_valid_attack_rank(card.rank, existing_turn.id, ctx) {
+            return Err("Attack card rank must match existing cards on table".to_string());
+        }
+
+        // Check attack limits
+        let settings = get_game_settings_for_game(ctx, game_id)?;
+        let attack_limit = turn_attack_limit(&settings, &existing_turn);
+        if attack_limit > 0 {
+            let current_attacks = ctx.db.draw()
+                .iter()
+                .filter(|draw| draw.turn_id == existing_turn.id)
+                .count();
+            
+            if current_attacks >= attack_limit {
+                return Err("Maximum attack cards reached".to_string());
+            }
+        }
+
+        // Never more unbeaten cards than the defender can answer
+        let defender_hand = get_player_cards(ctx, game_id, target).len();
+        if count_pending_draws(ctx, existing_turn.id) >= defender_hand {
+            return Err("Defender has no cards left to answer another attack".to_string());
+        }
+
+        // Check if anyone can attack or just specific players
+        if !settings.anyone_can_attack {
+            // In traditional rules, only the original attacker can add cards
+            if existing_turn.attacker != player {
+                return Err("Only the original attacker can add more cards".to_string());
+            }
+        }
+
+        // Secondary attackers wait until everyone ahead of them clockwise has declined
+        if settings.throw_in_priority && existing_turn.attacker != player {
+            let attackers = get_eligible_attackers(ctx, game_id, &existing_turn)?;
+            let my_index = attackers.iter()
+                .position(|a| *a == player)
+                .ok_or("You cannot attack in this turn")?;
+
+            let waiting_on = attackers[..my_index].iter().any(|ahead| {
+                ctx.db.turn_pass().id().find(generate_turn_pass_id(existing_turn.id, *ahead)).is_none()
+            });
+
+            if waiting_on {
+                return Err("Players ahead of you have priority to throw in".to_string());
+            }
+        }
+
+        existing_turn
+    } else {
+        // Create new turn with this attack
+        let turn_number = ctx.db.turn()
+            .iter()
+            .filter(|t| t.round_id == round.id)
+            .count() as u32 + 1;
+
+        // The opening turn of a round belongs to the announced attacker and defender
+        if turn_number == 1 {
+            if let Some(first_attacker) = round.first_attacker {
+                if first_attacker != player {
+                    return Err("Another player opens this round".to_string());
+                }
+            }
+            if let Some(first_defender) = round.first_defender {
+                if first_defender != target {
+                    return Err("The opening attack must target the announced defender".to_string());
+                }
+            }
+        }
+
+        create_turn(ctx, game_id, round.id, player, target)
+    };
+
+    // Create the draw (attack)
+    let draw_id = generate_draw_id(turn.id, ctx.timestamp);
+    ctx.db.draw().insert(Draw {
+        id: draw_id,
+        game_id,
+        turn_id: turn.id,
+        attacker: player,
+        attacking_card: card.clone(),
+        defending_card: None,
+        status: DrawStatus::Pending,
+        transferred: false,
+        face_down: false,
+        created_at: ctx.timestamp,
+    });
+
+    // A new card reopens the turn for everyone who already passed
+    clear_turn_passes(ctx, turn.id);
+
+    // Move card from hand to table
+    if let Some(player_card) = ctx.db.player_card()
+        .iter()
+        .find(|pc| pc.game_id == game_id && pc.player == player && 
+                   pc.location == CardLocation::Hand && pc.card == card) {
+        put_card(ctx, PlayerCard {
+            location: CardLocation::OnTable,
+            ..player_card
+        });
+    }
+
+    log_event(ctx, game_id, GameEventKind::Attack, EventDetail {
+        player: Some(player),
+        target: Some(target),
+        card: Some(card.clone()),
+        draw_id: Some(draw_id),
+        ..Default::default()
+    });
+
+    log::info!("Player {:?} attacked {:?} with {:?} of {:?}", 
+               player, target, card.rank, card.suit);
+    Ok(())
+}
+
+#[reducer]
+/// Take back an attack card that nobody has answered or thrown in after yet
+pub fn undo_attack(ctx: &ReducerContext, game_id: u64, draw_id: u64) -> Result<(), String> {
+    undo_attack_as(ctx, caller(ctx), game_id, draw_id)?;
+    after_game_action(ctx, game_id)
+}
+
+fn undo_attack_as(ctx: &ReducerContext, player: Identity, game_id: u64, draw_id: u64) -> Result<(), String> {
+    // Validate game exists and is active
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    if game.status != GameStatus::Active {
+        return Err("Game is not active".to_string());
+    }
+
+    let draw = ctx.db.draw().id().find(draw_id)
+        .ok_or("Attack not found")?;
+
+    if draw.attacker != player {
+        return Err("You can only take back your own attack".to_string());
+    }
+
+    if draw.status != DrawStatus::Pending || draw.transferred {
+        return Err("This attack can no longer be taken back".to_string());
+    }
+
+    let turn = ctx.db.turn().id().find(draw.turn_id)
+        .ok_or("Turn not found")?;
+
+    if turn.status != TurnStatus::Active {
+        return Err("Turn is not active".to_string());
+    }
+
+    let other_draws: Vec<Draw> = ctx.db.draw()
+        .iter()
+        .filter(|d| d.turn_id == turn.id && d.id != draw_id)
+        .collect();
+
+    // Nobody may have thrown in on top of this card
+    if other_draws.iter().any(|d| d.created_at >= draw.created_at && d.attacker != player) {
+        return Err("Another player has already thrown in after this attack".to_string());
+    }
+
+    // Move card from table back to hand
+    if let Some(player_card) = ctx.db.player_card()
+        .iter()
+        .find(|pc| pc.game_id == game_id && pc.player == player &&
+                   pc.location == CardLocation::OnTable && pc.card == draw.attacking_card) {
+        put_card(ctx, PlayerCard {
+            location: CardLocation::Hand,
+            ..player_card
+        });
+    }
+
+    ctx.db.draw().id().delete(draw_id);
+
+    // The turn itself stays, the attacker leads it again
+    if other_draws.is_empty() {
+        clear_turn_passes(ctx, turn.id);
+    }
+
+    log_event(ctx, game_id, GameEventKind::UndoAttack, EventDetail {
+        player: Some(player),
+        card: Some(draw.attacking_card.clone()),
+        draw_id: Some(draw_id),
+        ..Default::default()
+    });
+
+    log::info!("Player {:?} took back {:?} of {:?}",
+               player, draw.attacking_card.rank, draw.attacking_card.suit);
+    Ok(())
+}
+
+#[reducer]
+/// Defend against a specific attack with a card
+pub fn defend(ctx: &ReducerContext, game_id: u64, turn_id: u64, draw_id: u64, card: Card) -> Result<(), String> {
+    defend_as(ctx, caller(ctx), game_id, turn_id, draw_id, card)?;
+    after_game_action(ctx, game_id)
+}
+
+fn defend_as(ctx: &ReducerContext, player: Identity, game_id: u64, turn_id: u64, draw_id: u64, card: Card) -> Result<(), String> {
+    // Validate game exists and is active
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+    
+    if game.status != GameStatus::Active {
+        return Err("Game is not active".to_string());
+    }
+
+    // Validate defender is in the game
+    let defender = ctx.db.user().identity().find(player)
+        .ok_or("User not found")?;
+    
+    if defender.current_game_id != Some(game_id) {
+        return Err("You are not in this game".to_string());
+    }
+
+    // Get the turn
+    let turn = ctx.db.turn().id().find(turn_id)
+        .ok_or("Turn not found")?;
+    
+    if turn.defender != player {
+        return Err("You are not the defender for this turn".to_string());
+    }
+
+    if turn.status != TurnStatus::Active {
+        return Err("Turn is not active".to_string());
+    }
+
+    // Check if defender has the card
+    if !player_has_card(ctx, game_id, player, &card) {
+        return Err("You don't have this card".to_string());
+    }
+
+    // Find the attack the defender chose to beat
+    let pending_draw = find_pending_draw(ctx, turn_id, draw_id)?;
+
+    // Validate defense is legal
+    if !can_beat_card(&pending_draw.attacking_card, &card, game.trump_suit) {
+        return Err("Your card cannot beat the attacking card".to_string());
+    }
+
+    // Update the draw with defense
+    ctx.db.draw().id().update(Draw {
+        defending_card: Some(card.clone()),
+        status: DrawStatus::Beaten,
+        ..pending_draw
+    });
+
+    // Move defending card from hand to table
+    if let Some(player_card) = ctx.db.player_card()
+        .iter()
+        .find(|pc| pc.game_id == game_id && pc.player == player && 
+                   pc.location == CardLocation::Hand && pc.card == card) {
+        put_card(ctx, PlayerCard {
+            location: CardLocation::OnTable,
+            ..player_card
+        });
+    }
+
+    log_event(ctx, game_id, GameEventKind::Defend, EventDetail {
+        player: Some(player),
+        card: Some(card.clone()),
+        draw_id: Some(draw_id),
+        ..Default::default()
+    });
+
+    // All attacks beaten and every attacker already said bito - defender wins the turn
+    try_finish_turn(ctx, game_id, &turn)?;
+
+    log::info!("Player {:?} defended with {:?} of {:?}", 
+               player, card.rank, card.suit);
+    Ok(())
+}
+
+#[reducer]
+/// Defend face-down with any card (cheating durak); legality is only checked if challenged
+pub fn defend_face_down(ctx: &ReducerContext, game_id: u64, turn_id: u64, draw_id: u64, card: Card) -> Result<(), String> {
+    defend_face_down_as(ctx, caller(ctx), game_id, turn_id, draw_id, card)?;
+    after_game_action(ctx, game_id)
+}
+
+fn defend_face_down_as(ctx: &ReducerContext, player: Identity, game_id: u64, turn_id: u64, draw_id: u64, card: Card) -> Result<(), String> {
+    // Validate game exists and is active
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    if game.status != GameStatus::Active {
+        return Err("Game is not active".to_string());
+    }
+
+    let settings = get_game_settings_for_game(ctx, game_id)?;
+    if !settings.cheating_durak {
+        return Err("Face-down defense is only allowed in cheating durak".to_string());
+    }
+
+    // Get the turn
+    let turn = ctx.db.turn().id().find(turn_id)
+        .ok_or("Turn not found")?;
+
+    if turn.defender != player {
+        return Err("You are not the defender for this turn".to_string());
+    }
+
+    if turn.status != TurnStatus::Active {
+        return Err("Turn is not active".to_string());
+    }
+
+    // Check if defender has the card
+    if !player_has_card(ctx, game_id, player, &card) {
+        return Err("You don't have this card".to_string());
+    }
+
+    // Find the attack the defender chose to beat
+    let pending_draw = find_pending_draw(ctx, turn_id, draw_id)?;
+
+    // Keep the real card hidden until someone challenges it
+    ctx.db.hidden_defense().insert(HiddenDefense {
+        draw_id: pending_draw.id,
+        turn_id,
+        card: card.clone(),
+    });
+
+    ctx.db.draw().id().update(Draw {
+        defending_card: None,
+        status: DrawStatus::Beaten,
+        face_down: true,
+        ..pending_draw
+    });
+
+    // Move defending card from hand to table
+    if let Some(player_card) = ctx.db.player_card()
+        .iter()
+        .find(|pc| pc.game_id == game_id && pc.player == player &&
+                   pc.location == CardLocation::Hand && pc.card == card) {
+        put_card(ctx, PlayerCard {
+            location: CardLocation::FaceDown,
+            ..player_card
+        });
+    }
+
+    log_event(ctx, game_id, GameEventKind::DefendFaceDown, EventDetail {
+        player: Some(player),
+        draw_id: Some(draw_id),
+        ..Default::default()
+    });
+
+    log::info!("Player {:?} defended face-down", player);
+    Ok(())
+}
+
+#[reducer]
+/// Challenge a face-down defense: the cheater takes the table if it was illegal, otherwise the challenger does
+pub fn challenge(ctx: &ReducerContext, game_id: u64, draw_id: u64) -> Result<(), String> {
+    challenge_as(ctx, caller(ctx), game_id, draw_id)?;
+    after_game_action(ctx, game_id)
+}
+
+fn challenge_as(ctx: &ReducerContext, player: Identity, game_id: u64, draw_id: u64) -> Result<(), String> {
+    // Validate game exists and is active
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    if game.status != GameStatus::Active {
+        return Err("Game is not active".to_string());
+    }
+
+    // Validate challenger is in the game
+    let challenger = ctx.db.user().identity().find(player)
+        .ok_or("User not found")?;
+
+    if challenger.current_game_id != Some(game_id) {
+        return Err("You are not in this game".to_string());
+    }
+
+    if challenger.player_status != Some(PlayerStatus::Active) {
+        return Err("You are not active in this game".to_string());
+    }
+
+    let draw = ctx.db.draw().id().find(draw_id)
+        .ok_or("Draw not found")?;
+
+    if !draw.face_down {
+        return Err("This defense is not face-down".to_string());
+    }
+
+    let turn = ctx.db.turn().id().find(draw.turn_id)
+        .ok_or("Turn not found")?;
+
+    if turn.status != TurnStatus::Active {
+        return Err("Turn is not active".to_string());
+    }
+
+    if turn.defender == player {
+        return Err("You cannot challenge your own defense".to_string());
+    }
+
+    // Only the attacking side of this turn may challenge, never the defender's team
+    let defender = ctx.db.user().identity().find(turn.defender)
+        .ok_or("Defender not found")?;
+    if is_teammate(&defender, &challenger) {
+        return Err("You cannot challenge your teammate's defense".to_string());
+    }
+
+    let attacked = ctx.db.draw()
+        .iter()
+        .any(|d| d.turn_id == turn.id && d.attacker == player);
+    if !attacked && !get_eligible_attackers(ctx, game_id, &turn)?.contains(&player) {
+        return Err("Only attackers on this turn can challenge".to_string());
+    }
+
+    let hidden = ctx.db.hidden_defense().draw_id().find(draw_id)
+        .ok_or("Hidden defense not found")?;
+
+    // Reveal the card
+    let legal = can_beat_card(&draw.attacking_card, &hidden.card, game.trump_suit);
+    ctx.db.draw().id().update(Draw {
+        defending_card: Some(hidden.card.clone()),
+        face_down: false,
+        ..draw
+    });
+    ctx.db.hidden_defense().draw_id().delete(draw_id);
+    if let Some(player_card) = ctx.db.player_card()
+        .iter()
+        .find(|pc| pc.game_id == game_id && pc.player == turn.defender &&
+                   pc.location == CardLocation::FaceDown && pc.card == hidden.card) {
+        put_card(ctx, PlayerCard {
+            location: CardLocation::OnTable,
+            ..player_card
+        });
+    }
+
+    log_event(ctx, game_id, GameEventKind::Challenge, EventDetail {
+        player: Some(player),
+        target: Some(turn.defender),
+        card: Some(hidden.card.clone()),
+        draw_id: Some(draw_id),
+        ..Default::default()
+    });
+
+    if !legal {
+        // Caught cheating - defender takes everything on the table
+        complete_take(ctx, game_id, &turn)?;
+
+        log::info!("Player {:?} caught {:?} cheating", player, turn.defender);
+    } else {
+        // False accusation - challenger takes the table and the defender holds
+        take_table(ctx, game_id, turn.id, player);
+        clear_hidden_defenses(ctx, turn.id);
+
+        ctx.db.turn().id().update(Turn {
+            status: TurnStatus::DefenderBeat,
+            finished_at: Some(ctx.timestamp),
+            ..turn.clone()
+        });
+
+        refill_hands(ctx, game_id, &turn)?;
+        start_next_turn_after_defense(ctx, game_id, turn.round_id, turn.defender)?;
+
+        log::info!("Player {:?} wrongly challenged {:?}", player, turn.defender);
+    }
+
+    Ok(())
+}
+
+#[reducer]
+/// Transfer the attack to the next player by adding a card of the same rank (perevodnoy)
+pub fn transfer_attack(ctx: &ReducerContext, game_id: u64, turn_id: u64, card: Card) -> Result<(), String> {
+    transfer_attack_as(ctx, caller(ctx), game_id, turn_id, card)?;
+    after_game_action(ctx, game_id)
+}
+
+fn transfer_attack_as(ctx: &ReducerContext, player: Identity, game_id: u64, turn_id: u64, card: Card) -> Result<(), String> {
+    // Validate game exists and is active
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    if game.status != GameStatus::Active {
+        return Err("Game is not active".to_string());
+    }
+
+    let settings = get_game_settings_for_game(ctx, game_id)?;
+    if !settings.perevodnoy {
+        return Err("Transferring attacks is not allowed in this game".to_string());
+    }
+
+    // Get the turn
+    let turn = ctx.db.turn().id().find(turn_id)
+        .ok_or("Turn not found")?;
+
+    if turn.defender != player {
+        return Err("You are not the defender for this turn".to_string());
+    }
+
+    if turn.status != TurnStatus::Active {
+        return Err("Turn is not active".to_string());
+    }
+
+    // Check if defender has the card
+    if !player_has_card(ctx, game_id, player, &card) {
+        return Err("You don't have this card".to_string());
+    }
+
+    let draws: Vec<Draw> = ctx.db.draw()
+        .iter()
+        .filter(|draw| draw.turn_id == turn_id)
+        .collect();
+
+    if draws.is_empty() {
+        return Err("No attack to transfer".to_string());
+    }
+
+    // Transfer is only possible before any attack card has been beaten
+    if draws.iter().any(|draw| draw.status != DrawStatus::Pending) {
+        return Err("Cannot transfer after you started defending".to_string());
+    }
+
+    // Every card on the table must be of the same rank as the transfer card
+    if draws.iter().any(|draw| draw.attacking_card.rank != card.rank) {
+        return Err("Transfer card rank must match all cards on table".to_string());
+    }
+
+    let attack_limit = turn_attack_limit(&settings, &turn);
+    if attack_limit > 0 && draws.len() >= attack_limit {
+        return Err("Maximum attack cards reached".to_string());
+    }
+
+    // The attack moves on to the next player clockwise, who must be able to answer every card
+    let new_defender = get_next_opponent_clockwise(ctx, game_id, player)?;
+    let new_defender_hand = get_player_cards(ctx, game_id, new_defender).len();
+    if new_defender_hand < draws.len() + 1 {
+        return Err("Next player does not have enough cards to receive the transfer".to_string());
+    }
+
+    // Create the transfer draw
+    let draw_id = generate_draw_id(turn_id, ctx.timestamp);
+    ctx.db.draw().insert(Draw {
+        id: draw_id,
+        game_id,
+        turn_id,
+        attacker: player,
+        attacking_card: card.clone(),
+        defending_card: None,
+        status: DrawStatus::Pending,
+        transferred: true,
+        face_down: false,
+        created_at: ctx.timestamp,
+    });
+
+    // A new card reopens the turn for everyone who already passed
+    clear_turn_passes(ctx, turn.id);
+
+    // Move card from hand to table
+    if let Some(player_card) = ctx.db.player_card()
+        .iter()
+        .find(|pc| pc.game_id == game_id && pc.player == player &&
+                   pc.location == CardLocation::Hand && pc.card == card) {
+        put_card(ctx, PlayerCard {
+            location: CardLocation::OnTable,
+            ..player_card
+        });
+    }
+
+    // Former defender leads the attack against the next player
+    ctx.db.turn().id().update(Turn {
+        attacker: player,
+        defender: new_defender,
+        transfers: turn.transfers + 1,
+        ..turn
+    });
+
+    log_event(ctx, game_id, GameEventKind::Transfer, EventDetail {
+        player: Some(player),
+        target: Some(new_defender),
+        card: Some(card.clone()),
+        draw_id: Some(draw_id),
+        ..Default::default()
+    });
+
+    log::info!("Player {:?} transferred attack to {:?} with {:?} of {:?}",
+               player, new_defender, card.rank, card.suit);
+    Ok(())
+}
+
+#[reducer]
+/// Defender declares a take; attackers get to throw in more cards before it completes
+pub fn take_cards(ctx: &ReducerContext, game_id: u64, turn_id: u64) -> Result<(), String> {
+    take_cards_as(ctx, caller(ctx), game_id, turn_id)?;
+    after_game_action(ctx, game_id)
+}
+
+fn take_cards_as(ctx: &ReducerContext, player: Identity, game_id: u64, turn_id: u64) -> Result<(), String> {
+    // Validate game exists and is active
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+    
+    if game.status != GameStatus::Active {
+        return Err("Game is not active".to_string());
+    }
+
+    // Get the turn
+    let turn = ctx.db.turn().id().find(turn_id)
+        .ok_or("Turn not found")?;
+    
+    if turn.defender != player {
+        return Err("You are not the defender for this turn".to_string());
+    }
+
+    if turn.status != TurnStatus::Active {
+        return Err("Turn is not active".to_string());
+    }
+
+    if count_pending_draws(ctx, turn_id) == 0 {
+        return Err("Nothing to take".to_string());
+    }
+
+    // Open the throw-in window; attackers pass again to let the take complete
+    let taking_turn = Turn {
+        status: TurnStatus::Taking,
+        ..turn
+    };
+    ctx.db.turn().id().update(taking_turn.clone());
+    clear_turn_passes(ctx, turn_id);
+
+    log_event(ctx, game_id, GameEventKind::Take, EventDetail {
+        player: Some(player),
+        ..Default::default()
+    });
+
+    log::info!("Player {:?} declared a take", player);
+
+    // Nobody left who could throw in - take right away
+    if all_attackers_passed(ctx, game_id, &taking_turn)? {
+        complete_take(ctx, game_id, &taking_turn)?;
+    }
+
+    Ok(())
+}
+
+#[reducer]
+/// Primary attacker closes the throw-in window and lets the defender's take complete
+pub fn confirm_take(ctx: &ReducerContext, game_id: u64, turn_id: u64) -> Result<(), String> {
+    confirm_take_as(ctx, caller(ctx), game_id, turn_id)?;
+    after_game_action(ctx, game_id)
+}
+
+fn confirm_take_as(ctx: &ReducerContext, player: Identity, game_id: u64, turn_id: u64) -> Result<(), String> {
+    // Validate game exists and is active
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    if game.status != GameStatus::Active {
+        return Err("Game is not active".to_string());
+    }
+
+    let turn = ctx.db.turn().id().find(turn_id)
+        .ok_or("Turn not found")?;
+
+    if turn.status != TurnStatus::Taking {
+        return Err("Defender is not taking".to_string());
+    }
+
+    if turn.attacker != player {
+        return Err("Only the primary attacker can confirm the take".to_string());
+    }
+
+    complete_take(ctx, game_id, &turn)?;
+    Ok(())
+}
+
+#[reducer]
+/// Pass turn (attacker cannot or chooses not to add more cards)
+pub fn pass_turn(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    pass_turn_as(ctx, caller(ctx), game_id)?;
+    after_game_action(ctx, game_id)
+}
+
+fn pass_turn_as(ctx: &ReducerContext, player: Identity, game_id: u64) -> Result<(), String> {
+    // Validate game exists and is active
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+    
+    if game.status != GameStatus::Active {
+        return Err("Game is not active".to_string());
+    }
+
+    // Get current round
+    let round = get_current_round(ctx, game_id)
+        .ok_or("No active round found")?;
+
+    // Get current turn
+    let turn = get_active_turn(ctx, round.id)
+        .ok_or("No active turn found")?;
+
+    if !ctx.db.draw().iter().any(|draw| draw.turn_id == turn.id) {
+        return Err("The turn has not been led yet".to_string());
+    }
+
+    // Check if there are any pending attacks (while taking, nothing will be defended anyway)
+    let pending_draws = count_pending_draws(ctx, turn.id);
+    if pending_draws > 0 && turn.status != TurnStatus::Taking {
+        return Err("Cannot pass while there are undefended attacks".to_string());
+    }
+
+    // Only attackers can pass (or anyone if anyone_can_attack is true)
+    let settings = get_game_settings_for_game(ctx, game_id)?;
+    if !settings.anyone_can_attack && turn.attacker != player {
+        return Err("Only the attacker can pass".to_string());
+    }
+
+    if turn.defender == player {
+        return Err("The defender cannot pass".to_string());
+    }
+
+    if !get_eligible_attackers(ctx, game_id, &turn)?.contains(&player) {
+        return Err("You cannot attack in this turn".to_string());
+    }
+
+    // Record the bito once per attacker
+    let pass_id = generate_turn_pass_id(turn.id, player);
+    if ctx.db.turn_pass().id().find(pass_id).is_none() {
+        ctx.db.turn_pass().insert(TurnPass {
+            id: pass_id,
+            turn_id: turn.id,
+            player: player,
+            passed_at: ctx.timestamp,
+        });
+        log_event(ctx, game_id, GameEventKind::Pass, EventDetail {
+            player: Some(player),
+            ..Default::default()
+        });
+    }
+
+    log::info!("Player {:?} passed turn", player);
+
+    // A declared take completes once every attacker is done throwing in
+    if turn.status == TurnStatus::Taking {
+        if all_attackers_passed(ctx, game_id, &turn)? {
+            complete_take(ctx, game_id, &turn)?;
+        }
+        return Ok(());
+    }
+
+    // Turn is finished once every attacker has passed and all attacks are defended
+    try_finish_turn(ctx, game_id, &turn)?;
+    Ok(())
+}
+
+// Game Events
+
+/// Optional parts of a game event, most kinds use only a few
+#[derive(Default)]
+struct EventDetail {
+    player: Option<Identity>,
+    target: Option<Identity>,
+    card: Option<Card>,
+    suit: Option<Suit>,
+    draw_id: Option<u64>,
+}
+
+fn generate_game_event_id(game_id: u64, seq: u32) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    game_id.hash(&mut hasher);
+    seq.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn log_event(ctx: &ReducerContext, game_id: u64, kind: GameEventKind, detail: EventDetail) {
+    let seq = ctx.db.game_event().game_id().filter(game_id).count() as u32 + 1;
+
+    ctx.db.game_event().insert(GameEvent {
+        id: generate_game_event_id(game_id, seq),
+        game_id,
+        seq,
+        kind,
+        player: detail.player,
+        target: detail.target,
+        card: detail.card,
+        suit: detail.suit,
+        draw_id: detail.draw_id,
+        created_at: ctx.timestamp,
+    });
+
+    if let Some(player) = detail.player {
+        match kind {
+            GameEventKind::Defend | GameEventKind::DefendFaceDown => advance_quests(ctx, player, QuestKind::DefendAttacks, 1),
+            GameEventKind::Attack => advance_quests(ctx, player, QuestKind::AttackCards, 1),
+            _ => {}
+        }
+    }
+}
+
+/// Log a freshly dealt round: who opens, the trump, every dealt card and the exposed trump card
+fn log_round_start(ctx: &ReducerContext, game_id: u64, first_attacker: Identity, first_defender: Identity, trump_suit: Option<Suit>) {
+    log_event(ctx, game_id, GameEventKind::RoundStarted, EventDetail {
+        player: Some(first_attacker),
+        target: Some(first_defender),
+        suit: trump_suit,
+        ..Default::default()
+    });
+
+    let dealt: Vec<PlayerCard> = ctx.db.player_card()
+        .iter()
+        .filter(|pc| pc.game_id == game_id && matches!(pc.location, CardLocation::Hand | CardLocation::TrumpCard))
+        .collect();
+
+    for player_card in dealt {
+        let kind = if player_card.location == CardLocation::TrumpCard {
+            GameEventKind::TrumpShown
+        } else {
+            GameEventKind::Dealt
+        };
+        let player = (kind == GameEventKind::Dealt).then_some(player_card.player);
+        log_event(ctx, game_id, kind, EventDetail {
+            player,
+            card: Some(player_card.card),
+            ..Default::default()
+        });
+    }
+}
+
+// Move Timers
+
+/// Bookkeeping after any move in a game
+fn after_game_action(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    // A player acting on their own is clearly at the table
+    let by_player = ctx.sender != ctx.identity();
+    if by_player {
+        ctx.db.timeout_strike().id().delete(generate_clock_id(game_id, caller(ctx)));
+        mark_active(ctx, caller(ctx));
+    }
+
+    if let Some(game) = ctx.db.game().id().find(game_id) {
+        ctx.db.game().id().update(Game {
+            last_activity: if by_player { ctx.timestamp } else { game.last_activity },
+            event_seq: game.event_seq + 1,
+            ..game
+        });
+    }
+
+    arm_turn_timer(ctx, game_id)?;
+    run_player_clocks(ctx, game_id)?;
+    schedule_bot_move(ctx, game_id)?;
+    update_hand_counts(ctx, game_id);
+    update_active_timers(ctx, game_id)?;
+    record_spectator_event(ctx, game_id)?;
+    update_resume_states(ctx, game_id)
+}
+
+fn generate_spectator_event_id(game_id: u64, seq: u64) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    game_id.hash(&mut hasher);
+    seq.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Snapshot the public game state for spectators, held back by the spectator delay
+fn record_spectator_event(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    // Once the game is over there is nothing left to spoil
+    if game.status == GameStatus::Finished {
+        let buffered: Vec<SpectatorEvent> = ctx.db.spectator_event_buffer()
+            .iter()
+            .filter(|event| event.game_id == game_id)
+            .collect();
+
+        for event in buffered {
+            ctx.db.spectator_event_buffer().id().delete(event.id);
+            ctx.db.spectator_event().insert(event);
+        }
+        return Ok(());
+    }
+
+    let watched = ctx.db.game_spectator().iter().any(|spectator| spectator.game_id == game_id)
+        || ctx.db.spectator().iter().any(|spectator| spectator.lobby_id == game.lobby_id);
+    if !watched {
+        return Ok(());
+    }
+
+    let turn = get_current_turn(ctx, game_id);
+    let table: Vec<Draw> = match &turn {
+        Some(turn) => ctx.db.draw().iter().filter(|draw| draw.turn_id == turn.id).collect(),
+        None => Vec::new(),
+    };
+    let hands: Vec<SeatView> = ctx.db.hand_count()
+        .iter()
+        .filter(|count| count.game_id == game_id)
+        .map(|count| SeatView { player: count.player, cards: count.cards })
+        .collect();
+
+    let event = SpectatorEvent {
+        id: generate_spectator_event_id(game_id, game.event_seq),
+        game_id,
+        seq: game.event_seq,
+        round_number: game.current_round,
+        turn_id: turn.as_ref().map(|turn| turn.id),
+        attacker: turn.as_ref().map(|turn| turn.attacker),
+        defender: turn.as_ref().map(|turn| turn.defender),
+        table,
+        hands,
+        happened_at: ctx.timestamp,
+    };
+
+    let settings = get_game_settings_for_game(ctx, game_id)?;
+    if settings.spectator_delay_secs == 0 {
+        ctx.db.spectator_event().insert(event);
+        return Ok(());
+    }
+
+    let event_id = event.id;
+    ctx.db.spectator_event_buffer().insert(event);
+    ctx.db.spectator_release().insert(SpectatorRelease {
+        scheduled_id: 0,
+        scheduled_at: ScheduleAt::Time(ctx.timestamp + TimeDuration::from_micros(settings.spectator_delay_secs as i64 * 1_000_000)),
+        event_id,
+    });
+    Ok(())
+}
+
+#[reducer]
+/// Publish a buffered spectator snapshot once the delay has passed (scheduled)
+pub fn release_spectator_event(ctx: &ReducerContext, release: SpectatorRelease) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can release spectator events".to_string());
+    }
+
+    let Some(event) = ctx.db.spectator_event_buffer().id().find(release.event_id) else {
+        return Ok(());
+    };
+
+    ctx.db.spectator_event_buffer().id().delete(event.id);
+    ctx.db.spectator_event().insert(event);
+    Ok(())
+}
+
+fn generate_active_timer_id(game_id: u64, kind: CountdownKind, player: Option<Identity>) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    game_id.hash(&mut hasher);
+    (kind as u8).hash(&mut hasher);
+    player.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Mirror the game's scheduled deadlines into active_timer
+fn update_active_timers(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    let stale: Vec<u64> = ctx.db.active_timer()
+        .iter()
+        .filter(|timer| timer.game_id == game_id)
+        .map(|timer| timer.id)
+        .collect();
+
+    for timer_id in stale {
+        ctx.db.active_timer().id().delete(timer_id);
+    }
+
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+    if game.status != GameStatus::Active && game.status != GameStatus::Paused {
+        return Ok(());
+    }
+
+    let turn = get_current_turn(ctx, game_id);
+    let taking = turn.as_ref().is_some_and(|turn| turn.status == TurnStatus::Taking);
+
+    let mut countdowns: Vec<(CountdownKind, Option<Identity>, ScheduleAt)> = Vec::new();
+    for timer in ctx.db.turn_timer().iter().filter(|timer| timer.game_id == game_id) {
+        let kind = match timer.action {
+            TimerAction::Defend => CountdownKind::Defend,
+            TimerAction::Attack if taking => CountdownKind::TakeWindow,
+            TimerAction::Attack => CountdownKind::Attack,
+        };
+        countdowns.push((kind, Some(timer.player), timer.scheduled_at));
+    }
+    for expiry in ctx.db.clock_expiry().iter().filter(|expiry| expiry.game_id == game_id) {
+        countdowns.push((CountdownKind::TimeBank, Some(expiry.player), expiry.scheduled_at));
+    }
+    for expiry in ctx.db.pause_expiry().iter().filter(|expiry| expiry.game_id == game_id) {
+        countdowns.push((CountdownKind::Pause, None, expiry.scheduled_at));
+    }
+    for limit in ctx.db.game_time_limit().iter().filter(|limit| limit.game_id == game_id) {
+        countdowns.push((CountdownKind::GameLimit, None, limit.scheduled_at));
+    }
+    for limit in ctx.db.round_time_limit().iter().filter(|limit| limit.game_id == game_id) {
+        countdowns.push((CountdownKind::RoundLimit, None, limit.scheduled_at));
+    }
+
+    for (kind, player, scheduled_at) in countdowns {
+        let ScheduleAt::Time(deadline) = scheduled_at else {
+            continue;
+        };
+        let id = generate_active_timer_id(game_id, kind, player);
+        ctx.db.active_timer().id().delete(id);
+        ctx.db.active_timer().insert(ActiveTimer {
+            id,
+            game_id,
+            kind,
+            player,
+            deadline,
+        });
+    }
+
+    Ok(())
+}
+
+/// Refresh the public hand sizes and seats of a game
+fn update_hand_counts(ctx: &ReducerContext, game_id: u64) {
+    let stale: Vec<Identity> = ctx.db.hand_count()
+        .iter()
+        .filter(|count| count.game_id == game_id)
+        .map(|count| count.player)
+        .collect();
+
+    for player in stale {
+        ctx.db.hand_count().player().delete(player);
+    }
+    let stale: Vec<Identity> = ctx.db.game_seat()
+        .game_id()
+        .filter(game_id)
+        .map(|seat| seat.player)
+        .collect();
+    for player in stale {
+        ctx.db.game_seat().player().delete(player);
+    }
+
+    let players: Vec<Identity> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id))
+        .map(|user| user.identity)
+        .collect();
+
+    for player in players {
+        ctx.db.hand_count().insert(HandCount {
+            player,
+            game_id,
+            cards: get_player_cards(ctx, game_id, player).len().min(u8::MAX as usize) as u8,
+        });
+        ctx.db.game_seat().player().delete(player);
+        ctx.db.game_seat().insert(GameSeat { player, game_id });
+    }
+
+    let deck = ctx.db.player_card()
+        .iter()
+        .filter(|pc| pc.game_id == game_id && matches!(pc.location, CardLocation::Deck | CardLocation::TrumpCard))
+        .count() as u32;
+    ctx.db.deck_count().game_id().delete(game_id);
+    ctx.db.deck_count().insert(DeckCount { game_id, cards: deck });
+}
+
+/// Rewrite the resume rows of everyone still in the game
+fn update_resume_states(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    let stale: Vec<Identity> = ctx.db.resume_state()
+        .iter()
+        .filter(|state| state.game_id == game_id)
+        .map(|state| state.player)
+        .collect();
+
+    for player in stale {
+        ctx.db.resume_state().player().delete(player);
+    }
+
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+    if game.status == GameStatus::Finished {
+        return Ok(());
+    }
+
+    let turn = get_current_turn(ctx, game_id);
+    let to_act = turn.as_ref().map(|turn| player_to_act(ctx, turn));
+
+    let players: Vec<Identity> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id))
+        .filter(|user| matches!(user.player_status, Some(PlayerStatus::Active) | Some(PlayerStatus::Finished)))
+        .map(|user| user.identity)
+        .collect();
+
+    for player in players {
+        let pending_action = match to_act {
+            Some((actor, action)) if actor == player && game.status == GameStatus::Active => Some(action),
+            _ => None,
+        };
+
+        // The move timer runs out first; the chess clock only matters without one
+        let deadline = |at: &ScheduleAt| match at {
+            ScheduleAt::Time(time) => Some(*time),
+            ScheduleAt::Interval(_) => None,
+        };
+        let timer_deadline = ctx.db.turn_timer()
+            .iter()
+            .find(|timer| timer.game_id == game_id && timer.player == player)
+            .and_then(|timer| deadline(&timer.scheduled_at))
+            .or_else(|| ctx.db.clock_expiry()
+                .iter()
+                .find(|expiry| expiry.game_id == game_id && expiry.player == player)
+                .and_then(|expiry| deadline(&expiry.scheduled_at)));
+
+        ctx.db.resume_state().insert(ResumeState {
+            player,
+            game_id,
+            turn_id: turn.as_ref().map(|turn| turn.id),
+            pending_action,
+            timer_deadline,
+            last_event_seq: game.event_seq,
+            updated_at: ctx.timestamp,
+        });
+    }
+
+    Ok(())
+}
+
+/// Unbeaten cards wait on the defender, everything else waits on the attackers
+fn player_to_act(ctx: &ReducerContext, turn: &Turn) -> (Identity, TimerAction) {
+    if turn.status == TurnStatus::Active && count_pending_draws(ctx, turn.id) > 0 {
+        (turn.defender, TimerAction::Defend)
+    } else {
+        (turn.attacker, TimerAction::Attack)
+    }
+}
+
+fn clear_turn_timers(ctx: &ReducerContext, game_id: u64) {
+    let pending: Vec<u64> = ctx.db.turn_timer()
+        .iter()
+        .filter(|timer| timer.game_id == game_id)
+        .map(|timer| timer.scheduled_id)
+        .collect();
+
+    for scheduled_id in pending {
+        ctx.db.turn_timer().scheduled_id().delete(scheduled_id);
+    }
+}
+
+/// Replace the game's move timer with one for whoever has to act now
+fn arm_turn_timer(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    clear_turn_timers(ctx, game_id);
+
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+    if game.status != GameStatus::Active {
+        return Ok(());
+    }
+
+    let Some(turn) = get_current_turn(ctx, game_id) else {
+        return Ok(());
+    };
+
+    let settings = get_game_settings_for_game(ctx, game_id)?;
+    let (player, action) = player_to_act(ctx, &turn);
+    let timeout_secs = match action {
+        TimerAction::Attack => settings.attack_timeout_secs,
+        TimerAction::Defend => settings.defend_timeout_secs,
+    };
+
+    if timeout_secs == 0 {
+        return Ok(());
+    }
+
+    ctx.db.turn_timer().insert(TurnTimer {
+        scheduled_id: 0,
+        scheduled_at: ScheduleAt::Time(ctx.timestamp + TimeDuration::from_micros(timeout_secs as i64 * 1_000_000)),
+        game_id,
+        turn_id: turn.id,
+        player,
+        action,
+    });
+    Ok(())
+}
+
+fn generate_clock_id(game_id: u64, player: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    game_id.hash(&mut hasher);
+    player.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Stop the running chess clock and start the one of the player who has to act now
+fn run_player_clocks(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    let pending: Vec<u64> = ctx.db.clock_expiry()
+        .iter()
+        .filter(|expiry| expiry.game_id == game_id)
+        .map(|expiry| expiry.scheduled_id)
+        .collect();
+
+    for scheduled_id in pending {
+        ctx.db.clock_expiry().scheduled_id().delete(scheduled_id);
+    }
+
+    let settings = get_game_settings_for_game(ctx, game_id)?;
+    if settings.time_bank_secs == 0 {
+        return Ok(());
+    }
+
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+    let Some(round) = get_current_round(ctx, game_id) else {
+        return Ok(());
+    };
+
+    // Settle the clock that was running; a new round refills every bank
+    let bank_micros = settings.time_bank_secs as i64 * 1_000_000;
+    let clocks: Vec<PlayerClock> = ctx.db.player_clock()
+        .iter()
+        .filter(|clock| clock.game_id == game_id)
+        .collect();
+
+    for clock in clocks {
+        let remaining_micros = if clock.round_id != round.id {
+            bank_micros
+        } else if let Some(since) = clock.running_since {
+            let elapsed = ctx.timestamp.duration_since(since).map_or(0, |d| d.as_micros() as i64);
+            clock.remaining_micros - elapsed
+        } else {
+            clock.remaining_micros
+        };
+
+        ctx.db.player_clock().id().update(PlayerClock {
+            round_id: round.id,
+            remaining_micros,
+            running_since: None,
+            ..clock
+        });
+    }
+
+    if game.status != GameStatus::Active {
+        return Ok(());
+    }
+
+    let Some(turn) = get_active_turn(ctx, round.id) else {
+        return Ok(());
+    };
+
+    let (player, _) = player_to_act(ctx, &turn);
+    let clock_id = generate_clock_id(game_id, player);
+    let clock = match ctx.db.player_clock().id().find(clock_id) {
+        Some(clock) => clock,
+        None => ctx.db.player_clock().insert(PlayerClock {
+            id: clock_id,
+            game_id,
+            player,
+            round_id: round.id,
+            remaining_micros: bank_micros,
+            running_since: None,
+        }),
+    };
+
+    ctx.db.clock_expiry().insert(ClockExpiry {
+        scheduled_id: 0,
+        scheduled_at: ScheduleAt::Time(ctx.timestamp + TimeDuration::from_micros(clock.remaining_micros.max(0))),
+        game_id,
+        round_id: round.id,
+        player,
+    });
+
+    ctx.db.player_clock().id().update(PlayerClock {
+        running_since: Some(ctx.timestamp),
+        ..clock
+    });
+    Ok(())
+}
+
+#[reducer]
+/// A player's time bank ran dry: they lose the round (scheduled)
+pub fn expire_player_clock(ctx: &ReducerContext, expiry: ClockExpiry) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can expire clocks".to_string());
+    }
+
+    let Some(round) = get_current_round(ctx, expiry.game_id) else {
+        return Ok(());
+    };
+
+    let still_running = ctx.db.player_clock().id().find(generate_clock_id(expiry.game_id, expiry.player))
+        .is_some_and(|clock| clock.round_id == expiry.round_id && clock.running_since.is_some());
+    if round.id != expiry.round_id || !still_running {
+        return Ok(());
+    }
+
+    log::info!("Player {:?} ran out of time in round {}", expiry.player, round.round_number);
+    end_round(ctx, expiry.game_id, round.id, Some(expiry.player))?;
+    after_game_action(ctx, expiry.game_id)
+}
+
+/// Lowest card in a player's hand, plain suits before trumps
+fn lowest_card(ctx: &ReducerContext, game_id: u64, player: Identity, trump_suit: Option<Suit>) -> Option<Card> {
+    get_player_cards(ctx, game_id, player)
+        .into_iter()
+        .map(|pc| pc.card)
+        .min_by_key(|card| (Some(card.suit) == trump_suit, card.rank))
+}
+
+/// Every attacker still allowed to throw in declines at once
+fn force_pass(ctx: &ReducerContext, game_id: u64, turn: &Turn) -> Result<(), String> {
+    for attacker in get_eligible_attackers(ctx, game_id, turn)? {
+        let pass_id = generate_turn_pass_id(turn.id, attacker);
+        if ctx.db.turn_pass().id().find(pass_id).is_none() {
+            ctx.db.turn_pass().insert(TurnPass {
+                id: pass_id,
+                turn_id: turn.id,
+                player: attacker,
+                passed_at: ctx.timestamp,
+            });
+            log_event(ctx, game_id, GameEventKind::Pass, EventDetail {
+                player: Some(attacker),
+                ..Default::default()
+            });
+        }
+    }
+
+    if turn.status == TurnStatus::Taking {
+        complete_take(ctx, game_id, turn)
+    } else {
+        try_finish_turn(ctx, game_id, turn)
+    }
+}
+
+#[reducer]
+/// Make the stalled move for a player whose timer ran out (scheduled)
+pub fn expire_turn_timer(ctx: &ReducerContext, timer: TurnTimer) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can expire timers".to_string());
+    }
+
+    let Some(turn) = get_current_turn(ctx, timer.game_id) else {
+        return Ok(());
+    };
+
+    // Timers are replaced on every move, but never act on a turn that moved on
+    if turn.id != timer.turn_id {
+        return Ok(());
+    }
+
+    let game = ctx.db.game().id().find(timer.game_id)
+        .ok_or("Game not found")?;
+
+    add_timeout_strike(ctx, timer.game_id, timer.player);
+
+    match timer.action {
+        TimerAction::Defend => {
+            log::info!("Defender {:?} ran out of time and takes", timer.player);
+            take_cards_as(ctx, timer.player, timer.game_id, turn.id)?;
+        }
+        TimerAction::Attack => {
+            let has_draws = ctx.db.draw().iter().any(|draw| draw.turn_id == turn.id);
+            if has_draws {
+                log::info!("Attackers ran out of time in turn {}", turn.id);
+                force_pass(ctx, timer.game_id, &turn)?;
+            } else if let Some(card) = lowest_card(ctx, timer.game_id, turn.attacker, game.trump_suit) {
+                log::info!("Attacker {:?} ran out of time and leads their lowest card", turn.attacker);
+                attack_as(ctx, turn.attacker, timer.game_id, card, turn.defender)?;
+            }
+        }
+    }
+
+    after_game_action(ctx, timer.game_id)
+}
+
+// Bots
+
+/// Pause between bot moves so people can follow what happened
+const BOT_MOVE_DELAY_MICROS: i64 = 1_000_000;
+
+fn is_bot(ctx: &ReducerContext, player: Identity) -> bool {
+    ctx.db.bot_seat().player().find(player).is_some()
+}
+
+/// Queue a bot move if a seat played by the server has something to do
+fn schedule_bot_move(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    let pending: Vec<u64> = ctx.db.bot_move()
+        .iter()
+        .filter(|bot_move| bot_move.game_id == game_id)
+        .map(|bot_move| bot_move.scheduled_id)
+        .collect();
+
+    for scheduled_id in pending {
+        ctx.db.bot_move().scheduled_id().delete(scheduled_id);
+    }
+
+    if !ctx.db.bot_seat().iter().any(|seat| seat.game_id == game_id) {
+        return Ok(());
+    }
+
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+    if game.status != GameStatus::Active {
+        return Ok(());
+    }
+
+    ctx.db.bot_move().insert(BotMove {
+        scheduled_id: 0,
+        scheduled_at: ScheduleAt::Time(ctx.timestamp + TimeDuration::from_micros(BOT_MOVE_DELAY_MICROS)),
+        game_id,
+    });
+    Ok(())
+}
+
+/// Make one simple legal move for a bot seat, if any bot has to act. Returns whether a move was made.
+/// Bots beat with their cheapest card or take, lead their lowest card, and never throw in.
+fn make_bot_move(ctx: &ReducerContext, game_id: u64) -> Result<bool, String> {
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    let Some(turn) = get_current_turn(ctx, game_id) else {
+        return Ok(false);
+    };
+
+    let draws: Vec<Draw> = ctx.db.draw()
+        .iter()
+        .filter(|draw| draw.turn_id == turn.id)
+        .collect();
+
+    // Defend the first unbeaten card, or take if it can't be beaten
+    if turn.status == TurnStatus::Active && is_bot(ctx, turn.defender) {
+        if let Some(draw) = draws.iter().find(|draw| draw.status == DrawStatus::Pending) {
+            let answer = get_player_cards(ctx, game_id, turn.defender)
+                .into_iter()
+                .map(|pc| pc.card)
+                .filter(|card| can_beat_card(&draw.attacking_card, card, game.trump_suit))
+                .min_by_key(|card| (Some(card.suit) == game.trump_suit, card.rank));
+
+            match answer {
+                Some(card) => defend_as(ctx, turn.defender, game_id, turn.id, draw.id, card)?,
+                None => take_cards_as(ctx, turn.defender, game_id, turn.id)?,
+            }
+            return Ok(true);
+        }
+    }
+
+    // Lead an empty turn
+    if draws.is_empty() {
+        if is_bot(ctx, turn.attacker) {
+            if let Some(card) = lowest_card(ctx, game_id, turn.attacker, game.trump_suit) {
+                attack_as(ctx, turn.attacker, game_id, card, turn.defender)?;
+                return Ok(true);
+            }
+        }
+        return Ok(false);
+    }
+
+    // Decline to throw in once nothing is waiting on the defender
+    if turn.status == TurnStatus::Taking || draws.iter().all(|draw| draw.status != DrawStatus::Pending) {
+        let waiting_bot = get_eligible_attackers(ctx, game_id, &turn)?
+            .into_iter()
+            .filter(|attacker| is_bot(ctx, *attacker))
+            .find(|attacker| ctx.db.turn_pass().id().find(generate_turn_pass_id(turn.id, *attacker)).is_none());
+
+        if let Some(attacker) = waiting_bot {
+            pass_turn_as(ctx, attacker, game_id)?;
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Timeouts in a row after which the server plays for the player
+const AUTOPILOT_AFTER_TIMEOUTS: u8 = 3;
+
+/// Count a missed move and switch to autopilot once the player keeps missing them
+fn add_timeout_strike(ctx: &ReducerContext, game_id: u64, player: Identity) {
+    if is_bot(ctx, player) {
+        return;
+    }
+
+    // One strike row per player and game, keyed like their clock
+    let strike_id = generate_clock_id(game_id, player);
+    let count = match ctx.db.timeout_strike().id().find(strike_id) {
+        Some(strike) => {
+            let count = strike.count.saturating_add(1);
+            ctx.db.timeout_strike().id().update(TimeoutStrike { count, ..strike });
+            count
+        }
+        None => {
+            ctx.db.timeout_strike().insert(TimeoutStrike {
+                id: strike_id,
+                game_id,
+                player,
+                count: 1,
+            });
+            1
+        }
+    };
+
+    if count >= AUTOPILOT_AFTER_TIMEOUTS {
+        log::info!("Autopilot turned on for {:?} after {} timeouts", player, count);
+        ctx.db.timeout_strike().id().delete(strike_id);
+        ctx.db.bot_seat().insert(BotSeat {
+            player,
+            game_id,
+            reason: BotReason::Autopilot,
+            since: ctx.timestamp,
+        });
+        notify(ctx, player, "Autopilot is on after several missed moves".to_string());
+    }
+}
+
+#[reducer]
+/// Let the server play the simplest legal moves for you while you're away
+pub fn set_autopilot(ctx: &ReducerContext, enabled: bool) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    let game_id = user.current_game_id
+        .ok_or("You are not in a game")?;
+
+    if user.player_status != Some(PlayerStatus::Active) {
+        return Err("You are not playing in this round".to_string());
+    }
+
+    ctx.db.bot_seat().player().delete(caller(ctx));
+    if enabled {
+        ctx.db.bot_seat().insert(BotSeat {
+            player: caller(ctx),
+            game_id,
+            reason: BotReason::Autopilot,
+            since: ctx.timestamp,
+        });
+    }
+
+    after_game_action(ctx, game_id)
+}
+
+#[reducer]
+/// Let the server play for bot seats (scheduled)
+pub fn play_bot_move(ctx: &ReducerContext, bot_move: BotMove) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can move for bots".to_string());
+    }
+
+    if make_bot_move(ctx, bot_move.game_id)? {
+        after_game_action(ctx, bot_move.game_id)?;
+    }
+    Ok(())
+}
+
+// Turn Resolution Helpers
+
+/// Generate unique pass ID (one per attacker per turn)
+fn generate_turn_pass_id(turn_id: u64, player: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    turn_id.hash(&mut hasher);
+    player.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Players who may still throw cards into a turn, clockwise starting with the primary attacker
+fn get_eligible_attackers(ctx: &ReducerContext, game_id: u64, turn: &Turn) -> Result<Vec<Identity>, String> {
+    let settings = get_game_settings_for_game(ctx, game_id)?;
+    let defender = ctx.db.user().identity().find(turn.defender)
+        .ok_or("Defender not found")?;
+
+    let mut players: Vec<User> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id) && user.player_status == Some(PlayerStatus::Active))
+        .filter(|user| user.identity != turn.defender && !is_teammate(&defender, user))
+        .filter(|user| settings.anyone_can_attack || user.identity == turn.attacker)
+        .filter(|user| !get_player_cards(ctx, game_id, user.identity).is_empty())
+        .collect();
+
+    players.sort_by_key(|p| p.game_position.unwrap_or(0));
+
+    // Rotate so that the primary attacker comes first
+    if let Some(start) = players.iter().position(|p| p.identity == turn.attacker) {
+        players.rotate_left(start);
+    }
+
+    Ok(players.into_iter().map(|p| p.identity).collect())
+}
+
+/// Check whether every eligible attacker has passed on this turn
+fn all_attackers_passed(ctx: &ReducerContext, game_id: u64, turn: &Turn) -> Result<bool, String> {
+    let attackers = get_eligible_attackers(ctx, game_id, turn)?;
+    Ok(attackers.iter().all(|attacker| {
+        ctx.db.turn_pass().id().find(generate_turn_pass_id(turn.id, *attacker)).is_some()
+    }))
+}
+
+/// Forget all passes of a turn (a new card on the table reopens throwing in)
+fn clear_turn_passes(ctx: &ReducerContext, turn_id: u64) {
+    let passes: Vec<TurnPass> = ctx.db.turn_pass()
+        .iter()
+        .filter(|pass| pass.turn_id == turn_id)
+        .collect();
+
+    for pass in passes {
+        ctx.db.turn_pass().id().delete(pass.id);
+    }
+}
+
+/// Defender picks up the whole table and the game moves past them
+fn complete_take(ctx: &ReducerContext, game_id: u64, turn: &Turn) -> Result<(), String> {
+    // Move all cards on table to defender's hand
+    take_table(ctx, game_id, turn.id, turn.defender);
+    clear_hidden_defenses(ctx, turn.id);
+
+    // Finish turn - defender took cards
+    ctx.db.turn().id().update(Turn {
+        status: TurnStatus::DefenderTook,
+        finished_at: Some(ctx.timestamp),
+        ..turn.clone()
+    });
+
+    // Refill hands and start next turn
+    refill_hands(ctx, game_id, turn)?;
+    start_next_turn_after_take(ctx, game_id, turn.round_id)?;
+
+    log::info!("Player {:?} took all cards", turn.defender);
+    Ok(())
+}
+
+/// Resolve the turn in the defender's favor once nothing is pending and all attackers passed
+fn try_finish_turn(ctx: &ReducerContext, game_id: u64, turn: &Turn) -> Result<bool, String> {
+    if count_pending_draws(ctx, turn.id) > 0 {
+        return Ok(false);
+    }
+
+    if !all_attackers_passed(ctx, game_id, turn)? {
+        return Ok(false);
+    }
+
+    finish_turn_defender_won(ctx, game_id, turn.id)?;
+    Ok(true)
+}
+
+/// Move every card on the table into the taker's hand and mark the turn's draws as taken
+fn take_table(ctx: &ReducerContext, game_id: u64, turn_id: u64, taker: Identity) {
+    let draws: Vec<Draw> = ctx.db.draw()
+        .iter()
+        .filter(|draw| draw.turn_id == turn_id)
+        .collect();
+
+    for draw in draws {
+        ctx.db.draw().id().update(Draw {
+            status: DrawStatus::Taken,
+            ..draw
+        });
+    }
+
+    let table_cards: Vec<PlayerCard> = ctx.db.player_card()
+        .iter()
+        .filter(|pc| pc.game_id == game_id && matches!(pc.location, CardLocation::OnTable | CardLocation::FaceDown))
+        .collect();
+
+    for player_card in table_cards {
+        log_event(ctx, game_id, GameEventKind::TableTaken, EventDetail {
+            player: Some(taker),
+            card: Some(player_card.card.clone()),
+            ..Default::default()
+        });
+        put_card(ctx, PlayerCard {
+            player: taker,
+            location: CardLocation::Hand,
+            ..player_card
+        });
+    }
+}
+
+/// Drop any face-down defenses left on a finished turn
+fn clear_hidden_defenses(ctx: &ReducerContext, turn_id: u64) {
+    let hidden: Vec<HiddenDefense> = ctx.db.hidden_defense()
+        .iter()
+        .filter(|h| h.turn_id == turn_id)
+        .collect();
+
+    for h in hidden {
+        ctx.db.hidden_defense().draw_id().delete(h.draw_id);
+    }
+}
+
+/// Finish turn when defender successfully beat all attacks
+fn finish_turn_defender_won(ctx: &ReducerContext, game_id: u64, turn_id: u64) -> Result<(), String> {
+    let turn = ctx.db.turn().id().find(turn_id)
+        .ok_or("Turn not found")?;
+
+    // Update turn status
+    ctx.db.turn().id().update(Turn {
+        status: TurnStatus::DefenderBeat,
+        finished_at: Some(ctx.timestamp),
+        ..turn
+    });
+
+    clear_hidden_defenses(ctx, turn_id);
+
+    log_event(ctx, game_id, GameEventKind::TableDiscarded, EventDetail {
+        player: Some(turn.defender),
+        ..Default::default()
+    });
+
+    let beaten = ctx.db.draw()
+        .iter()
+        .filter(|draw| draw.turn_id == turn_id && draw.status == DrawStatus::Beaten)
+        .count() as u32;
+    if beaten >= PERFECT_DEFENSE_ATTACKS {
+        record_highlight(ctx, game_id, &turn, turn.defender, HighlightKind::PerfectDefense, beaten);
+    }
+
+    // Move all cards on table to discard pile
+    let table_cards: Vec<PlayerCard> = ctx.db.player_card()
+        .iter()
+        .filter(|pc| pc.game_id == game_id && matches!(pc.location, CardLocation::OnTable | CardLocation::FaceDown))
+        .collect();
+
+    for player_card in table_cards {
+        put_card(ctx, PlayerCard {
+            location: CardLocation::Discarded,
+            ..player_card
+        });
+    }
+
+    // Refill hands
+    refill_hands(ctx, game_id, &turn)?;
+
+    // Check if round ended (someone emptied their hand)
+    if check_round_end(ctx, game_id, turn.round_id)? {
+        return Ok(());
+    }
+
+    // Start next turn with defender as new attacker
+    start_next_turn_after_defense(ctx, game_id, turn.round_id, turn.defender)?;
+
+    Ok(())
+}
+
+/// Start next turn after defender took cards (skips defender)
+fn start_next_turn_after_take(ctx: &ReducerContext, game_id: u64, round_id: u64) -> Result<(), String> {
+    let _game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    let last_turn = ctx.db.turn()
+        .iter()
+        .filter(|t| t.round_id == round_id)
+        .max_by_key(|t| t.turn_number)
+        .ok_or("No previous turn found")?;
+
+    // Check if round ended
+    if check_round_end(ctx, game_id, round_id)? {
+        return Ok(());
+    }
+
+    // Next attacker is the player after the defender (clockwise)
+    let next_attacker = get_next_player_clockwise(ctx, game_id, last_turn.defender)?;
+    let next_defender = get_next_opponent_clockwise(ctx, game_id, next_attacker)?;
+
+    // Advertise the next turn right away so clients know who leads
+    create_turn(ctx, game_id, round_id, next_attacker, next_defender);
+    log::info!("Next turn: {:?} can attack {:?}", next_attacker, next_defender);
+    Ok(())
+}
+
+/// Start next turn after successful defense (defender becomes attacker)
+fn start_next_turn_after_defense(ctx: &ReducerContext, game_id: u64, round_id: u64, new_attacker: Identity) -> Result<(), String> {
+    // Check if round ended
+    if check_round_end(ctx, game_id, round_id)? {
+        return Ok(());
+    }
+
+    let new_defender = get_next_opponent_clockwise(ctx, game_id, new_attacker)?;
+
+    // Advertise the next turn right away so clients know who leads
+    create_turn(ctx, game_id, round_id, new_attacker, new_defender);
+    log::info!("Next turn: {:?} can attack {:?}", new_attacker, new_defender);
+    Ok(())
+}
+
+/// Create an empty active turn; the attacker leads it with the first card
+fn create_turn(ctx: &ReducerContext, game_id: u64, round_id: u64, attacker: Identity, defender: Identity) -> Turn {
+    let turn_number = ctx.db.turn()
+        .iter()
+        .filter(|t| t.round_id == round_id)
+        .count() as u32 + 1;
+
+    ctx.db.turn().insert(Turn {
+        id: generate_turn_id(round_id, turn_number),
+        game_id,
+        round_id,
+        turn_number,
+        attacker,
+        lead_attacker: attacker,
+        defender,
+        status: TurnStatus::Active,
+        transfers: 0,
+        started_at: ctx.timestamp,
+        finished_at: None,
+    })
+}
+
+/// Get next active player in clockwise order
+fn get_next_player_clockwise(ctx: &ReducerContext, game_id: u64, current_player: Identity) -> Result<Identity, String> {
+    let current_user = ctx.db.user().identity().find(current_player)
+        .ok_or("Current player not found")?;
+    
+    let _current_position = current_user.game_position
+        .ok_or("Player has no game position")?;
+
+    let game_players: Vec<User> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id) && user.player_status == Some(PlayerStatus::Active))
+        .collect();
+
+    if game_players.len() < 2 {
+        return Err("Not enough active players".to_string());
+    }
+
+    // Sort by position and find next active player
+    let mut sorted_players = game_players;
+    sorted_players.sort_by_key(|p| p.game_position.unwrap_or(0));
+
+    let current_index = sorted_players.iter()
+        .position(|p| p.identity == current_player)
+        .ok_or("Current player not found in game")?;
+
+    let next_index = (current_index + 1) % sorted_players.len();
+    Ok(sorted_players[next_index].identity)
+}
+
+/// Get previous active player clockwise (the player to the right) who is not a teammate
+fn get_previous_opponent_clockwise(ctx: &ReducerContext, game_id: u64, current_player: Identity) -> Result<Identity, String> {
+    let current_user = ctx.db.user().identity().find(current_player)
+        .ok_or("Current player not found")?;
+
+    let mut sorted_players: Vec<User> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id) && user.player_status == Some(PlayerStatus::Active))
+        .collect();
+
+    sorted_players.sort_by_key(|p| p.game_position.unwrap_or(0));
+
+    let current_index = sorted_players.iter()
+        .position(|p| p.identity == current_player)
+        .ok_or("Current player not found in game")?;
+
+    // Walk counter-clockwise until we reach an opponent
+    let len = sorted_players.len();
+    (1..len)
+        .map(|step| &sorted_players[(current_index + len - step) % len])
+        .find(|p| !is_teammate(&current_user, p))
+        .map(|p| p.identity)
+        .ok_or_else(|| "No active opponent left".to_string())
+}
+
+/// Check if two players are on the same team (team mode only)
+fn is_teammate(a: &User, b: &User) -> bool {
+    a.team.is_some() && a.team == b.team
+}
+
+/// Get next active player clockwise who is not on the given player's team
+fn get_next_opponent_clockwise(ctx: &ReducerContext, game_id: u64, current_player: Identity) -> Result<Identity, String> {
+    let current_user = ctx.db.user().identity().find(current_player)
+        .ok_or("Current player not found")?;
+
+    let mut candidate = get_next_player_clockwise(ctx, game_id, current_player)?;
+    while candidate != current_player {
+        let candidate_user = ctx.db.user().identity().find(candidate)
+            .ok_or("Player not found")?;
+        if !is_teammate(&current_user, &candidate_user) {
+            return Ok(candidate);
+        }
+        candidate = get_next_player_clockwise(ctx, game_id, candidate)?;
+    }
+
+    Err("No active opponent left".to_string())
+}
+
+/// Refill all players' hands from deck
+fn refill_hands(ctx: &ReducerContext, game_id: u64, turn: &Turn) -> Result<(), String> {
+    let settings = get_game_settings_for_game(ctx, game_id)?;
+    let target_hand_size = settings.starting_cards as usize;
+
+    // Get all active players sorted by position
+    let mut players: Vec<User> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id) && user.player_status == Some(PlayerStatus::Active))
+        .collect();
+    
+    players.sort_by_key(|p| p.game_position.unwrap_or(0));
+
+    if settings.refill_order == RefillOrder::Traditional {
+        // Start from the player who opened the turn and go clockwise, the defender draws last
+        if let Some(start) = players.iter().position(|p| p.identity == turn.lead_attacker) {
+            players.rotate_left(start);
+        }
+        if let Some(defender_index) = players.iter().position(|p| p.identity == turn.defender) {
+            let defender = players.remove(defender_index);
+            players.push(defender);
+        }
+    }
+
+    // Get deck cards in dealing order, the next one to draw last so it pops first
+    let mut deck_cards: Vec<PlayerCard> = ctx.db.player_card()
+        .iter()
+        .filter(|pc| pc.game_id == game_id && pc.location == CardLocation::Deck)
+        .collect();
+    deck_cards.sort_by_key(|pc| std::cmp::Reverse(pc.deck_position));
+
+    // The exposed trump card is drawn last, unless it stays out of play
+    if settings.trump_card_to_player {
+        if let Some(trump_card) = ctx.db.player_card()
+            .iter()
+            .find(|pc| pc.game_id == game_id && pc.location == CardLocation::TrumpCard)
+        {
+            deck_cards.insert(0, trump_card);
+        }
+    }
+
+    // Refill hands in the chosen order
+    for player in players {
+        let current_hand_size = get_player_cards(ctx, game_id, player.identity).len();
+        let cards_needed = target_hand_size.saturating_sub(current_hand_size);
+
+        for _ in 0..cards_needed {
+            if let Some(deck_card) = deck_cards.pop() {
+                log_event(ctx, game_id, GameEventKind::Refill, EventDetail {
+                    player: Some(player.identity),
+                    card: Some(deck_card.card.clone()),
+                    ..Default::default()
+                });
+                put_card(ctx, PlayerCard {
+                    player: player.identity,
+                    location: CardLocation::Hand,
+                    ..deck_card
+                });
+            } else {
+                // No more cards in deck
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Highlights
+
+/// Attacks beaten in one turn that make a perfect defense
+const PERFECT_DEFENSE_ATTACKS: u32 = 6;
+
+fn generate_highlight_id(turn_id: u64, player: Identity, kind: HighlightKind) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    "highlight".hash(&mut hasher);
+    turn_id.hash(&mut hasher);
+    player.hash(&mut hasher);
+    (kind as u8).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Note a notable moment of a turn, once per player and kind
+fn record_highlight(ctx: &ReducerContext, game_id: u64, turn: &Turn, player: Identity, kind: HighlightKind, value: u32) {
+    let id = generate_highlight_id(turn.id, player, kind);
+    if ctx.db.highlight().id().find(id).is_some() {
+        return;
+    }
+
+    let round_number = ctx.db.round().id().find(turn.round_id)
+        .map_or(0, |round| round.round_number);
+
+    ctx.db.highlight().insert(Highlight {
+        id,
+        game_id,
+        round_number,
+        turn_id: turn.id,
+        player,
+        kind,
+        value,
+        happened_at: ctx.timestamp,
+    });
+    log::info!("Highlight in game {}: {:?} by {:?}", game_id, kind, player);
+}
+
+/// Highlights of a player going out of the round on its latest turn
+fn record_exit_highlights(ctx: &ReducerContext, game_id: u64, round_id: u64, player: Identity) {
+    let Some(turn) = ctx.db.turn()
+        .iter()
+        .filter(|turn| turn.round_id == round_id)
+        .max_by_key(|turn| turn.turn_number)
+    else {
+        return;
+    };
+
+    if turn.turn_number == 1 {
+        record_highlight(ctx, game_id, &turn, player, HighlightKind::FirstTurnOut, 1);
+    }
+
+    // Whoever drew the last card usually holds the most to get rid of
+    let events: Vec<GameEvent> = ctx.db.game_event().game_id().filter(game_id).collect();
+    let round_start = events.iter()
+        .filter(|event| event.kind == GameEventKind::RoundStarted)
+        .map(|event| event.seq)
+        .max()
+        .unwrap_or(0);
+    let last_refill = events.iter()
+        .filter(|event| event.kind == GameEventKind::Refill && event.seq > round_start)
+        .max_by_key(|event| event.seq);
+    // The exposed trump card only counts when it is dealt out last
+    let trump_drawn = get_game_settings_for_game(ctx, game_id).map_or(false, |settings| settings.trump_card_to_player);
+    let deck_empty = !ctx.db.player_card()
+        .iter()
+        .any(|pc| pc.game_id == game_id
+            && (pc.location == CardLocation::Deck || (trump_drawn && pc.location == CardLocation::TrumpCard)));
+
+    if deck_empty && last_refill.is_some_and(|event| event.player == Some(player)) {
+        record_highlight(ctx, game_id, &turn, player, HighlightKind::LastCardOut, 1);
+    }
+}
+
+/// Check if round has ended (only one player with cards)
+fn check_round_end(ctx: &ReducerContext, game_id: u64, round_id: u64) -> Result<bool, String> {
+    let players: Vec<User> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id) && user.player_status == Some(PlayerStatus::Active))
+        .collect();
+
+    let mut players_with_cards = Vec::new();
+
+    for player in players {
+        let hand_size = get_player_cards(ctx, game_id, player.identity).len();
+        if hand_size > 0 {
+            players_with_cards.push(player);
+        } else {
+            // Player finished this round
+            record_exit_highlights(ctx, game_id, round_id, player.identity);
+            ctx.db.user().identity().update(User {
+                player_status: Some(PlayerStatus::Finished),
+                ..player
+            });
+        }
+    }
+
+    // In team mode the round also ends once only one team still holds cards
+    let one_team_left = players_with_cards.first().map_or(false, |first| {
+        players_with_cards.iter().all(|p| is_teammate(first, p))
+    });
+
+    if players_with_cards.len() <= 1 || one_team_left {
+        // Round ended; everyone emptied their hand on the same turn - the round is a draw
+        let loser = players_with_cards.first().map(|p| p.identity);
+        end_round(ctx, game_id, round_id, loser)?;
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// Close a round with the given fool (None for a draw), score it and move on
+fn end_round(ctx: &ReducerContext, game_id: u64, round_id: u64, loser: Option<Identity>) -> Result<(), String> {
+    let round = ctx.db.round().id().find(round_id)
+        .ok_or("Round not found")?;
+
+    let losing_team = match loser {
+        Some(loser) => ctx.db.user().identity().find(loser).and_then(|user| user.team),
+        None => None,
+    };
+    let epaulettes = match loser {
+        Some(loser) => has_epaulettes(ctx, game_id, loser)?,
+        None => false,
+    };
+
+    let status = if loser.is_some() { RoundStatus::Finished } else { RoundStatus::Drawn };
+
+    ctx.db.round().id().update(Round {
+        status,
+        loser,
+        losing_team,
+        epaulettes,
+        finished_at: Some(ctx.timestamp),
+        ..round
+    });
+    record_round_stats(ctx, round_id, loser);
+
+    log_event(ctx, game_id, GameEventKind::RoundEnded, EventDetail {
+        player: loser,
+        ..Default::default()
+    });
+
+    // Handle scoring and check if game ended; a draw has no loser to score
+    handle_round_scoring(ctx, game_id, round_id, loser, epaulettes)?;
+
+    log::info!("Round {} ended, loser: {:?}, epaulettes: {}", round.round_number, loser, epaulettes);
+    Ok(())
+}
+
+/// Check if the loser is left holding a pair of the lowest rank or the two lowest trumps
+fn has_epaulettes(ctx: &ReducerContext, game_id: u64, loser: Identity) -> Result<bool, String> {
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+    let settings = get_game_settings_for_game(ctx, game_id)?;
+
+    let (lowest, second_lowest) = match settings.deck_size {
+        DeckSize::Standard36 => (Rank::Six, Rank::Seven),
+        DeckSize::Extended52 => (Rank::Two, Rank::Three),
+    };
+
+    let hand = get_player_cards(ctx, game_id, loser);
+
+    let lowest_pair = hand.iter().filter(|pc| pc.card.rank == lowest).count() >= 2;
+    let lowest_trumps = game.trump_suit.map_or(false, |trump| {
+        let holds = |rank: Rank| hand.iter().any(|pc| pc.card == Card { suit: trump, rank });
+        holds(lowest) && holds(second_lowest)
+    });
+
+    Ok(lowest_pair || lowest_trumps)
+}
+
+/// Points the loser of a round takes under the given scoring mode
+fn round_penalty(ctx: &ReducerContext, game_id: u64, loser: Identity, scoring_mode: ScoringMode) -> u8 {
+    let hand = get_player_cards(ctx, game_id, loser);
+
+    match scoring_mode {
+        ScoringMode::Flat => 5, // 5 points for losing a round
+        ScoringMode::PerCard => hand.len().min(u8::MAX as usize) as u8,
+        ScoringMode::CardValue => hand.iter()
+            .fold(0u8, |total, pc| total.saturating_add(pc.card.rank as u8)),
+    }
+}
+
+/// Handle scoring after round ends
+fn handle_round_scoring(ctx: &ReducerContext, game_id: u64, round_id: u64, loser: Option<Identity>, epaulettes: bool) -> Result<(), String> {
+    let settings = get_game_settings_for_game(ctx, game_id)?;
+
+    if !settings.multi_round_mode {
+        // Single round mode - game ends here
+        finish_game(ctx, game_id, loser)?;
+        return Ok(());
+    }
+
+    if settings.knockout_mode {
+        return handle_knockout(ctx, game_id, loser);
+    }
+
+    // Multi-round mode - add points and check if game should end (a drawn round scores nothing)
+    if let Some(loser_identity) = loser {
+        let loser_user = ctx.db.user().identity().find(loser_identity)
+            .ok_or("Loser not found")?;
+
+        // In team mode the whole losing team takes the points
+        let losers: Vec<User> = ctx.db.user()
+            .iter()
+            .filter(|user| user.current_game_id == Some(game_id) &&
+                           (user.identity == loser_identity || is_teammate(&loser_user, user)))
+            .collect();
+
+        let mut penalty = round_penalty(ctx, game_id, loser_identity, settings.scoring_mode);
+        if epaulettes {
+            penalty = penalty.saturating_add(5); // 5 more for losing with epaulettes
+        }
+
+        let mut reached_max = false;
+        let mut penalized = Vec::new();
+        for loser_user in losers {
+            let new_points = loser_user.total_points.unwrap_or(0).saturating_add(penalty);
+            reached_max |= new_points >= settings.max_points;
+            penalized.push(loser_user.identity);
+
+            ctx.db.user().identity().update(User {
+                total_points: Some(new_points),
+                ..loser_user
+            });
+        }
+        record_round_scores(ctx, game_id, round_id, &penalized, penalty);
+
+        // Check if player reached max points (becomes the "Fool")
+        if reached_max {
+            finish_game(ctx, game_id, Some(loser_identity))?;
+            return Ok(());
+        }
     } else {
-        Ok(name)
+        record_round_scores(ctx, game_id, round_id, &[], 0);
+    }
+
+    // Start new round
+    start_new_round(ctx, game_id, loser)?;
+    Ok(())
+}
+
+fn generate_round_score_id(round_id: u64, player: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    "round_score".hash(&mut hasher);
+    round_id.hash(&mut hasher);
+    player.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Add a round to the score sheet for everyone still at the table; no losers means a drawn round
+fn record_round_scores(ctx: &ReducerContext, game_id: u64, round_id: u64, losers: &[Identity], penalty: u8) {
+    let round_number = ctx.db.round().id().find(round_id)
+        .map_or(0, |round| round.round_number);
+
+    let players: Vec<User> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id) && user.player_status != Some(PlayerStatus::Left))
+        .collect();
+
+    for player in players {
+        let lost = losers.contains(&player.identity);
+        let outcome = match (losers.is_empty(), lost) {
+            (true, _) => RoundOutcome::Drawn,
+            (false, true) => RoundOutcome::Lost,
+            (false, false) => RoundOutcome::Escaped,
+        };
+
+        ctx.db.round_score().insert(RoundScore {
+            id: generate_round_score_id(round_id, player.identity),
+            game_id,
+            round_id,
+            round_number,
+            player: player.identity,
+            outcome,
+            points_delta: if lost { penalty } else { 0 },
+            total_points: player.total_points.unwrap_or(0),
+        });
+    }
+}
+
+/// Knockout mode: eliminate the round's fool, the loser of the final two-player round loses the game
+fn handle_knockout(ctx: &ReducerContext, game_id: u64, loser: Option<Identity>) -> Result<(), String> {
+    let loser_identity = match loser {
+        Some(loser) => loser,
+        // A drawn round is replayed by the same players
+        None => return start_new_round(ctx, game_id, None),
+    };
+
+    let loser_user = ctx.db.user().identity().find(loser_identity)
+        .ok_or("Loser not found")?;
+
+    let in_contention = |user: &User| {
+        user.current_game_id == Some(game_id) &&
+        matches!(user.player_status, Some(PlayerStatus::Active) | Some(PlayerStatus::Finished))
+    };
+
+    let remaining: Vec<User> = ctx.db.user()
+        .iter()
+        .filter(|user| in_contention(user))
+        .collect();
+
+    // The final has been played
+    if remaining.len() <= 2 {
+        finish_game(ctx, game_id, Some(loser_identity))?;
+        return Ok(());
+    }
+
+    // Eliminate the fool (and their teammates in team mode)
+    let mut survivors = 0;
+    for user in remaining {
+        if user.identity == loser_identity || is_teammate(&loser_user, &user) {
+            ctx.db.user().identity().update(User {
+                player_status: Some(PlayerStatus::Eliminated),
+                ..user
+            });
+        } else {
+            survivors += 1;
+        }
+    }
+
+    log::info!("Player {:?} eliminated from game {}", loser_identity, game_id);
+
+    if survivors < 2 {
+        finish_game(ctx, game_id, Some(loser_identity))?;
+        return Ok(());
+    }
+
+    start_new_round(ctx, game_id, None)
+}
+
+/// Start a new round, with the previous round's loser defending the opening attack
+fn start_new_round(ctx: &ReducerContext, game_id: u64, previous_loser: Option<Identity>) -> Result<(), String> {
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    let new_round_number = game.current_round + 1;
+    let round_id = generate_round_id(game_id, new_round_number);
+
+    // Reset all players to active (eliminated and departed players stay out)
+    let players: Vec<User> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id))
+        .filter(|user| !matches!(user.player_status, Some(PlayerStatus::Eliminated) | Some(PlayerStatus::Left)))
+        .collect();
+
+    for player in players {
+        ctx.db.user().identity().update(User {
+            player_status: Some(PlayerStatus::Active),
+            ..player
+        });
+    }
+
+    // Redeal a fresh deck to the remaining players in seat order
+    let settings = get_game_settings_for_game(ctx, game_id)?;
+    let mut seated: Vec<(u8, Identity)> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id))
+        .filter(|user| user.player_status == Some(PlayerStatus::Active))
+        .map(|user| (user.game_position.unwrap_or(0), user.identity))
+        .collect();
+    seated.sort_by_key(|(position, _)| *position);
+    let seats: Vec<Identity> = seated.into_iter().map(|(_, identity)| identity).collect();
+
+    if seats.len() < 2 {
+        return Err("Not enough players for a new round".to_string());
+    }
+
+    let trump_card_exposed = is_trump_card_exposed(&settings, new_round_number);
+    let bottom_card = deal_cards(ctx, game_id, new_round_number, &settings, &seats, trump_card_exposed)?;
+    let trump_suit = choose_trump(ctx, &settings, game.trump_suit, &bottom_card);
+
+    // The loser is attacked by the player to their right, otherwise the lowest trump opens
+    let (first_attacker, first_defender) = match previous_loser {
+        Some(loser) if seats.contains(&loser) => (get_previous_opponent_clockwise(ctx, game_id, loser)?, loser),
+        _ => {
+            let attacker = find_first_attacker(ctx, game_id, trump_suit, &seats);
+            (attacker, get_next_opponent_clockwise(ctx, game_id, attacker)?)
+        }
+    };
+
+    // Create new round
+    ctx.db.round().insert(Round {
+        id: round_id,
+        game_id,
+        round_number: new_round_number,
+        status: RoundStatus::Active,
+        loser: None,
+        losing_team: None,
+        first_attacker: Some(first_attacker),
+        first_defender: Some(first_defender),
+        epaulettes: false,
+        started_at: ctx.timestamp,
+        finished_at: None,
+    });
+
+    // Announce the opening turn
+    create_turn(ctx, game_id, round_id, first_attacker, first_defender);
+    log_round_start(ctx, game_id, first_attacker, first_defender, trump_suit);
+    arm_round_time_limit(ctx, game_id, round_id, &settings);
+
+    // Update game
+    ctx.db.game().id().update(Game {
+        current_round: new_round_number,
+        trump_suit,
+        ..game
+    });
+
+    log::info!("Started new round {} for game {}", new_round_number, game_id);
+    Ok(())
+}
+
+/// Tally each player's game for the results screen
+fn write_game_summaries(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    let duration_secs = ctx.timestamp.duration_since(game.started_at)
+        .map_or(0, |duration| duration.as_secs());
+
+    let rounds: Vec<Round> = ctx.db.round()
+        .iter()
+        .filter(|round| round.game_id == game_id)
+        .collect();
+
+    let mut turns: Vec<Turn> = ctx.db.turn()
+        .iter()
+        .filter(|turn| rounds.iter().any(|round| round.id == turn.round_id))
+        .filter(|turn| matches!(turn.status, TurnStatus::DefenderBeat | TurnStatus::DefenderTook))
+        .collect();
+    turns.sort_by_key(|turn| turn.started_at);
+
+    let players: Vec<User> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id))
+        .collect();
+
+    for player in players {
+        let rounds_lost = rounds.iter()
+            .filter(|round| round.status == RoundStatus::Finished && round.loser == Some(player.identity))
+            .count() as u32;
+
+        let cards_taken = ctx.db.game_event()
+            .game_id()
+            .filter(game_id)
+            .filter(|event| event.kind == GameEventKind::TableTaken && event.player == Some(player.identity))
+            .count() as u32;
+
+        let mut streak = 0;
+        let mut longest_defense_streak = 0;
+        for turn in turns.iter().filter(|turn| turn.defender == player.identity) {
+            if turn.status == TurnStatus::DefenderBeat {
+                streak += 1;
+                longest_defense_streak = longest_defense_streak.max(streak);
+            } else {
+                streak = 0;
+            }
+        }
+
+        let highlights = ctx.db.highlight()
+            .game_id()
+            .filter(game_id)
+            .filter(|highlight| highlight.player == player.identity)
+            .count() as u32;
+
+        ctx.db.game_summary().insert(GameSummary {
+            id: generate_participant_id(game_id, player.identity),
+            game_id,
+            player: player.identity,
+            final_points: player.total_points.unwrap_or(0),
+            rounds_lost,
+            cards_taken,
+            longest_defense_streak,
+            highlights,
+            duration_secs,
+        });
+    }
+
+    Ok(())
+}
+
+/// Add the game to every participant's history
+fn write_game_history(ctx: &ReducerContext, game_id: u64, final_loser: Option<Identity>) -> Result<(), String> {
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+    let rules = get_game_settings_for_game(ctx, game_id)?;
+    let variant = variant_fingerprint(&rules);
+
+    let duration_secs = ctx.timestamp.duration_since(game.started_at)
+        .map_or(0, |duration| duration.as_secs());
+
+    let players: Vec<User> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id))
+        .collect();
+
+    let loser = final_loser.and_then(|loser| players.iter().find(|p| p.identity == loser));
+
+    for player in &players {
+        let result = match (player.player_status, loser) {
+            (Some(PlayerStatus::Left), _) => GameResult::Left,
+            (Some(PlayerStatus::Eliminated), _) => GameResult::Lost,
+            (_, None) => GameResult::Drawn,
+            (_, Some(loser)) if loser.identity == player.identity || is_teammate(loser, player) => GameResult::Lost,
+            _ => GameResult::Won,
+        };
+
+        ctx.db.game_history().insert(GameHistory {
+            id: generate_participant_id(game_id, player.identity),
+            player: player.identity,
+            game_id,
+            opponents: players.iter()
+                .filter(|other| other.identity != player.identity)
+                .map(|other| other.identity)
+                .collect(),
+            result,
+            rules: rules.clone(),
+            variant,
+            duration_secs,
+            finished_at: ctx.timestamp,
+            expected_score: None,
+            actual_score: None,
+        });
+        add_clan_game_result(ctx, player.identity, result);
+        record_game_stats(ctx, player.identity, result, duration_secs);
+        record_variant_stats(ctx, player.identity, variant, result, duration_secs);
+        add_xp(ctx, player.identity, game_xp(result, &rules, players.len() - 1));
+        grant_game_titles(ctx, game_id, player.identity);
+        if result != GameResult::Left {
+            advance_quests(ctx, player.identity, QuestKind::PlayGames, 1);
+        }
+        if result == GameResult::Won {
+            advance_quests(ctx, player.identity, QuestKind::WinGames, 1);
+            if players.len() >= 4 {
+                advance_quests(ctx, player.identity, QuestKind::WinFourPlayerGame, 1);
+            }
+        }
+        sync_profile_view(ctx, player.identity);
+    }
+
+    Ok(())
+}
+
+// Hall of Fame
+
+/// Put a feat in the hall of fame if it beats the standing record
+fn record_feat(ctx: &ReducerContext, record: FameRecord, value: u64, game_id: Option<u64>, player: Option<Identity>) {
+    let id = record as u8;
+    if value == 0 || ctx.db.hall_of_fame().id().find(id).is_some_and(|best| best.value >= value) {
+        return;
+    }
+
+    ctx.db.hall_of_fame().id().delete(id);
+    ctx.db.hall_of_fame().insert(HallOfFame {
+        id,
+        record,
+        value,
+        game_id,
+        player,
+        set_at: ctx.timestamp,
+    });
+
+    if let Some(player) = player {
+        notify(ctx, player, format!("You set a new record: {:?}", record));
+    }
+    log::info!("New {:?} record: {}", record, value);
+}
+
+/// Check a finished game against the game records
+fn record_game_feats(ctx: &ReducerContext, game_id: u64) {
+    let Some(game) = ctx.db.game().id().find(game_id) else { return };
+    let duration_secs = ctx.timestamp.duration_since(game.started_at)
+        .map_or(0, |duration| duration.as_secs());
+    record_feat(ctx, FameRecord::LongestGame, duration_secs, Some(game.id), None);
+    record_feat(ctx, FameRecord::MostRounds, game.current_round as u64, Some(game.id), None);
+
+    // A comeback is how far a winner trailed the round's leader on the score sheet
+    let scores: Vec<RoundScore> = ctx.db.round_score().game_id().filter(game.id).collect();
+    let winners: Vec<Identity> = ctx.db.game_history()
+        .game_id()
+        .filter(game.id)
+        .filter(|entry| entry.result == GameResult::Won)
+        .map(|entry| entry.player)
+        .collect();
+
+    let comeback = scores.iter()
+        .filter(|score| winners.contains(&score.player))
+        .filter_map(|score| {
+            let leader = scores.iter()
+                .filter(|other| other.round_id == score.round_id)
+                .map(|other| other.total_points)
+                .min()?;
+            Some((score.player, score.total_points.saturating_sub(leader)))
+        })
+        .max_by_key(|(_, deficit)| *deficit);
+
+    if let Some((player, deficit)) = comeback {
+        record_feat(ctx, FameRecord::BiggestComeback, deficit as u64, Some(game.id), Some(player));
+    }
+}
+
+// Server Statistics
+
+const SERVER_STATS_ID: u8 = 0;
+
+/// Change the server stats row, creating it on first use
+fn update_server_stats(ctx: &ReducerContext, change: impl FnOnce(&mut ServerStats)) {
+    let mut stats = ctx.db.server_stats().id().find(SERVER_STATS_ID).unwrap_or(ServerStats {
+        id: SERVER_STATS_ID,
+        day: current_day(ctx.timestamp),
+        games_today: 0,
+        active_games: 0,
+        online_players: 0,
+        popular_variant: None,
+        popular_variant_games: 0,
+        updated_at: ctx.timestamp,
+    });
+    change(&mut stats);
+
+    ctx.db.server_stats().id().delete(SERVER_STATS_ID);
+    ctx.db.server_stats().insert(ServerStats { updated_at: ctx.timestamp, ..stats });
+}
+
+fn generate_variant_popularity_id(day: i64, variant: VariantFingerprint) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    "variant_popularity".hash(&mut hasher);
+    day.hash(&mut hasher);
+    variant.perevodnoy.hash(&mut hasher);
+    variant.team_mode.hash(&mut hasher);
+    variant.no_trump.hash(&mut hasher);
+    (variant.deck_size as u8).hash(&mut hasher);
+    hasher.finish()
+}
+
+fn start_server_stats_day(stats: &mut ServerStats, day: i64) {
+    stats.day = day;
+    stats.games_today = 0;
+    stats.popular_variant = None;
+    stats.popular_variant_games = 0;
+}
+
+/// Count a newly started game: today's games, running games and the popularity of its variant
+fn count_game_started(ctx: &ReducerContext, settings: &GameSettings) {
+    let day = current_day(ctx.timestamp);
+    let variant = variant_fingerprint(settings);
+
+    // Yesterday's counts are dropped with the first game of a new day
+    let stale: Vec<u64> = ctx.db.variant_popularity()
+        .iter()
+        .filter(|popularity| popularity.day != day)
+        .map(|popularity| popularity.id)
+        .collect();
+    for id in stale {
+        ctx.db.variant_popularity().id().delete(id);
+    }
+
+    let id = generate_variant_popularity_id(day, variant);
+    let games = ctx.db.variant_popularity().id().find(id).map_or(0, |popularity| popularity.games) + 1;
+    ctx.db.variant_popularity().id().delete(id);
+    ctx.db.variant_popularity().insert(VariantPopularity { id, day, variant, games });
+
+    update_server_stats(ctx, |stats| {
+        if stats.day != day {
+            start_server_stats_day(stats, day);
+        }
+        stats.games_today += 1;
+        stats.active_games += 1;
+        if games > stats.popular_variant_games {
+            stats.popular_variant = Some(variant);
+            stats.popular_variant_games = games;
+        }
+    });
+}
+
+// Player Statistics
+
+/// A player's stats, all zero if they have none yet
+fn get_player_stats(ctx: &ReducerContext, player: Identity) -> PlayerStats {
+    ctx.db.player_stats().identity().find(player).unwrap_or(PlayerStats {
+        identity: player,
+        games_played: 0,
+        wins: 0,
+        fool_count: 0,
+        cards_beaten: 0,
+        total_game_secs: 0,
+        average_game_secs: 0,
+        fool_streak: 0,
+        longest_fool_streak: 0,
+        updated_at: ctx.timestamp,
+    })
+}
+
+fn save_player_stats(ctx: &ReducerContext, stats: PlayerStats) {
+    ctx.db.player_stats().identity().delete(stats.identity);
+    ctx.db.player_stats().insert(PlayerStats {
+        average_game_secs: stats.total_game_secs / stats.games_played.max(1) as u64,
+        updated_at: ctx.timestamp,
+        ..stats
+    });
+}
+
+/// Count a finished game into a player's stats
+fn record_game_stats(ctx: &ReducerContext, player: Identity, result: GameResult, duration_secs: u64) {
+    let stats = get_player_stats(ctx, player);
+    let fool_streak = if result == GameResult::Lost { stats.fool_streak + 1 } else { 0 };
+    save_player_stats(ctx, PlayerStats {
+        games_played: stats.games_played + 1,
+        wins: stats.wins + (result == GameResult::Won) as u32,
+        total_game_secs: stats.total_game_secs + duration_secs,
+        fool_streak,
+        longest_fool_streak: stats.longest_fool_streak.max(fool_streak),
+        ..stats
+    });
+    record_feat(ctx, FameRecord::LongestFoolStreak, fool_streak as u64, None, Some(player));
+}
+
+fn variant_fingerprint(rules: &GameSettings) -> VariantFingerprint {
+    VariantFingerprint {
+        perevodnoy: rules.perevodnoy,
+        team_mode: rules.team_mode,
+        no_trump: rules.no_trump,
+        deck_size: rules.deck_size,
+    }
+}
+
+fn generate_variant_stats_id(player: Identity, variant: VariantFingerprint) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    "variant_stats".hash(&mut hasher);
+    player.hash(&mut hasher);
+    variant.perevodnoy.hash(&mut hasher);
+    variant.team_mode.hash(&mut hasher);
+    variant.no_trump.hash(&mut hasher);
+    (variant.deck_size as u8).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Count a finished game into a player's record for its variant
+fn record_variant_stats(ctx: &ReducerContext, player: Identity, variant: VariantFingerprint, result: GameResult, duration_secs: u64) {
+    let id = generate_variant_stats_id(player, variant);
+    let stats = ctx.db.variant_stats().id().find(id).unwrap_or(VariantStats {
+        id,
+        player,
+        variant,
+        games_played: 0,
+        wins: 0,
+        losses: 0,
+        total_game_secs: 0,
+    });
+
+    ctx.db.variant_stats().id().delete(id);
+    ctx.db.variant_stats().insert(VariantStats {
+        games_played: stats.games_played + 1,
+        wins: stats.wins + (result == GameResult::Won) as u32,
+        losses: stats.losses + (result == GameResult::Lost) as u32,
+        total_game_secs: stats.total_game_secs + duration_secs,
+        ..stats
+    });
+}
+
+/// Count a finished round: the loser was the fool, and every defender gets the cards they beat
+fn record_round_stats(ctx: &ReducerContext, round_id: u64, loser: Option<Identity>) {
+    if let Some(loser) = loser {
+        let stats = get_player_stats(ctx, loser);
+        save_player_stats(ctx, PlayerStats { fool_count: stats.fool_count + 1, ..stats });
+    }
+
+    let turns: Vec<Turn> = ctx.db.turn()
+        .iter()
+        .filter(|turn| turn.round_id == round_id)
+        .collect();
+    for turn in turns {
+        let beaten = ctx.db.draw()
+            .iter()
+            .filter(|draw| draw.turn_id == turn.id && draw.status == DrawStatus::Beaten)
+            .count() as u32;
+        if beaten > 0 {
+            let stats = get_player_stats(ctx, turn.defender);
+            save_player_stats(ctx, PlayerStats { cards_beaten: stats.cards_beaten + beaten, ..stats });
+        }
+    }
+}
+
+// Ratings
+
+const INITIAL_RATING: f64 = 1500.0;
+const INITIAL_DEVIATION: f64 = 350.0;
+const MIN_DEVIATION: f64 = 30.0;
+
+/// Ranked games a new player plays in each pool before showing up on the ladder
+const PLACEMENT_GAMES: u32 = 5;
+
+/// Placement games move the rating this much faster
+const PLACEMENT_SPEEDUP: f64 = 1.5;
+
+/// Deviation regained per day without games; after about a year away a rating is as uncertain as a new one
+const DEVIATION_GROWTH_PER_DAY: f64 = 18.0;
+
+fn generate_rating_id(player: Identity, pool: RatingPool) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    "rating".hash(&mut hasher);
+    player.hash(&mut hasher);
+    (pool as u8).hash(&mut hasher);
+    hasher.finish()
+}
+
+fn rating_pool(players: usize) -> RatingPool {
+    if players == 2 { RatingPool::Duel } else { RatingPool::Multiplayer }
+}
+
+/// A player's rating in a pool, its deviation grown for the days since their last rated game
+fn get_player_rating(ctx: &ReducerContext, player: Identity, pool: RatingPool) -> PlayerRating {
+    let id = generate_rating_id(player, pool);
+    match ctx.db.player_rating().id().find(id) {
+        Some(rating) => {
+            let idle_days = ctx.timestamp.duration_since(rating.updated_at)
+                .map_or(0.0, |duration| duration.as_secs_f64() / 86_400.0);
+            let deviation = (rating.deviation.powi(2) + DEVIATION_GROWTH_PER_DAY.powi(2) * idle_days)
+                .sqrt()
+                .min(INITIAL_DEVIATION);
+            PlayerRating { deviation, ..rating }
+        }
+        None => PlayerRating {
+            id,
+            player,
+            pool,
+            rating: INITIAL_RATING,
+            deviation: INITIAL_DEVIATION,
+            games: 0,
+            provisional: true,
+            updated_at: ctx.timestamp,
+        },
+    }
+}
+
+/// How a result compares with others in the same game, higher is better
+fn result_standing(result: GameResult) -> u8 {
+    match result {
+        GameResult::Won => 3,
+        GameResult::Drawn => 2,
+        GameResult::Lost => 1,
+        GameResult::Left => 0,
+    }
+}
+
+/// Rate a finished game as if every pair of players had played a match: the better
+/// result wins it, equal results draw. All new ratings come from the ratings before the game.
+fn update_ratings(ctx: &ReducerContext, game_id: u64) {
+    use std::cmp::Ordering;
+    use std::f64::consts::{LN_10, PI};
+
+    // Casual games don't move the ladder
+    if !get_game_settings_for_game(ctx, game_id).is_ok_and(|rules| rules.ranked) {
+        return;
+    }
+
+    let results: Vec<(Identity, GameResult)> = ctx.db.game_history()
+        .game_id()
+        .filter(game_id)
+        .map(|entry| (entry.player, entry.result))
+        .collect();
+    if results.len() < 2 {
+        return;
+    }
+
+    let pool = rating_pool(results.len());
+    let ratings: Vec<PlayerRating> = results.iter()
+        .map(|(player, _)| get_player_rating(ctx, *player, pool))
+        .collect();
+
+    let q = LN_10 / 400.0;
+    let g = |deviation: f64| 1.0 / (1.0 + 3.0 * q * q * deviation * deviation / (PI * PI)).sqrt();
+
+    // New rating, with the average expected and actual score against the other players
+    let updated: Vec<(PlayerRating, f64, f64)> = ratings.iter()
+        .zip(&results)
+        .map(|(own, (_, result))| {
+            let mut inverse_variance = 0.0;
+            let mut improvement = 0.0;
+            let mut expected_total = 0.0;
+            let mut score_total = 0.0;
+            for (other, (_, other_result)) in ratings.iter().zip(&results) {
+                if other.player == own.player {
+                    continue;
+                }
+                let score = match result_standing(*result).cmp(&result_standing(*other_result)) {
+                    Ordering::Greater => 1.0,
+                    Ordering::Equal => 0.5,
+                    Ordering::Less => 0.0,
+                };
+                let weight = g(other.deviation);
+                let expected = 1.0 / (1.0 + 10f64.powf(-weight * (own.rating - other.rating) / 400.0));
+                inverse_variance += q * q * weight * weight * expected * (1.0 - expected);
+                improvement += weight * (score - expected);
+                expected_total += expected;
+                score_total += score;
+            }
+
+            let precision = 1.0 / own.deviation.powi(2) + inverse_variance;
+            let change = q / precision * improvement;
+            let games = own.games + 1;
+            let opponents = (ratings.len() - 1) as f64;
+            let rating = PlayerRating {
+                rating: own.rating + if own.provisional { change * PLACEMENT_SPEEDUP } else { change },
+                deviation: (1.0 / precision).sqrt().max(MIN_DEVIATION),
+                games,
+                provisional: games < PLACEMENT_GAMES,
+                updated_at: ctx.timestamp,
+                ..own.clone()
+            };
+            (rating, expected_total / opponents, score_total / opponents)
+        })
+        .collect();
+
+    let season = active_season(ctx);
+    for (((rating, expected, actual), previous), (_, result)) in updated.into_iter().zip(&ratings).zip(&results) {
+        let history_id = generate_participant_id(game_id, rating.player);
+        if let Some(entry) = ctx.db.game_history().id().find(history_id) {
+            ctx.db.game_history().id().update(GameHistory {
+                expected_score: Some(expected),
+                actual_score: Some(actual),
+                ..entry
+            });
+        }
+
+        let placed = previous.provisional && !rating.provisional;
+        if let Some(season) = season.as_ref().filter(|_| !rating.provisional) {
+            record_season_standing(ctx, season.id, &rating, *result == GameResult::Won);
+        }
+        ctx.db.player_rating().id().delete(rating.id);
+        let rating = ctx.db.player_rating().insert(rating);
+        update_player_rank(ctx, &rating, placed);
+        update_leaderboard(ctx, &rating);
+        if placed {
+            let tier = ctx.db.player_rank().id().find(rating.id).map(|rank| rank.tier);
+            notify(ctx, rating.player, format!("Placement complete: you start in {:?}", tier.unwrap_or(RankTier::Bronze)));
+        }
+    }
+}
+
+// Rank Tiers
+
+/// Tier, promotion rating, demotion rating and icon of every rank tier, lowest first
+const RANK_TIERS: [(RankTier, f64, f64, u32); 7] = [
+    (RankTier::Bronze, 0.0, 0.0, 1),
+    (RankTier::Silver, 1400.0, 1375.0, 2),
+    (RankTier::Gold, 1550.0, 1525.0, 3),
+    (RankTier::Platinum, 1700.0, 1675.0, 4),
+    (RankTier::Diamond, 1850.0, 1825.0, 5),
+    (RankTier::Master, 2000.0, 1975.0, 6),
+    (RankTier::Grandmaster, 2150.0, 2125.0, 7),
+];
+
+/// Move a player's tier in a pool to match their rating. Going up takes the tier's promotion
+/// rating, going down needs a drop below its demotion rating; `fresh` ignores the current tier.
+/// Players still in placement get no tier.
+fn update_player_rank(ctx: &ReducerContext, rating: &PlayerRating, fresh: bool) {
+    if rating.provisional {
+        return;
+    }
+
+    let mut thresholds: Vec<RankTierThreshold> = ctx.db.rank_tier().iter().collect();
+    thresholds.sort_by_key(|threshold| threshold.id);
+    let Some(lowest) = thresholds.first().map(|threshold| threshold.tier) else {
+        return;
+    };
+
+    let id = generate_rating_id(rating.player, rating.pool);
+    let current = ctx.db.player_rank().id().find(id)
+        .filter(|_| !fresh)
+        .map_or(lowest, |rank| rank.tier);
+
+    let tier = thresholds.iter()
+        .filter(|threshold| if threshold.tier > current {
+            rating.rating >= threshold.promote_at
+        } else {
+            rating.rating >= threshold.demote_below
+        })
+        .map(|threshold| threshold.tier)
+        .max()
+        .unwrap_or(lowest);
+
+    ctx.db.player_rank().id().delete(id);
+    ctx.db.player_rank().insert(PlayerRank {
+        id,
+        player: rating.player,
+        pool: rating.pool,
+        tier,
+        updated_at: ctx.timestamp,
+    });
+
+    if !fresh && tier > current {
+        notify(ctx, rating.player, format!("You were promoted to {:?}", tier));
+    }
+    sync_user_rank(ctx, rating.player);
+}
+
+/// Copy a player's best tier to their user row for player lists
+fn sync_user_rank(ctx: &ReducerContext, player: Identity) {
+    let rank = ctx.db.player_rank()
+        .player()
+        .filter(player)
+        .map(|rank| rank.tier)
+        .max();
+    if let Some(user) = ctx.db.user().identity().find(player) {
+        if user.rank != rank {
+            ctx.db.user().identity().update(User { rank, ..user });
+        }
+    }
+}
+
+// Leaderboard
+
+/// The player at a rank of a pool's ladder
+fn leaderboard_entry_at(ctx: &ReducerContext, pool: RatingPool, rank: u32) -> Option<LeaderboardEntry> {
+    ctx.db.leaderboard().pool_rank().filter((pool as u8, rank)).next()
+}
+
+fn leaderboard_size(ctx: &ReducerContext, pool: RatingPool) -> u32 {
+    ctx.db.leaderboard_size().pool_id().find(pool as u8)
+        .map_or(0, |size| size.entries)
+}
+
+fn set_leaderboard_size(ctx: &ReducerContext, pool: RatingPool, entries: u32) {
+    ctx.db.leaderboard_size().pool_id().delete(pool as u8);
+    ctx.db.leaderboard_size().insert(LeaderboardSize {
+        pool_id: pool as u8,
+        pool,
+        entries,
+    });
+}
+
+/// Move a player to their place on the ladder after a rating change. Only the players
+/// they pass are touched, each shifting one place; new players come in from the bottom.
+fn update_leaderboard(ctx: &ReducerContext, rating: &PlayerRating) {
+    if rating.provisional {
+        return;
+    }
+
+    let pool = rating.pool;
+    let start = match ctx.db.leaderboard().id().find(rating.id) {
+        Some(entry) => entry.rank,
+        None => {
+            let entries = leaderboard_size(ctx, pool) + 1;
+            set_leaderboard_size(ctx, pool, entries);
+            entries
+        }
+    };
+
+    // Climb past everyone now rated lower
+    let mut rank = start;
+    while rank > 1 {
+        match leaderboard_entry_at(ctx, pool, rank - 1) {
+            Some(above) if above.rating < rating.rating => {
+                ctx.db.leaderboard().id().update(LeaderboardEntry { rank, ..above });
+                rank -= 1;
+            }
+            _ => break,
+        }
+    }
+
+    // Or sink below everyone now rated higher
+    if rank == start {
+        while let Some(below) = leaderboard_entry_at(ctx, pool, rank + 1) {
+            if below.rating <= rating.rating {
+                break;
+            }
+            ctx.db.leaderboard().id().update(LeaderboardEntry { rank, ..below });
+            rank += 1;
+        }
+    }
+
+    ctx.db.leaderboard().id().delete(rating.id);
+    ctx.db.leaderboard().insert(LeaderboardEntry {
+        id: rating.id,
+        player: rating.player,
+        pool,
+        pool_id: pool as u8,
+        rank,
+        rating: rating.rating,
+    });
+}
+
+/// Take a player off the ladder, closing the gap they leave
+fn remove_from_leaderboard(ctx: &ReducerContext, id: u64) {
+    let Some(entry) = ctx.db.leaderboard().id().find(id) else {
+        return;
+    };
+    ctx.db.leaderboard().id().delete(id);
+    set_leaderboard_size(ctx, entry.pool, leaderboard_size(ctx, entry.pool).saturating_sub(1));
+
+    let mut rank = entry.rank;
+    while let Some(below) = leaderboard_entry_at(ctx, entry.pool, rank + 1) {
+        ctx.db.leaderboard().id().update(LeaderboardEntry { rank, ..below });
+        rank += 1;
+    }
+}
+
+// Matchmaking
+
+/// How often the queue is matched up
+const MATCHMAKING_CHECK_MICROS: i64 = 5 * 1_000_000;
+
+const MATCHMAKING_BASE_WINDOW: f64 = 100.0;
+const MATCHMAKING_WINDOW_GROWTH_PER_MINUTE: f64 = 50.0;
+const MATCHMAKING_MAX_WINDOW: f64 = 500.0;
+
+fn generate_match_lobby_id(timestamp: Timestamp, anchor: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    "match_lobby".hash(&mut hasher);
+    timestamp.hash(&mut hasher);
+    anchor.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Rating difference a ticket accepts after its time in the queue
+fn matchmaking_window(ctx: &ReducerContext, schedule: &MatchmakingSchedule, ticket: &MatchmakingTicket) -> f64 {
+    let minutes = ctx.timestamp.duration_since(ticket.queued_at)
+        .map_or(0.0, |duration| duration.as_secs_f64() / 60.0);
+    (schedule.base_window + schedule.window_growth_per_minute * minutes).min(schedule.max_window)
+}
+
+#[reducer]
+/// Queue for a ranked game with the given number of players
+pub fn join_matchmaking(ctx: &ReducerContext, players: u8) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    if user.current_lobby_id.is_some() || user.current_game_id.is_some() {
+        return Err("You are already in a lobby or game".to_string());
+    }
+
+    if ctx.db.matchmaking_ticket().player().find(caller(ctx)).is_some() {
+        return Err("You are already in the queue".to_string());
+    }
+
+    // Matched games are played with the default rules, so the deck has to go round
+    let defaults = get_default_settings(0);
+    if players < 2 || players as usize * defaults.starting_cards as usize > deck_card_count(defaults.deck_size) * defaults.deck_count as usize {
+        return Err("Not enough cards for that many players".to_string());
+    }
+
+    check_guest_allowed(&user, &GameSettings { ranked: true, ..defaults })?;
+
+    let pool = rating_pool(players as usize);
+    ctx.db.matchmaking_ticket().insert(MatchmakingTicket {
+        player: caller(ctx),
+        players,
+        pool,
+        rating: get_player_rating(ctx, caller(ctx), pool).rating,
+        queued_at: ctx.timestamp,
+    });
+
+    log::info!("User {:?} queued for a {} player game", caller(ctx), players);
+    Ok(())
+}
+
+#[reducer]
+/// Leave the matchmaking queue
+pub fn leave_matchmaking(ctx: &ReducerContext) -> Result<(), String> {
+    if !ctx.db.matchmaking_ticket().player().delete(caller(ctx)) {
+        return Err("You are not in the queue".to_string());
+    }
+
+    log::info!("User {:?} left the matchmaking queue", caller(ctx));
+    Ok(())
+}
+
+#[reducer]
+/// Change the rating window of matchmaking (only admins can do this)
+pub fn set_matchmaking_window(ctx: &ReducerContext, base_window: f64, window_growth_per_minute: f64, max_window: f64) -> Result<(), String> {
+    check_admin(ctx)?;
+
+    if !(base_window >= 0.0 && window_growth_per_minute >= 0.0 && max_window >= base_window) {
+        return Err("Windows can't be negative and the maximum can't be below the base".to_string());
+    }
+
+    let schedules: Vec<MatchmakingSchedule> = ctx.db.matchmaking_schedule().iter().collect();
+    for schedule in schedules {
+        ctx.db.matchmaking_schedule().scheduled_id().update(MatchmakingSchedule {
+            base_window,
+            window_growth_per_minute,
+            max_window,
+            ..schedule
+        });
+    }
+
+    log::info!("Matchmaking window now {} + {}/min, at most {}", base_window, window_growth_per_minute, max_window);
+    Ok(())
+}
+
+#[reducer]
+/// Match queued players into ranked games (scheduled). The longest waiting ticket goes first and
+/// takes the longest waiting players of the same game size within its rating window.
+pub fn run_matchmaking(ctx: &ReducerContext, schedule: MatchmakingSchedule) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can run matchmaking".to_string());
+    }
+
+    // Players who joined a lobby or game some other way, or are gone, drop out of the queue
+    let (mut tickets, stale): (Vec<MatchmakingTicket>, Vec<MatchmakingTicket>) = ctx.db.matchmaking_ticket()
+        .iter()
+        .partition(|ticket| ctx.db.user().identity().find(ticket.player)
+            .is_some_and(|user| user.online && user.current_lobby_id.is_none() && user.current_game_id.is_none()));
+    for ticket in stale {
+        ctx.db.matchmaking_ticket().player().delete(ticket.player);
+    }
+    tickets.sort_by_key(|ticket| ticket.queued_at);
+
+    let mut matched: Vec<Identity> = Vec::new();
+    for anchor in &tickets {
+        if matched.contains(&anchor.player) {
+            continue;
+        }
+
+        let window = matchmaking_window(ctx, &schedule, anchor);
+        let mut group: Vec<&MatchmakingTicket> = vec![anchor];
+        for ticket in &tickets {
+            if group.len() == anchor.players as usize {
+                break;
+            }
+            let fits = ticket.player != anchor.player
+                && !matched.contains(&ticket.player)
+                && ticket.players == anchor.players
+                && (ticket.rating - anchor.rating).abs() <= window
+                && group.iter().all(|member| !is_blocked_between(ctx, member.player, ticket.player));
+            if fits {
+                group.push(ticket);
+            }
+        }
+        if group.len() < anchor.players as usize {
+            continue;
+        }
+
+        let players: Vec<Identity> = group.iter().map(|ticket| ticket.player).collect();
+        matched.extend(&players);
+
+        // A match that can't start only costs its own players their tickets
+        if let Err(err) = start_matched_game(ctx, &players) {
+            log::warn!("Could not start a match for {:?}: {}", players, err);
+            abandon_matched_game(ctx, &players);
+        }
+    }
+
+    Ok(())
+}
+
+/// Put matched players in a private ranked lobby and deal
+fn start_matched_game(ctx: &ReducerContext, players: &[Identity]) -> Result<(), String> {
+    let lobby_id = generate_match_lobby_id(ctx.timestamp, players[0]);
+    let lobby = ctx.db.lobby().insert(Lobby {
+        id: lobby_id,
+        name: "Ranked match".to_string(),
+        creator: players[0],
+        max_players: players.len() as u8,
+        current_players: players.len() as u8,
+        status: LobbyStatus::Waiting,
+        created_at: ctx.timestamp,
+        visibility: LobbyVisibility::Private,
+        listed: false,
+        has_password: false,
+        last_activity: ctx.timestamp,
+        auto_start_when_full: false,
+        carry_scores_from: None,
+        tags: LobbyTags {
+            region: None,
+            language: None,
+            skill_level: None,
+        },
+        scheduled_start: None,
+    });
+    ctx.db.game_settings().insert(GameSettings {
+        ranked: true,
+        ..get_default_settings(lobby_id)
+    });
+
+    for player in players {
+        ctx.db.matchmaking_ticket().player().delete(*player);
+        if let Some(user) = ctx.db.user().identity().find(*player) {
+            ctx.db.user().identity().update(User {
+                current_lobby_id: Some(lobby_id),
+                lobby_joined_at: Some(ctx.timestamp),
+                ..user
+            });
+        }
+        grant_lobby_access(ctx, &lobby, *player);
+        notify(ctx, *player, "Match found, your game is starting".to_string());
+    }
+
+    log::info!("Matched {:?} in lobby {}", players, lobby_id);
+    launch_game(ctx, lobby)
+}
+
+/// Undo the lobby of a match whose game didn't start and send its players back out of the queue
+fn abandon_matched_game(ctx: &ReducerContext, players: &[Identity]) {
+    let lobby_id = generate_match_lobby_id(ctx.timestamp, players[0]);
+    for player in players {
+        ctx.db.matchmaking_ticket().player().delete(*player);
+        if let Some(user) = ctx.db.user().identity().find(*player).filter(|user| user.current_lobby_id == Some(lobby_id)) {
+            ctx.db.user().identity().update(User {
+                current_lobby_id: None,
+                lobby_joined_at: None,
+                ..user
+            });
+        }
+        notify(ctx, *player, "Your match could not start, please queue again".to_string());
+    }
+    ctx.db.game_settings().lobby_id().delete(lobby_id);
+    delete_lobby(ctx, lobby_id);
+}
+
+// Seasons
+
+const SEASON_LENGTH_MICROS: i64 = 91 * DAY_MICROS;
+
+/// Deviation every rating is brought up to when a season starts, so the new ladder settles quickly
+const SEASON_RESET_DEVIATION: f64 = 150.0;
+
+fn generate_season_standing_id(season_id: u32, player: Identity, pool: RatingPool) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    "season_standing".hash(&mut hasher);
+    season_id.hash(&mut hasher);
+    player.hash(&mut hasher);
+    (pool as u8).hash(&mut hasher);
+    hasher.finish()
+}
+
+fn active_season(ctx: &ReducerContext) -> Option<Season> {
+    ctx.db.season().iter().find(|season| season.active)
+}
+
+fn start_season(ctx: &ReducerContext, season_id: u32) {
+    let ends_at = ctx.timestamp + TimeDuration::from_micros(SEASON_LENGTH_MICROS);
+    ctx.db.season().insert(Season {
+        id: season_id,
+        starts_at: ctx.timestamp,
+        ends_at,
+        active: true,
+    });
+    ctx.db.season_rollover_schedule().insert(SeasonRolloverSchedule {
+        scheduled_id: 0,
+        scheduled_at: ScheduleAt::Time(ends_at),
+    });
+}
+
+/// Put a ranked game's new rating and result on the player's season standing
+fn record_season_standing(ctx: &ReducerContext, season_id: u32, rating: &PlayerRating, won: bool) {
+    let id = generate_season_standing_id(season_id, rating.player, rating.pool);
+    let (games, wins) = ctx.db.season_standing().id().find(id)
+        .map_or((0, 0), |standing| (standing.games, standing.wins));
+
+    ctx.db.season_standing().id().delete(id);
+    ctx.db.season_standing().insert(SeasonStanding {
+        id,
+        season_id,
+        player: rating.player,
+        pool: rating.pool,
+        rating: rating.rating,
+        games: games + 1,
+        wins: wins + won as u32,
+        final_rank: None,
+    });
+}
+
+#[reducer]
+/// Close the active season: freeze its standings with final ranks, soft-reset ratings and start the next one (scheduled)
+pub fn end_season(ctx: &ReducerContext, _schedule: SeasonRolloverSchedule) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can end a season".to_string());
+    }
+
+    let season = active_season(ctx)
+        .ok_or("No active season")?;
+
+    for pool in [RatingPool::Duel, RatingPool::Multiplayer] {
+        let mut standings: Vec<SeasonStanding> = ctx.db.season_standing()
+            .season_id()
+            .filter(season.id)
+            .filter(|standing| standing.pool == pool)
+            .collect();
+        standings.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+
+        for (index, standing) in standings.into_iter().enumerate() {
+            let rank = index as u32 + 1;
+            notify(ctx, standing.player, format!("Season {} is over: you finished #{} in {}", season.id, rank, match pool {
+                RatingPool::Duel => "duels",
+                RatingPool::Multiplayer => "multiplayer",
+            }));
+            ctx.db.season_standing().id().update(SeasonStanding {
+                final_rank: Some(rank),
+                ..standing
+            });
+        }
+    }
+
+    // Soft reset: ratings move halfway back to the start and become less certain
+    let ratings: Vec<PlayerRating> = ctx.db.player_rating().iter().collect();
+    for rating in ratings {
+        let rating = ctx.db.player_rating().id().update(PlayerRating {
+            rating: INITIAL_RATING + (rating.rating - INITIAL_RATING) / 2.0,
+            deviation: rating.deviation.max(SEASON_RESET_DEVIATION),
+            ..rating
+        });
+        update_player_rank(ctx, &rating, true);
+        // The reset keeps everyone in order, so this only refreshes the shown ratings
+        update_leaderboard(ctx, &rating);
+    }
+
+    let next_season = season.id + 1;
+    ctx.db.season().id().update(Season { active: false, ..season });
+    start_season(ctx, next_season);
+
+    log::info!("Season {} ended, season {} started", next_season - 1, next_season);
+    Ok(())
+}
+
+// Weekly Digests
+
+const WEEK_MICROS: i64 = 7 * DAY_MICROS;
+
+/// How many past weeks are kept per player
+const MAX_WEEKLY_DIGESTS: usize = 12;
+
+/// Weeks since the epoch, starting on Mondays (the epoch fell on a Thursday)
+fn current_week(timestamp: Timestamp) -> i64 {
+    (current_day(timestamp) + 3) / 7
+}
+
+fn week_start(week: i64) -> Timestamp {
+    Timestamp::from_micros_since_unix_epoch(week * WEEK_MICROS - 3 * DAY_MICROS)
+}
+
+fn generate_weekly_digest_id(player: Identity, week: i64) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    "weekly_digest".hash(&mut hasher);
+    player.hash(&mut hasher);
+    week.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Schedule the next digests for the coming Monday midnight (UTC)
+fn schedule_weekly_digest(ctx: &ReducerContext, post_summary: bool) {
+    ctx.db.weekly_digest_schedule().insert(WeeklyDigestSchedule {
+        scheduled_id: 0,
+        scheduled_at: ScheduleAt::Time(week_start(current_week(ctx.timestamp) + 1)),
+        post_summary,
+    });
+}
+
+/// How rare a highlight is, to pick a week's best moment
+fn highlight_rarity(kind: HighlightKind) -> u8 {
+    match kind {
+        HighlightKind::FirstTurnOut => 3,
+        HighlightKind::PerfectDefense => 2,
+        HighlightKind::LastCardOut => 1,
+    }
+}
+
+#[reducer]
+/// Write last week's digest for everyone who finished a game in it and optionally sum the week up in the chat (scheduled)
+pub fn write_weekly_digests(ctx: &ReducerContext, schedule: WeeklyDigestSchedule) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can write weekly digests".to_string());
+    }
+
+    use std::collections::{BTreeMap, BTreeSet};
+
+    // The week that just ended, even if this run comes late or is retried
+    let week = current_week(ctx.timestamp) - 1;
+    let since = week_start(week);
+    let until = week_start(week + 1);
+    let in_week = |at: Timestamp| at >= since && at < until;
+
+    let mut played: BTreeMap<Identity, Vec<GameHistory>> = BTreeMap::new();
+    let mut games: BTreeSet<u64> = BTreeSet::new();
+    for entry in ctx.db.game_history().iter().filter(|entry| in_week(entry.finished_at)) {
+        if ctx.db.deleted_player().identity().find(entry.player).is_some() {
+            continue;
+        }
+        games.insert(entry.game_id);
+        played.entry(entry.player).or_default().push(entry);
+    }
+
+    let mut best_moments: BTreeMap<Identity, Highlight> = BTreeMap::new();
+    for highlight in ctx.db.highlight().iter().filter(|highlight| in_week(highlight.happened_at)) {
+        let rank = |highlight: &Highlight| (highlight_rarity(highlight.kind), highlight.value);
+        if best_moments.get(&highlight.player).is_none_or(|best| rank(&highlight) > rank(best)) {
+            best_moments.insert(highlight.player, highlight);
+        }
+    }
+
+    let mut top_climber: Option<(Identity, f64)> = None;
+    for (&player, entries) in &played {
+
+        // Rating changes count from the player's previous digest
+        let previous = ctx.db.weekly_digest()
+            .player()
+            .filter(player)
+            .filter(|digest| digest.week < week)
+            .max_by_key(|digest| digest.week);
+        let ratings: Vec<RatingChange> = ctx.db.player_rating()
+            .player()
+            .filter(player)
+            .map(|rating| {
+                let before = previous.as_ref()
+                    .and_then(|digest| digest.ratings.iter().find(|change| change.pool == rating.pool))
+                    .map_or(INITIAL_RATING, |change| change.rating);
+                RatingChange { pool: rating.pool, rating: rating.rating, change: rating.rating - before }
+            })
+            .collect();
+
+        for change in &ratings {
+            if change.change > top_climber.map_or(0.0, |(_, best)| best) {
+                top_climber = Some((player, change.change));
+            }
+        }
+
+        let best_moment = best_moments.get(&player).map(|highlight| highlight.id);
+
+        let id = generate_weekly_digest_id(player, week);
+        ctx.db.weekly_digest().id().delete(id);
+        ctx.db.weekly_digest().insert(WeeklyDigest {
+            id,
+            player,
+            week,
+            games_played: entries.len() as u32,
+            wins: entries.iter().filter(|entry| entry.result == GameResult::Won).count() as u32,
+            ratings,
+            best_moment,
+            created_at: ctx.timestamp,
+        });
+
+        let mut digests: Vec<WeeklyDigest> = ctx.db.weekly_digest().player().filter(player).collect();
+        if digests.len() > MAX_WEEKLY_DIGESTS {
+            digests.sort_by_key(|digest| std::cmp::Reverse(digest.week));
+            for digest in digests.into_iter().skip(MAX_WEEKLY_DIGESTS) {
+                ctx.db.weekly_digest().id().delete(digest.id);
+            }
+        }
+    }
+
+    if schedule.post_summary && !games.is_empty() {
+        let mut text = format!("Week in review: {} games finished by {} players", games.len(), played.len());
+        if let Some((player, change)) = top_climber {
+            let name = ctx.db.user().identity().find(player)
+                .and_then(|user| user.name)
+                .unwrap_or_else(|| "someone".to_string());
+            text.push_str(&format!("; top climber {} (+{:.0})", name, change));
+        }
+        post_server_message(ctx, text);
+    }
+
+    schedule_weekly_digest(ctx, schedule.post_summary);
+
+    log::info!("Weekly digests written for week {}: {} players", week, played.len());
+    Ok(())
+}
+
+/// Chat message from the server itself, delivered to everyone
+fn post_server_message(ctx: &ReducerContext, text: String) {
+    let message_id = generate_message_id(ctx.identity(), ctx.timestamp, &text);
+    ctx.db.message().insert(Message {
+        id: message_id,
+        sender: ctx.identity(),
+        text,
+        sent: ctx.timestamp,
+    });
+
+    let recipients: Vec<Identity> = ctx.db.user().iter().map(|user| user.identity).collect();
+    for recipient in recipients {
+        ctx.db.chat_delivery().insert(ChatDelivery {
+            id: generate_chat_delivery_id(message_id, recipient),
+            message_id,
+            recipient,
+            sender: ctx.identity(),
+        });
+    }
+}
+
+#[reducer]
+/// Turn the weekly summary in the chat on or off (only admins can do this)
+pub fn set_weekly_summary(ctx: &ReducerContext, enabled: bool) -> Result<(), String> {
+    check_admin(ctx)?;
+
+    let schedules: Vec<WeeklyDigestSchedule> = ctx.db.weekly_digest_schedule().iter().collect();
+    for schedule in schedules {
+        ctx.db.weekly_digest_schedule().scheduled_id().update(WeeklyDigestSchedule {
+            post_summary: enabled,
+            ..schedule
+        });
+    }
+    Ok(())
+}
+
+// Experience
+
+/// XP for finishing a game, before variant and table size bonuses
+fn base_game_xp(result: GameResult) -> u64 {
+    match result {
+        GameResult::Won => 100,
+        GameResult::Drawn => 60,
+        GameResult::Lost => 40,
+        GameResult::Left => 0,
+    }
+}
+
+/// XP a finished game is worth: every non-standard variant adds 10% and every opponent 10 points
+fn game_xp(result: GameResult, rules: &GameSettings, opponents: usize) -> u64 {
+    let base = base_game_xp(result);
+    if base == 0 {
+        return 0;
+    }
+
+    let variants = [rules.perevodnoy, rules.no_trump, rules.cheating_durak, rules.team_mode]
+        .iter()
+        .filter(|enabled| **enabled)
+        .count() as u64;
+
+    base * (10 + variants) / 10 + 10 * opponents as u64
+}
+
+/// Level reached with this much XP; each level takes 100 XP more than the one before
+fn level_for_xp(xp: u64) -> u32 {
+    let mut level = 1;
+    let mut needed = 100;
+    let mut remaining = xp;
+    while remaining >= needed {
+        remaining -= needed;
+        level += 1;
+        needed += 100;
+    }
+    level
+}
+
+/// Add XP to a player and carry a level change over to their user row
+fn add_xp(ctx: &ReducerContext, player: Identity, amount: u64) {
+    if amount == 0 {
+        return;
+    }
+
+    let xp = ctx.db.player_xp().identity().find(player).map_or(0, |entry| entry.xp) + amount;
+    let level = level_for_xp(xp);
+
+    ctx.db.player_xp().identity().delete(player);
+    ctx.db.player_xp().insert(PlayerXp {
+        identity: player,
+        xp,
+        level,
+        updated_at: ctx.timestamp,
+    });
+
+    if let Some(user) = ctx.db.user().identity().find(player) {
+        if user.level != level {
+            ctx.db.user().identity().update(User { level, ..user });
+            notify(ctx, player, format!("You reached level {}", level));
+        }
+    }
+}
+
+// Daily Quests
+
+const DAY_MICROS: i64 = 24 * 60 * 60 * 1_000_000;
+
+/// How many quests are offered each day
+const DAILY_QUEST_COUNT: usize = 3;
+
+/// Every quest that can come up: kind, target, XP reward and cosmetic reward
+const QUEST_POOL: [(QuestKind, u32, u64, Option<Cosmetic>); 5] = [
+    (QuestKind::DefendAttacks, 10, 50, None),
+    (QuestKind::AttackCards, 15, 50, None),
+    (QuestKind::PlayGames, 5, 60, None),
+    (QuestKind::WinGames, 3, 80, Some(Cosmetic { kind: CosmeticKind::CardBack, item_id: 1 })),
+    (QuestKind::WinFourPlayerGame, 1, 60, Some(Cosmetic { kind: CosmeticKind::TableTheme, item_id: 1 })),
+];
+
+fn current_day(timestamp: Timestamp) -> i64 {
+    timestamp.to_micros_since_unix_epoch() / DAY_MICROS
+}
+
+fn generate_quest_id(day: i64, kind: QuestKind) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    day.hash(&mut hasher);
+    (kind as u8).hash(&mut hasher);
+    hasher.finish()
+}
+
+fn generate_quest_progress_id(player: Identity, quest_id: u64) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    player.hash(&mut hasher);
+    quest_id.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Replace older quests and their progress with today's picks from the pool
+fn generate_daily_quests(ctx: &ReducerContext) {
+    let day = current_day(ctx.timestamp);
+
+    let stale: Vec<u64> = ctx.db.daily_quest()
+        .iter()
+        .filter(|quest| quest.day != day)
+        .map(|quest| quest.id)
+        .collect();
+    let stale_progress: Vec<u64> = ctx.db.quest_progress()
+        .iter()
+        .filter(|progress| stale.contains(&progress.quest_id))
+        .map(|progress| progress.id)
+        .collect();
+    for progress_id in stale_progress {
+        ctx.db.quest_progress().id().delete(progress_id);
+    }
+    for quest_id in stale {
+        ctx.db.daily_quest().id().delete(quest_id);
+    }
+
+    // Rotate through the pool, a different mix each day
+    for offset in 0..DAILY_QUEST_COUNT {
+        let (kind, target, reward_xp, reward_cosmetic) = QUEST_POOL[(day as usize + offset) % QUEST_POOL.len()];
+        let id = generate_quest_id(day, kind);
+        if ctx.db.daily_quest().id().find(id).is_none() {
+            ctx.db.daily_quest().insert(DailyQuest {
+                id,
+                day,
+                kind,
+                target,
+                reward_xp,
+                reward_cosmetic,
+            });
+        }
+    }
+}
+
+/// Schedule the next rotation for the coming midnight (UTC)
+fn schedule_quest_rotation(ctx: &ReducerContext) {
+    let next_day_micros = (current_day(ctx.timestamp) + 1) * DAY_MICROS;
+    ctx.db.quest_rotation_schedule().insert(QuestRotationSchedule {
+        scheduled_id: 0,
+        scheduled_at: ScheduleAt::Time(Timestamp::from_micros_since_unix_epoch(next_day_micros)),
+    });
+}
+
+#[reducer]
+/// Hand out a new set of daily quests, reset broken login streaks and start a new day of server stats at midnight (scheduled)
+pub fn rotate_daily_quests(ctx: &ReducerContext, _schedule: QuestRotationSchedule) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can rotate quests".to_string());
+    }
+
+    generate_daily_quests(ctx);
+    schedule_quest_rotation(ctx);
+    reset_missed_login_streaks(ctx);
+    update_server_stats(ctx, |stats| start_server_stats_day(stats, current_day(ctx.timestamp)));
+
+    log::info!("Daily quests rotated for day {}", current_day(ctx.timestamp));
+    Ok(())
+}
+
+/// Count progress towards today's quests of a kind, rewarding the ones it completes
+fn advance_quests(ctx: &ReducerContext, player: Identity, kind: QuestKind, amount: u32) {
+    let day = current_day(ctx.timestamp);
+    let quests: Vec<DailyQuest> = ctx.db.daily_quest()
+        .iter()
+        .filter(|quest| quest.day == day && quest.kind == kind)
+        .collect();
+
+    for quest in quests {
+        let id = generate_quest_progress_id(player, quest.id);
+        let existing = ctx.db.quest_progress().id().find(id);
+        if existing.as_ref().is_some_and(|progress| progress.completed_at.is_some()) {
+            continue;
+        }
+
+        let progress = existing.map_or(0, |progress| progress.progress) + amount;
+        let completed = progress >= quest.target;
+
+        ctx.db.quest_progress().id().delete(id);
+        ctx.db.quest_progress().insert(QuestProgress {
+            id,
+            player,
+            quest_id: quest.id,
+            progress: progress.min(quest.target),
+            completed_at: if completed { Some(ctx.timestamp) } else { None },
+        });
+
+        if completed {
+            add_xp(ctx, player, quest.reward_xp);
+            if let Some(cosmetic) = quest.reward_cosmetic {
+                grant_cosmetic(ctx, player, cosmetic);
+            }
+            notify(ctx, player, format!("Daily quest complete: +{} XP", quest.reward_xp));
+        }
+    }
+}
+
+// Daily Login Rewards
+
+/// XP per day of the streak, growing until the streak reaches this many days
+const LOGIN_STREAK_CAP: u32 = 7;
+const LOGIN_REWARD_XP: u64 = 20;
+
+/// Claimed on every full week of the streak
+const LOGIN_STREAK_COSMETIC: Cosmetic = Cosmetic { kind: CosmeticKind::CardBack, item_id: 2 };
+
+#[reducer]
+/// Collect today's login reward; the reward grows with every day in a row
+pub fn claim_daily_reward(ctx: &ReducerContext) -> Result<(), String> {
+    ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    let day = current_day(ctx.timestamp);
+    let existing = ctx.db.daily_reward().identity().find(caller(ctx));
+
+    if existing.as_ref().is_some_and(|reward| reward.last_claim_day == day) {
+        return Err("Today's reward was already claimed".to_string());
+    }
+
+    let streak = match existing {
+        Some(reward) if reward.last_claim_day == day - 1 => reward.streak + 1,
+        _ => 1,
+    };
+
+    ctx.db.daily_reward().identity().delete(caller(ctx));
+    ctx.db.daily_reward().insert(DailyReward {
+        identity: caller(ctx),
+        streak,
+        last_claim_day: day,
+        last_claimed_at: ctx.timestamp,
+    });
+
+    let xp = LOGIN_REWARD_XP * streak.min(LOGIN_STREAK_CAP) as u64;
+    add_xp(ctx, caller(ctx), xp);
+    if streak % LOGIN_STREAK_CAP == 0 {
+        grant_cosmetic(ctx, caller(ctx), LOGIN_STREAK_COSMETIC);
+    }
+
+    log::info!("User {:?} claimed a daily reward of {} XP, streak {}", caller(ctx), xp, streak);
+    Ok(())
+}
+
+/// Zero the streak of everyone who didn't claim yesterday
+fn reset_missed_login_streaks(ctx: &ReducerContext) {
+    let yesterday = current_day(ctx.timestamp) - 1;
+    let missed: Vec<DailyReward> = ctx.db.daily_reward()
+        .iter()
+        .filter(|reward| reward.streak > 0 && reward.last_claim_day < yesterday)
+        .collect();
+
+    for reward in missed {
+        ctx.db.daily_reward().identity().update(DailyReward {
+            streak: 0,
+            ..reward
+        });
+    }
+}
+
+// Cosmetics
+
+fn generate_inventory_id(owner: Identity, cosmetic: Cosmetic) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    owner.hash(&mut hasher);
+    (cosmetic.kind as u8).hash(&mut hasher);
+    cosmetic.item_id.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Add a cosmetic to a player's inventory unless they already own it
+fn grant_cosmetic(ctx: &ReducerContext, owner: Identity, cosmetic: Cosmetic) {
+    let id = generate_inventory_id(owner, cosmetic);
+    if ctx.db.inventory().id().find(id).is_some() {
+        return;
+    }
+
+    ctx.db.inventory().insert(InventoryItem {
+        id,
+        owner,
+        cosmetic,
+        equipped: false,
+        acquired_at: ctx.timestamp,
+    });
+    notify(ctx, owner, "You got a new cosmetic".to_string());
+}
+
+/// The item a player has equipped for a kind, the default design if none
+fn equipped_cosmetic(ctx: &ReducerContext, owner: Identity, kind: CosmeticKind) -> u32 {
+    ctx.db.inventory()
+        .owner()
+        .filter(owner)
+        .find(|item| item.equipped && item.cosmetic.kind == kind)
+        .map_or(0, |item| item.cosmetic.item_id)
+}
+
+#[reducer]
+/// Use an owned card back or table theme; item 0 goes back to the default design
+pub fn equip_cosmetic(ctx: &ReducerContext, kind: CosmeticKind, item_id: u32) -> Result<(), String> {
+    let cosmetic = Cosmetic { kind, item_id };
+    if item_id != 0 && ctx.db.inventory().id().find(generate_inventory_id(caller(ctx), cosmetic)).is_none() {
+        return Err("You don't own this cosmetic".to_string());
+    }
+
+    let items: Vec<InventoryItem> = ctx.db.inventory()
+        .owner()
+        .filter(caller(ctx))
+        .filter(|item| item.cosmetic.kind == kind)
+        .collect();
+
+    for item in items {
+        let equipped = item.cosmetic.item_id == item_id;
+        if item.equipped != equipped {
+            ctx.db.inventory().id().update(InventoryItem { equipped, ..item });
+        }
+    }
+
+    Ok(())
+}
+
+/// Record every seated player's equipped cosmetics for a game
+fn record_game_cosmetics(ctx: &ReducerContext, game_id: u64, players: &[Identity]) {
+    for player in players {
+        let id = generate_participant_id(game_id, *player);
+        ctx.db.game_cosmetic().id().delete(id);
+        ctx.db.game_cosmetic().insert(GameCosmetic {
+            id,
+            game_id,
+            player: *player,
+            card_back: equipped_cosmetic(ctx, *player, CosmeticKind::CardBack),
+            table_theme: equipped_cosmetic(ctx, *player, CosmeticKind::TableTheme),
+        });
+    }
+}
+
+// Titles
+
+fn generate_player_title_id(player: Identity, title: Title) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    player.hash(&mut hasher);
+    (title as u8).hash(&mut hasher);
+    hasher.finish()
+}
+
+fn title_name(title: Title) -> &'static str {
+    match title {
+        Title::GrandFool => "Grand Fool",
+        Title::IronDefender => "Iron Defender",
+        Title::Veteran => "Veteran",
+        Title::TournamentChampion => "Champion",
+        Title::BrickWall => "Brick Wall",
+    }
+}
+
+/// Give a player a title unless they already have it
+fn grant_title(ctx: &ReducerContext, player: Identity, title: Title) {
+    let id = generate_player_title_id(player, title);
+    if ctx.db.player_title().id().find(id).is_some() {
+        return;
+    }
+
+    ctx.db.player_title().insert(PlayerTitle {
+        id,
+        player,
+        title,
+        earned_at: ctx.timestamp,
+    });
+    notify(ctx, player, format!("You earned the title \"{}\"", title_name(title)));
+}
+
+/// Titles earned over a player's game record, checked after each finished game
+fn grant_game_titles(ctx: &ReducerContext, game_id: u64, player: Identity) {
+    let results: Vec<GameResult> = ctx.db.game_history()
+        .player()
+        .filter(player)
+        .map(|entry| entry.result)
+        .collect();
+
+    if results.iter().filter(|result| **result == GameResult::Lost).count() >= 10 {
+        grant_title(ctx, player, Title::GrandFool);
+    }
+
+    if results.len() >= 100 {
+        grant_title(ctx, player, Title::Veteran);
+    }
+
+    let streak = ctx.db.game_summary().id().find(generate_participant_id(game_id, player))
+        .map_or(0, |summary| summary.longest_defense_streak);
+    if streak >= 8 {
+        grant_title(ctx, player, Title::IronDefender);
+    }
+
+    let perfect_defense = ctx.db.highlight()
+        .game_id()
+        .filter(game_id)
+        .any(|highlight| highlight.player == player && highlight.kind == HighlightKind::PerfectDefense);
+    if perfect_defense {
+        grant_title(ctx, player, Title::BrickWall);
+    }
+}
+
+#[reducer]
+/// Show one of your titles next to your name, or none
+pub fn equip_title(ctx: &ReducerContext, title: Option<Title>) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    if let Some(title) = title {
+        if ctx.db.player_title().id().find(generate_player_title_id(caller(ctx), title)).is_none() {
+            return Err("You have not earned this title".to_string());
+        }
+    }
+
+    ctx.db.user().identity().update(User { title, ..user });
+    Ok(())
+}
+
+/// How many recent co-players are remembered per user
+const MAX_RECENT_PLAYERS: usize = 30;
+
+fn generate_recent_player_id(owner: Identity, player: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    owner.hash(&mut hasher);
+    player.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Remember everyone at the table as a recent player of everyone else, oldest dropping off
+fn write_recent_players(ctx: &ReducerContext, game_id: u64) {
+    let players: Vec<Identity> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id))
+        .map(|user| user.identity)
+        .collect();
+
+    for owner in &players {
+        for player in players.iter().filter(|player| *player != owner) {
+            let id = generate_recent_player_id(*owner, *player);
+            ctx.db.recent_player().id().delete(id);
+            ctx.db.recent_player().insert(RecentPlayer {
+                id,
+                owner: *owner,
+                player: *player,
+                game_id,
+                played_at: ctx.timestamp,
+            });
+        }
+
+        let mut recent: Vec<RecentPlayer> = ctx.db.recent_player().owner().filter(*owner).collect();
+        if recent.len() > MAX_RECENT_PLAYERS {
+            recent.sort_by_key(|entry| std::cmp::Reverse(entry.played_at));
+            for entry in recent.into_iter().skip(MAX_RECENT_PLAYERS) {
+                ctx.db.recent_player().id().delete(entry.id);
+            }
+        }
+    }
+}
+
+/// Finish the entire game
+fn finish_game(ctx: &ReducerContext, game_id: u64, final_loser: Option<Identity>) -> Result<(), String> {
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    if matches!(game.status, GameStatus::Active | GameStatus::Paused) {
+        update_server_stats(ctx, |stats| stats.active_games = stats.active_games.saturating_sub(1));
+    }
+
+    ctx.db.game().id().update(Game {
+        status: GameStatus::Finished,
+        finished_at: Some(ctx.timestamp),
+        ..game
+    });
+
+    // Time limits have nothing left to end
+    let limits: Vec<u64> = ctx.db.game_time_limit()
+        .iter()
+        .filter(|limit| limit.game_id == game_id)
+        .map(|limit| limit.scheduled_id)
+        .collect();
+    for scheduled_id in limits {
+        ctx.db.game_time_limit().scheduled_id().delete(scheduled_id);
+    }
+    let limits: Vec<u64> = ctx.db.round_time_limit()
+        .iter()
+        .filter(|limit| limit.game_id == game_id)
+        .map(|limit| limit.scheduled_id)
+        .collect();
+    for scheduled_id in limits {
+        ctx.db.round_time_limit().scheduled_id().delete(scheduled_id);
+    }
+
+    log_event(ctx, game_id, GameEventKind::GameFinished, EventDetail {
+        player: final_loser,
+        ..Default::default()
+    });
+
+    write_game_summaries(ctx, game_id)?;
+    write_game_history(ctx, game_id, final_loser)?;
+    write_recent_players(ctx, game_id);
+    update_ratings(ctx, game_id);
+    record_game_feats(ctx, game_id);
+    record_clan_series_game(ctx, game.lobby_id, game_id, final_loser);
+
+    // Reset all players' game state, remembering who played for a rematch
+    let players: Vec<User> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id))
+        .collect();
+
+    for player in players {
+        ctx.db.game_participant().insert(GameParticipant {
+            id: generate_participant_id(game_id, player.identity),
+            game_id,
+            player: player.identity,
+            final_points: player.total_points.unwrap_or(0),
+        });
+
+        ctx.db.user().identity().update(User {
+            current_game_id: None,
+            game_position: None,
+            total_points: None,
+            player_status: None,
+            team: None,
+            ..player
+        });
+        ctx.db.bot_seat().player().delete(player.identity);
+        ctx.db.timeout_strike().id().delete(generate_clock_id(game_id, player.identity));
+        clear_seat_vacancy(ctx, player.identity);
+    }
+
+    let spectators: Vec<Identity> = ctx.db.game_spectator()
+        .iter()
+        .filter(|spectator| spectator.game_id == game_id)
+        .map(|spectator| spectator.identity)
+        .collect();
+    for spectator in spectators {
+        ctx.db.game_spectator().identity().delete(spectator);
+    }
+
+    // Update lobby status; the rematch window runs from here
+    ctx.db.lobby().id().update(Lobby {
+        status: LobbyStatus::Finished,
+        last_activity: ctx.timestamp,
+        ..ctx.db.lobby().id().find(game.lobby_id).unwrap()
+    });
+    sync_open_lobby(ctx, game.lobby_id);
+
+    log::info!("Game {} finished, final loser: {:?}", game_id, final_loser);
+    Ok(())
+}
+
+// Pausing
+
+/// Longest a game can stay paused before it resumes by itself
+const MAX_PAUSE_MICROS: i64 = 5 * 60 * 1_000_000;
+
+fn generate_pause_vote_id(game_id: u64, player: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    game_id.hash(&mut hasher);
+    player.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Players whose agreement counts in a unanimous game vote (bots don't get a say)
+fn get_voting_players(ctx: &ReducerContext, game_id: u64) -> Vec<Identity> {
+    ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id) && user.player_status == Some(PlayerStatus::Active))
+        .filter(|user| user.online && !is_bot(ctx, user.identity))
+        .map(|user| user.identity)
+        .collect()
+}
+
+fn clear_pause_votes(ctx: &ReducerContext, game_id: u64) {
+    let votes: Vec<u64> = ctx.db.pause_vote()
+        .iter()
+        .filter(|vote| vote.game_id == game_id)
+        .map(|vote| vote.id)
+        .collect();
+
+    for vote_id in votes {
+        ctx.db.pause_vote().id().delete(vote_id);
+    }
+}
+
+fn check_pause_voter(ctx: &ReducerContext, game_id: u64) -> Result<Game, String> {
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    if game.status != GameStatus::Active {
+        return Err("Game is not active".to_string());
+    }
+
+    if !get_voting_players(ctx, game_id).contains(&caller(ctx)) {
+        return Err("Only active players can vote on a pause".to_string());
+    }
+
+    Ok(game)
+}
+
+/// Pause the game once every voter agreed
+fn try_start_pause(ctx: &ReducerContext, game: Game) -> Result<(), String> {
+    let voters = get_voting_players(ctx, game.id);
+    let all_agreed = voters.iter().all(|voter| {
+        ctx.db.pause_vote().id().find(generate_pause_vote_id(game.id, *voter)).is_some()
+    });
+
+    if !all_agreed {
+        return Ok(());
+    }
+
+    clear_pause_votes(ctx, game.id);
+    let game_id = game.id;
+    ctx.db.game().id().update(Game {
+        status: GameStatus::Paused,
+        paused_at: Some(ctx.timestamp),
+        ..game
+    });
+
+    ctx.db.pause_expiry().insert(PauseExpiry {
+        scheduled_id: 0,
+        scheduled_at: ScheduleAt::Time(ctx.timestamp + TimeDuration::from_micros(MAX_PAUSE_MICROS)),
+        game_id,
+    });
+
+    log::info!("Game {} paused", game_id);
+
+    // Stops the move timers and chess clocks
+    after_game_action(ctx, game_id)
+}
+
+fn resume_paused_game(ctx: &ReducerContext, game: Game) -> Result<(), String> {
+    let pending: Vec<u64> = ctx.db.pause_expiry()
+        .iter()
+        .filter(|expiry| expiry.game_id == game.id)
+        .map(|expiry| expiry.scheduled_id)
+        .collect();
+
+    for scheduled_id in pending {
+        ctx.db.pause_expiry().scheduled_id().delete(scheduled_id);
+    }
+
+    let game_id = game.id;
+    ctx.db.game().id().update(Game {
+        status: GameStatus::Active,
+        paused_at: None,
+        ..game
+    });
+
+    log::info!("Game {} resumed", game_id);
+
+    // Restarts the move timers and chess clocks
+    after_game_action(ctx, game_id)
+}
+
+#[reducer]
+/// Ask the other players to pause the game
+pub fn request_pause(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    let game = check_pause_voter(ctx, game_id)?;
+
+    if ctx.db.pause_vote().iter().any(|vote| vote.game_id == game_id) {
+        return Err("A pause has already been requested".to_string());
+    }
+
+    ctx.db.pause_vote().insert(PauseVote {
+        id: generate_pause_vote_id(game_id, caller(ctx)),
+        game_id,
+        player: caller(ctx),
+        voted_at: ctx.timestamp,
+    });
+
+    for voter in get_voting_players(ctx, game_id) {
+        if voter != caller(ctx) {
+            notify(ctx, voter, "A player asks to pause the game".to_string());
+        }
+    }
+
+    try_start_pause(ctx, game)
+}
+
+#[reducer]
+/// Agree to or turn down a pending pause request
+pub fn vote_pause(ctx: &ReducerContext, game_id: u64, agree: bool) -> Result<(), String> {
+    let game = check_pause_voter(ctx, game_id)?;
+
+    if !ctx.db.pause_vote().iter().any(|vote| vote.game_id == game_id) {
+        return Err("No pause has been requested".to_string());
+    }
+
+    if !agree {
+        clear_pause_votes(ctx, game_id);
+        return Ok(());
+    }
+
+    let vote_id = generate_pause_vote_id(game_id, caller(ctx));
+    if ctx.db.pause_vote().id().find(vote_id).is_none() {
+        ctx.db.pause_vote().insert(PauseVote {
+            id: vote_id,
+            game_id,
+            player: caller(ctx),
+            voted_at: ctx.timestamp,
+        });
+    }
+
+    try_start_pause(ctx, game)
+}
+
+#[reducer]
+/// Any player of a paused game can resume it; an adjourned game is resumed by the lobby creator
+pub fn resume_game(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    if game.status == GameStatus::Adjourned {
+        return resume_adjourned_game(ctx, game);
+    }
+
+    if game.status != GameStatus::Paused {
+        return Err("Game is not paused".to_string());
+    }
+
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+    if user.current_game_id != Some(game_id) {
+        return Err("You are not in this game".to_string());
+    }
+
+    resume_paused_game(ctx, game)
+}
+
+#[reducer]
+/// Resume a game that stayed paused for too long (scheduled)
+pub fn end_pause(ctx: &ReducerContext, expiry: PauseExpiry) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can end pauses".to_string());
+    }
+
+    let Some(game) = ctx.db.game().id().find(expiry.game_id) else {
+        return Ok(());
+    };
+
+    if game.status != GameStatus::Paused {
+        return Ok(());
+    }
+
+    resume_paused_game(ctx, game)
+}
+
+// Adjourning
+
+#[reducer]
+/// Save a multi-round game to finish another day: the round in play is voided, scores are kept
+/// and everyone is released (only lobby creator can do this)
+pub fn adjourn_game(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    if !matches!(game.status, GameStatus::Active | GameStatus::Paused) {
+        return Err("Game is not running".to_string());
+    }
+
+    let lobby = ctx.db.lobby().id().find(game.lobby_id)
+        .ok_or("Lobby not found")?;
+    if lobby.creator != caller(ctx) {
+        return Err("Only lobby creator can adjourn the game".to_string());
+    }
+
+    let settings = get_game_settings_for_game(ctx, game_id)?;
+    if !settings.multi_round_mode {
+        return Err("Only multi-round games can be adjourned".to_string());
+    }
+
+    if let Some(round) = get_current_round(ctx, game_id) {
+        if let Some(turn) = get_active_turn(ctx, round.id) {
+            clear_hidden_defenses(ctx, turn.id);
+        }
+        ctx.db.round().id().update(Round {
+            status: RoundStatus::Voided,
+            finished_at: Some(ctx.timestamp),
+            ..round
+        });
+        log_event(ctx, game_id, GameEventKind::RoundVoided, EventDetail::default());
+    }
+
+    clear_pause_votes(ctx, game_id);
+    clear_draw_votes(ctx, game_id);
+    clear_restart_votes(ctx, game_id);
+
+    let players: Vec<User> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id))
+        .collect();
+
+    for player in players {
+        if player.player_status != Some(PlayerStatus::Left) {
+            ctx.db.adjourned_seat().insert(AdjournedSeat {
+                id: generate_participant_id(game_id, player.identity),
+                game_id,
+                player: player.identity,
+                game_position: player.game_position,
+                total_points: player.total_points,
+                player_status: player.player_status,
+                team: player.team,
+                rejoined: false,
+            });
+            notify(ctx, player.identity, "The game was adjourned, rejoin it when it resumes".to_string());
+        }
+
+        ctx.db.bot_seat().player().delete(player.identity);
+        cancel_disconnect_forfeit(ctx, player.identity);
+        ctx.db.user().identity().update(User {
+            current_game_id: None,
+            game_position: None,
+            total_points: None,
+            player_status: None,
+            team: None,
+            ..player
+        });
+    }
+
+    ctx.db.game().id().update(Game {
+        status: GameStatus::Adjourned,
+        paused_at: None,
+        ..game
+    });
+    update_server_stats(ctx, |stats| stats.active_games = stats.active_games.saturating_sub(1));
+
+    log::info!("Game {} adjourned", game_id);
+    after_game_action(ctx, game_id)
+}
+
+#[reducer]
+/// Take your seat in an adjourned game again, ready for it to resume
+pub fn rejoin_adjourned_game(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    if user.current_lobby_id.is_some() || user.current_game_id.is_some() {
+        return Err("You are already in a lobby or game".to_string());
+    }
+
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+    if game.status != GameStatus::Adjourned {
+        return Err("Game is not adjourned".to_string());
+    }
+
+    let seat = ctx.db.adjourned_seat().id().find(generate_participant_id(game_id, caller(ctx)))
+        .ok_or("You have no seat in this game")?;
+
+    ctx.db.user().identity().update(User {
+        current_game_id: Some(game_id),
+        game_position: seat.game_position,
+        total_points: seat.total_points,
+        player_status: seat.player_status,
+        team: seat.team,
+        ..user
+    });
+    ctx.db.game_spectator().identity().delete(caller(ctx));
+
+    ctx.db.adjourned_seat().id().update(AdjournedSeat {
+        rejoined: true,
+        ..seat
+    });
+
+    log::info!("User {:?} rejoined adjourned game {}", caller(ctx), game_id);
+    Ok(())
+}
+
+#[reducer]
+/// Give up your seat in an adjourned game; it goes on without you
+pub fn leave_adjourned_game(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+    if game.status != GameStatus::Adjourned {
+        return Err("Game is not adjourned".to_string());
+    }
+
+    let seat = ctx.db.adjourned_seat().id().find(generate_participant_id(game_id, caller(ctx)))
+        .ok_or("You have no seat in this game")?;
+
+    if seat.rejoined {
+        let user = ctx.db.user().identity().find(caller(ctx))
+            .ok_or("User not found")?;
+        ctx.db.user().identity().update(User {
+            current_game_id: None,
+            game_position: None,
+            total_points: None,
+            player_status: None,
+            team: None,
+            ..user
+        });
+    }
+
+    ctx.db.adjourned_seat().id().delete(seat.id);
+
+    log::info!("User {:?} gave up their seat in adjourned game {}", caller(ctx), game_id);
+    Ok(())
+}
+
+/// Deal the next round once every remaining seat is taken again
+fn resume_adjourned_game(ctx: &ReducerContext, game: Game) -> Result<(), String> {
+    let game_id = game.id;
+
+    let lobby = ctx.db.lobby().id().find(game.lobby_id)
+        .ok_or("Lobby not found")?;
+    if lobby.creator != caller(ctx) {
+        return Err("Only lobby creator can resume an adjourned game".to_string());
+    }
+
+    let seats: Vec<AdjournedSeat> = ctx.db.adjourned_seat()
+        .iter()
+        .filter(|seat| seat.game_id == game_id)
+        .collect();
+
+    if seats.iter().any(|seat| !seat.rejoined) {
+        return Err("Not every player has rejoined yet".to_string());
+    }
+
+    let contenders = seats.iter()
+        .filter(|seat| seat.player_status != Some(PlayerStatus::Eliminated))
+        .count();
+    if contenders < 2 {
+        return Err("Not enough players to resume".to_string());
+    }
+
+    for seat in seats {
+        ctx.db.adjourned_seat().id().delete(seat.id);
+    }
+
+    ctx.db.game().id().update(Game {
+        status: GameStatus::Active,
+        last_activity: ctx.timestamp,
+        ..game
+    });
+    update_server_stats(ctx, |stats| stats.active_games += 1);
+
+    // The fool of the last finished round is attacked first, as after any round
+    let previous_loser = ctx.db.round()
+        .iter()
+        .filter(|round| round.game_id == game_id && round.status == RoundStatus::Finished)
+        .max_by_key(|round| round.round_number)
+        .and_then(|round| round.loser);
+    start_new_round(ctx, game_id, previous_loser)?;
+
+    log::info!("Adjourned game {} resumed", game_id);
+    after_game_action(ctx, game_id)
+}
+
+// Draw Offers
+
+fn generate_round_vote_id(round_id: u64, player: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    round_id.hash(&mut hasher);
+    player.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn clear_draw_votes(ctx: &ReducerContext, game_id: u64) {
+    let votes: Vec<u64> = ctx.db.draw_vote()
+        .iter()
+        .filter(|vote| vote.game_id == game_id)
+        .map(|vote| vote.id)
+        .collect();
+
+    for vote_id in votes {
+        ctx.db.draw_vote().id().delete(vote_id);
+    }
+}
+
+/// Current round of a game the sender is still playing in
+fn check_draw_voter(ctx: &ReducerContext, game_id: u64) -> Result<Round, String> {
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    if game.status != GameStatus::Active {
+        return Err("Game is not active".to_string());
+    }
+
+    if !get_voting_players(ctx, game_id).contains(&caller(ctx)) {
+        return Err("Only players still in the round can agree to a draw".to_string());
+    }
+
+    get_current_round(ctx, game_id)
+        .ok_or("No active round found".to_string())
+}
+
+fn record_draw_vote(ctx: &ReducerContext, game_id: u64, round: &Round) -> Result<(), String> {
+    let vote_id = generate_round_vote_id(round.id, caller(ctx));
+    if ctx.db.draw_vote().id().find(vote_id).is_none() {
+        ctx.db.draw_vote().insert(DrawVote {
+            id: vote_id,
+            game_id,
+            round_id: round.id,
+            player: caller(ctx),
+            voted_at: ctx.timestamp,
+        });
     }
+
+    let all_agreed = get_voting_players(ctx, game_id).iter().all(|voter| {
+        ctx.db.draw_vote().id().find(generate_round_vote_id(round.id, *voter)).is_some()
+    });
+
+    if !all_agreed {
+        return Ok(());
+    }
+
+    log::info!("Round {} of game {} drawn by agreement", round.round_number, game_id);
+    clear_draw_votes(ctx, game_id);
+    end_round(ctx, game_id, round.id, None)?;
+    after_game_action(ctx, game_id)
 }
 
 #[reducer]
-/// Clients invoke this reducer to send messages.
-pub fn send_message(ctx: &ReducerContext, text: String) -> Result<(), String> {
-    let text = validate_message(text)?;
-    log::info!("{}", text);
-    ctx.db.message().insert(Message {
-        sender: ctx.sender,
-        text,
-        sent: ctx.timestamp,
+/// Offer to end the current round with no fool
+pub fn offer_draw(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    let round = check_draw_voter(ctx, game_id)?;
+
+    if ctx.db.draw_vote().iter().any(|vote| vote.round_id == round.id) {
+        return Err("A draw has already been offered".to_string());
+    }
+
+    // Offers from earlier rounds are void
+    clear_draw_votes(ctx, game_id);
+
+    for voter in get_voting_players(ctx, game_id) {
+        if voter != caller(ctx) {
+            notify(ctx, voter, "A player offers to draw the round".to_string());
+        }
+    }
+
+    record_draw_vote(ctx, game_id, &round)
+}
+
+#[reducer]
+/// Agree to a pending draw offer; the round is drawn once every remaining player agreed
+pub fn accept_draw(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    let round = check_draw_voter(ctx, game_id)?;
+
+    if !ctx.db.draw_vote().iter().any(|vote| vote.round_id == round.id) {
+        return Err("No draw has been offered".to_string());
+    }
+
+    record_draw_vote(ctx, game_id, &round)
+}
+
+#[reducer]
+/// Turn down a pending draw offer
+pub fn decline_draw(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    let round = check_draw_voter(ctx, game_id)?;
+
+    if !ctx.db.draw_vote().iter().any(|vote| vote.round_id == round.id) {
+        return Err("No draw has been offered".to_string());
+    }
+
+    clear_draw_votes(ctx, game_id);
+    Ok(())
+}
+
+// Round Restarts
+
+fn clear_restart_votes(ctx: &ReducerContext, game_id: u64) {
+    let votes: Vec<u64> = ctx.db.restart_vote()
+        .iter()
+        .filter(|vote| vote.game_id == game_id)
+        .map(|vote| vote.id)
+        .collect();
+
+    for vote_id in votes {
+        ctx.db.restart_vote().id().delete(vote_id);
+    }
+}
+
+#[reducer]
+/// Vote to void the current round and deal it again (misdeal, agreed do-over); needs everyone dealt in
+pub fn vote_restart_round(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    if game.status != GameStatus::Active {
+        return Err("Game is not active".to_string());
+    }
+
+    let round = get_current_round(ctx, game_id)
+        .ok_or("No active round found")?;
+
+    // Players who already went out lose their escape, so they get a say too
+    let voters: Vec<Identity> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id))
+        .filter(|user| matches!(user.player_status, Some(PlayerStatus::Active) | Some(PlayerStatus::Finished)))
+        .filter(|user| user.online && !is_bot(ctx, user.identity))
+        .map(|user| user.identity)
+        .collect();
+
+    if !voters.contains(&caller(ctx)) {
+        return Err("Only players dealt into this round can vote".to_string());
+    }
+
+    // Votes from earlier rounds are void
+    let stale: Vec<u64> = ctx.db.restart_vote()
+        .iter()
+        .filter(|vote| vote.game_id == game_id && vote.round_id != round.id)
+        .map(|vote| vote.id)
+        .collect();
+    for vote_id in stale {
+        ctx.db.restart_vote().id().delete(vote_id);
+    }
+
+    let vote_id = generate_round_vote_id(round.id, caller(ctx));
+    if ctx.db.restart_vote().id().find(vote_id).is_some() {
+        return Err("You already voted to restart this round".to_string());
+    }
+
+    ctx.db.restart_vote().insert(RestartVote {
+        id: vote_id,
+        game_id,
+        round_id: round.id,
+        player: caller(ctx),
+        voted_at: ctx.timestamp,
+    });
+
+    let all_agreed = voters.iter().all(|voter| {
+        ctx.db.restart_vote().id().find(generate_round_vote_id(round.id, *voter)).is_some()
+    });
+
+    if !all_agreed {
+        for voter in voters.iter().filter(|voter| **voter != caller(ctx)) {
+            notify(ctx, *voter, "A player votes to restart the round".to_string());
+        }
+        return Ok(());
+    }
+
+    clear_restart_votes(ctx, game_id);
+    clear_draw_votes(ctx, game_id);
+    if let Some(turn) = get_active_turn(ctx, round.id) {
+        clear_hidden_defenses(ctx, turn.id);
+    }
+
+    ctx.db.round().id().update(Round {
+        status: RoundStatus::Voided,
+        finished_at: Some(ctx.timestamp),
+        ..round.clone()
     });
+    log_event(ctx, game_id, GameEventKind::RoundVoided, EventDetail::default());
+
+    // Open the new deal the way the voided one was opened, no points change hands
+    let previous_loser = match round.round_number {
+        1 => None,
+        number => ctx.db.round().id().find(generate_round_id(game_id, number - 1)).and_then(|previous| previous.loser),
+    };
+    start_new_round(ctx, game_id, previous_loser)?;
+
+    log::info!("Round {} of game {} voided and dealt again", round.round_number, game_id);
+    after_game_action(ctx, game_id)
+}
+
+// Leaving Games
+
+/// How often running games are checked for inactivity
+const GAME_EXPIRY_CHECK_MICROS: i64 = 5 * 60 * 1_000_000;
+
+/// Games without a player move for this long are abandoned
+const GAME_MAX_IDLE_MICROS: i64 = 60 * 60 * 1_000_000;
+
+#[reducer]
+/// Finish abandoned games with no loser so their players aren't stuck in them (scheduled)
+pub fn finish_idle_games(ctx: &ReducerContext, schedule: GameExpirySchedule) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can finish idle games".to_string());
+    }
+
+    let deadline = ctx.timestamp - TimeDuration::from_micros(schedule.max_idle_micros);
+    let idle: Vec<u64> = ctx.db.game()
+        .iter()
+        .filter(|game| matches!(game.status, GameStatus::Active | GameStatus::Paused) && game.last_activity < deadline)
+        .map(|game| game.id)
+        .collect();
+
+    for game_id in idle {
+        let players: Vec<Identity> = ctx.db.user()
+            .iter()
+            .filter(|user| user.current_game_id == Some(game_id))
+            .map(|user| user.identity)
+            .collect();
+
+        finish_game(ctx, game_id, None)?;
+        after_game_action(ctx, game_id)?;
+
+        for player in players {
+            notify(ctx, player, "Your game was ended after being idle".to_string());
+        }
+        log::info!("Game {} finished after inactivity", game_id);
+    }
+
     Ok(())
 }
 
-/// Takes a message's text and checks if it's acceptable to send.
-fn validate_message(text: String) -> Result<String, String> {
-    if text.is_empty() {
-        Err("Messages must not be empty".to_string())
-    } else {
-        Ok(text)
+/// How often an expired game or round limit is checked again while the game is paused
+const TIME_LIMIT_RETRY_MICROS: i64 = 10 * 1_000_000;
+
+#[reducer]
+/// End a game that ran out of total time (scheduled). The loser is the player with the most points,
+/// or the most cards in hand when points are tied or not kept; a full tie ends without a loser.
+pub fn end_game_on_time_limit(ctx: &ReducerContext, limit: GameTimeLimit) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can end games on time".to_string());
+    }
+
+    let Some(game) = ctx.db.game().id().find(limit.game_id) else {
+        return Ok(());
+    };
+
+    match game.status {
+        GameStatus::Active => {}
+        // The game ends as soon as play resumes
+        GameStatus::Paused => {
+            ctx.db.game_time_limit().insert(GameTimeLimit {
+                scheduled_id: 0,
+                scheduled_at: ScheduleAt::Time(ctx.timestamp + TimeDuration::from_micros(TIME_LIMIT_RETRY_MICROS)),
+                ..limit
+            });
+            return Ok(());
+        }
+        // Adjourned games are on hold, not on the clock
+        _ => return Ok(()),
+    }
+
+    let game_id = game.id;
+    let settings = get_game_settings_for_game(ctx, game_id)?;
+
+    let standings: Vec<(Identity, u8, usize)> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id))
+        .filter(|user| matches!(user.player_status, Some(PlayerStatus::Active) | Some(PlayerStatus::Finished)))
+        .map(|user| {
+            let points = if settings.multi_round_mode { user.total_points.unwrap_or(0) } else { 0 };
+            (user.identity, points, get_player_cards(ctx, game_id, user.identity).len())
+        })
+        .collect();
+
+    let worst = standings.iter().map(|(_, points, cards)| (*points, *cards)).max();
+    let mut worst_players = standings.iter().filter(|(_, points, cards)| Some((*points, *cards)) == worst);
+    let loser = match (worst_players.next(), worst_players.next()) {
+        (Some((player, _, _)), None) => Some(*player),
+        _ => None,
+    };
+
+    log::info!("Game {} ran out of time, loser: {:?}", game_id, loser);
+
+    let players: Vec<Identity> = standings.iter().map(|(player, _, _)| *player).collect();
+    finish_game(ctx, game_id, loser)?;
+    for player in players {
+        notify(ctx, player, "Time is up, the game is over".to_string());
     }
+
+    after_game_action(ctx, game_id)
 }
 
-#[reducer(client_connected)]
-// Called when a client connects to a SpacetimeDB database server
-pub fn client_connected(ctx: &ReducerContext) {
-    if let Some(user) = ctx.db.user().identity().find(ctx.sender) {
-        // If this is a returning user, i.e. we already have a `User` with this `Identity`,
-        // set `online: true`, but leave other fields unchanged.
-        ctx.db.user().identity().update(User { online: true, ..user });
-    } else {
-        // If this is a new user, create a `User` row for the `Identity`,
-        // which is online, but hasn't set a name or joined any lobbies/games.
-        ctx.db.user().insert(User {
-            name: None,
-            identity: ctx.sender,
-            online: true,
-            current_lobby_id: None,
-            lobby_joined_at: None,
-            current_game_id: None,
-            game_position: None,
-            total_points: None,
-            player_status: None,
+
+fn arm_round_time_limit(ctx: &ReducerContext, game_id: u64, round_id: u64, settings: &GameSettings) {
+    let stale: Vec<u64> = ctx.db.round_time_limit()
+        .iter()
+        .filter(|limit| limit.game_id == game_id)
+        .map(|limit| limit.scheduled_id)
+        .collect();
+    for scheduled_id in stale {
+        ctx.db.round_time_limit().scheduled_id().delete(scheduled_id);
+    }
+
+    if settings.max_round_minutes > 0 {
+        ctx.db.round_time_limit().insert(RoundTimeLimit {
+            scheduled_id: 0,
+            scheduled_at: ScheduleAt::Time(ctx.timestamp + TimeDuration::from_micros(settings.max_round_minutes as i64 * 60 * 1_000_000)),
+            game_id,
+            round_id,
         });
     }
 }
 
-#[reducer(client_disconnected)]
-// Called when a client disconnects from SpacetimeDB database server
-pub fn identity_disconnected(ctx: &ReducerContext) {
-    if let Some(user) = ctx.db.user().identity().find(ctx.sender) {
-        ctx.db.user().identity().update(User { online: false, ..user });
-    } else {
-        // This branch should be unreachable,
-        // as it doesn't make sense for a client to disconnect without connecting first.
-        log::warn!("Disconnect event for unknown user with identity {:?}", ctx.sender);
+#[reducer]
+/// End a round that ran out of time (scheduled). The player holding the most cards is the
+/// round's loser and scoring goes on as usual; a tie for most cards ends the round as a draw.
+pub fn end_round_on_time_limit(ctx: &ReducerContext, limit: RoundTimeLimit) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can end rounds on time".to_string());
+    }
+
+    let Some(game) = ctx.db.game().id().find(limit.game_id) else {
+        return Ok(());
+    };
+
+    let Some(round) = get_current_round(ctx, game.id) else {
+        return Ok(());
+    };
+    if round.id != limit.round_id || round.status != RoundStatus::Active {
+        return Ok(());
+    }
+
+    match game.status {
+        GameStatus::Active => {}
+        // The round ends as soon as play resumes
+        GameStatus::Paused => {
+            ctx.db.round_time_limit().insert(RoundTimeLimit {
+                scheduled_id: 0,
+                scheduled_at: ScheduleAt::Time(ctx.timestamp + TimeDuration::from_micros(TIME_LIMIT_RETRY_MICROS)),
+                ..limit
+            });
+            return Ok(());
+        }
+        _ => return Ok(()),
+    }
+
+    let game_id = game.id;
+    let hands: Vec<(Identity, usize)> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id) && user.player_status == Some(PlayerStatus::Active))
+        .map(|user| (user.identity, get_player_cards(ctx, game_id, user.identity).len()))
+        .collect();
+
+    let most = hands.iter().map(|(_, cards)| *cards).max();
+    let mut holders = hands.iter().filter(|(_, cards)| Some(*cards) == most);
+    let loser = match (holders.next(), holders.next()) {
+        (Some((player, _)), None) => Some(*player),
+        _ => None,
+    };
+
+    log::info!("Round {} of game {} ran out of time, loser: {:?}", round.round_number, game_id, loser);
+
+    end_round(ctx, game_id, round.id, loser)?;
+    for (player, _) in hands {
+        notify(ctx, player, "Time is up, the round is over".to_string());
     }
+
+    after_game_action(ctx, game_id)
 }
 
-// Lobby Management
+/// Take a player out of a running game: their hand leaves play, the current turn moves on
+/// without them, and the game ends if fewer than two players are left in contention.
+fn drop_player_from_game(ctx: &ReducerContext, game_id: u64, player: Identity) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(player)
+        .ok_or("User not found")?;
 
-/// Generate a unique lobby ID (simple counter approach for now)
-fn generate_lobby_id(_timestamp: Timestamp) -> u64 {
-    // For now, use a simple random-like ID. In production, this could be more sophisticated.
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    
-    let mut hasher = DefaultHasher::new();
-    _timestamp.hash(&mut hasher);
-    hasher.finish()
+    if user.current_game_id != Some(game_id) {
+        return Err("Player is not in this game".to_string());
+    }
+
+    if !matches!(user.player_status, Some(PlayerStatus::Active) | Some(PlayerStatus::Finished)) {
+        return Err("Player is no longer playing".to_string());
+    }
+
+    let round = get_current_round(ctx, game_id);
+    let turn = round.as_ref().and_then(|round| get_active_turn(ctx, round.id));
+
+    // Whoever sits after the leaving player picks up their role in the turn
+    let involved = turn.as_ref().is_some_and(|turn| turn.attacker == player || turn.defender == player);
+    let successor = if involved {
+        Some(get_next_player_clockwise(ctx, game_id, player)?)
+    } else {
+        None
+    };
+
+    ctx.db.seat_vacancy().player().delete(player);
+    ctx.db.seat_vacancy().insert(SeatVacancy {
+        player,
+        game_id,
+        game_position: user.game_position,
+        total_points: user.total_points,
+        team: user.team,
+        hand: get_player_cards(ctx, game_id, player).iter().map(|pc| pc.id).collect(),
+        left_at: ctx.timestamp,
+    });
+
+    ctx.db.user().identity().update(User {
+        player_status: Some(PlayerStatus::Left),
+        ..user
+    });
+    log_event(ctx, game_id, GameEventKind::PlayerLeft, EventDetail {
+        player: Some(player),
+        ..Default::default()
+    });
+
+    // The deck is drawn in table order, so returned cards end up mixed in
+    let settings = get_game_settings_for_game(ctx, game_id)?;
+    let location = match settings.leaver_hand {
+        LeaverHand::Discard => CardLocation::Discarded,
+        LeaverHand::ReturnToDeck => CardLocation::Deck,
+    };
+
+    for player_card in get_player_cards(ctx, game_id, player) {
+        put_card(ctx, PlayerCard {
+            location,
+            ..player_card
+        });
+    }
+
+    log::info!("Player {:?} left game {}", player, game_id);
+
+    let in_contention = ctx.db.user()
+        .iter()
+        .filter(|u| u.current_game_id == Some(game_id))
+        .filter(|u| matches!(u.player_status, Some(PlayerStatus::Active) | Some(PlayerStatus::Finished)))
+        .count();
+
+    if in_contention < 2 {
+        return finish_game(ctx, game_id, Some(player));
+    }
+
+    let (Some(round), Some(turn)) = (round, turn) else {
+        return Ok(());
+    };
+
+    match successor {
+        Some(successor) => {
+            // The turn can't go on without one of its sides: clear the table and start over
+            clear_hidden_defenses(ctx, turn.id);
+            let table_cards: Vec<PlayerCard> = ctx.db.player_card()
+                .iter()
+                .filter(|pc| pc.game_id == game_id && matches!(pc.location, CardLocation::OnTable | CardLocation::FaceDown))
+                .collect();
+
+            for player_card in table_cards {
+                put_card(ctx, PlayerCard {
+                    location: CardLocation::Discarded,
+                    ..player_card
+                });
+            }
+
+            ctx.db.turn().id().update(Turn {
+                status: TurnStatus::DefenderBeat,
+                finished_at: Some(ctx.timestamp),
+                ..turn.clone()
+            });
+
+            refill_hands(ctx, game_id, &turn)?;
+            start_next_turn_after_defense(ctx, game_id, round.id, successor)
+        }
+        None if turn.status == TurnStatus::Taking => {
+            // The leaving player may have been the last one still throwing in
+            if all_attackers_passed(ctx, game_id, &turn)? {
+                complete_take(ctx, game_id, &turn)?;
+            }
+            Ok(())
+        }
+        None => {
+            if ctx.db.draw().iter().any(|draw| draw.turn_id == turn.id) {
+                try_finish_turn(ctx, game_id, &turn)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn cancel_disconnect_forfeit(ctx: &ReducerContext, player: Identity) {
+    let pending: Vec<u64> = ctx.db.disconnect_forfeit()
+        .iter()
+        .filter(|forfeit| forfeit.player == player)
+        .map(|forfeit| forfeit.scheduled_id)
+        .collect();
+
+    for scheduled_id in pending {
+        ctx.db.disconnect_forfeit().scheduled_id().delete(scheduled_id);
+    }
 }
 
 #[reducer]
-/// Creates a new lobby with the specified name and max players
-pub fn create_lobby(ctx: &ReducerContext, name: String, max_players: u8) -> Result<(), String> {
-    if name.is_empty() {
-        return Err("Lobby name cannot be empty".to_string());
-    }
-    
-    if max_players < 2 || max_players > 6 {
-        return Err("Max players must be between 2 and 6".to_string());
+/// A disconnected player didn't come back in time and leaves the game (scheduled)
+pub fn forfeit_disconnected_player(ctx: &ReducerContext, forfeit: DisconnectForfeit) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can forfeit players".to_string());
     }
 
-    let user = ctx.db.user().identity().find(ctx.sender)
-        .ok_or("User not found")?;
+    let Some(user) = ctx.db.user().identity().find(forfeit.player) else {
+        return Ok(());
+    };
 
-    if user.current_lobby_id.is_some() {
-        return Err("You are already in a lobby".to_string());
+    let still_playing = matches!(user.player_status, Some(PlayerStatus::Active) | Some(PlayerStatus::Finished));
+    if user.online || user.current_game_id != Some(forfeit.game_id) || !still_playing {
+        return Ok(());
     }
 
-    if user.current_game_id.is_some() {
-        return Err("You are currently in a game".to_string());
+    // Players on autopilot keep their seat, the table moves on without them anyway
+    if is_bot(ctx, forfeit.player) {
+        return Ok(());
     }
 
-    let lobby_id = generate_lobby_id(ctx.timestamp);
-    
-    // Create the lobby
-    ctx.db.lobby().insert(Lobby {
-        id: lobby_id,
-        name,
-        creator: ctx.sender,
-        max_players,
-        current_players: 1,
-        status: LobbyStatus::Waiting,
-        created_at: ctx.timestamp,
-    });
-
-    // Update user to join the lobby
-    ctx.db.user().identity().update(User {
-        current_lobby_id: Some(lobby_id),
-        lobby_joined_at: Some(ctx.timestamp),
-        ..user
-    });
+    // A pause does not protect players who are gone
+    let game_running = ctx.db.game().id().find(forfeit.game_id)
+        .is_some_and(|game| game.status != GameStatus::Finished);
+    if !game_running {
+        return Ok(());
+    }
 
-    log::info!("User {:?} created lobby {}", ctx.sender, lobby_id);
-    Ok(())
+    let settings = get_game_settings_for_game(ctx, forfeit.game_id)?;
+    if settings.bot_takeover {
+        log::info!("Bot takes over for {:?} in game {}", forfeit.player, forfeit.game_id);
+        ctx.db.bot_seat().insert(BotSeat {
+            player: forfeit.player,
+            game_id: forfeit.game_id,
+            reason: BotReason::Disconnected,
+            since: ctx.timestamp,
+        });
+    } else {
+        log::info!("Player {:?} did not reconnect to game {}", forfeit.player, forfeit.game_id);
+        drop_player_from_game(ctx, forfeit.game_id, forfeit.player)?;
+    }
+    after_game_action(ctx, forfeit.game_id)
 }
 
 #[reducer]
-/// Join an existing lobby by ID
-pub fn join_lobby(ctx: &ReducerContext, lobby_id: u64) -> Result<(), String> {
-    let user = ctx.db.user().identity().find(ctx.sender)
+/// Surrender: leave a running game, taking the loser points of the round with you
+pub fn leave_game(ctx: &ReducerContext) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
         .ok_or("User not found")?;
 
-    if user.current_lobby_id.is_some() {
-        return Err("You are already in a lobby".to_string());
+    let game_id = user.current_game_id
+        .ok_or("You are not in a game")?;
+
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+    if game.status == GameStatus::Finished {
+        return Err("Game is already finished".to_string());
     }
 
-    if user.current_game_id.is_some() {
-        return Err("You are currently in a game".to_string());
+    if !matches!(user.player_status, Some(PlayerStatus::Active) | Some(PlayerStatus::Finished)) {
+        return Err("You already left this game".to_string());
     }
 
-    let lobby = ctx.db.lobby().id().find(lobby_id)
-        .ok_or("Lobby not found")?;
+    let player_status = user.player_status;
+    let total_points = user.total_points;
+    let hand: Vec<u64> = get_player_cards(ctx, game_id, caller(ctx))
+        .iter()
+        .map(|pc| pc.id)
+        .collect();
 
-    if lobby.status != LobbyStatus::Waiting {
-        return Err("Lobby is not accepting new players".to_string());
+    // Charged like losing the round, before the hand is gone
+    let settings = get_game_settings_for_game(ctx, game_id)?;
+    if settings.multi_round_mode {
+        let penalty = round_penalty(ctx, game_id, caller(ctx), settings.scoring_mode);
+        ctx.db.user().identity().update(User {
+            total_points: Some(user.total_points.unwrap_or(0).saturating_add(penalty)),
+            ..user
+        });
     }
 
-    if lobby.current_players >= lobby.max_players {
-        return Err("Lobby is full".to_string());
-    }
+    ctx.db.bot_seat().player().delete(caller(ctx));
+    cancel_disconnect_forfeit(ctx, caller(ctx));
+    drop_player_from_game(ctx, game_id, caller(ctx))?;
 
-    // Update lobby player count
-    ctx.db.lobby().id().update(Lobby {
-        current_players: lobby.current_players + 1,
-        ..lobby
+    let turn = get_current_turn(ctx, game_id);
+    ctx.db.recent_leave().player().delete(caller(ctx));
+    ctx.db.recent_leave().insert(RecentLeave {
+        player: caller(ctx),
+        lobby_id: None,
+        lobby_joined_at: None,
+        team: None,
+        game_id: Some(game_id),
+        player_status,
+        total_points,
+        hand,
+        turn_id: turn.as_ref().map(|turn| turn.id),
+        draw_count: turn.map_or(0, |turn| count_turn_draws(ctx, turn.id)),
+        left_at: ctx.timestamp,
     });
 
-    // Update user to join the lobby
-    ctx.db.user().identity().update(User {
-        current_lobby_id: Some(lobby_id),
-        lobby_joined_at: Some(ctx.timestamp),
-        ..user
-    });
+    after_game_action(ctx, game_id)
+}
 
-    log::info!("User {:?} joined lobby {}", ctx.sender, lobby_id);
-    Ok(())
+/// How long a fat-fingered exit can still be taken back
+const UNDO_LEAVE_MICROS: i64 = 30 * 1_000_000;
+
+fn count_turn_draws(ctx: &ReducerContext, turn_id: u64) -> u32 {
+    ctx.db.draw().iter().filter(|draw| draw.turn_id == turn_id).count() as u32
 }
 
 #[reducer]
-/// Leave the current lobby
-pub fn leave_lobby(ctx: &ReducerContext) -> Result<(), String> {
-    let user = ctx.db.user().identity().find(ctx.sender)
-        .ok_or("User not found")?;
+/// Take back a leave_lobby or leave_game made in the last 30 seconds, if nothing moved on since
+pub fn undo_leave(ctx: &ReducerContext) -> Result<(), String> {
+    let leave = ctx.db.recent_leave().player().find(caller(ctx))
+        .ok_or("Nothing to undo")?;
+
+    if ctx.timestamp > leave.left_at + TimeDuration::from_micros(UNDO_LEAVE_MICROS) {
+        ctx.db.recent_leave().player().delete(caller(ctx));
+        return Err("Too late to undo leaving".to_string());
+    }
 
-    let lobby_id = user.current_lobby_id
-        .ok_or("You are not in a lobby")?;
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
 
-    let lobby = ctx.db.lobby().id().find(lobby_id)
-        .ok_or("Lobby not found")?;
+    if let Some(lobby_id) = leave.lobby_id {
+        if user.current_lobby_id.is_some() || user.current_game_id.is_some() {
+            return Err("You are already in a lobby or game".to_string());
+        }
 
-    // Update lobby player count
-    let new_player_count = lobby.current_players.saturating_sub(1);
-    
-    if new_player_count == 0 || lobby.creator == ctx.sender {
-        // If lobby is empty or creator left, delete the lobby
-        ctx.db.lobby().id().delete(lobby_id);
-        log::info!("Lobby {} deleted", lobby_id);
-    } else {
-        // Just update player count
-        ctx.db.lobby().id().update(Lobby {
-            current_players: new_player_count,
-            ..lobby
-        });
-    }
+        let lobby = ctx.db.lobby().id().find(lobby_id)
+            .ok_or("The lobby no longer exists")?;
 
-    // Update user to leave the lobby
-    ctx.db.user().identity().update(User {
-        current_lobby_id: None,
-        lobby_joined_at: None,
-        ..user
-    });
+        if lobby.status != LobbyStatus::Waiting {
+            return Err("The lobby is no longer waiting".to_string());
+        }
 
-    log::info!("User {:?} left lobby {}", ctx.sender, lobby_id);
-    Ok(())
-}
+        if lobby.current_players as usize + count_reserved_seats(ctx, lobby_id, caller(ctx)) >= lobby.max_players as usize {
+            return Err("Your seat has been taken".to_string());
+        }
 
-// Game Settings Management
+        enter_lobby(ctx, user, lobby);
 
-#[reducer]
-/// Update game settings for a lobby (only creator can do this)
-pub fn update_game_settings(
-    ctx: &ReducerContext, 
-    lobby_id: u64,
-    deck_size: DeckSize,
-    starting_cards: u8,
-    max_attack_cards: u8,
-    multi_round_mode: bool,
-    max_points: u8,
-    anyone_can_attack: bool,
-    trump_card_to_player: bool
-) -> Result<(), String> {
-    let user = ctx.db.user().identity().find(ctx.sender)
-        .ok_or("User not found")?;
+        // Back in the original place in the queue and team
+        let user = ctx.db.user().identity().find(caller(ctx))
+            .ok_or("User not found")?;
+        ctx.db.user().identity().update(User {
+            lobby_joined_at: leave.lobby_joined_at,
+            team: leave.team,
+            ..user
+        });
 
-    if user.current_lobby_id != Some(lobby_id) {
-        return Err("You are not in this lobby".to_string());
+        ctx.db.recent_leave().player().delete(caller(ctx));
+        log::info!("User {:?} returned to lobby {}", caller(ctx), lobby_id);
+        return Ok(());
     }
 
-    let lobby = ctx.db.lobby().id().find(lobby_id)
-        .ok_or("Lobby not found")?;
-
-    if lobby.creator != ctx.sender {
-        return Err("Only lobby creator can change settings".to_string());
-    }
+    let game_id = leave.game_id
+        .ok_or("Nothing to undo")?;
 
-    if lobby.status != LobbyStatus::Waiting {
-        return Err("Cannot change settings after game has started".to_string());
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+    if game.status == GameStatus::Finished {
+        return Err("The game is over".to_string());
     }
 
-    // Validate settings
-    if starting_cards < 3 || starting_cards > 20 {
-        return Err("Starting cards must be between 3 and 20".to_string());
+    if user.current_game_id != Some(game_id) || user.player_status != Some(PlayerStatus::Left) {
+        return Err("You are not out of this game".to_string());
     }
 
-    if max_points < 5 || max_points > 50 {
-        return Err("Max points must be between 5 and 50".to_string());
+    // Any move since leaving could have touched the cards that were given up
+    let turn = get_current_turn(ctx, game_id);
+    let moved_on = turn.as_ref().map(|turn| turn.id) != leave.turn_id
+        || turn.map_or(0, |turn| count_turn_draws(ctx, turn.id)) != leave.draw_count;
+    if moved_on {
+        return Err("The game has moved on".to_string());
     }
 
-    // Insert or update settings
-    if let Some(existing) = ctx.db.game_settings().lobby_id().find(lobby_id) {
-        ctx.db.game_settings().lobby_id().update(GameSettings {
-            deck_size,
-            starting_cards,
-            max_attack_cards,
-            multi_round_mode,
-            max_points,
-            anyone_can_attack,
-            trump_card_to_player,
-            ..existing
-        });
-    } else {
-        ctx.db.game_settings().insert(GameSettings {
-            lobby_id,
-            deck_size,
-            starting_cards,
-            max_attack_cards,
-            multi_round_mode,
-            max_points,
-            anyone_can_attack,
-            trump_card_to_player,
+    for card_id in &leave.hand {
+        let player_card = ctx.db.player_card().id().find(*card_id)
+            .ok_or("Card not found")?;
+        put_card(ctx, PlayerCard {
+            player: caller(ctx),
+            location: CardLocation::Hand,
+            ..player_card
         });
     }
 
-    log::info!("Game settings updated for lobby {}", lobby_id);
-    Ok(())
-}
+    ctx.db.user().identity().update(User {
+        player_status: leave.player_status,
+        total_points: leave.total_points,
+        ..user
+    });
 
-/// Get default game settings
-fn get_default_settings(lobby_id: u64) -> GameSettings {
-    GameSettings {
-        lobby_id,
-        deck_size: DeckSize::Standard36,
-        starting_cards: 7,
-        max_attack_cards: 6,
-        multi_round_mode: true,
-        max_points: 15,
-        anyone_can_attack: true,
-        trump_card_to_player: true,
-    }
+    ctx.db.recent_leave().player().delete(caller(ctx));
+    clear_seat_vacancy(ctx, caller(ctx));
+    log_event(ctx, game_id, GameEventKind::PlayerReturned, EventDetail {
+        player: Some(caller(ctx)),
+        ..Default::default()
+    });
+    log::info!("User {:?} returned to game {}", caller(ctx), game_id);
+    after_game_action(ctx, game_id)
 }
 
-// Card and Deck Management
-
-/// Generate a full deck based on deck size setting
-fn create_deck(deck_size: DeckSize) -> Vec<Card> {
-    let mut deck = Vec::new();
-    let suits = [Suit::Hearts, Suit::Diamonds, Suit::Clubs, Suit::Spades];
-    
-    let ranks = match deck_size {
-        DeckSize::Standard36 => vec![
-            Rank::Six, Rank::Seven, Rank::Eight, Rank::Nine, Rank::Ten,
-            Rank::Jack, Rank::Queen, Rank::King, Rank::Ace
-        ],
-        DeckSize::Extended52 => vec![
-            Rank::Six, Rank::Seven, Rank::Eight, Rank::Nine, Rank::Ten,
-            Rank::Jack, Rank::Queen, Rank::King, Rank::Ace
-        ], // TODO: Add ranks 2-5 for extended deck
-    };
-
-    for suit in suits {
-        for rank in &ranks {
-            deck.push(Card { suit, rank: *rank });
-        }
-    }
-
-    deck
-}
+// Substitutes
 
-/// Shuffle deck using timestamp-based seeding
-fn shuffle_deck(mut deck: Vec<Card>, timestamp: Timestamp) -> Vec<Card> {
+fn generate_substitution_id(game_id: u64, seat_of: Identity, candidate: Identity) -> u64 {
     use std::collections::hash_map::DefaultHasher;
     use std::hash::{Hash, Hasher};
-    
-    // Create a deterministic but unpredictable seed
-    let mut hasher = DefaultHasher::new();
-    timestamp.hash(&mut hasher);
-    let seed = hasher.finish();
-    
-    // Simple Fisher-Yates shuffle with our seed
-    for i in (1..deck.len()).rev() {
-        let j = (seed.wrapping_mul(i as u64 + 1) % (i as u64 + 1)) as usize;
-        deck.swap(i, j);
-    }
-    
-    deck
-}
 
-/// Generate unique IDs for game entities
-fn generate_game_id(timestamp: Timestamp) -> u64 {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    
     let mut hasher = DefaultHasher::new();
-    timestamp.hash(&mut hasher);
+    game_id.hash(&mut hasher);
+    seat_of.hash(&mut hasher);
+    candidate.hash(&mut hasher);
     hasher.finish()
 }
 
-fn generate_round_id(game_id: u64, round_number: u32) -> u64 {
+fn generate_substitution_vote_id(request_id: u64, voter: Identity) -> u64 {
     use std::collections::hash_map::DefaultHasher;
     use std::hash::{Hash, Hasher};
-    
+
     let mut hasher = DefaultHasher::new();
-    game_id.hash(&mut hasher);
-    round_number.hash(&mut hasher);
+    request_id.hash(&mut hasher);
+    voter.hash(&mut hasher);
     hasher.finish()
 }
 
-#[reducer]
-/// Start the game from a lobby (only creator can do this)
-pub fn start_game(ctx: &ReducerContext, lobby_id: u64) -> Result<(), String> {
-    let user = ctx.db.user().identity().find(ctx.sender)
-        .ok_or("User not found")?;
-
-    if user.current_lobby_id != Some(lobby_id) {
-        return Err("You are not in this lobby".to_string());
-    }
-
-    let lobby = ctx.db.lobby().id().find(lobby_id)
-        .ok_or("Lobby not found")?;
-
-    if lobby.creator != ctx.sender {
-        return Err("Only lobby creator can start the game".to_string());
-    }
-
-    if lobby.status != LobbyStatus::Waiting {
-        return Err("Game has already been started".to_string());
-    }
-
-    if lobby.current_players < 2 {
-        return Err("Need at least 2 players to start".to_string());
-    }
-
-    // Get or create game settings
-    let settings = ctx.db.game_settings().lobby_id().find(lobby_id)
-        .unwrap_or_else(|| get_default_settings(lobby_id));
+/// Close a vacant seat along with every request for it
+fn clear_seat_vacancy(ctx: &ReducerContext, seat_of: Identity) {
+    ctx.db.seat_vacancy().player().delete(seat_of);
 
-    // Get all players in the lobby
-    let players: Vec<User> = ctx.db.user()
+    let requests: Vec<u64> = ctx.db.substitution_request()
         .iter()
-        .filter(|user| user.current_lobby_id == Some(lobby_id))
+        .filter(|request| request.seat_of == seat_of)
+        .map(|request| request.id)
         .collect();
 
-    if players.len() != lobby.current_players as usize {
-        return Err("Player count mismatch".to_string());
+    for request_id in requests {
+        clear_substitution_request(ctx, request_id);
     }
+}
 
-    // Generate deck and determine trump suit
-    let deck = create_deck(settings.deck_size);
-    let shuffled_deck = shuffle_deck(deck, ctx.timestamp);
-    
-    // Trump suit is the suit of the last card (bottom of deck)
-    let trump_suit = shuffled_deck.last().unwrap().suit;
-
-    // Create game
-    let game_id = generate_game_id(ctx.timestamp);
-    ctx.db.game().insert(Game {
-        id: game_id,
-        lobby_id,
-        status: GameStatus::Active,
-        trump_suit,
-        current_round: 1,
-        started_at: ctx.timestamp,
-        finished_at: None,
-    });
-
-    // Deal cards to players
-    let mut card_index = 0;
-    let mut card_id_counter = 0;
-
-    // Deal starting cards to each player
-    for (position, player) in players.iter().enumerate() {
-        for _ in 0..settings.starting_cards {
-            if card_index >= shuffled_deck.len() {
-                return Err("Not enough cards in deck".to_string());
-            }
-
-            ctx.db.player_card().insert(PlayerCard {
-                id: card_id_counter,
-                game_id,
-                player: player.identity,
-                card: shuffled_deck[card_index].clone(),
-                location: CardLocation::Hand,
-            });
-
-            card_index += 1;
-            card_id_counter += 1;
-        }
+fn clear_substitution_request(ctx: &ReducerContext, request_id: u64) {
+    ctx.db.substitution_request().id().delete(request_id);
 
-        // Update user to join game
-        ctx.db.user().identity().update(User {
-            identity: player.identity,
-            name: player.name.clone(),
-            online: player.online,
-            current_lobby_id: None,
-            lobby_joined_at: None,
-            current_game_id: Some(game_id),
-            game_position: Some(position as u8),
-            total_points: Some(0),
-            player_status: Some(PlayerStatus::Active),
-        });
+    let votes: Vec<u64> = ctx.db.substitution_vote()
+        .iter()
+        .filter(|vote| vote.request_id == request_id)
+        .map(|vote| vote.id)
+        .collect();
+
+    for vote_id in votes {
+        ctx.db.substitution_vote().id().delete(vote_id);
     }
+}
 
-    // Put remaining cards in deck
-    for i in card_index..shuffled_deck.len() {
-        ctx.db.player_card().insert(PlayerCard {
-            id: card_id_counter,
-            game_id,
-            player: players[0].identity, // Assign to first player for now, doesn't matter for deck cards
-            card: shuffled_deck[i].clone(),
-            location: CardLocation::Deck,
-        });
-        card_id_counter += 1;
+#[reducer]
+/// Ask to take over the seat a player left; the lobby creator or a majority of active players must approve
+pub fn substitute_player(ctx: &ReducerContext, seat_of: Identity) -> Result<(), String> {
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+
+    if user.current_lobby_id.is_some() || user.current_game_id.is_some() {
+        return Err("You are already in a lobby or game".to_string());
     }
 
-    // If trump card goes to player (traditional rule)
-    if settings.trump_card_to_player && !shuffled_deck.is_empty() {
-        let trump_card = shuffled_deck.last().unwrap();
-        // Find the trump card in deck and move to last player's hand
-        let last_player = &players[players.len() - 1];
-        
-        // This is simplified - in real implementation you'd find the actual trump card record
-        ctx.db.player_card().insert(PlayerCard {
-            id: card_id_counter,
-            game_id,
-            player: last_player.identity,
-            card: trump_card.clone(),
-            location: CardLocation::Hand,
-        });
+    let vacancy = ctx.db.seat_vacancy().player().find(seat_of)
+        .ok_or("That seat is not vacant")?;
+
+    let game = ctx.db.game().id().find(vacancy.game_id)
+        .ok_or("Game not found")?;
+    if !matches!(game.status, GameStatus::Active | GameStatus::Paused) {
+        return Err("Game is not running".to_string());
     }
 
-    // Create first round
-    let round_id = generate_round_id(game_id, 1);
-    ctx.db.round().insert(Round {
-        id: round_id,
-        game_id,
-        round_number: 1,
-        status: RoundStatus::Active,
-        loser: None,
-        started_at: ctx.timestamp,
-        finished_at: None,
-    });
+    check_guest_allowed(&user, &get_game_settings_for_game(ctx, game.id)?)?;
 
-    // Update lobby status
-    ctx.db.lobby().id().update(Lobby {
-        status: LobbyStatus::InGame,
-        ..lobby
+    let request_id = generate_substitution_id(vacancy.game_id, seat_of, caller(ctx));
+    if ctx.db.substitution_request().id().find(request_id).is_some() {
+        return Err("You already asked for this seat".to_string());
+    }
+
+    ctx.db.substitution_request().insert(SubstitutionRequest {
+        id: request_id,
+        game_id: vacancy.game_id,
+        seat_of,
+        candidate: caller(ctx),
+        requested_at: ctx.timestamp,
     });
 
-    log::info!("Game {} started from lobby {} with {} players", game_id, lobby_id, players.len());
+    if let Some(lobby) = ctx.db.lobby().id().find(game.lobby_id) {
+        notify(ctx, lobby.creator, "Someone asks to take over an empty seat".to_string());
+    }
+
+    log::info!("User {:?} asks to take the seat of {:?} in game {}", caller(ctx), seat_of, vacancy.game_id);
     Ok(())
 }
 
-// Query functions (these don't modify state, just return data)
+#[reducer]
+/// Approve a substitute: the lobby creator decides alone, otherwise a majority of active players
+pub fn approve_substitution(ctx: &ReducerContext, request_id: u64) -> Result<(), String> {
+    let request = ctx.db.substitution_request().id().find(request_id)
+        .ok_or("Request not found")?;
 
-/// Get all available lobbies that can be joined
-pub fn get_available_lobbies(ctx: &ReducerContext) -> Vec<Lobby> {
-    ctx.db.lobby()
-        .iter()
-        .filter(|lobby| lobby.status == LobbyStatus::Waiting)
-        .collect()
-}
+    let game = ctx.db.game().id().find(request.game_id)
+        .ok_or("Game not found")?;
+    let lobby = ctx.db.lobby().id().find(game.lobby_id)
+        .ok_or("Lobby not found")?;
 
-/// Get all players in a specific lobby
-pub fn get_lobby_players(ctx: &ReducerContext, lobby_id: u64) -> Vec<User> {
-    ctx.db.user()
+    let electorate: Vec<Identity> = ctx.db.user()
         .iter()
-        .filter(|user| user.current_lobby_id == Some(lobby_id))
-        .collect()
-}
+        .filter(|user| user.current_game_id == Some(request.game_id) && user.player_status == Some(PlayerStatus::Active))
+        .map(|user| user.identity)
+        .collect();
 
-/// Get all players in a specific game
-pub fn get_game_players(ctx: &ReducerContext, game_id: u64) -> Vec<User> {
-    ctx.db.user()
-        .iter()
-        .filter(|user| user.current_game_id == Some(game_id))
-        .collect()
-}
+    if lobby.creator != caller(ctx) {
+        if !electorate.contains(&caller(ctx)) {
+            return Err("Only the lobby creator or active players can approve".to_string());
+        }
 
-/// Get current player's hand
-pub fn get_player_hand(ctx: &ReducerContext, game_id: u64) -> Vec<Card> {
-    ctx.db.player_card()
-        .iter()
-        .filter(|pc| pc.game_id == game_id && pc.player == ctx.sender && pc.location == CardLocation::Hand)
-        .map(|pc| pc.card.clone())
-        .collect()
-}
+        let vote_id = generate_substitution_vote_id(request_id, caller(ctx));
+        if ctx.db.substitution_vote().id().find(vote_id).is_none() {
+            ctx.db.substitution_vote().insert(SubstitutionVote {
+                id: vote_id,
+                request_id,
+                voter: caller(ctx),
+            });
+        }
 
-/// Get current game state
-pub fn get_game_state(ctx: &ReducerContext, game_id: u64) -> Option<Game> {
-    ctx.db.game().id().find(game_id)
-}
+        let votes = ctx.db.substitution_vote()
+            .iter()
+            .filter(|vote| vote.request_id == request_id && electorate.contains(&vote.voter))
+            .count();
 
-/// Get game settings for a lobby
-pub fn get_game_settings(ctx: &ReducerContext, lobby_id: u64) -> GameSettings {
-    ctx.db.game_settings()
-        .lobby_id()
-        .find(lobby_id)
-        .unwrap_or_else(|| get_default_settings(lobby_id))
-}
+        if votes * 2 <= electorate.len() {
+            return Ok(());
+        }
+    }
 
-/// Get current round for a game
-pub fn get_current_round(ctx: &ReducerContext, game_id: u64) -> Option<Round> {
-    ctx.db.round()
-        .iter()
-        .filter(|round| round.game_id == game_id && round.status == RoundStatus::Active)
-        .next()
+    seat_substitute(ctx, request)
 }
 
-// Card Validation Helpers
+/// Put the approved substitute in the vacant seat with whatever is left of the old hand
+fn seat_substitute(ctx: &ReducerContext, request: SubstitutionRequest) -> Result<(), String> {
+    let game_id = request.game_id;
 
-/// Check if a defending card can beat an attacking card
-fn can_beat_card(attacking_card: &Card, defending_card: &Card, trump_suit: Suit) -> bool {
-    let attack_is_trump = attacking_card.suit == trump_suit;
-    let defend_is_trump = defending_card.suit == trump_suit;
+    let vacancy = ctx.db.seat_vacancy().player().find(request.seat_of)
+        .ok_or("That seat is not vacant")?;
 
-    match (attack_is_trump, defend_is_trump) {
-        // Trump vs trump: higher rank wins
-        (true, true) => defending_card.rank > attacking_card.rank,
-        // Non-trump vs trump: trump always wins
-        (false, true) => true,
-        // Trump vs non-trump: trump always wins (defense invalid)
-        (true, false) => false,
-        // Non-trump vs non-trump: same suit and higher rank
-        (false, false) => {
-            defending_card.suit == attacking_card.suit && defending_card.rank > attacking_card.rank
+    let candidate = ctx.db.user().identity().find(request.candidate)
+        .ok_or("User not found")?;
+    if candidate.current_lobby_id.is_some() || candidate.current_game_id.is_some() {
+        clear_substitution_request(ctx, request.id);
+        return Err("The substitute has joined another lobby or game".to_string());
+    }
+
+    // Cards that went back into play in the meantime stay with whoever has them now
+    let mut held = 0;
+    for card_id in &vacancy.hand {
+        let Some(player_card) = ctx.db.player_card().id().find(*card_id) else {
+            continue;
+        };
+        if matches!(player_card.location, CardLocation::Discarded | CardLocation::Deck) {
+            put_card(ctx, PlayerCard {
+                player: request.candidate,
+                location: CardLocation::Hand,
+                ..player_card
+            });
+            held += 1;
         }
     }
-}
 
-/// Check if an attacking card rank is valid (must match existing ranks on table)
-fn is_valid_attack_rank(rank: Rank, turn_id: u64, ctx: &ReducerContext) -> bool {
-    let existing_draws: Vec<Draw> = ctx.db.draw()
-        .iter()
-        .filter(|draw| draw.turn_id == turn_id)
-        .collect();
+    // Without cards the substitute sits out until the next deal
+    let player_status = if held > 0 { PlayerStatus::Active } else { PlayerStatus::Finished };
 
-    if existing_draws.is_empty() {
-        // First attack can be any rank
-        return true;
+    ctx.db.user().identity().update(User {
+        current_game_id: Some(game_id),
+        game_position: vacancy.game_position,
+        total_points: vacancy.total_points,
+        player_status: Some(player_status),
+        team: vacancy.team,
+        ..candidate
+    });
+    ctx.db.spectator().identity().delete(request.candidate);
+    ctx.db.game_spectator().identity().delete(request.candidate);
+    if let Some(lobby) = ctx.db.game().id().find(game_id).and_then(|game| ctx.db.lobby().id().find(game.lobby_id)) {
+        grant_lobby_access(ctx, &lobby, request.candidate);
     }
 
-    // Additional attacks must match existing ranks on table
-    existing_draws.iter().any(|draw| {
-        draw.attacking_card.rank == rank || 
-        draw.defending_card.as_ref().map_or(false, |card| card.rank == rank)
-    })
-}
+    // The player who left is done with this game for good
+    if let Some(leaver) = ctx.db.user().identity().find(vacancy.player) {
+        if leaver.current_game_id == Some(game_id) {
+            ctx.db.user().identity().update(User {
+                current_game_id: None,
+                game_position: None,
+                total_points: None,
+                player_status: None,
+                team: None,
+                ..leaver
+            });
+        }
+    }
+    ctx.db.recent_leave().player().delete(vacancy.player);
+    clear_seat_vacancy(ctx, vacancy.player);
 
-/// Get player's cards in hand
-fn get_player_cards(ctx: &ReducerContext, game_id: u64, player: Identity) -> Vec<PlayerCard> {
-    ctx.db.player_card()
-        .iter()
-        .filter(|pc| pc.game_id == game_id && pc.player == player && pc.location == CardLocation::Hand)
-        .collect()
-}
+    log_event(ctx, game_id, GameEventKind::Substituted, EventDetail {
+        player: Some(request.candidate),
+        target: Some(vacancy.player),
+        ..Default::default()
+    });
+    notify(ctx, request.candidate, "You took over a seat in the game".to_string());
 
-/// Check if player has the specified card in hand
-fn player_has_card(ctx: &ReducerContext, game_id: u64, player: Identity, card: &Card) -> bool {
-    get_player_cards(ctx, game_id, player)
-        .iter()
-        .any(|pc| pc.card == *card)
-}
+    record_game_cosmetics(ctx, game_id, &[request.candidate]);
 
-/// Generate unique turn ID
-fn generate_turn_id(round_id: u64, turn_number: u32) -> u64 {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    
-    let mut hasher = DefaultHasher::new();
-    round_id.hash(&mut hasher);
-    turn_number.hash(&mut hasher);
-    hasher.finish()
+    log::info!("User {:?} took over the seat of {:?} in game {}", request.candidate, vacancy.player, game_id);
+    after_game_action(ctx, game_id)
 }
 
-/// Generate unique draw ID
-fn generate_draw_id(turn_id: u64, timestamp: Timestamp) -> u64 {
+fn generate_kick_vote_id(game_id: u64, target: Identity, voter: Identity) -> u64 {
     use std::collections::hash_map::DefaultHasher;
     use std::hash::{Hash, Hasher};
-    
+
     let mut hasher = DefaultHasher::new();
-    turn_id.hash(&mut hasher);
-    timestamp.hash(&mut hasher);
+    game_id.hash(&mut hasher);
+    target.hash(&mut hasher);
+    voter.hash(&mut hasher);
     hasher.finish()
 }
 
-/// Get current active turn for a round
-fn get_active_turn(ctx: &ReducerContext, round_id: u64) -> Option<Turn> {
-    ctx.db.turn()
+fn clear_kick_votes(ctx: &ReducerContext, game_id: u64, target: Identity) {
+    let votes: Vec<u64> = ctx.db.kick_vote()
         .iter()
-        .filter(|turn| turn.round_id == round_id && turn.status == TurnStatus::Active)
-        .next()
-}
+        .filter(|vote| vote.game_id == game_id && vote.target == target)
+        .map(|vote| vote.id)
+        .collect();
 
-/// Count pending draws (attacks waiting for defense)
-fn count_pending_draws(ctx: &ReducerContext, turn_id: u64) -> usize {
-    ctx.db.draw()
-        .iter()
-        .filter(|draw| draw.turn_id == turn_id && draw.status == DrawStatus::Pending)
-        .count()
+    for vote_id in votes {
+        ctx.db.kick_vote().id().delete(vote_id);
+    }
 }
 
-/// Get game settings with defaults if not found
-fn get_game_settings_for_game(ctx: &ReducerContext, game_id: u64) -> Result<GameSettings, String> {
-    let game = ctx.db.game().id().find(game_id)
-        .ok_or("Game not found")?;
-    
-    Ok(ctx.db.game_settings()
-        .lobby_id()
-        .find(game.lobby_id)
-        .unwrap_or_else(|| get_default_settings(game.lobby_id)))
-}
+#[reducer]
+/// Vote to remove an AFK player from your game; a majority of the other active players kicks them
+pub fn vote_kick(ctx: &ReducerContext, target: Identity) -> Result<(), String> {
+    let voter = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
 
-// Core Game Actions
+    let game_id = voter.current_game_id
+        .ok_or("You are not in a game")?;
 
-#[reducer]
-/// Attack another player with a card
-pub fn attack(ctx: &ReducerContext, game_id: u64, card: Card, target: Identity) -> Result<(), String> {
-    // Validate game exists and is active
     let game = ctx.db.game().id().find(game_id)
         .ok_or("Game not found")?;
-    
-    if game.status != GameStatus::Active {
-        return Err("Game is not active".to_string());
+    if game.status == GameStatus::Finished {
+        return Err("Game is already finished".to_string());
     }
 
-    // Validate attacker is in the game
-    let attacker = ctx.db.user().identity().find(ctx.sender)
-        .ok_or("User not found")?;
-    
-    if attacker.current_game_id != Some(game_id) {
-        return Err("You are not in this game".to_string());
+    if voter.player_status != Some(PlayerStatus::Active) {
+        return Err("Only active players can vote to kick".to_string());
     }
 
-    if attacker.player_status != Some(PlayerStatus::Active) {
-        return Err("You are not active in this game".to_string());
+    if target == caller(ctx) {
+        return Err("Cannot vote to kick yourself".to_string());
     }
 
-    // Validate target is in the game
-    let defender = ctx.db.user().identity().find(target)
-        .ok_or("Target player not found")?;
-    
-    if defender.current_game_id != Some(game_id) {
-        return Err("Target player is not in this game".to_string());
+    let target_user = ctx.db.user().identity().find(target)
+        .ok_or("Player not found")?;
+    if target_user.current_game_id != Some(game_id) || target_user.player_status != Some(PlayerStatus::Active) {
+        return Err("Player is not active in this game".to_string());
     }
 
-    if defender.player_status != Some(PlayerStatus::Active) {
-        return Err("Target player is not active".to_string());
+    let vote_id = generate_kick_vote_id(game_id, target, caller(ctx));
+    if ctx.db.kick_vote().id().find(vote_id).is_some() {
+        return Err("You already voted to kick this player".to_string());
     }
 
-    // Get current round
-    let round = get_current_round(ctx, game_id)
-        .ok_or("No active round found")?;
-
-    // Check if attacker has the card
-    if !player_has_card(ctx, game_id, ctx.sender, &card) {
-        return Err("You don't have this card".to_string());
-    }
+    ctx.db.kick_vote().insert(KickVote {
+        id: vote_id,
+        game_id,
+        target,
+        voter: caller(ctx),
+        voted_at: ctx.timestamp,
+    });
 
-    // Get current turn or create new one
-    let turn = if let Some(existing_turn) = get_active_turn(ctx, round.id) {
-        // Validate this is an additional attack on existing turn
-        if existing_turn.defender != target {
-            return Err("Can only attack the current defender".to_string());
-        }
+    // Votes only count while the voter is still playing
+    let electorate: Vec<Identity> = ctx.db.user()
+        .iter()
+        .filter(|user| user.current_game_id == Some(game_id) && user.player_status == Some(PlayerStatus::Active))
+        .filter(|user| user.identity != target)
+        .map(|user| user.identity)
+        .collect();
 
-        // Check if rank is valid for additional attack
-        if !is_valid_attack_rank(card.rank, existing_turn.id, ctx) {
-            return Err("Attack card rank must match existing cards on table".to_string());
-        }
+    let votes = ctx.db.kick_vote()
+        .iter()
+        .filter(|vote| vote.game_id == game_id && vote.target == target && electorate.contains(&vote.voter))
+        .count();
 
-        // Check attack limits
-        let settings = get_game_settings_for_game(ctx, game_id)?;
-        if settings.max_attack_cards > 0 {
-            let current_attacks = ctx.db.draw()
-                .iter()
-                .filter(|draw| draw.turn_id == existing_turn.id)
-                .count();
-            
-            if current_attacks >= settings.max_attack_cards as usize {
-                return Err("Maximum attack cards reached".to_string());
-            }
-        }
+    if votes * 2 <= electorate.len() {
+        return Ok(());
+    }
 
-        // Check if anyone can attack or just specific players
-        if !settings.anyone_can_attack {
-            // In traditional rules, only the original attacker can add cards
-            if existing_turn.attacker != ctx.sender {
-                return Err("Only the original attacker can add more cards".to_string());
-            }
-        }
+    log::info!("Player {:?} was voted out of game {}", target, game_id);
+    clear_kick_votes(ctx, game_id, target);
+    ctx.db.bot_seat().player().delete(target);
+    drop_player_from_game(ctx, game_id, target)?;
+    notify(ctx, target, "You were voted out of the game".to_string());
+    after_game_action(ctx, game_id)
+}
 
-        existing_turn
-    } else {
-        // Create new turn with this attack
-        let turn_number = ctx.db.turn()
-            .iter()
-            .filter(|t| t.round_id == round.id)
-            .count() as u32 + 1;
+// Snapshots
 
-        let turn_id = generate_turn_id(round.id, turn_number);
-        let new_turn = Turn {
-            id: turn_id,
-            round_id: round.id,
-            turn_number,
-            attacker: ctx.sender,
-            defender: target,
-            status: TurnStatus::Active,
-            started_at: ctx.timestamp,
-            finished_at: None,
-        };
+fn check_admin(ctx: &ReducerContext) -> Result<(), String> {
+    if ctx.db.admin().identity().find(caller(ctx)).is_none() {
+        return Err("Only admins can do this".to_string());
+    }
+    Ok(())
+}
 
-        ctx.db.turn().insert(new_turn.clone());
-        new_turn
-    };
+#[reducer]
+/// Grant or revoke admin rights (only admins can do this)
+pub fn set_admin(ctx: &ReducerContext, target: Identity, enabled: bool) -> Result<(), String> {
+    check_admin(ctx)?;
 
-    // Create the draw (attack)
-    let draw_id = generate_draw_id(turn.id, ctx.timestamp);
-    ctx.db.draw().insert(Draw {
-        id: draw_id,
-        turn_id: turn.id,
-        attacker: ctx.sender,
-        attacking_card: card.clone(),
-        defending_card: None,
-        status: DrawStatus::Pending,
-        created_at: ctx.timestamp,
-    });
+    if !enabled && target == caller(ctx) {
+        return Err("Cannot revoke your own admin rights".to_string());
+    }
 
-    // Move card from hand to table
-    if let Some(player_card) = ctx.db.player_card()
-        .iter()
-        .find(|pc| pc.game_id == game_id && pc.player == ctx.sender && 
-                   pc.location == CardLocation::Hand && pc.card == card) {
-        ctx.db.player_card().id().update(PlayerCard {
-            location: CardLocation::OnTable,
-            ..player_card
+    ctx.db.admin().identity().delete(target);
+    if enabled {
+        ctx.db.admin().insert(Admin {
+            identity: target,
+            added_at: ctx.timestamp,
         });
+        let reports: Vec<u64> = ctx.db.player_report().iter().map(|report| report.id).collect();
+        for report_id in reports {
+            deliver_report(ctx, report_id, target);
+        }
+    } else {
+        clear_report_deliveries(ctx, target);
     }
 
-    log::info!("Player {:?} attacked {:?} with {:?} of {:?}", 
-               ctx.sender, target, card.rank, card.suit);
+    log::info!("Admin rights of {:?} set to {}", target, enabled);
     Ok(())
 }
 
+fn generate_snapshot_id(game_id: u64, timestamp: Timestamp) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    game_id.hash(&mut hasher);
+    timestamp.hash(&mut hasher);
+    hasher.finish()
+}
+
 #[reducer]
-/// Defend against an attack with a card
-pub fn defend(ctx: &ReducerContext, game_id: u64, turn_id: u64, card: Card) -> Result<(), String> {
-    // Validate game exists and is active
+/// Save a copy of a running game's state, e.g. at the last turn known to be good (only admins can do this)
+pub fn snapshot_game(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
+    check_admin(ctx)?;
+
     let game = ctx.db.game().id().find(game_id)
         .ok_or("Game not found")?;
-    
-    if game.status != GameStatus::Active {
-        return Err("Game is not active".to_string());
-    }
 
-    // Validate defender is in the game
-    let defender = ctx.db.user().identity().find(ctx.sender)
-        .ok_or("User not found")?;
-    
-    if defender.current_game_id != Some(game_id) {
-        return Err("You are not in this game".to_string());
+    if game.status == GameStatus::Finished {
+        return Err("Game is already finished".to_string());
     }
 
-    // Get the turn
-    let turn = ctx.db.turn().id().find(turn_id)
-        .ok_or("Turn not found")?;
-    
-    if turn.defender != ctx.sender {
-        return Err("You are not the defender for this turn".to_string());
-    }
+    let rounds: Vec<Round> = ctx.db.round()
+        .iter()
+        .filter(|round| round.game_id == game_id)
+        .collect();
+    let turns: Vec<Turn> = ctx.db.turn()
+        .iter()
+        .filter(|turn| rounds.iter().any(|round| round.id == turn.round_id))
+        .collect();
+    let in_game = |turn_id: u64| turns.iter().any(|turn| turn.id == turn_id);
 
-    if turn.status != TurnStatus::Active {
-        return Err("Turn is not active".to_string());
-    }
+    let snapshot_id = generate_snapshot_id(game_id, ctx.timestamp);
+    ctx.db.game_snapshot().insert(GameSnapshot {
+        id: snapshot_id,
+        game_id,
+        taken_at: ctx.timestamp,
+        taken_by: caller(ctx),
+        draws: ctx.db.draw().iter().filter(|draw| in_game(draw.turn_id)).collect(),
+        turn_passes: ctx.db.turn_pass().iter().filter(|pass| in_game(pass.turn_id)).collect(),
+        hidden_defenses: ctx.db.hidden_defense().iter().filter(|hidden| in_game(hidden.turn_id)).collect(),
+        player_cards: ctx.db.player_card().iter().filter(|pc| pc.game_id == game_id).collect(),
+        seats: ctx.db.user()
+            .iter()
+            .filter(|user| user.current_game_id == Some(game_id))
+            .map(|user| SeatSnapshot {
+                player: user.identity,
+                game_position: user.game_position,
+                total_points: user.total_points,
+                player_status: user.player_status,
+                team: user.team,
+            })
+            .collect(),
+        game,
+        rounds,
+        turns,
+    });
 
-    // Check if defender has the card
-    if !player_has_card(ctx, game_id, ctx.sender, &card) {
-        return Err("You don't have this card".to_string());
-    }
+    log::info!("Snapshot {} taken of game {}", snapshot_id, game_id);
+    Ok(())
+}
 
-    // Find a pending draw to defend against
-    let pending_draw = ctx.db.draw()
+/// Delete every round, turn, draw and card of a game
+fn clear_game_rows(ctx: &ReducerContext, game_id: u64) {
+    let rounds: Vec<u64> = ctx.db.round()
+        .iter()
+        .filter(|round| round.game_id == game_id)
+        .map(|round| round.id)
+        .collect();
+    let turns: Vec<u64> = ctx.db.turn()
         .iter()
-        .find(|draw| draw.turn_id == turn_id && draw.status == DrawStatus::Pending)
-        .ok_or("No attack to defend against")?;
+        .filter(|turn| rounds.contains(&turn.round_id))
+        .map(|turn| turn.id)
+        .collect();
 
-    // Validate defense is legal
-    if !can_beat_card(&pending_draw.attacking_card, &card, game.trump_suit) {
-        return Err("Your card cannot beat the attacking card".to_string());
+    let draws: Vec<u64> = ctx.db.draw()
+        .iter()
+        .filter(|draw| turns.contains(&draw.turn_id))
+        .map(|draw| draw.id)
+        .collect();
+    for draw_id in draws {
+        ctx.db.draw().id().delete(draw_id);
+        ctx.db.hidden_defense().draw_id().delete(draw_id);
     }
 
-    // Update the draw with defense
-    ctx.db.draw().id().update(Draw {
-        defending_card: Some(card.clone()),
-        status: DrawStatus::Beaten,
-        ..pending_draw
-    });
-
-    // Move defending card from hand to table
-    if let Some(player_card) = ctx.db.player_card()
-        .iter()
-        .find(|pc| pc.game_id == game_id && pc.player == ctx.sender && 
-                   pc.location == CardLocation::Hand && pc.card == card) {
-        ctx.db.player_card().id().update(PlayerCard {
-            location: CardLocation::OnTable,
-            ..player_card
-        });
+    for turn_id in &turns {
+        clear_turn_passes(ctx, *turn_id);
+        ctx.db.turn().id().delete(*turn_id);
     }
 
-    // Check if all attacks are beaten
-    let remaining_pending = count_pending_draws(ctx, turn_id);
-    if remaining_pending == 0 {
-        // All attacks beaten - defender wins the turn
-        finish_turn_defender_won(ctx, game_id, turn_id)?;
+    for round_id in rounds {
+        ctx.db.round().id().delete(round_id);
     }
 
-    log::info!("Player {:?} defended with {:?} of {:?}", 
-               ctx.sender, card.rank, card.suit);
-    Ok(())
+    let cards: Vec<u64> = ctx.db.player_card()
+        .iter()
+        .filter(|pc| pc.game_id == game_id)
+        .map(|pc| pc.id)
+        .collect();
+    for card_id in cards {
+        ctx.db.player_card().id().delete(card_id);
+    }
 }
 
 #[reducer]
-/// Defender takes all cards on the table (gives up defending)
-pub fn take_cards(ctx: &ReducerContext, game_id: u64, turn_id: u64) -> Result<(), String> {
-    // Validate game exists and is active
+/// Roll a game back to a snapshot (only admins can do this)
+pub fn restore_game(ctx: &ReducerContext, snapshot_id: u64) -> Result<(), String> {
+    check_admin(ctx)?;
+
+    let snapshot = ctx.db.game_snapshot().id().find(snapshot_id)
+        .ok_or("Snapshot not found")?;
+    let game_id = snapshot.game_id;
+
     let game = ctx.db.game().id().find(game_id)
         .ok_or("Game not found")?;
-    
-    if game.status != GameStatus::Active {
-        return Err("Game is not active".to_string());
+
+    if game.status == GameStatus::Finished {
+        return Err("Game is already finished".to_string());
     }
 
-    // Get the turn
-    let turn = ctx.db.turn().id().find(turn_id)
-        .ok_or("Turn not found")?;
-    
-    if turn.defender != ctx.sender {
-        return Err("You are not the defender for this turn".to_string());
+    // Seats can only be given back to players who are still here
+    for seat in &snapshot.seats {
+        let user = ctx.db.user().identity().find(seat.player)
+            .ok_or("Player not found")?;
+        if user.current_lobby_id.is_some() || user.current_game_id.is_some_and(|current| current != game_id) {
+            return Err("A player of the snapshot is in another lobby or game".to_string());
+        }
     }
 
-    if turn.status != TurnStatus::Active {
-        return Err("Turn is not active".to_string());
+    clear_game_rows(ctx, game_id);
+    clear_pause_votes(ctx, game_id);
+    clear_draw_votes(ctx, game_id);
+    clear_restart_votes(ctx, game_id);
+
+    for round in snapshot.rounds {
+        ctx.db.round().insert(round);
+    }
+    for turn in snapshot.turns {
+        ctx.db.turn().insert(turn);
+    }
+    for draw in snapshot.draws {
+        ctx.db.draw().insert(draw);
+    }
+    for pass in snapshot.turn_passes {
+        ctx.db.turn_pass().insert(pass);
+    }
+    for hidden in snapshot.hidden_defenses {
+        ctx.db.hidden_defense().insert(hidden);
+    }
+    for player_card in snapshot.player_cards {
+        ctx.db.player_card().insert(player_card);
     }
 
-    // Mark all draws as taken
-    let draws: Vec<Draw> = ctx.db.draw()
-        .iter()
-        .filter(|draw| draw.turn_id == turn_id)
+    // Rounds played after the snapshot drop off the score sheet
+    let scores: Vec<u64> = ctx.db.round_score()
+        .game_id()
+        .filter(game_id)
+        .filter(|score| ctx.db.round().id().find(score.round_id).is_none())
+        .map(|score| score.id)
         .collect();
+    for score_id in scores {
+        ctx.db.round_score().id().delete(score_id);
+    }
 
-    for draw in draws {
-        ctx.db.draw().id().update(Draw {
-            status: DrawStatus::Taken,
-            ..draw
-        });
+    // So do highlights of turns played after it
+    let highlights: Vec<u64> = ctx.db.highlight()
+        .game_id()
+        .filter(game_id)
+        .filter(|highlight| ctx.db.turn().id().find(highlight.turn_id).is_none())
+        .map(|highlight| highlight.id)
+        .collect();
+    for highlight_id in highlights {
+        ctx.db.highlight().id().delete(highlight_id);
     }
 
-    // Move all cards on table to defender's hand
-    let table_cards: Vec<PlayerCard> = ctx.db.player_card()
+    // Players who aren't in the snapshot leave the game
+    let others: Vec<User> = ctx.db.user()
         .iter()
-        .filter(|pc| pc.game_id == game_id && pc.location == CardLocation::OnTable)
+        .filter(|user| user.current_game_id == Some(game_id))
+        .filter(|user| !snapshot.seats.iter().any(|seat| seat.player == user.identity))
         .collect();
+    for user in others {
+        ctx.db.bot_seat().player().delete(user.identity);
+        ctx.db.user().identity().update(User {
+            current_game_id: None,
+            game_position: None,
+            total_points: None,
+            player_status: None,
+            team: None,
+            ..user
+        });
+    }
 
-    for player_card in table_cards {
-        ctx.db.player_card().id().update(PlayerCard {
-            player: ctx.sender,
-            location: CardLocation::Hand,
-            ..player_card
+    for seat in snapshot.seats {
+        let user = ctx.db.user().identity().find(seat.player)
+            .ok_or("Player not found")?;
+        ctx.db.user().identity().update(User {
+            current_game_id: Some(game_id),
+            game_position: seat.game_position,
+            total_points: seat.total_points,
+            player_status: seat.player_status,
+            team: seat.team,
+            ..user
         });
     }
 
-    // Finish turn - defender took cards
-    ctx.db.turn().id().update(Turn {
-        status: TurnStatus::DefenderTook,
-        finished_at: Some(ctx.timestamp),
-        ..turn
+    // Keep the live bookkeeping counters moving forward so clients notice the change
+    ctx.db.game().id().update(Game {
+        last_activity: ctx.timestamp,
+        event_seq: game.event_seq,
+        ..snapshot.game
     });
 
-    // Refill hands and start next turn
-    refill_hands(ctx, game_id)?;
-    start_next_turn_after_take(ctx, game_id, turn.round_id)?;
+    log::info!("Game {} restored from snapshot {}", game_id, snapshot_id);
+    after_game_action(ctx, game_id)
+}
 
-    log::info!("Player {:?} took all cards", ctx.sender);
-    Ok(())
+// Reports
+
+/// How many reports one player may file per window
+const MAX_REPORTS_PER_WINDOW: usize = 5;
+const REPORT_WINDOW_MICROS: i64 = 60 * 60 * 1_000_000;
+const MAX_REPORT_REASON_LENGTH: usize = 500;
+
+fn generate_report_id(reporter: Identity, target: Identity, context_game_id: Option<u64>) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    reporter.hash(&mut hasher);
+    target.hash(&mut hasher);
+    context_game_id.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn generate_report_delivery_id(report_id: u64, admin: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    "report_delivery".hash(&mut hasher);
+    report_id.hash(&mut hasher);
+    admin.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Put a report in an admin's queue
+fn deliver_report(ctx: &ReducerContext, report_id: u64, admin: Identity) {
+    let id = generate_report_delivery_id(report_id, admin);
+    if ctx.db.report_delivery().id().find(id).is_none() {
+        ctx.db.report_delivery().insert(ReportDelivery { id, report_id, admin });
+    }
+}
+
+/// Take every report out of a former admin's queue
+fn clear_report_deliveries(ctx: &ReducerContext, admin: Identity) {
+    let deliveries: Vec<u64> = ctx.db.report_delivery()
+        .admin()
+        .filter(admin)
+        .map(|delivery| delivery.id)
+        .collect();
+    for id in deliveries {
+        ctx.db.report_delivery().id().delete(id);
+    }
+}
+
+/// Whether a player is in a game or finished it
+fn took_part_in_game(ctx: &ReducerContext, game_id: u64, player: Identity) -> bool {
+    ctx.db.user().identity().find(player).is_some_and(|user| user.current_game_id == Some(game_id))
+        || ctx.db.game_history().id().find(generate_participant_id(game_id, player)).is_some()
 }
 
 #[reducer]
-/// Pass turn (attacker cannot or chooses not to add more cards)
-pub fn pass_turn(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
-    // Validate game exists and is active
-    let game = ctx.db.game().id().find(game_id)
-        .ok_or("Game not found")?;
-    
-    if game.status != GameStatus::Active {
-        return Err("Game is not active".to_string());
+/// Report a player to the admins, optionally pointing at a game you both played
+pub fn report_player(ctx: &ReducerContext, target: Identity, reason: String, context_game_id: Option<u64>) -> Result<(), String> {
+    if target == caller(ctx) {
+        return Err("You cannot report yourself".to_string());
     }
 
-    // Get current round
-    let round = get_current_round(ctx, game_id)
-        .ok_or("No active round found")?;
+    ctx.db.user().identity().find(target)
+        .ok_or("User not found")?;
 
-    // Get current turn
-    let turn = get_active_turn(ctx, round.id)
-        .ok_or("No active turn found")?;
+    let reason = reason.trim().to_string();
+    if reason.is_empty() {
+        return Err("Please give a reason".to_string());
+    }
+    if reason.chars().count() > MAX_REPORT_REASON_LENGTH {
+        return Err(format!("Reason must be at most {} characters", MAX_REPORT_REASON_LENGTH));
+    }
 
-    // Check if there are any pending attacks
-    let pending_draws = count_pending_draws(ctx, turn.id);
-    if pending_draws > 0 {
-        return Err("Cannot pass while there are undefended attacks".to_string());
+    // One open report per player and game is enough
+    let id = generate_report_id(caller(ctx), target, context_game_id);
+    if ctx.db.player_report().id().find(id).is_some_and(|report| report.status == ReportStatus::Open) {
+        return Err("You already reported this player".to_string());
     }
 
-    // Only attackers can pass (or anyone if anyone_can_attack is true)
-    let settings = get_game_settings_for_game(ctx, game_id)?;
-    if !settings.anyone_can_attack && turn.attacker != ctx.sender {
-        return Err("Only the attacker can pass".to_string());
+    let window_start = ctx.timestamp - TimeDuration::from_micros(REPORT_WINDOW_MICROS);
+    let recent = ctx.db.player_report()
+        .iter()
+        .filter(|report| report.reporter == caller(ctx) && report.created_at > window_start)
+        .count();
+    if recent >= MAX_REPORTS_PER_WINDOW {
+        return Err("Too many reports, try again later".to_string());
+    }
+
+    let event_seq = match context_game_id {
+        Some(game_id) => {
+            ctx.db.game().id().find(game_id)
+                .ok_or("Game not found")?;
+
+            if !took_part_in_game(ctx, game_id, caller(ctx)) || !took_part_in_game(ctx, game_id, target) {
+                return Err("You can only report players from games you played together".to_string());
+            }
+
+            Some(ctx.db.game_event().game_id().filter(game_id).count() as u32)
+        }
+        None => None,
+    };
+
+    ctx.db.player_report().id().delete(id);
+    ctx.db.player_report().insert(PlayerReport {
+        id,
+        reporter: caller(ctx),
+        target,
+        reason,
+        context_game_id,
+        event_seq,
+        status: ReportStatus::Open,
+        created_at: ctx.timestamp,
+        reviewed_by: None,
+    });
+    let admins: Vec<Identity> = ctx.db.admin().iter().map(|admin| admin.identity).collect();
+    for admin in admins {
+        deliver_report(ctx, id, admin);
     }
 
-    // Turn is implicitly finished when all attacks are defended and no more attacks come
-    // This is handled by a timeout or explicit pass
-    log::info!("Player {:?} passed turn", ctx.sender);
+    log::info!("User {:?} reported {:?} (game {:?})", caller(ctx), target, context_game_id);
     Ok(())
 }
 
-// Turn Resolution Helpers
+#[reducer]
+/// Close a report as actioned or dismissed (only admins can do this)
+pub fn review_report(ctx: &ReducerContext, report_id: u64, status: ReportStatus) -> Result<(), String> {
+    check_admin(ctx)?;
 
-/// Finish turn when defender successfully beat all attacks
-fn finish_turn_defender_won(ctx: &ReducerContext, game_id: u64, turn_id: u64) -> Result<(), String> {
-    let turn = ctx.db.turn().id().find(turn_id)
-        .ok_or("Turn not found")?;
+    let report = ctx.db.player_report().id().find(report_id)
+        .ok_or("Report not found")?;
 
-    // Update turn status
-    ctx.db.turn().id().update(Turn {
-        status: TurnStatus::DefenderBeat,
-        finished_at: Some(ctx.timestamp),
-        ..turn
+    if status == ReportStatus::Open {
+        return Err("A review must action or dismiss the report".to_string());
+    }
+
+    ctx.db.player_report().id().update(PlayerReport {
+        status,
+        reviewed_by: Some(caller(ctx)),
+        ..report
     });
 
-    // Move all cards on table to discard pile
-    let table_cards: Vec<PlayerCard> = ctx.db.player_card()
+    log::info!("Report {} marked {:?} by {:?}", report_id, status, caller(ctx));
+    Ok(())
+}
+
+// Rematch
+
+fn generate_participant_id(game_id: u64, player: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    game_id.hash(&mut hasher);
+    player.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Points a player starts a game with: their final points of the previous game when carried over
+fn carried_points(ctx: &ReducerContext, lobby: &Lobby, player: Identity) -> u8 {
+    lobby.carry_scores_from
+        .and_then(|game_id| ctx.db.game_participant().id().find(generate_participant_id(game_id, player)))
+        .map(|participant| participant.final_points)
+        .unwrap_or(0)
+}
+
+#[reducer]
+/// Vote for a rematch of a finished game; once everyone voted the lobby reopens with the same players
+pub fn rematch(ctx: &ReducerContext, game_id: u64, carry_scores: bool) -> Result<(), String> {
+    let game = ctx.db.game().id().find(game_id)
+        .ok_or("Game not found")?;
+
+    if game.status != GameStatus::Finished {
+        return Err("Game is not finished".to_string());
+    }
+
+    let participants: Vec<Identity> = ctx.db.game_participant()
         .iter()
-        .filter(|pc| pc.game_id == game_id && pc.location == CardLocation::OnTable)
+        .filter(|participant| participant.game_id == game_id)
+        .map(|participant| participant.player)
         .collect();
 
-    for player_card in table_cards {
-        ctx.db.player_card().id().update(PlayerCard {
-            location: CardLocation::Discarded,
-            ..player_card
-        });
+    if !participants.contains(&caller(ctx)) {
+        return Err("You did not play in this game".to_string());
     }
 
-    // Refill hands
-    refill_hands(ctx, game_id)?;
+    let lobby = ctx.db.lobby().id().find(game.lobby_id)
+        .ok_or("Lobby not found")?;
 
-    // Check if round ended (someone emptied their hand)
-    if check_round_end(ctx, game_id, turn.round_id)? {
-        return Ok(());
+    if lobby.status != LobbyStatus::Finished {
+        return Err("Rematch has already started".to_string());
     }
 
-    // Start next turn with defender as new attacker
-    start_next_turn_after_defense(ctx, game_id, turn.round_id, turn.defender)?;
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
 
-    Ok(())
-}
+    if user.current_lobby_id.is_some() || user.current_game_id.is_some() {
+        return Err("You are already in a lobby or game".to_string());
+    }
 
-/// Start next turn after defender took cards (skips defender)
-fn start_next_turn_after_take(ctx: &ReducerContext, game_id: u64, round_id: u64) -> Result<(), String> {
-    let _game = ctx.db.game().id().find(game_id)
-        .ok_or("Game not found")?;
+    let vote_id = generate_participant_id(game_id, caller(ctx));
+    ctx.db.rematch_vote().id().delete(vote_id);
+    ctx.db.rematch_vote().insert(RematchVote {
+        id: vote_id,
+        game_id,
+        player: caller(ctx),
+        carry_scores,
+    });
 
-    let last_turn = ctx.db.turn()
+    log::info!("User {:?} voted for a rematch of game {}", caller(ctx), game_id);
+
+    // Voters who have since joined another lobby or game have to vote again
+    let (votes, stale): (Vec<RematchVote>, Vec<RematchVote>) = ctx.db.rematch_vote()
         .iter()
-        .filter(|t| t.round_id == round_id)
-        .max_by_key(|t| t.turn_number)
-        .ok_or("No previous turn found")?;
+        .filter(|vote| vote.game_id == game_id)
+        .partition(|vote| ctx.db.user().identity().find(vote.player)
+            .is_some_and(|voter| voter.current_lobby_id.is_none() && voter.current_game_id.is_none()));
+    for vote in stale {
+        ctx.db.rematch_vote().id().delete(vote.id);
+    }
 
-    // Check if round ended
-    if check_round_end(ctx, game_id, round_id)? {
+    if votes.len() < participants.len() {
         return Ok(());
     }
 
-    // Next attacker is the player after the defender (clockwise)
-    let next_attacker = get_next_player_clockwise(ctx, game_id, last_turn.defender)?;
-    let next_defender = get_next_player_clockwise(ctx, game_id, next_attacker)?;
+    // Everybody is in: reopen the lobby with the same players
+    for participant in &participants {
+        if let Some(player) = ctx.db.user().identity().find(*participant) {
+            ctx.db.user().identity().update(User {
+                current_lobby_id: Some(lobby.id),
+                lobby_joined_at: Some(ctx.timestamp),
+                ..player
+            });
+        }
+        grant_lobby_access(ctx, &lobby, *participant);
+    }
 
-    // Don't create a new turn immediately - wait for attacker to make a move
-    log::info!("Next turn: {:?} can attack {:?}", next_attacker, next_defender);
-    Ok(())
-}
+    // Scores carry over only if everyone asked for it
+    let carry = votes.iter().all(|vote| vote.carry_scores);
+    let creator = if participants.contains(&lobby.creator) { lobby.creator } else { participants[0] };
+    let lobby_id = lobby.id;
 
-/// Start next turn after successful defense (defender becomes attacker)
-fn start_next_turn_after_defense(ctx: &ReducerContext, game_id: u64, round_id: u64, new_attacker: Identity) -> Result<(), String> {
-    // Check if round ended
-    if check_round_end(ctx, game_id, round_id)? {
-        return Ok(());
+    ctx.db.lobby().id().update(Lobby {
+        creator,
+        current_players: participants.len() as u8,
+        status: LobbyStatus::Waiting,
+        last_activity: ctx.timestamp,
+        carry_scores_from: if carry { Some(game_id) } else { None },
+        ..lobby
+    });
+    sync_open_lobby(ctx, lobby_id);
+
+    for vote in votes {
+        ctx.db.rematch_vote().id().delete(vote.id);
     }
 
-    let new_defender = get_next_player_clockwise(ctx, game_id, new_attacker)?;
-    
-    // Don't create a new turn immediately - wait for attacker to make a move
-    log::info!("Next turn: {:?} can attack {:?}", new_attacker, new_defender);
+    log::info!("Lobby {} reopened for a rematch of game {}", lobby_id, game_id);
     Ok(())
 }
 
-/// Get next active player in clockwise order
-fn get_next_player_clockwise(ctx: &ReducerContext, game_id: u64, current_player: Identity) -> Result<Identity, String> {
-    let current_user = ctx.db.user().identity().find(current_player)
-        .ok_or("Current player not found")?;
-    
-    let _current_position = current_user.game_position
-        .ok_or("Player has no game position")?;
-
-    let game_players: Vec<User> = ctx.db.user()
-        .iter()
-        .filter(|user| user.current_game_id == Some(game_id) && user.player_status == Some(PlayerStatus::Active))
-        .collect();
+// Tournaments
 
-    if game_players.len() < 2 {
-        return Err("Not enough active players".to_string());
-    }
+fn generate_tournament_id(organizer: Identity, timestamp: Timestamp) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
 
-    // Sort by position and find next active player
-    let mut sorted_players = game_players;
-    sorted_players.sort_by_key(|p| p.game_position.unwrap_or(0));
+    let mut hasher = DefaultHasher::new();
+    organizer.hash(&mut hasher);
+    timestamp.hash(&mut hasher);
+    hasher.finish()
+}
 
-    let current_index = sorted_players.iter()
-        .position(|p| p.identity == current_player)
-        .ok_or("Current player not found in game")?;
+fn generate_entrant_id(tournament_id: u64, player: Identity) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
 
-    let next_index = (current_index + 1) % sorted_players.len();
-    Ok(sorted_players[next_index].identity)
+    let mut hasher = DefaultHasher::new();
+    tournament_id.hash(&mut hasher);
+    player.hash(&mut hasher);
+    hasher.finish()
 }
 
-/// Refill all players' hands from deck
-fn refill_hands(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
-    let settings = get_game_settings_for_game(ctx, game_id)?;
-    let target_hand_size = settings.starting_cards as usize;
-
-    // Get all active players sorted by position
-    let mut players: Vec<User> = ctx.db.user()
+fn get_tournament_entrants(ctx: &ReducerContext, tournament_id: u64) -> Vec<TournamentEntrant> {
+    ctx.db.tournament_entrant()
         .iter()
-        .filter(|user| user.current_game_id == Some(game_id) && user.player_status == Some(PlayerStatus::Active))
-        .collect();
-    
-    players.sort_by_key(|p| p.game_position.unwrap_or(0));
+        .filter(|entrant| entrant.tournament_id == tournament_id)
+        .collect()
+}
 
-    // Get deck cards
-    let mut deck_cards: Vec<PlayerCard> = ctx.db.player_card()
-        .iter()
-        .filter(|pc| pc.game_id == game_id && pc.location == CardLocation::Deck)
-        .collect();
+#[reducer]
+/// Announce a tournament with a registration window and entrant limits
+pub fn create_tournament(
+    ctx: &ReducerContext,
+    name: String,
+    registration_opens_at: Timestamp,
+    registration_closes_at: Timestamp,
+    min_entrants: u32,
+    max_entrants: u32,
+) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Tournament name cannot be empty".to_string());
+    }
 
-    // Refill hands (attackers first, then defender)
-    for player in players {
-        let current_hand_size = get_player_cards(ctx, game_id, player.identity).len();
-        let cards_needed = target_hand_size.saturating_sub(current_hand_size);
+    if registration_closes_at <= registration_opens_at || registration_closes_at <= ctx.timestamp {
+        return Err("Registration must close in the future, after it opens".to_string());
+    }
 
-        for _ in 0..cards_needed {
-            if let Some(deck_card) = deck_cards.pop() {
-                ctx.db.player_card().id().update(PlayerCard {
-                    player: player.identity,
-                    location: CardLocation::Hand,
-                    ..deck_card
-                });
-            } else {
-                // No more cards in deck
-                break;
-            }
-        }
+    if min_entrants < 2 || max_entrants < min_entrants || max_entrants > 256 {
+        return Err("Entrants must be between 2 and 256, with min not above max".to_string());
     }
 
+    let tournament_id = generate_tournament_id(caller(ctx), ctx.timestamp);
+    ctx.db.tournament().insert(Tournament {
+        id: tournament_id,
+        name,
+        organizer: caller(ctx),
+        status: TournamentStatus::Registration,
+        registration_opens_at,
+        registration_closes_at,
+        min_entrants,
+        max_entrants,
+        created_at: ctx.timestamp,
+    });
+
+    ctx.db.tournament_close_schedule().insert(TournamentCloseSchedule {
+        scheduled_id: 0,
+        scheduled_at: ScheduleAt::Time(registration_closes_at),
+        tournament_id,
+    });
+
+    log::info!("User {:?} created tournament {}", caller(ctx), tournament_id);
     Ok(())
 }
 
-/// Check if round has ended (only one player with cards)
-fn check_round_end(ctx: &ReducerContext, game_id: u64, round_id: u64) -> Result<bool, String> {
-    let players: Vec<User> = ctx.db.user()
-        .iter()
-        .filter(|user| user.current_game_id == Some(game_id) && user.player_status == Some(PlayerStatus::Active))
-        .collect();
+#[reducer]
+/// Sign up for a tournament while its registration window is open
+pub fn register_for_tournament(ctx: &ReducerContext, tournament_id: u64) -> Result<(), String> {
+    let tournament = ctx.db.tournament().id().find(tournament_id)
+        .ok_or("Tournament not found")?;
 
-    let mut players_with_cards = Vec::new();
+    let user = ctx.db.user().identity().find(caller(ctx))
+        .ok_or("User not found")?;
+    if !user.registered {
+        return Err("Guests cannot enter tournaments".to_string());
+    }
 
-    for player in players {
-        let hand_size = get_player_cards(ctx, game_id, player.identity).len();
-        if hand_size > 0 {
-            players_with_cards.push(player);
-        } else {
-            // Player finished this round
-            ctx.db.user().identity().update(User {
-                player_status: Some(PlayerStatus::Finished),
-                ..player
-            });
-        }
+    if tournament.status != TournamentStatus::Registration
+        || ctx.timestamp < tournament.registration_opens_at
+        || ctx.timestamp >= tournament.registration_closes_at
+    {
+        return Err("Registration is not open".to_string());
+    }
+
+    let entrant_id = generate_entrant_id(tournament_id, caller(ctx));
+    if ctx.db.tournament_entrant().id().find(entrant_id).is_some() {
+        return Err("You are already registered".to_string());
     }
 
-    if players_with_cards.len() <= 1 {
-        // Round ended
-        let round = ctx.db.round().id().find(round_id)
-            .ok_or("Round not found")?;
+    if get_tournament_entrants(ctx, tournament_id).len() >= tournament.max_entrants as usize {
+        return Err("Tournament is full".to_string());
+    }
 
-        let loser = players_with_cards.first().map(|p| p.identity);
+    ctx.db.tournament_entrant().insert(TournamentEntrant {
+        id: entrant_id,
+        tournament_id,
+        player: caller(ctx),
+        registered_at: ctx.timestamp,
+        seed: None,
+    });
 
-        ctx.db.round().id().update(Round {
-            status: RoundStatus::Finished,
-            loser,
-            finished_at: Some(ctx.timestamp),
-            ..round
-        });
+    log::info!("User {:?} registered for tournament {}", caller(ctx), tournament_id);
+    Ok(())
+}
 
-        // Handle scoring and check if game ended
-        handle_round_scoring(ctx, game_id, loser)?;
+#[reducer]
+/// Withdraw from a tournament before registration closes
+pub fn withdraw_from_tournament(ctx: &ReducerContext, tournament_id: u64) -> Result<(), String> {
+    let tournament = ctx.db.tournament().id().find(tournament_id)
+        .ok_or("Tournament not found")?;
 
-        log::info!("Round {} ended, loser: {:?}", round.round_number, loser);
-        return Ok(true);
+    if tournament.status != TournamentStatus::Registration {
+        return Err("Registration is closed".to_string());
     }
 
-    Ok(false)
+    let entrant_id = generate_entrant_id(tournament_id, caller(ctx));
+    if ctx.db.tournament_entrant().id().find(entrant_id).is_none() {
+        return Err("You are not registered".to_string());
+    }
+
+    ctx.db.tournament_entrant().id().delete(entrant_id);
+
+    log::info!("User {:?} withdrew from tournament {}", caller(ctx), tournament_id);
+    Ok(())
 }
 
-/// Handle scoring after round ends
-fn handle_round_scoring(ctx: &ReducerContext, game_id: u64, loser: Option<Identity>) -> Result<(), String> {
-    let settings = get_game_settings_for_game(ctx, game_id)?;
+#[reducer]
+/// Close registration: seed the entrants, or cancel if too few signed up (scheduled)
+pub fn close_tournament_registration(ctx: &ReducerContext, schedule: TournamentCloseSchedule) -> Result<(), String> {
+    if ctx.sender != ctx.identity() {
+        return Err("Only the scheduler can close registration".to_string());
+    }
 
-    if !settings.multi_round_mode {
-        // Single round mode - game ends here
-        finish_game(ctx, game_id, loser)?;
+    let Some(tournament) = ctx.db.tournament().id().find(schedule.tournament_id) else {
+        return Ok(());
+    };
+
+    if tournament.status != TournamentStatus::Registration {
         return Ok(());
     }
 
-    // Multi-round mode - add points and check if game should end
-    if let Some(loser_identity) = loser {
-        let loser_user = ctx.db.user().identity().find(loser_identity)
-            .ok_or("Loser not found")?;
+    let mut entrants = get_tournament_entrants(ctx, tournament.id);
 
-        let new_points = loser_user.total_points.unwrap_or(0) + 5; // 5 points for losing a round
+    if entrants.len() < tournament.min_entrants as usize {
+        // No entry fees exist, so cancelling only needs to let everyone know
+        for entrant in &entrants {
+            notify(ctx, entrant.player, format!("Tournament \"{}\" was cancelled: not enough entrants", tournament.name));
+        }
 
-        ctx.db.user().identity().update(User {
-            total_points: Some(new_points),
-            ..loser_user
+        log::info!("Tournament {} cancelled with {} entrants", tournament.id, entrants.len());
+        ctx.db.tournament().id().update(Tournament {
+            status: TournamentStatus::Cancelled,
+            ..tournament
         });
+        return Ok(());
+    }
 
-        // Check if player reached max points (becomes the "Fool")
-        if new_points >= settings.max_points {
-            finish_game(ctx, game_id, Some(loser_identity))?;
-            return Ok(());
-        }
+    // Matches are duels, so the duel rating decides the seeds; earlier sign-ups break ties
+    entrants.sort_by(|a, b| {
+        let rating_a = get_player_rating(ctx, a.player, RatingPool::Duel).rating;
+        let rating_b = get_player_rating(ctx, b.player, RatingPool::Duel).rating;
+        rating_b.total_cmp(&rating_a).then(a.registered_at.cmp(&b.registered_at))
+    });
+    let seeded: Vec<Identity> = entrants.iter().map(|entrant| entrant.player).collect();
+    for (index, entrant) in entrants.into_iter().enumerate() {
+        ctx.db.tournament_entrant().id().update(TournamentEntrant {
+            seed: Some(index as u32 + 1),
+            ..entrant
+        });
     }
 
-    // Start new round
-    start_new_round(ctx, game_id)?;
+    create_bracket(ctx, tournament.id, &seeded, tournament.registration_closes_at);
+
+    log::info!("Tournament {} registration closed and seeded", tournament.id);
+    ctx.db.tournament().id().update(Tournament {
+        status: TournamentStatus::Seeded,
+        ..tournament
+    });
     Ok(())
 }
 
-/// Start a new round
-fn start_new_round(ctx: &ReducerContext, game_id: u64) -> Result<(), String> {
-    let game = ctx.db.game().id().find(game_id)
-        .ok_or("Game not found")?;
+/// Gap between registration closing and the first round
+const TOURNAMENT_FIRST_ROUND_DELAY_MICROS: i64 = 10 * 60 * 1_000_000;
 
-    let new_round_number = game.current_round + 1;
-    let round_id = generate_round_id(game_id, new_round_number);
+fn generate_match_id(tournament_id: u64, round: u32, slot: u32) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
 
-    // Reset all players to active
-    let players: Vec<User> = ctx.db.user()
-        .iter()
-        .filter(|user| user.current_game_id == Some(game_id))
-        .collect();
+    let mut hasher = DefaultHasher::new();
+    tournament_id.hash(&mut hasher);
+    round.hash(&mut hasher);
+    slot.hash(&mut hasher);
+    hasher.finish()
+}
 
-    for player in players {
-        ctx.db.user().identity().update(User {
-            player_status: Some(PlayerStatus::Active),
-            ..player
+/// Lay out a single elimination bracket for players given in seed order.
+/// Top seeds are spread so they can only meet late, missing opponents are byes.
+fn create_bracket(ctx: &ReducerContext, tournament_id: u64, seeded: &[Identity], closes_at: Timestamp) {
+    let size = seeded.len().next_power_of_two();
+
+    // Seed order for the first round: 1 v 8, 4 v 5, 2 v 7, 3 v 6, ...
+    let mut order = vec![1usize];
+    while order.len() < size {
+        let n = order.len() * 2;
+        order = order.iter().flat_map(|&seed| [seed, n + 1 - seed]).collect();
+    }
+
+    let rounds = size.trailing_zeros();
+    for round in 1..=rounds {
+        for slot in 0..(size >> round) as u32 {
+            let (player_a, player_b) = if round == 1 {
+                let seat = |seed: usize| seeded.get(seed - 1).copied();
+                (seat(order[slot as usize * 2]), seat(order[slot as usize * 2 + 1]))
+            } else {
+                (None, None)
+            };
+
+            ctx.db.tournament_match().insert(TournamentMatch {
+                id: generate_match_id(tournament_id, round, slot),
+                tournament_id,
+                round,
+                slot,
+                player_a,
+                player_b,
+                scheduled_at: if round == 1 {
+                    Some(closes_at + TimeDuration::from_micros(TOURNAMENT_FIRST_ROUND_DELAY_MICROS))
+                } else {
+                    None
+                },
+                lobby_id: None,
+                winner: None,
+            });
+        }
+    }
+
+    // Players without an opponent advance straight away
+    for slot in 0..(size >> 1) as u32 {
+        let Some(first) = ctx.db.tournament_match().id().find(generate_match_id(tournament_id, 1, slot)) else {
+            continue;
+        };
+        if let (Some(player), None) | (None, Some(player)) = (first.player_a, first.player_b) {
+            set_match_winner(ctx, first, player);
+        }
+    }
+}
+
+/// Record a match winner and move them into their next-round slot
+fn set_match_winner(ctx: &ReducerContext, finished: TournamentMatch, winner: Identity) {
+    let next_id = generate_match_id(finished.tournament_id, finished.round + 1, finished.slot / 2);
+    if let Some(next) = ctx.db.tournament_match().id().find(next_id) {
+        let feeds_a = finished.slot % 2 == 0;
+        ctx.db.tournament_match().id().update(TournamentMatch {
+            player_a: if feeds_a { Some(winner) } else { next.player_a },
+            player_b: if feeds_a { next.player_b } else { Some(winner) },
+            ..next
         });
+    } else {
+        // The final has no next match
+        grant_title(ctx, winner, Title::TournamentChampion);
     }
 
-    // Create new round
-    ctx.db.round().insert(Round {
-        id: round_id,
-        game_id,
-        round_number: new_round_number,
-        status: RoundStatus::Active,
-        loser: None,
-        started_at: ctx.timestamp,
-        finished_at: None,
+    ctx.db.tournament_match().id().update(TournamentMatch {
+        winner: Some(winner),
+        ..finished
     });
+}
 
-    // Update game
-    ctx.db.game().id().update(Game {
-        current_round: new_round_number,
-        ..game
+#[reducer]
+/// Publish when and where a tournament match is played (only the organizer can do this)
+pub fn schedule_tournament_match(
+    ctx: &ReducerContext,
+    match_id: u64,
+    scheduled_at: Option<Timestamp>,
+    lobby_id: Option<u64>,
+) -> Result<(), String> {
+    let tournament_match = ctx.db.tournament_match().id().find(match_id)
+        .ok_or("Match not found")?;
+
+    let tournament = ctx.db.tournament().id().find(tournament_match.tournament_id)
+        .ok_or("Tournament not found")?;
+
+    if tournament.organizer != caller(ctx) {
+        return Err("Only the organizer can schedule matches".to_string());
+    }
+
+    if lobby_id.is_some_and(|id| ctx.db.lobby().id().find(id).is_none()) {
+        return Err("Lobby not found".to_string());
+    }
+
+    ctx.db.tournament_match().id().update(TournamentMatch {
+        scheduled_at,
+        lobby_id,
+        ..tournament_match
     });
 
-    // Redeal cards (simplified - would need proper shuffle and deal logic)
-    log::info!("Started new round {} for game {}", new_round_number, game_id);
+    log::info!("Tournament match {} scheduled at {:?} in lobby {:?}", match_id, scheduled_at, lobby_id);
     Ok(())
 }
 
-/// Finish the entire game
-fn finish_game(ctx: &ReducerContext, game_id: u64, final_loser: Option<Identity>) -> Result<(), String> {
-    let game = ctx.db.game().id().find(game_id)
-        .ok_or("Game not found")?;
+#[reducer]
+/// Record who won a tournament match (only the organizer can do this)
+pub fn report_tournament_match(ctx: &ReducerContext, match_id: u64, winner: Identity) -> Result<(), String> {
+    let tournament_match = ctx.db.tournament_match().id().find(match_id)
+        .ok_or("Match not found")?;
 
-    ctx.db.game().id().update(Game {
-        status: GameStatus::Finished,
-        finished_at: Some(ctx.timestamp),
-        ..game
-    });
+    let tournament = ctx.db.tournament().id().find(tournament_match.tournament_id)
+        .ok_or("Tournament not found")?;
 
-    // Reset all players' game state
-    let players: Vec<User> = ctx.db.user()
-        .iter()
-        .filter(|user| user.current_game_id == Some(game_id))
-        .collect();
+    if tournament.organizer != caller(ctx) {
+        return Err("Only the organizer can report results".to_string());
+    }
 
-    for player in players {
-        ctx.db.user().identity().update(User {
-            current_game_id: None,
-            game_position: None,
-            total_points: None,
-            player_status: None,
-            ..player
-        });
+    if tournament_match.winner.is_some() {
+        return Err("Match already has a winner".to_string());
     }
 
-    // Update lobby status
-    ctx.db.lobby().id().update(Lobby {
-        status: LobbyStatus::Finished,
-        ..ctx.db.lobby().id().find(game.lobby_id).unwrap()
-    });
+    if tournament_match.player_a != Some(winner) && tournament_match.player_b != Some(winner) {
+        return Err("Winner did not play in this match".to_string());
+    }
 
-    log::info!("Game {} finished, final loser: {:?}", game_id, final_loser);
+    set_match_winner(ctx, tournament_match, winner);
+
+    log::info!("Tournament match {} won by {:?}", match_id, winner);
     Ok(())
 }
 
@@ -1513,6 +13234,6 @@ pub fn get_turn_draws(ctx: &ReducerContext, turn_id: u64) -> Vec<Draw> {
 pub fn get_table_cards(ctx: &ReducerContext, game_id: u64) -> Vec<PlayerCard> {
     ctx.db.player_card()
         .iter()
-        .filter(|pc| pc.game_id == game_id && pc.location == CardLocation::OnTable)
+        .filter(|pc| pc.game_id == game_id && matches!(pc.location, CardLocation::OnTable | CardLocation::FaceDown))
         .collect()
 }
\ No newline at end of file